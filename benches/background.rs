@@ -0,0 +1,99 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Measures what a large function library costs the jobs a shell starts:
+//! copying the interpreter, whose function bodies are shared, against
+//! copying every body, and backgrounding many no-op functions, each of which
+//! runs in a forked copy of the shell.
+//!
+//! Run with `cargo bench --bench background`.
+
+use bellos::shell::shell::Shell;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+/// The number of functions in the library.
+const FUNCTIONS: usize = 500;
+
+/// The number of jobs backgrounded in one run.
+const JOBS: usize = 100;
+
+/// A library of `FUNCTIONS` functions, each with a body of a few dozen
+/// commands, loops and conditionals.
+fn library() -> String {
+    let body = "  if [ \"$1\" = start ]; then\n\
+                \x20   echo \"starting $1\" | tr a-z A-Z > /dev/null 2>&1\n\
+                \x20 elif [ \"$1\" = stop ]; then\n\
+                \x20   count=$((count - 1))\n\
+                \x20 fi\n\
+                \x20 for item in a b c d e f; do\n\
+                \x20   case $item in a | b) total=$((total + 1)) ;; *) : ;; esac\n\
+                \x20 done\n\
+                \x20 while [ $count -lt 0 ]; do count=$((count + 1)); done\n";
+    (0..FUNCTIONS)
+        .map(|i| format!("library_{}() {{\n{}}}\n", i, body.repeat(4)))
+        .collect()
+}
+
+fn best(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, count: usize, time: Duration) {
+    println!(
+        "{:<22} {:>9.2} ms {:>9.2} µs each",
+        name,
+        time.as_secs_f64() * 1000.0,
+        time.as_secs_f64() * 1e6 / count as f64
+    );
+}
+
+fn main() {
+    let mut shell = Shell::new();
+    shell.run(&library()).unwrap();
+    shell.run("noop() { :; }").unwrap();
+    println!("functions: {}", shell.interpreter.functions.len());
+
+    let time = best(|| {
+        black_box(shell.interpreter.clone());
+    });
+    report("interpreter clone", 1, time);
+    let time = best(|| {
+        let bodies: Vec<_> = shell
+            .interpreter
+            .functions
+            .values()
+            .map(|body| body.as_ref().clone())
+            .collect();
+        black_box(bodies);
+    });
+    report("deep copy of bodies", 1, time);
+
+    // Each job announces itself on standard output: keep that out of the
+    // report while the jobs start.
+    let jobs =
+        "exec 9>&1 >/dev/null\n".to_string() + &"noop &\n".repeat(JOBS) + "wait\nexec >&9 9>&-\n";
+    let time = best(|| shell.run(&jobs).unwrap());
+    report("background no-ops", JOBS, time);
+}
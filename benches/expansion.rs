@@ -0,0 +1,68 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Expands many command arguments with and without variable references,
+//! printing the best time of several runs.
+//!
+//! Run with `cargo bench --bench expansion`.
+
+use bellos::interpreter_logic::interpreter::Interpreter;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+/// The number of arguments expanded in one run.
+const ARGUMENTS: usize = 100_000;
+
+fn best(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, time: Duration) {
+    println!(
+        "{:<16} {:>8.2} ms {:>8.1} ns/argument",
+        name,
+        time.as_secs_f64() * 1000.0,
+        time.as_secs_f64() * 1e9 / ARGUMENTS as f64
+    );
+}
+
+fn main() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_variable("name", "bellos".to_string());
+    let plain: Vec<String> = (0..ARGUMENTS)
+        .map(|i| format!("--option-{}=some/plain/value", i))
+        .collect();
+    let referencing: Vec<String> = (0..ARGUMENTS)
+        .map(|i| format!("--option-{}=some/$name/value", i))
+        .collect();
+
+    for (name, arguments) in [("plain", &plain), ("one variable", &referencing)] {
+        let time = best(|| {
+            for argument in arguments {
+                black_box(interpreter.expand_variables(black_box(argument)).unwrap());
+            }
+        });
+        report(name, time);
+    }
+}
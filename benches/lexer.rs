@@ -0,0 +1,73 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tokenizes a generated multi-megabyte script with the allocating and the
+//! borrowing lexer APIs, printing the best throughput of several runs.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+use bellos::lexer::lexer::Lexer;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+/// A script of about 4 MB mixing the constructs the lexer handles:
+/// words, quoted strings with multibyte text, pipes, redirections,
+/// substitutions, keywords and comments.
+fn script() -> String {
+    let block = "# déploiement du service ✓\n\
+                 name=\"bellos-$i\" count=$((count + 1))\n\
+                 if [ \"$name\" = 'bellos-1' ]; then\n\
+                 \x20   echo \"héllo $name — ünïcödé\" | tr a-z A-Z > out.txt 2>&1\n\
+                 fi\n\
+                 for f in *.log; do cat \"$f\" >> all.log; done\n\
+                 result=$(grep -c 'error' all.log) && echo \"$result errors\"\n";
+    block.repeat(4 * 1024 * 1024 / block.len())
+}
+
+fn best(mut run: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, bytes: usize, time: Duration) {
+    let megabytes = bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<18} {:>8.2} ms {:>8.1} MB/s",
+        name,
+        time.as_secs_f64() * 1000.0,
+        megabytes / time.as_secs_f64()
+    );
+}
+
+fn main() {
+    let input = script();
+    println!("input: {} bytes", input.len());
+    let time = best(|| {
+        black_box(Lexer::new(black_box(&input)).tokenize());
+    });
+    report("tokenize", input.len(), time);
+    let time = best(|| {
+        black_box(Lexer::new(black_box(&input)).tokenize_borrowed());
+    });
+    report("tokenize_borrowed", input.len(), time);
+}
@@ -0,0 +1,43 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs two shells on their own threads. Each keeps its own variables,
+//! functions and exported environment, so the commands they start see
+//! different values of `GREETING`.
+
+use bellos::shell::shell::Shell;
+use std::thread;
+
+fn main() {
+    let workers: Vec<_> = [("first", "hello"), ("second", "bonjour")]
+        .into_iter()
+        .map(|(name, greeting)| {
+            thread::spawn(move || {
+                let mut shell = Shell::new();
+                shell.interpreter.init_variables();
+                let script = format!(
+                    "export GREETING={}\ngreet() {{ echo {}: $GREETING; }}\ngreet\nprintenv GREETING\n",
+                    greeting, name
+                );
+                shell.run(&script)
+            })
+        })
+        .collect();
+    for worker in workers {
+        if let Err(e) = worker.join().expect("shell thread panicked") {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
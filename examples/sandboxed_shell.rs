@@ -0,0 +1,53 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs a script in a shell that has files only in memory and records the
+//! external commands it would run instead of starting them.
+
+use bellos::executor_processes::testing::{MemoryFs, RecordingSpawner};
+use bellos::shell::shell::Shell;
+
+fn main() {
+    let fs = MemoryFs::new();
+    fs.insert("notes/todo.txt", "buy milk\ncall home\n");
+    let spawner = RecordingSpawner::new();
+    spawner.respond("date", 0, "Mon Jan  1 00:00:00 UTC 2024\n");
+
+    let mut shell = Shell::new();
+    shell.interpreter.init_variables();
+    shell.processes.fs = Box::new(fs.clone());
+    shell.processes.spawner = Some(Box::new(spawner.clone()));
+    let script = "\
+for note in notes/*.txt; do wc -l $note; done
+write notes/done.txt \"$(date)\"
+if [ -f notes/done.txt ]; then delete notes/todo.txt; fi
+git commit -m notes | cat
+";
+    if let Err(e) = shell.run(script) {
+        eprintln!("Error: {}", e);
+    }
+
+    for path in fs.paths() {
+        let content = fs.get(&path).unwrap_or_default();
+        println!(
+            "{}: {:?}",
+            path.display(),
+            String::from_utf8_lossy(&content)
+        );
+    }
+    for invocation in spawner.invocations() {
+        println!("ran {} {:?}", invocation.program, invocation.args);
+    }
+}
@@ -1 +1 @@
-bellande_rust_executable -d dependencies.bellande -s src -m bellos.rs -o executable/bellos
+bellande_rust_executable -d dependencies.bellande -s src -m main.rs -o executable/bellos
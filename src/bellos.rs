@@ -13,21 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-mod executor_processes;
-mod interpreter_logic;
-mod lexer;
-mod parser;
-mod shell;
-mod utilities;
+#![allow(clippy::module_inception)]
 
-use crate::executor_processes::executor::Executor;
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    let mut executor = Executor::new();
-    if let Err(e) = executor.run(args) {
-        eprintln!("Application error: {}", e);
-        std::process::exit(1);
-    }
-}
+pub mod executor_processes;
+pub mod interpreter_logic;
+pub mod lexer;
+pub mod parser;
+pub mod shell;
+pub mod utilities;
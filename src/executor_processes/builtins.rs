@@ -0,0 +1,568 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// A command run by the shell itself, with the documentation `help` shows.
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    /// One line for `help` listings.
+    pub summary: &'static str,
+    /// The options and behavior in more detail, for `help NAME`.
+    pub description: &'static str,
+}
+
+/// Every builtin, in listing order. `exit`, `return`, `break`, `continue`,
+/// `assert`, `assert_eq`, `include`, `fc` and `bind` are handled by the shell, the rest
+/// by `Processes::execute_command`.
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: ":",
+        usage: ": [arg ...]",
+        summary: "Do nothing, successfully.",
+        description: "The arguments are expanded and ignored, and the status is 0.",
+    },
+    Builtin {
+        name: "[",
+        usage: "[ expr ]",
+        summary: "Evaluate a condition, like test.",
+        description: "The same as `test expr`, but the last argument must be `]`.",
+    },
+    Builtin {
+        name: "[[",
+        usage: "[[ expr ]]",
+        summary: "Evaluate a condition, with regex matching.",
+        description: "Accepts everything `test` does, plus `text =~ regex`. A match stores \
+                      the matched text in ${BASH_REMATCH[0]} and capture group N in \
+                      ${BASH_REMATCH[N]}.",
+    },
+    Builtin {
+        name: "append",
+        usage: "append <filename> [content]",
+        summary: "Append content to a file.",
+        description: "The file must already exist. Without content, appends standard input, \
+                      byte for byte.",
+    },
+    Builtin {
+        name: "argv",
+        usage: "argv [arg ...]",
+        summary: "Show the arguments a command receives.",
+        description: "Prints `argc: N`, then each argument on its own line as `<arg>`, \
+                      making the result of quoting, splitting and globbing visible.",
+    },
+    Builtin {
+        name: "assert",
+        usage: "assert [-k] command [args ...]",
+        summary: "Fail the script unless a command succeeds.",
+        description: "Runs the command, usually a test like `[ \"$x\" -eq 5 ]`. If it fails, \
+                      prints the assertion, its line and its expanded words, and exits with \
+                      status 1.\n\
+                      -k    keep going: return 1 instead of exiting",
+    },
+    Builtin {
+        name: "assert_eq",
+        usage: "assert_eq [-k] left right",
+        summary: "Fail the script unless two strings are equal.",
+        description: "If they differ, prints the assertion, its line and where the strings \
+                      differ, and exits with status 1.\n\
+                      -k    keep going: return 1 instead of exiting",
+    },
+    Builtin {
+        name: "bg",
+        usage: "bg [jobspec]",
+        summary: "Resume a stopped job in the background.",
+        description: "Defaults to the current job.",
+    },
+    Builtin {
+        name: "bind",
+        usage: "bind '\"keyseq\": text' | bind -x '\"keyseq\": command' | bind -p | bind -r keyseq...",
+        summary: "Bind key sequences in the interactive line editor.",
+        description: "A plain binding inserts its text, as a macro; a trailing \\n in it \
+                      accepts the line. Key sequences take \\C-x, \\M-x, \\e and C escapes.\n\
+                      -x    run a command, which can read and change READLINE_LINE and READLINE_POINT\n\
+                      -p    list the bindings\n\
+                      -r    remove the bindings of the key sequences",
+    },
+    Builtin {
+        name: "break",
+        usage: "break [n]",
+        summary: "Leave a loop.",
+        description: "Leaves the n-th enclosing loop, by default the innermost one.",
+    },
+    Builtin {
+        name: "caller",
+        usage: "caller [n]",
+        summary: "Describe an enclosing function call.",
+        description: "Without n, prints the line and script the current function was \
+                      called from. With n, prints the line, calling function and script of \
+                      the call n levels up. Fails outside of functions.",
+    },
+    Builtin {
+        name: "cat",
+        usage: "cat [file ...]",
+        summary: "Print files.",
+        description: "Copies the files, or standard input for none or `-`, to standard output.",
+    },
+    Builtin {
+        name: "cd",
+        usage: "cd [dir | -]",
+        summary: "Change the current directory.",
+        description: "Changes to dir, to $HOME without one, or to $OLDPWD with `-`, which \
+                      also prints the new directory. A dir that is not below the current \
+                      directory is looked for in the colon-separated CDPATH, and printed when \
+                      found there, unless it is absolute or starts with `.` or `..`. Keeps PWD \
+                      and OLDPWD up to date.",
+    },
+    Builtin {
+        name: "chmod",
+        usage: "chmod mode file ...",
+        summary: "Change the permissions of files.",
+        description: "The mode is octal, like 755, or symbolic clauses like `u+x,go-w`, \
+                      made of the classes u, g, o and a, an operator +, - or =, and the \
+                      permissions r, w, x, s and t.",
+    },
+    Builtin {
+        name: "clear",
+        usage: "clear",
+        summary: "Clear the terminal screen.",
+        description: "Does nothing when standard output is not a terminal. Ctrl-L does \
+                      the same in the line editor, keeping the line being edited.",
+    },
+    Builtin {
+        name: "complete",
+        usage: "complete [-W words | -F function | -p | -r] [name ...]",
+        summary: "Set how arguments of commands are completed.",
+        description: "-W words    complete to the words of the list, expanded when used\n\
+                      -F function call function with the command, the current word and \
+                      the previous word, and complete to the words it leaves in COMPREPLY. \
+                      COMP_WORDS, COMP_CWORD and COMP_LINE describe the line.\n\
+                      -p          list the registrations\n\
+                      -r          remove the registrations, or all of them",
+    },
+    Builtin {
+        name: "continue",
+        usage: "continue [n]",
+        summary: "Go on to the next iteration of a loop.",
+        description: "Resumes the n-th enclosing loop, by default the innermost one.",
+    },
+    Builtin {
+        name: "date",
+        usage: "date [-u] [-d @seconds] [+format]",
+        summary: "Print the date and time.",
+        description: "Prints the current time, or with -d the given seconds since the epoch, \
+                      in the time zone of the exported TZ or with -u in UTC. The format \
+                      understands %Y, %m, %d, %e, %H, %M, %S, %s, %N (nanoseconds), %z, %Z, \
+                      %a, %b and %%, and keeps other specifiers as written. Without one, it is \
+                      `%a %b %e %H:%M:%S %Z %Y`.",
+    },
+    Builtin {
+        name: "declare",
+        usage: "declare -F",
+        summary: "List the defined functions.",
+        description: "Prints `declare -f name` for each function, sorted by name.",
+    },
+    Builtin {
+        name: "delete",
+        usage: "delete <filename>",
+        summary: "Delete a file.",
+        description: "Removes one file. Directories are not deleted.",
+    },
+    Builtin {
+        name: "disown",
+        usage: "disown [-h] [-a] [jobspec ...]",
+        summary: "Remove jobs from the job table.",
+        description: "Defaults to the current job.\n\
+                      -a    all jobs\n\
+                      -h    keep the jobs, but do not send them SIGHUP when the shell \
+                      exits under huponexit",
+    },
+    Builtin {
+        name: "echo",
+        usage: "echo [arg ...]",
+        summary: "Print arguments to standard output.",
+        description: "The arguments are separated by spaces and followed by a newline.",
+    },
+    Builtin {
+        name: "exec",
+        usage: "exec [command [arg ...]]",
+        summary: "Replace the shell with a command.",
+        description: "Without a command, makes the redirections of `exec` apply to the \
+                      shell itself from then on.",
+    },
+    Builtin {
+        name: "exit",
+        usage: "exit [n]",
+        summary: "Exit the shell.",
+        description: "Exits with status n, by default that of the last command, after \
+                      running the EXIT trap.",
+    },
+    Builtin {
+        name: "expr",
+        usage: "expr expression",
+        summary: "Evaluate an expression.",
+        description: "Prints the value of an expression built from integer arithmetic, \
+                      comparisons, `|`, `&`, `length`, `substr`, `index` and `string : \
+                      regex`. The status is 0 if the value is neither empty nor zero, 1 \
+                      if it is and 2 on an error.",
+    },
+    Builtin {
+        name: "export",
+        usage: "export [name[=value] ...]",
+        summary: "Set variables and pass them to commands.",
+        description: "Without names, lists the exported variables as `export \
+                      name=value`, sorted by name.",
+    },
+    Builtin {
+        name: "fc",
+        usage: "fc -l [-nr] [first [last]] | fc -s [old=new] [spec] | fc [first [last]]",
+        summary: "List, edit or rerun history entries.",
+        description: "-l    list entries, by default the last 16\n\
+                      -n    list without numbers\n\
+                      -r    list in reverse order\n\
+                      -s    run an entry again, by default the last, replacing old with new\n\
+                      Without -l or -s, opens the entries, by default the last, in $FCEDIT or \
+                      $EDITOR and runs what is saved. An entry is a number, a negative offset \
+                      from the last entry, or the start of a command.",
+    },
+    Builtin {
+        name: "fg",
+        usage: "fg [jobspec]",
+        summary: "Bring a job to the foreground.",
+        description: "Defaults to the current job, which may be stopped. Waits for the job \
+                      and returns its status.",
+    },
+    Builtin {
+        name: "head",
+        usage: "head [-n count] [file ...]",
+        summary: "Print the first lines of files.",
+        description: "Prints the first count lines, 10 by default, of the files or of \
+                      standard input.",
+    },
+    Builtin {
+        name: "help",
+        usage: "help [name ...]",
+        summary: "Describe builtins.",
+        description: "Without names, lists every builtin with a summary.",
+    },
+    Builtin {
+        name: "http_get",
+        usage: "http_get [-H 'Name: value'] [-t seconds] url [outfile]",
+        summary: "Fetch a URL over HTTP.",
+        description: "Writes the body of the response to standard output or outfile and \
+                      sets HTTP_STATUS to its status code. Redirects are followed. Only \
+                      https:// URLs go through the openssl command, which checks the \
+                      server's certificate. The status is 0 for a 2xx response.\n\
+                      -H header    send an extra header, repeatable\n\
+                      -t seconds   give up on the whole request, redirects included, \
+                      after seconds, 30 by default",
+    },
+    Builtin {
+        name: "http_post",
+        usage: "http_post [-H 'Name: value'] [-t seconds] url data",
+        summary: "Send data to a URL over HTTP.",
+        description: "Posts data, as application/x-www-form-urlencoded unless -H gives a \
+                      Content-Type, and writes the body of the response to standard \
+                      output. Otherwise like http_get.",
+    },
+    Builtin {
+        name: "include",
+        usage: "include file | include -l",
+        summary: "Source a library once.",
+        description: "Looks for the file in the directories of BELLOS_PATH, then in that of \
+                      the including script, unless its name holds a `/`, and runs it in the \
+                      current shell. A file already included is not run again.\n\
+                      -l    list the files included so far",
+    },
+    Builtin {
+        name: "jobs",
+        usage: "jobs",
+        summary: "List background jobs.",
+        description: "Prints each job's number, whether it is running, stopped or done, and \
+                      its command. Finished jobs are reported once and then forgotten.",
+    },
+    Builtin {
+        name: "kill",
+        usage: "kill [-s sig | -sig] pid | jobspec ...",
+        summary: "Send a signal to processes or jobs.",
+        description: "Sends SIGTERM unless a signal is given by name or number.",
+    },
+    Builtin {
+        name: "ln",
+        usage: "ln [-s] [-f] target link",
+        summary: "Create a link to a file.",
+        description: "-s    create a symbolic link instead of a hard link\n\
+                      -f    replace an existing link\n\
+                      A link that is a directory gets a link inside it with the target's name.",
+    },
+    Builtin {
+        name: "local",
+        usage: "local [-n] name[=value] ...",
+        summary: "Make variables local to a function.",
+        description: "The variables get their previous values back when the function \
+                      returns.\n\
+                      -n    make each name a reference to the variable its value names, \
+                      so that reading or assigning it reads or assigns that variable",
+    },
+    Builtin {
+        name: "match",
+        usage: "match [-v] [-i] [-o] pattern [file ...]",
+        summary: "Print lines matching a regular expression.",
+        description: "Reads the files, or standard input, and prints the lines matching the \
+                      extended regular expression pattern.\n\
+                      -v    print the lines that do not match\n\
+                      -i    ignore case\n\
+                      -o    print only the matched text, a match per line\n\
+                      The status is 0 if a line matched, 1 if none did and 2 on an error.",
+    },
+    Builtin {
+        name: "mkfifo",
+        usage: "mkfifo [-m mode] path ...",
+        summary: "Create named pipes.",
+        description: "-m mode   set the permissions to the octal mode instead of 666 \
+                      less the umask",
+    },
+    Builtin {
+        name: "read",
+        usage: "read [-r] [-u fd] [name ...]",
+        summary: "Read a line into variables.",
+        description: "Reads a line from standard input into REPLY or the named variables, \
+                      split on IFS. The last variable gets the rest of the line.\n\
+                      -r    do not treat backslashes as escapes\n\
+                      -u fd read from descriptor fd instead of standard input\n\
+                      Fails at end of input.",
+    },
+    Builtin {
+        name: "readfile",
+        usage: "readfile [-r] <filename>",
+        summary: "Print the contents of a file.",
+        description: "-r    print the bytes exactly, without adding a newline",
+    },
+    Builtin {
+        name: "return",
+        usage: "return [n]",
+        summary: "Return from a function.",
+        description: "Ends the function with status n, by default that of the last command.",
+    },
+    Builtin {
+        name: "seq",
+        usage: "seq [start] [step] end",
+        summary: "Print a sequence of numbers.",
+        description: "Counts from start, 1 by default, up to end in steps of step.",
+    },
+    Builtin {
+        name: "set",
+        usage: "set [-nvx] [+nvx]",
+        summary: "Set or unset shell options.",
+        description: "-n    read commands without running them\n\
+                      -v    print input lines as they are read\n\
+                      -x    print commands as they are run\n\
+                      `+` instead of `-` turns an option off. Without arguments, \
+                      lists the shell variables as `name=value`, sorted by name.",
+    },
+    Builtin {
+        name: "shopt",
+        usage: "shopt [-s | -u] [-q] [name ...]",
+        summary: "Set, unset or list shopt options.",
+        description: "-s    enable the named options\n\
+                      -u    disable the named options\n\
+                      -q    print nothing; succeed if every named option is on\n\
+                      Without -s or -u, lists the options and their state. The options \
+                      are dotglob, extglob, globstar, huponexit, nocaseglob and nullglob. \
+                      extglob is accepted but extended patterns are not supported yet.",
+    },
+    Builtin {
+        name: "split",
+        usage: "split name string delimiter",
+        summary: "Split a string into fields.",
+        description: "Splits string on every occurrence of delimiter, or into characters if \
+                      it is empty, into the indexed array name: ${name[0]} is the first \
+                      field, ${#name[@]} the number of fields and \"${name[@]}\" all of \
+                      them. Adjacent delimiters give empty fields, and an empty string \
+                      gives none.",
+    },
+    Builtin {
+        name: "stat",
+        usage: "stat [-c format] file ...",
+        summary: "Describe files.",
+        description: "Prints the size, type, permissions and modification time of files, \
+                      without following symbolic links.\n\
+                      -c format   print the format instead, where %n is the name, %s the \
+                      size, %a and %A the permissions in octal and symbolically, %F the \
+                      type and %Y the modification time in seconds since the epoch",
+    },
+    Builtin {
+        name: "tail",
+        usage: "tail [-n count] [file ...]",
+        summary: "Print the last lines of files.",
+        description: "Prints the last count lines, 10 by default, of the files or of \
+                      standard input. Files are read backwards from their end.",
+    },
+    Builtin {
+        name: "test",
+        usage: "test expr",
+        summary: "Evaluate a condition.",
+        description: "Supports -n, -z, -v name, -o option, the file tests -e, -f, -d and -s, \
+                      string comparison with =, == and !=, and integer comparison with -eq, \
+                      -ne, -lt, -le, -gt and -ge. Returns 2 on usage errors.",
+    },
+    Builtin {
+        name: "timeout",
+        usage: "timeout [-k grace] duration command [args ...]",
+        summary: "Run a command with a time limit.",
+        description: "Durations are seconds, or end in s, m, h or d, like 3.5s or 2m. A command \
+                      still running after the duration is sent SIGTERM, and the status is 124; \
+                      otherwise it is the command's own. A duration of 0 never times out. A \
+                      function runs in a copy of the shell, so its assignments are lost.\n\
+                      -k    send SIGKILL if the command is still running this long after SIGTERM",
+    },
+    Builtin {
+        name: "trap",
+        usage: "trap [-p] [action] [condition ...]",
+        summary: "Run a command when the shell exits or a command fails.",
+        description: "Conditions are EXIT, when the shell ends, ERR, after a command \
+                      fails, and DEBUG, before every simple command. An action of `-` \
+                      resets the conditions and an empty one ignores them. With -p or no \
+                      arguments, lists the traps set.",
+    },
+    Builtin {
+        name: "type",
+        usage: "type name ...",
+        summary: "Tell how each name would run as a command.",
+        description: "Prints whether each name is a shell builtin, a function or a file \
+                      found in PATH. Returns 1 if any name is none of these.",
+    },
+    Builtin {
+        name: "unset",
+        usage: "unset [-f | -v] name ...",
+        summary: "Remove variables or functions.",
+        description: "-f    remove functions\n\
+                      -v    remove variables, the default",
+    },
+    Builtin {
+        name: "wait",
+        usage: "wait [pid | jobspec ...]",
+        summary: "Wait for background jobs.",
+        description: "Without arguments, waits for every job. Returns the status of the \
+                      last job waited for.",
+    },
+    Builtin {
+        name: "wc",
+        usage: "wc [-l] [-w] [-m] [-c] [file ...]",
+        summary: "Count lines, words and bytes.",
+        description: "-l    count lines\n\
+                      -w    count words\n\
+                      -m    count UTF-8 characters, fewer than bytes for multibyte text\n\
+                      -c    count bytes\n\
+                      The counts are printed in this order. Without options, counts\n\
+                      lines, words and bytes.",
+    },
+    Builtin {
+        name: "which",
+        usage: "which [-a] [-s] name ...",
+        summary: "Locate commands in PATH.",
+        description: "-a    print every executable match, in PATH order\n\
+                      -s    print nothing, only set the status\n\
+                      Functions and builtins are ignored. Returns 1 if any name is not found.",
+    },
+    Builtin {
+        name: "write",
+        usage: "write <filename> [content]",
+        summary: "Write content to a file.",
+        description: "Creates the file or replaces its contents. Without content, writes \
+                      standard input, byte for byte.",
+    },
+];
+
+/// The builtins of this build: `http_get` and `http_post` need the `http`
+/// feature.
+pub fn available() -> impl Iterator<Item = &'static Builtin> {
+    BUILTINS
+        .iter()
+        .filter(|builtin| cfg!(feature = "http") || !builtin.name.starts_with("http_"))
+}
+
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    available().find(|builtin| builtin.name == name)
+}
+
+/// What `help` prints without arguments: each builtin's name, padded to the
+/// longest one, and summary.
+pub fn listing() -> String {
+    let width = available().map(|b| b.name.len()).max().unwrap_or(0);
+    available()
+        .map(|builtin| format!("{:<width$}  {}\n", builtin.name, builtin.summary))
+        .collect()
+}
+
+impl Builtin {
+    /// What `help NAME` prints: the usage, then the summary and description
+    /// indented under it.
+    pub fn help(&self) -> String {
+        let mut text = format!("{}: {}\n    {}\n", self.name, self.usage, self.summary);
+        for line in self.description.lines() {
+            text.push_str(&format!("    {}\n", line));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_builtin_has_help() {
+        for builtin in available() {
+            assert!(
+                builtin.usage.starts_with(builtin.name),
+                "{}: {}",
+                builtin.name,
+                builtin.usage
+            );
+            assert!(!builtin.summary.is_empty(), "{}", builtin.name);
+            assert!(!builtin.description.is_empty(), "{}", builtin.name);
+            assert!(builtin.help().lines().count() >= 3, "{}", builtin.name);
+            assert_eq!(find(builtin.name).map(|b| b.name), Some(builtin.name));
+        }
+    }
+
+    #[test]
+    fn http_builtins_exist_only_with_the_http_feature() {
+        assert_eq!(find("http_get").is_some(), cfg!(feature = "http"));
+        assert_eq!(listing().contains("http_post"), cfg!(feature = "http"));
+    }
+
+    #[test]
+    fn help_read_describes_its_flags() {
+        let help = find("read").unwrap().help();
+        assert!(
+            help.starts_with("read: read [-r] [-u fd] [name ...]\n"),
+            "{}",
+            help
+        );
+        assert!(help.contains("\n    -r "), "{}", help);
+        assert!(help.contains("\n    -u fd "), "{}", help);
+    }
+
+    #[test]
+    fn listing_aligns_summaries_after_the_longest_name() {
+        let listing = listing();
+        assert_eq!(listing.lines().count(), available().count());
+        let width = available().map(|b| b.name.len()).max().unwrap();
+        for (line, builtin) in listing.lines().zip(available()) {
+            assert_eq!(&line[..width + 2].trim_end(), &builtin.name);
+            assert_eq!(&line[width + 2..], builtin.summary);
+        }
+    }
+}
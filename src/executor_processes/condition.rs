@@ -0,0 +1,98 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::providers::FsProvider;
+use crate::interpreter_logic::interpreter::Interpreter;
+use crate::interpreter_logic::logic::compare_operands;
+use regex::Regex;
+use std::path::Path;
+
+/// Evaluates the expression given to `test` or `[` (without its `]`),
+/// choosing the form by argument count as POSIX does. An `Err` is a usage
+/// error, which `test` reports with status 2. File tests look in `fs`.
+pub fn evaluate(
+    interpreter: &Interpreter,
+    fs: &dyn FsProvider,
+    args: &[String],
+) -> Result<bool, String> {
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!args[0].is_empty()),
+        2 if args[0] == "!" => Ok(args[1].is_empty()),
+        2 => unary(interpreter, fs, &args[0], &args[1]),
+        3 if is_binary(&args[1]) => compare_operands(&args[0], &args[1], &args[2]),
+        3 | 4 if args[0] == "!" => evaluate(interpreter, fs, &args[1..]).map(|result| !result),
+        3 if args[0] == "(" && args[2] == ")" => Ok(!args[1].is_empty()),
+        3 => Err(format!("{}: binary operator expected", args[1])),
+        _ => Err("too many arguments".to_string()),
+    }
+}
+
+fn unary(
+    interpreter: &Interpreter,
+    fs: &dyn FsProvider,
+    op: &str,
+    operand: &str,
+) -> Result<bool, String> {
+    let metadata = || fs.metadata(Path::new(operand));
+    match op {
+        "-n" => Ok(!operand.is_empty()),
+        "-z" => Ok(operand.is_empty()),
+        "-v" => Ok(interpreter.is_set(operand)),
+        "-o" => match operand {
+            "noexec" => Ok(interpreter.options.noexec),
+            "verbose" => Ok(interpreter.options.verbose),
+            "xtrace" => Ok(interpreter.options.xtrace),
+            _ => Ok(false),
+        },
+        "-e" => Ok(metadata().is_ok()),
+        "-f" => Ok(metadata().is_ok_and(|metadata| metadata.is_file)),
+        "-d" => Ok(metadata().is_ok_and(|metadata| metadata.is_dir)),
+        "-s" => Ok(metadata().is_ok_and(|metadata| metadata.len > 0)),
+        _ => Err(format!("{}: unary operator expected", op)),
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge"
+    )
+}
+
+/// Matches `text` against the extended regular expression `pattern`, as
+/// `[[ text =~ pattern ]]` does, storing the whole match and then each
+/// capture group in the array `BASH_REMATCH`. It is empty when nothing
+/// matched.
+pub fn regex_match(
+    interpreter: &mut Interpreter,
+    text: &str,
+    pattern: &str,
+) -> Result<bool, String> {
+    let regex = Regex::new(pattern)
+        .map_err(|e| format!("{}: invalid regular expression: {}", pattern, e))?;
+    let groups: Vec<String> = regex
+        .captures(text)
+        .map(|captures| {
+            captures
+                .iter()
+                .map(|group| group.map_or("", |group| group.as_str()).to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let matched = !groups.is_empty();
+    interpreter.set_array("BASH_REMATCH", groups);
+    Ok(matched)
+}
@@ -0,0 +1,268 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::streams::Streams;
+use crate::interpreter_logic::interpreter::Interpreter;
+use chrono::{DateTime, Local};
+use chrono_tz::Tz;
+use std::fmt::Display;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What `date` prints without a `+format`, as GNU `date` does in the C
+/// locale: `Fri Oct 16 12:00:00 UTC 2026`.
+const DEFAULT_FORMAT: &str = "%a %b %e %H:%M:%S %Z %Y";
+
+/// The system's zone, a link into the zone database naming it.
+const LOCALTIME: &str = "/etc/localtime";
+
+/// The zone a time is shown in. A shell's own `TZ` is read here rather than
+/// by chrono, which only takes it from the process environment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Utc,
+    /// The process's local time, as chrono works it out from its `TZ`.
+    Local,
+    /// A zone of the database built into chrono-tz, like `Europe/Paris`.
+    Named(Tz),
+}
+
+impl Zone {
+    /// The zone a `TZ` value names: a zone of the database, with or without
+    /// a leading `:`. Other values, like the POSIX rule
+    /// `EST5EDT,M3.2.0,M11.1.0`, are understood only as the process's own
+    /// `TZ`, and are otherwise UTC.
+    pub fn from_tz(tz: &str) -> Zone {
+        let name = tz.strip_prefix(':').unwrap_or(tz);
+        if let Ok(zone) = name.parse() {
+            Zone::Named(zone)
+        } else if std::env::var("TZ").is_ok_and(|process| process == tz) {
+            Zone::Local
+        } else {
+            Zone::Utc
+        }
+    }
+
+    /// The system's zone, as `/etc/localtime` names it, or else the
+    /// process's local time.
+    fn system() -> Zone {
+        std::fs::read_link(LOCALTIME)
+            .ok()
+            .and_then(|target| {
+                let target = target.to_string_lossy().into_owned();
+                let (_, name) = target.split_once("zoneinfo/")?;
+                name.parse().ok()
+            })
+            .map_or(Zone::Local, Zone::Named)
+    }
+}
+
+/// `date [-u] [-d @seconds] [+format]`: prints the current time, or the
+/// given seconds since the epoch, in local time or with `-u` in UTC. The
+/// time zone is the shell's exported `TZ`, as for the commands it starts.
+pub fn date(interpreter: &Interpreter, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let mut utc = false;
+    let mut given = None;
+    let mut format = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-u" => utc = true,
+            "-d" => {
+                let date = args
+                    .next()
+                    .ok_or("date: option requires an argument -- 'd'")?;
+                given = Some(parse_date(date)?);
+            }
+            _ if arg.starts_with("-d") => given = Some(parse_date(&arg[2..])?),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("date: {}: invalid option", arg));
+            }
+            _ if arg.starts_with('+') && format.is_none() => format = Some(&arg[1..]),
+            _ => return Err(format!("date: extra operand '{}'", arg)),
+        }
+    }
+
+    let (seconds, nanoseconds) = match given {
+        Some(time) => time,
+        None => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| format!("date: {}", e))?;
+            (now.as_secs() as i64, now.subsec_nanos())
+        }
+    };
+    let format = format.unwrap_or(DEFAULT_FORMAT);
+    let zone = if utc {
+        Zone::Utc
+    } else {
+        shell_zone(interpreter)
+    };
+    let output = format_time(format, seconds, nanoseconds, &zone)
+        .ok_or_else(|| format!("date: time {} is out of range", seconds))?;
+    writeln!(streams.out(), "{}", output).map_err(|e| format!("date: write error: {}", e))?;
+    Ok(0)
+}
+
+/// Parses `@seconds`, which may have a fraction and be negative, into
+/// whole seconds since the epoch and the nanoseconds after them.
+fn parse_date(date: &str) -> Result<(i64, u32), String> {
+    let invalid = || format!("date: invalid date '{}'", date);
+    let number = date.strip_prefix('@').ok_or_else(invalid)?;
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let seconds: i64 = whole.parse().map_err(|_| invalid())?;
+    // Digits past nanoseconds are dropped.
+    let digits: String = fraction.chars().take(9).collect();
+    let nanoseconds: u32 = format!("{:0<9}", digits).parse().map_err(|_| invalid())?;
+    if whole.starts_with('-') && nanoseconds > 0 {
+        let seconds = seconds.checked_sub(1).ok_or_else(invalid)?;
+        Ok((seconds, 1_000_000_000 - nanoseconds))
+    } else {
+        Ok((seconds, nanoseconds))
+    }
+}
+
+/// The zone the shell's commands would show times in: the one its
+/// exported `TZ` names, or the system's own without one.
+fn shell_zone(interpreter: &Interpreter) -> Zone {
+    let tz = interpreter
+        .exported
+        .contains("TZ")
+        .then(|| interpreter.variable("TZ"))
+        .flatten();
+    tz.map_or_else(Zone::system, |tz| Zone::from_tz(&tz))
+}
+
+/// Formats a time given as seconds and nanoseconds since the epoch, in
+/// `zone`, like `strftime`: `%Y`, `%m`, `%d`, `%e`, `%H`, `%M`, `%S`, `%s`,
+/// `%N`, `%z`, `%Z`, `%a`, `%b` and `%%` are replaced, and other specifiers
+/// are kept as written. `None` if the time is out of range.
+pub fn format_time(format: &str, seconds: i64, nanoseconds: u32, zone: &Zone) -> Option<String> {
+    let time = DateTime::from_timestamp(seconds, nanoseconds)?;
+    Some(match zone {
+        Zone::Utc => expand(format, &time, None),
+        Zone::Named(tz) => expand(format, &time.with_timezone(tz), None),
+        Zone::Local => {
+            // chrono knows local offsets but not their names: `%Z` shows
+            // the offset, as GNU `date` does for a zone without a name.
+            let time = time.with_timezone(&Local);
+            let offset = time.format("%z").to_string();
+            expand(format, &time, Some(&offset))
+        }
+    })
+}
+
+/// Formats `time` after translating `format` into chrono's: the
+/// specifiers `date` takes are kept, `%N` becomes `%9f`, `%Z` is
+/// `abbreviation` when given, and the rest are escaped to print as written.
+fn expand<Z: chrono::TimeZone>(
+    format: &str,
+    time: &DateTime<Z>,
+    abbreviation: Option<&str>,
+) -> String
+where
+    Z::Offset: Display,
+{
+    let mut translated = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            translated.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(c @ ('Y' | 'm' | 'd' | 'e' | 'H' | 'M' | 'S' | 's' | 'z' | 'a' | 'b' | '%')) => {
+                translated.push('%');
+                translated.push(c);
+            }
+            Some('N') => translated.push_str("%9f"),
+            Some('Z') => match abbreviation {
+                Some(abbreviation) => translated.push_str(&abbreviation.replace('%', "%%")),
+                None => translated.push_str("%Z"),
+            },
+            Some(other) => {
+                translated.push_str("%%");
+                translated.push(other);
+            }
+            None => translated.push_str("%%"),
+        }
+    }
+    time.format(&translated).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-11-14 22:13:20 UTC, a Tuesday.
+    const SECONDS: i64 = 1_700_000_000;
+
+    #[test]
+    fn every_specifier_is_replaced() {
+        assert_eq!(
+            format_time(
+                "%Y-%m-%d|%e|%H:%M:%S|%s|%N|%z|%Z|%a %b|%%|%q|%",
+                SECONDS,
+                5,
+                &Zone::Utc
+            )
+            .unwrap(),
+            "2023-11-14|14|22:13:20|1700000000|000000005|+0000|UTC|Tue Nov|%|%q|%"
+        );
+        assert_eq!(
+            format_time(DEFAULT_FORMAT, 0, 0, &Zone::Utc).unwrap(),
+            "Thu Jan  1 00:00:00 UTC 1970"
+        );
+        assert_eq!(
+            format_time("%Y-%m-%d %H:%M:%S %a", -1, 0, &Zone::Utc).unwrap(),
+            "1969-12-31 23:59:59 Wed"
+        );
+    }
+
+    #[test]
+    fn zones_move_the_date_and_name_the_offset() {
+        let tokyo = Zone::from_tz("Asia/Tokyo");
+        assert_eq!(
+            format_time("%F %a %d %H:%M %z %Z", SECONDS, 0, &tokyo).unwrap(),
+            "%F Wed 15 07:13 +0900 JST"
+        );
+        assert_eq!(
+            format_time("%H:%M %z", SECONDS, 0, &Zone::from_tz(":Asia/Kolkata")).unwrap(),
+            "03:43 +0530"
+        );
+        let eastern = Zone::from_tz("America/New_York");
+        assert_eq!(
+            format_time("%m-%d %H:%M %z %Z", SECONDS, 0, &eastern).unwrap(),
+            "11-14 17:13 -0500 EST"
+        );
+        // 2023-07-01 12:00:00 UTC.
+        assert_eq!(
+            format_time("%m-%d %H:%M %z %Z", 1_688_212_800, 0, &eastern).unwrap(),
+            "07-01 08:00 -0400 EDT"
+        );
+        assert_eq!(Zone::from_tz("Not/A_Zone"), Zone::Utc);
+    }
+
+    #[test]
+    fn dates_are_read_with_their_fractions() {
+        assert_eq!(parse_date("@1.5"), Ok((1, 500_000_000)));
+        assert_eq!(parse_date("@-1.25"), Ok((-2, 750_000_000)));
+        assert!(parse_date("1").is_err());
+        assert!(parse_date("@1.x").is_err());
+    }
+}
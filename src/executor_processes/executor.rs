@@ -13,15 +13,33 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::executor_processes::jobs;
+use crate::executor_processes::runtime::block_on;
+use crate::lexer::lexer::{is_continued, is_incomplete, Lexer};
+use crate::parser::parser::{Parser, SYNTAX_ERROR_STATUS};
+use crate::shell::line_editor;
 use crate::shell::shell::Shell;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use crate::utilities::utilities::Interrupt;
+use std::fs;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
 
+/// The prompt of interactive mode.
+const PROMPT: &str = "bellos> ";
+
+/// The prompt for the lines that complete an unfinished one.
+const CONTINUATION_PROMPT: &str = "> ";
+
 pub struct Executor {
     shell: Shell,
 }
 
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Executor {
     pub fn new() -> Self {
         Executor {
@@ -29,57 +47,237 @@ impl Executor {
         }
     }
 
-    pub fn run(&mut self, args: Vec<String>) -> Result<(), String> {
-        if args.len() > 1 {
-            self.execute_script(&args[1])
-        } else {
-            self.run_interactive_mode()
+    /// Runs a script, or the interactive shell without one, returning the
+    /// status the process should exit with: that of the last command.
+    /// Options come before the script, or end at `--`, and the arguments
+    /// after it become the positional parameters. Scripts given with `-f`
+    /// run in order in the same shell instead, all the arguments being
+    /// their parameters. With `-i` the interactive shell follows the
+    /// scripts, keeping what they defined. A login shell, started with `-l`
+    /// or a name beginning with `-`, first sources the profile files.
+    pub fn run(&mut self, args: Vec<String>) -> Result<i32, String> {
+        self.shell.interpreter.init_variables();
+        // `login` starts shells with a `-` before their name.
+        let mut login = args.first().is_some_and(|name| name.starts_with('-'));
+        let mut args = args.into_iter().skip(1).peekable();
+        let mut scripts = Vec::new();
+        let mut keep_going = false;
+        let mut interactive = false;
+        while let Some(flag) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+            let options = &mut self.shell.interpreter.options;
+            match flag.as_str() {
+                "-n" => options.noexec = true,
+                "-v" => options.verbose = true,
+                "-x" => options.xtrace = true,
+                "-i" => interactive = true,
+                "-l" | "--login" => login = true,
+                "-f" => scripts.push(args.next().ok_or("-f: script name expected")?),
+                "--keep-going" => keep_going = true,
+                "--" => break,
+                _ => return Err(format!("Unknown option: {}", flag)),
+            }
+        }
+        self.shell.interpreter.update_option_flags();
+
+        if login {
+            self.shell.start_login();
         }
+        if interactive || scripts.is_empty() && args.peek().is_none() {
+            self.shell.start_interactive();
+        }
+        if scripts.is_empty() {
+            match args.next() {
+                Some(script) => scripts.push(script),
+                None => return self.run_interactive_mode(),
+            }
+        }
+        let parameters: Vec<String> = args.collect();
+        self.shell
+            .interpreter
+            .set_positional_parameters(&parameters);
+
+        let status = match scripts.as_slice() {
+            [script] if !interactive => match self.run_script(script) {
+                Ok(status) => status,
+                Err(e) => {
+                    self.shell.shutdown();
+                    return Err(e);
+                }
+            },
+            _ => self.execute_scripts(&scripts, keep_going),
+        };
+        if interactive {
+            return self.run_interactive_mode();
+        }
+        Ok(self.shell.shutdown().unwrap_or(status))
     }
 
-    fn execute_script(&mut self, filename: &str) -> Result<(), String> {
-        if !filename.ends_with(".bellos") {
-            return Err(format!("Not a .bellos script: {}", filename));
+    /// Runs several scripts in order in the same shell, so later ones see
+    /// the variables and functions of earlier ones. Stops at the first that
+    /// fails, one that cannot be read counting as status 1, unless
+    /// `keep_going`. Returns the status of the last script run.
+    fn execute_scripts(&mut self, scripts: &[String], keep_going: bool) -> i32 {
+        let mut status = 0;
+        for script in scripts {
+            status = self.run_script(script).unwrap_or_else(|e| {
+                let _ = writeln!(self.shell.processes.streams.err(), "bellos: {}", e);
+                1
+            });
+            if status != 0 && !keep_going {
+                break;
+            }
         }
+        status
+    }
 
+    /// Runs a script file of any name. A `#!` first line, as in scripts run
+    /// directly through `#!/usr/bin/env bellos`, is a comment like any other.
+    fn run_script(&mut self, filename: &str) -> Result<i32, String> {
         let path = Path::new(filename);
         if !path.exists() {
             return Err(format!("Script file does not exist: {}", filename));
         }
 
-        let file =
-            File::open(path).map_err(|e| format!("Error opening file {}: {}", filename, e))?;
-        let reader = BufReader::new(file);
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading file {}: {}", filename, e))?;
+        self.shell
+            .interpreter
+            .variables
+            .insert("0".to_string(), filename.to_string());
+        let lines: Vec<&str> = source.lines().collect();
+        let mut parser = Parser::from_lexer(Lexer::new(&source));
+        let mut syntax_errors = 0;
+        let mut lines_read = 0;
 
-        for (index, line) in reader.lines().enumerate() {
-            let line = line.map_err(|e| format!("Error reading line {}: {}", index + 1, e))?;
-            let trimmed_line = line.trim();
-            if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+        while let Some((line, statement)) = parser.next_statement() {
+            let mut end = parser.line().min(lines.len());
+            // A statement ending in a backslash-newline goes on to the
+            // next line, which is echoed with it.
+            while end > 0 && end < lines.len() && is_continued(lines[end - 1]) {
+                end += 1;
+            }
+            if self.shell.interpreter.options.verbose {
+                for text in &lines[lines_read.min(end)..end] {
+                    let _ = writeln!(self.shell.processes.streams.err(), "{}", text);
+                }
+            }
+            lines_read = lines_read.max(end);
+
+            let node = match statement {
+                Ok(node) => node,
+                Err(e) => {
+                    let _ = writeln!(
+                        self.shell.processes.streams.err(),
+                        "{}: line {}: syntax error: {}",
+                        filename,
+                        line,
+                        e
+                    );
+                    syntax_errors += 1;
+                    // Only `-n` reads on to report every error; a script
+                    // that is being run stops at the first one.
+                    if self.shell.interpreter.options.noexec {
+                        continue;
+                    }
+                    return Ok(SYNTAX_ERROR_STATUS);
+                }
+            };
+            if self.shell.interpreter.options.noexec {
                 continue;
             }
 
-            if let Err(e) = self.shell.run(trimmed_line) {
-                eprintln!("Error on line {}: {}", index + 1, e);
+            match block_on(self.shell.interpret_node(&node)) {
+                Ok(_) => {}
+                Err(Interrupt::Exit(status)) => self.shell.exit(status),
+                Err(e) => {
+                    let line = self.shell.interpreter.line;
+                    let _ = writeln!(
+                        self.shell.processes.streams.err(),
+                        "{}: line {}: {}",
+                        filename,
+                        line,
+                        e
+                    );
+                    for frame in self.shell.interpreter.take_error_trace() {
+                        let _ = writeln!(self.shell.processes.streams.err(), "{}", frame);
+                    }
+                }
             }
-            io::stdout().flush().unwrap();
+            // A reader that went away, as `head` does, is not an error.
+            let _ = self.shell.processes.streams.out().flush();
+            self.shell.check_jobs();
+        }
+
+        if self.shell.interpreter.options.verbose {
+            for text in &lines[lines_read..] {
+                let _ = writeln!(self.shell.processes.streams.err(), "{}", text);
+            }
+        }
+
+        if self.shell.interpreter.options.noexec && syntax_errors > 0 {
+            return Err(format!(
+                "{}: {} syntax error(s) found",
+                filename, syntax_errors
+            ));
         }
-        Ok(())
+        Ok(self.shell.last_status())
     }
 
-    fn run_interactive_mode(&mut self) -> Result<(), String> {
+    fn run_interactive_mode(&mut self) -> Result<i32, String> {
+        jobs::enable_job_control();
+        self.shell.interpreter.options.interactive = true;
+        self.shell.interpreter.update_option_flags();
         loop {
-            print!("bellos> ");
-            io::stdout().flush().unwrap();
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
+            self.shell.check_jobs();
+            self.shell.run_prompt_command();
+            let Some(mut input) = self.read_input(PROMPT)? else {
+                let status = self.shell.last_status();
+                return Ok(self.shell.shutdown().unwrap_or(status));
+            };
+            // A line ending inside a string or substitution goes on to the next.
+            while is_incomplete(&input) {
+                let Some(more) = self.read_input(CONTINUATION_PROMPT)? else {
+                    break;
+                };
+                if !input.ends_with('\n') {
+                    input.push('\n');
+                }
+                input.push_str(&more);
+            }
 
             if input.trim().is_empty() {
                 continue;
             }
+            if self.shell.interpreter.options.verbose {
+                let text = input.trim_end_matches('\n');
+                let _ = writeln!(self.shell.processes.streams.err(), "{}", text);
+            }
 
+            self.shell.history.add(&input);
             if let Err(e) = self.shell.run(&input) {
-                eprintln!("Error: {}", e);
+                let _ = writeln!(self.shell.processes.streams.err(), "Error: {}", e);
             }
         }
     }
+
+    /// Reads a line of interactive input after `prompt`, with the line
+    /// editor on a terminal. Returns `None` at the end of input.
+    fn read_input(&mut self, prompt: &str) -> Result<Option<String>, String> {
+        if line_editor::is_terminal() {
+            return line_editor::read_line(&mut self.shell, prompt).map_err(|e| e.to_string());
+        }
+        let mut out = self.shell.processes.streams.out();
+        let _ = write!(out, "{}", prompt);
+        let _ = out.flush();
+        // Bytes that are not UTF-8 are replaced rather than ending the shell.
+        let mut input = Vec::new();
+        let read = io::stdin()
+            .lock()
+            .read_until(b'\n', &mut input)
+            .map_err(|e| format!("Failed to read input: {}", e))?;
+        if read == 0 {
+            let _ = writeln!(self.shell.processes.streams.out());
+        }
+        Ok((!input.is_empty()).then(|| String::from_utf8_lossy(&input).into_owned()))
+    }
 }
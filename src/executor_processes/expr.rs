@@ -0,0 +1,309 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::interpreter_logic::interpreter::Interpreter;
+use regex::Regex;
+
+/// Evaluates the arguments of `expr` as one expression and returns its value.
+/// An `Err` is a syntax or evaluation error, which `expr` reports with
+/// status 2.
+pub fn evaluate(interpreter: &Interpreter, args: &[String]) -> Result<String, String> {
+    if args.is_empty() {
+        return Err("missing operand".to_string());
+    }
+    let mut parser = ExprParser {
+        interpreter,
+        args,
+        position: 0,
+    };
+    let value = parser.or()?;
+    match args.get(parser.position) {
+        Some(arg) => Err(format!("syntax error: unexpected argument '{}'", arg)),
+        None => Ok(value),
+    }
+}
+
+/// Whether a value counts as true for the exit status: neither empty nor
+/// zero.
+pub fn is_true(value: &str) -> bool {
+    !value.is_empty() && integer(value) != Some(0)
+}
+
+/// A recursive descent over the arguments, one function per precedence level
+/// from `|`, the loosest, down to `:`.
+struct ExprParser<'a> {
+    interpreter: &'a Interpreter,
+    args: &'a [String],
+    position: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.position).map(String::as_str)
+    }
+
+    /// Consumes the next argument if it is one of `operators`.
+    fn operator(&mut self, operators: &[&str]) -> Option<&str> {
+        let op = self.args.get(self.position)?;
+        operators.contains(&op.as_str()).then(|| {
+            self.position += 1;
+            op.as_str()
+        })
+    }
+
+    fn operand(&mut self) -> Result<String, String> {
+        let arg = self
+            .args
+            .get(self.position)
+            .ok_or_else(|| "syntax error: missing argument".to_string())?;
+        self.position += 1;
+        Ok(arg.clone())
+    }
+
+    fn or(&mut self) -> Result<String, String> {
+        let mut left = self.and()?;
+        while self.operator(&["|"]).is_some() {
+            let right = self.and()?;
+            left = match (is_true(&left), is_true(&right)) {
+                (true, _) => left,
+                (false, true) => right,
+                (false, false) => "0".to_string(),
+            };
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<String, String> {
+        let mut left = self.comparison()?;
+        while self.operator(&["&"]).is_some() {
+            let right = self.comparison()?;
+            if !is_true(&left) || !is_true(&right) {
+                left = "0".to_string();
+            }
+        }
+        Ok(left)
+    }
+
+    /// Compares as integers when both sides are integers, as strings
+    /// otherwise.
+    fn comparison(&mut self) -> Result<String, String> {
+        let mut left = self.additive()?;
+        while let Some(op) = self.operator(&["<", "<=", "=", "==", "!=", ">=", ">"]) {
+            let op = op.to_string();
+            let right = self.additive()?;
+            let ordering = match (integer(&left), integer(&right)) {
+                (Some(left), Some(right)) => left.cmp(&right),
+                _ => left.cmp(&right),
+            };
+            let holds = match op.as_str() {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                "=" | "==" => ordering.is_eq(),
+                "!=" => ordering.is_ne(),
+                ">=" => ordering.is_ge(),
+                _ => ordering.is_gt(),
+            };
+            left = if holds { "1" } else { "0" }.to_string();
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<String, String> {
+        let mut left = self.multiplicative()?;
+        while let Some(op) = self.operator(&["+", "-"]) {
+            let op = op.to_string();
+            let right = self.multiplicative()?;
+            left = self.arithmetic(&left, &op, &right)?;
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<String, String> {
+        let mut left = self.matching()?;
+        while let Some(op) = self.operator(&["*", "/", "%"]) {
+            let op = op.to_string();
+            let right = self.matching()?;
+            left = self.arithmetic(&left, &op, &right)?;
+        }
+        Ok(left)
+    }
+
+    fn matching(&mut self) -> Result<String, String> {
+        let mut left = self.primary()?;
+        while self.operator(&[":"]).is_some() {
+            let pattern = self.primary()?;
+            left = regex_match(&left, &pattern)?;
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some("(") => {
+                self.position += 1;
+                let value = self.or()?;
+                match self.operator(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => Err("syntax error: expecting ')'".to_string()),
+                }
+            }
+            Some("+") => {
+                self.position += 1;
+                self.operand()
+            }
+            Some("length") => {
+                self.position += 1;
+                Ok(self.primary()?.chars().count().to_string())
+            }
+            Some("match") => {
+                self.position += 1;
+                let text = self.primary()?;
+                let pattern = self.primary()?;
+                regex_match(&text, &pattern)
+            }
+            Some("index") => {
+                self.position += 1;
+                let text = self.primary()?;
+                let characters = self.primary()?;
+                let index = text
+                    .chars()
+                    .position(|c| characters.contains(c))
+                    .map_or(0, |index| index + 1);
+                Ok(index.to_string())
+            }
+            Some("substr") => {
+                self.position += 1;
+                let text = self.primary()?;
+                let start = self.primary()?;
+                let length = self.primary()?;
+                let (Some(start), Some(length)) = (integer(&start), integer(&length)) else {
+                    return Err("non-integer argument".to_string());
+                };
+                if start < 1 || length < 1 {
+                    return Ok(String::new());
+                }
+                Ok(text
+                    .chars()
+                    .skip(start as usize - 1)
+                    .take(length as usize)
+                    .collect())
+            }
+            _ => self.operand(),
+        }
+    }
+
+    /// Applies an arithmetic operator through the shell's arithmetic engine.
+    fn arithmetic(&self, left: &str, op: &str, right: &str) -> Result<String, String> {
+        if integer(left).is_none() || integer(right).is_none() {
+            return Err("non-integer argument".to_string());
+        }
+        let expression = format!("{} {} {}", left.trim(), op, right.trim());
+        let interpreter = self.interpreter;
+        let evaluation = interpreter
+            .logic
+            .evaluate_arithmetic(&interpreter.variables, &expression)?;
+        Ok(evaluation.value.to_string())
+    }
+}
+
+fn integer(value: &str) -> Option<i64> {
+    value.trim().parse().ok()
+}
+
+/// `text : pattern`: matches the basic regular expression `pattern` at the
+/// start of `text`. The value is the first capture group if the pattern has
+/// one, and the length of the match otherwise.
+fn regex_match(text: &str, pattern: &str) -> Result<String, String> {
+    let regex = Regex::new(&format!("^(?:{})", basic_to_extended(pattern)))
+        .map_err(|e| format!("{}: invalid regular expression: {}", pattern, e))?;
+    let captures = regex.captures(text);
+    if regex.captures_len() > 1 {
+        let group = captures.as_ref().and_then(|captures| captures.get(1));
+        return Ok(group.map_or("", |group| group.as_str()).to_string());
+    }
+    let length = captures.map_or(0, |captures| captures[0].chars().count());
+    Ok(length.to_string())
+}
+
+/// Rewrites a POSIX basic regular expression in the syntax of the `regex`
+/// crate: `\(`, `\)`, `\{`, `\}`, `\+`, `\?` and `\|` are operators, while
+/// the same characters unescaped are literal.
+fn basic_to_extended(pattern: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '{' | '}' | '+' | '?' | '|')) => converted.push(c),
+                Some(c) => {
+                    converted.push('\\');
+                    converted.push(c);
+                }
+                None => converted.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '+' | '?' | '|' => {
+                converted.push('\\');
+                converted.push(c);
+            }
+            _ => converted.push(c),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(args: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        evaluate(&Interpreter::new(), &args)
+    }
+
+    #[test]
+    fn classic_invocations_give_their_usual_values() {
+        for (args, value) in [
+            (&["1", "+", "2"][..], "3"),
+            (&["7", "/", "2", "*", "3"], "9"),
+            (&["5", "%", "3", "-", "1"], "1"),
+            (&["(", "1", "+", "2", ")", "*", "3"], "9"),
+            (&["-4", "+", "1"], "-3"),
+            (&["3", "<", "10"], "1"),
+            (&["abc", "<", "abd"], "1"),
+            (&["2", "=", "02"], "1"),
+            (&["a", "!=", "a"], "0"),
+            (&["length", "hello world"], "11"),
+            (&["substr", "hello", "2", "3"], "ell"),
+            (&["index", "hello", "lo"], "3"),
+            (&["abc123", ":", "[a-z]*"], "3"),
+            (&["key=value", ":", ".*=\\(.*\\)"], "value"),
+            (&["abc", ":", "b"], "0"),
+            (&["", "|", "fallback"], "fallback"),
+            (&["3", "&", "0"], "0"),
+        ] {
+            assert_eq!(expr(args).as_deref(), Ok(value), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn errors_are_reported_and_results_decide_the_status() {
+        assert!(expr(&["1", "+"]).is_err());
+        assert!(expr(&["1", "/", "0"]).is_err());
+        assert!(expr(&["a", "+", "1"]).is_err());
+        assert!(expr(&[]).is_err());
+        assert!(is_true("3") && is_true("abc"));
+        assert!(!is_true("0") && !is_true("") && !is_true("-0"));
+    }
+}
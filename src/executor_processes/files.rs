@@ -0,0 +1,382 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::date::{self, Zone};
+use crate::executor_processes::providers::{FileKind, FileStatus, FsProvider};
+use crate::executor_processes::streams::Streams;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// `ln [-s] [-f] target link`: creates a hard link, or with `-s` a symbolic
+/// one, named `link`. A `link` that is a directory gets a link inside it
+/// with the target's name. `-f` replaces an existing `link`. A symbolic
+/// link holds `target` as given.
+pub fn ln(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let usage = "Usage: ln [-s] [-f] target link";
+    let mut symbolic = false;
+    let mut force = false;
+    let mut operands = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-s" => symbolic = true,
+            "-f" => force = true,
+            "-sf" | "-fs" => (symbolic, force) = (true, true),
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("ln: {}: invalid option\n{}", flag, usage))
+            }
+            _ => operands.push(arg),
+        }
+    }
+    let [target, link] = operands[..] else {
+        return Err(usage.to_string());
+    };
+
+    let mut link = PathBuf::from(link);
+    if fs.metadata(&link).is_ok_and(|info| info.is_dir) {
+        if let Some(name) = Path::new(target).file_name() {
+            link.push(name);
+        }
+    }
+    if force && fs.stat(&link, false).is_ok() {
+        if let Err(e) = fs.remove(&link) {
+            let _ = writeln!(streams.err(), "ln: {}: {}", link.display(), e);
+            return Ok(1);
+        }
+    }
+    let result = if symbolic {
+        fs.symlink(Path::new(target), &link)
+    } else {
+        fs.hard_link(Path::new(target), &link)
+    };
+    match result {
+        Ok(()) => Ok(0),
+        Err(e) => {
+            let _ = writeln!(streams.err(), "ln: {}: {}", link.display(), e);
+            Ok(1)
+        }
+    }
+}
+
+/// `chmod mode file ...`: sets the permissions of files to an octal mode,
+/// like `755`, or changes them with symbolic clauses like `u+x,go-w`.
+pub fn chmod(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let [mode, files @ ..] = args else {
+        return Err("Usage: chmod mode file ...".to_string());
+    };
+    if files.is_empty() {
+        return Err("Usage: chmod mode file ...".to_string());
+    }
+    let octal = u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o7777);
+    if octal.is_none() {
+        // Report a bad mode once, not for every file.
+        apply_symbolic_mode(mode, 0)?;
+    }
+
+    let mut status = 0;
+    for file in files {
+        let path = Path::new(file);
+        let result = fs.stat(path, true).and_then(|status| {
+            let mode = match octal {
+                Some(mode) => mode,
+                None => apply_symbolic_mode(mode, status.mode).unwrap_or(status.mode),
+            };
+            fs.set_mode(path, mode)
+        });
+        if let Err(e) = result {
+            let _ = writeln!(streams.err(), "chmod: {}: {}", file, e);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// Applies comma-separated clauses of the form `[ugoa]*[+-=][rwxst]*` to
+/// `mode`. Without a `u`, `g`, `o` or `a`, a clause applies to everyone.
+fn apply_symbolic_mode(clauses: &str, mut mode: u32) -> Result<u32, String> {
+    let invalid = || format!("chmod: invalid mode: '{}'", clauses);
+    for clause in clauses.split(',') {
+        let who_end = clause
+            .find(|c: char| !"ugoa".contains(c))
+            .ok_or_else(invalid)?;
+        let mut classes = 0;
+        for who in clause[..who_end].chars() {
+            classes |= match who {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                _ => 0o7777,
+            };
+        }
+        if classes == 0 {
+            classes = 0o7777;
+        }
+
+        let mut rest = &clause[who_end..];
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        while let Some(op) = rest.chars().next().filter(|c| "+-=".contains(*c)) {
+            let perms_end = rest[1..]
+                .find(|c: char| "+-=".contains(c))
+                .map_or(rest.len(), |end| end + 1);
+            let mut bits = 0;
+            for perm in rest[1..perms_end].chars() {
+                bits |= match perm {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return Err(invalid()),
+                };
+            }
+            bits &= classes;
+            match op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                _ => mode = (mode & !classes) | bits,
+            }
+            rest = &rest[perms_end..];
+        }
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+    }
+    Ok(mode)
+}
+
+/// `stat [-c format] file ...`: describes files, without following symbolic
+/// links. The format may use `%n` for the name, `%s` for the size, `%a` and
+/// `%A` for the permissions in octal and as `ls` shows them, `%F` for the
+/// file type and `%Y` for the modification time in seconds since the epoch.
+pub fn stat(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let (format, files) = match args {
+        [flag, format, files @ ..] if flag == "-c" => (Some(format.as_str()), files),
+        _ => (None, args),
+    };
+    if files.is_empty() {
+        return Err("Usage: stat [-c format] file ...".to_string());
+    }
+
+    let mut status = 0;
+    for file in files {
+        match fs.stat(Path::new(file), false) {
+            Ok(status) => match format {
+                Some(format) => {
+                    let _ = writeln!(streams.out(), "{}", format_stat(format, file, &status));
+                }
+                None => {
+                    let _ = write!(
+                        streams.out(),
+                        "{}",
+                        format_stat(DEFAULT_STAT_FORMAT, file, &status)
+                    );
+                }
+            },
+            Err(e) => {
+                let _ = writeln!(streams.err(), "stat: {}: {}", file, e);
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+const DEFAULT_STAT_FORMAT: &str = "  File: %n\n  Size: %s\tType: %F\n\
+                                   Access: (%a/%A)\nModify: %y\n";
+
+fn format_stat(format: &str, name: &str, status: &FileStatus) -> String {
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push_str(name),
+            Some('s') => output.push_str(&status.len.to_string()),
+            Some('a') => output.push_str(&format!("{:o}", status.mode)),
+            Some('A') => output.push_str(&permission_string(status)),
+            Some('F') => output.push_str(file_type(status)),
+            Some('Y') => output.push_str(&status.modified.to_string()),
+            Some('y') => output.push_str(
+                &date::format_time("%Y-%m-%d %H:%M:%S", status.modified, 0, &Zone::Local)
+                    .unwrap_or_else(|| status.modified.to_string()),
+            ),
+            Some('%') => output.push('%'),
+            Some(other) => {
+                output.push('%');
+                output.push(other);
+            }
+            None => output.push('%'),
+        }
+    }
+    output
+}
+
+fn file_type(status: &FileStatus) -> &'static str {
+    match status.kind {
+        FileKind::Symlink => "symbolic link",
+        FileKind::Directory => "directory",
+        FileKind::Fifo => "fifo",
+        FileKind::Socket => "socket",
+        FileKind::CharDevice => "character special file",
+        FileKind::BlockDevice => "block special file",
+        FileKind::File if status.len == 0 => "regular empty file",
+        FileKind::File => "regular file",
+    }
+}
+
+/// The permissions as `ls -l` shows them, like `-rwxr-xr-x`.
+fn permission_string(status: &FileStatus) -> String {
+    let mode = status.mode;
+    let mut text = String::with_capacity(10);
+    text.push(match status.kind {
+        FileKind::Symlink => 'l',
+        FileKind::Directory => 'd',
+        FileKind::Fifo => 'p',
+        FileKind::Socket => 's',
+        FileKind::CharDevice => 'c',
+        FileKind::BlockDevice => 'b',
+        FileKind::File => '-',
+    });
+    // Each class, with the bit that replaces its `x`: setuid, setgid, sticky.
+    for (shift, special, set) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => set,
+            (false, true) => set.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::executor_processes::providers::StdFs;
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn args(args: &[&Path]) -> Vec<String> {
+        args.iter().map(|arg| arg.display().to_string()).collect()
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn ln_makes_symbolic_and_hard_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::write(&target, "data").unwrap();
+        let (symbolic, hard) = (dir.path().join("symbolic"), dir.path().join("hard"));
+
+        let mut with_flag = args(&[&target, &symbolic]);
+        with_flag.insert(0, "-s".to_string());
+        assert_eq!(ln(&StdFs, &Streams::new(), &with_flag), Ok(0));
+        assert_eq!(fs::read_link(&symbolic).unwrap(), target);
+        assert_eq!(ln(&StdFs, &Streams::new(), &args(&[&target, &hard])), Ok(0));
+        assert_eq!(fs::read_to_string(&hard).unwrap(), "data");
+        assert_eq!(fs::metadata(&target).unwrap().nlink(), 2);
+        assert_eq!(
+            fs::metadata(&target).unwrap().ino(),
+            fs::metadata(&hard).unwrap().ino()
+        );
+
+        // An existing link is only replaced with -f.
+        assert_eq!(ln(&StdFs, &Streams::new(), &args(&[&target, &hard])), Ok(1));
+        let mut forced = args(&[&symbolic, &hard]);
+        forced.insert(0, "-sf".to_string());
+        assert_eq!(ln(&StdFs, &Streams::new(), &forced), Ok(0));
+        assert_eq!(fs::read_link(&hard).unwrap(), symbolic);
+    }
+
+    #[test]
+    fn chmod_takes_octal_and_symbolic_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let chmod_file = |mode: &str| {
+            let mut args = args(&[&file]);
+            args.insert(0, mode.to_string());
+            chmod(&StdFs, &Streams::new(), &args)
+        };
+
+        assert_eq!(chmod_file("640"), Ok(0));
+        assert_eq!(mode(&file), 0o640);
+        assert_eq!(chmod_file("u+x"), Ok(0));
+        assert_eq!(mode(&file), 0o740);
+        assert_eq!(chmod_file("go-r,o+x"), Ok(0));
+        assert_eq!(mode(&file), 0o701);
+        assert_eq!(chmod_file("a=r"), Ok(0));
+        assert_eq!(mode(&file), 0o444);
+        assert!(chmod_file("u+q").is_err());
+        assert!(chmod_file("99").is_err());
+        assert_eq!(mode(&file), 0o444);
+
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            chmod(
+                &StdFs,
+                &Streams::new(),
+                &["600".to_string(), missing.display().to_string()]
+            ),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn stat_formats_size_mode_name_and_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "hello").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o754)).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        let modified = fs::metadata(&file).unwrap().mtime();
+
+        let status = StdFs.stat(&file, false).unwrap();
+        assert_eq!(
+            format_stat("%s %a %n %Y", "file", &status),
+            format!("5 754 file {}", modified)
+        );
+        assert_eq!(
+            format_stat("%A %F 100%%", "file", &status),
+            "-rwxr-xr-- regular file 100%"
+        );
+        let status = StdFs.stat(&link, false).unwrap();
+        assert_eq!(
+            format_stat("%F %A", "link", &status),
+            "symbolic link lrwxrwxrwx"
+        );
+        assert_eq!(
+            stat(
+                &StdFs,
+                &Streams::new(),
+                &args(&[&dir.path().join("missing")])
+            ),
+            Ok(1)
+        );
+    }
+}
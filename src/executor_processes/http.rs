@@ -0,0 +1,774 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::streams::Streams;
+use crate::interpreter_logic::interpreter::Interpreter;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How many redirects a request follows before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// `http_get [-H header] [-t seconds] url [outfile]`: fetches `url` and
+/// writes the body to standard output or `outfile`.
+pub fn get(
+    interpreter: &mut Interpreter,
+    streams: &Streams,
+    args: &[String],
+) -> Result<i32, String> {
+    let usage = "Usage: http_get [-H 'Name: value'] [-t seconds] url [outfile]";
+    let (options, operands) = parse_options(args, usage)?;
+    let (url, outfile) = match operands {
+        [url] => (url, None),
+        [url, outfile] => (url, Some(outfile.as_str())),
+        _ => return Err(usage.to_string()),
+    };
+    run(interpreter, streams, "GET", url, None, &options, outfile)
+}
+
+/// `http_post [-H header] [-t seconds] url data`: sends `data` to `url` and
+/// writes the body of the response to standard output.
+pub fn post(
+    interpreter: &mut Interpreter,
+    streams: &Streams,
+    args: &[String],
+) -> Result<i32, String> {
+    let usage = "Usage: http_post [-H 'Name: value'] [-t seconds] url data";
+    let (options, operands) = parse_options(args, usage)?;
+    let [url, data] = operands else {
+        return Err(usage.to_string());
+    };
+    run(
+        interpreter,
+        streams,
+        "POST",
+        url,
+        Some(data),
+        &options,
+        None,
+    )
+}
+
+struct Options {
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+fn parse_options<'a>(args: &'a [String], usage: &str) -> Result<(Options, &'a [String]), String> {
+    let mut options = Options {
+        headers: Vec::new(),
+        timeout: Duration::from_secs(30),
+    };
+    let mut rest = args;
+    loop {
+        match rest {
+            [flag, header, after @ ..] if flag == "-H" => {
+                let (name, value) = header
+                    .split_once(':')
+                    .ok_or_else(|| format!("{}: invalid header, expected 'Name: value'", header))?;
+                options
+                    .headers
+                    .push((name.trim().to_string(), value.trim().to_string()));
+                rest = after;
+            }
+            [flag, seconds, after @ ..] if flag == "-t" => {
+                let seconds: f64 = seconds
+                    .parse()
+                    .ok()
+                    .filter(|seconds: &f64| *seconds > 0.0)
+                    .ok_or_else(|| format!("{}: invalid timeout", seconds))?;
+                options.timeout = Duration::from_secs_f64(seconds);
+                rest = after;
+            }
+            [flag, ..] if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("{}: invalid option\n{}", flag, usage))
+            }
+            _ => return Ok((options, rest)),
+        }
+    }
+}
+
+/// Makes the request, following redirects, and stores the status code of
+/// the final response in `HTTP_STATUS`. The status is 0 only for 2xx. The
+/// timeout covers the whole request, redirects included. Errors are
+/// reported under the name of the builtin for `method`.
+fn run(
+    interpreter: &mut Interpreter,
+    streams: &Streams,
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    options: &Options,
+    outfile: Option<&str>,
+) -> Result<i32, String> {
+    let name = format!("http_{}", method.to_ascii_lowercase());
+    interpreter.set_variable("HTTP_STATUS", String::new());
+    let mut url = url.to_string();
+    let mut method = method;
+    let mut body = body;
+    let mut redirects = 0;
+    let deadline = Instant::now() + options.timeout;
+    let response = loop {
+        let response = match request(interpreter, method, &url, body, options, deadline) {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = writeln!(streams.err(), "{}: {}: {}", name, url, e);
+                return Ok(1);
+            }
+        };
+        let location = response.header("location");
+        match (response.status, location) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) if redirects < MAX_REDIRECTS => {
+                url = resolve_location(&url, location);
+                // Like browsers, turn a redirected POST into a GET unless
+                // the server asks for the method to be kept.
+                if !matches!(response.status, 307 | 308) {
+                    method = "GET";
+                    body = None;
+                }
+                redirects += 1;
+            }
+            _ => break response,
+        }
+    };
+
+    interpreter.set_variable("HTTP_STATUS", response.status.to_string());
+    let written = match outfile {
+        Some(path) => File::create(interpreter.resolve_path(path))
+            .and_then(|mut file| file.write_all(&response.body)),
+        None => streams.out().write_all(&response.body),
+    };
+    if let Err(e) = written {
+        let _ = writeln!(
+            streams.err(),
+            "{}: {}: {}",
+            name,
+            outfile.unwrap_or("stdout"),
+            e
+        );
+        return Ok(1);
+    }
+    Ok(if (200..300).contains(&response.status) {
+        0
+    } else {
+        1
+    })
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A URL split into what an HTTP/1.1 request needs.
+struct Url<'a> {
+    https: bool,
+    host: &'a str,
+    port: u16,
+    /// The host and port as the `Host` header gives them.
+    authority: &'a str,
+    /// The path and query, at least `/`.
+    target: String,
+}
+
+fn parse_url(url: &str) -> io::Result<Url<'_>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let (https, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+        (Some(rest), _) => (false, rest),
+        (_, Some(rest)) => (true, rest),
+        _ => return Err(invalid("not an http:// or https:// URL")),
+    };
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse().map_err(|_| invalid("invalid port"))?)
+        }
+        _ => (authority, if https { 443 } else { 80 }),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    Ok(Url {
+        https,
+        host,
+        port,
+        authority,
+        target,
+    })
+}
+
+/// The URL a `Location` header points to, relative to `base`.
+fn resolve_location(base: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let origin_end = base
+        .find("://")
+        .and_then(|scheme| base[scheme + 3..].find('/').map(|i| scheme + 3 + i))
+        .unwrap_or(base.len());
+    if location.starts_with('/') {
+        format!("{}{}", &base[..origin_end], location)
+    } else {
+        let directory = base.rfind('/').filter(|&i| i >= origin_end);
+        match directory {
+            Some(i) => format!("{}/{}", &base[..i], location),
+            None => format!("{}/{}", base, location),
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "timed out waiting for the response",
+    )
+}
+
+/// The time left before `deadline`, or an error once it has passed.
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|left| !left.is_zero())
+        .ok_or_else(timed_out)
+}
+
+fn request(
+    interpreter: &Interpreter,
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    options: &Options,
+    deadline: Instant,
+) -> io::Result<Response> {
+    let url = parse_url(url)?;
+    let has_header = |name: &str| {
+        options
+            .headers
+            .iter()
+            .any(|(header, _)| header.eq_ignore_ascii_case(name))
+    };
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n",
+        method, url.target, url.authority
+    );
+    if !has_header("user-agent") {
+        head.push_str("User-Agent: bellos\r\n");
+    }
+    if !has_header("accept") {
+        head.push_str("Accept: */*\r\n");
+    }
+    for (name, value) in &options.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = body {
+        if !has_header("content-type") {
+            head.push_str("Content-Type: application/x-www-form-urlencoded\r\n");
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    let mut message = head.into_bytes();
+    if let Some(body) = body {
+        message.extend_from_slice(body.as_bytes());
+    }
+
+    let raw = if url.https {
+        exchange_tls(interpreter, &url, &message, deadline)?
+    } else {
+        exchange(connect(&url, deadline)?, &message, deadline)?
+    };
+    parse_response(&raw)
+}
+
+/// Opens a TCP connection to the URL's host, trying each of its addresses
+/// until `deadline`.
+fn connect(url: &Url, deadline: Instant) -> io::Result<TcpStream> {
+    let addresses = (url.host, url.port)
+        .to_socket_addrs()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not resolve {}: {}", url.host, e)))?;
+    let mut last_error = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, remaining(deadline)?) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other(format!("no address for {}", url.host))))
+}
+
+/// A connection to a server, plain or TLS, over the socket its timeouts
+/// are set on.
+trait Connection: Read + Write {
+    fn socket(&self) -> &TcpStream;
+}
+
+impl Connection for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Connection for StreamOwned<ClientConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+}
+
+/// Sends `message` and reads until the server closes the connection,
+/// giving up at `deadline`.
+fn exchange(
+    mut connection: impl Connection,
+    message: &[u8],
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    connection
+        .socket()
+        .set_write_timeout(Some(remaining(deadline)?))?;
+    connection.write_all(message)?;
+    connection.flush()?;
+
+    // A timeout per read would let a server that trickles bytes run on
+    // forever, so each read gets only what is left of the deadline.
+    let mut raw = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        connection
+            .socket()
+            .set_read_timeout(Some(remaining(deadline)?))?;
+        match connection.read(&mut buffer) {
+            Ok(0) => return Ok(raw),
+            Ok(read) => raw.extend_from_slice(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(timed_out())
+            }
+            // Many servers close TLS connections without saying so first,
+            // which ends the response as `Connection: close` asked.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(raw),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Sends `message` over TLS, checking the server's certificate and name
+/// against the Mozilla roots, or against the certificates in the file the
+/// shell's exported `SSL_CERT_FILE` names.
+fn exchange_tls(
+    interpreter: &Interpreter,
+    url: &Url,
+    message: &[u8],
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(trusted_roots(interpreter)?)
+            .with_no_client_auth();
+    let name = ServerName::try_from(url.host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let tls = ClientConnection::new(Arc::new(config), name).map_err(io::Error::other)?;
+    exchange(
+        StreamOwned::new(tls, connect(url, deadline)?),
+        message,
+        deadline,
+    )
+}
+
+/// The certificates TLS servers are checked against.
+fn trusted_roots(interpreter: &Interpreter) -> io::Result<RootCertStore> {
+    let cert_file = interpreter
+        .exported
+        .contains("SSL_CERT_FILE")
+        .then(|| interpreter.variable("SSL_CERT_FILE"))
+        .flatten();
+    let Some(cert_file) = cert_file else {
+        return Ok(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+    };
+    let path = interpreter.resolve_path(&cert_file);
+    let unreadable =
+        |e: &dyn std::fmt::Display| io::Error::other(format!("SSL_CERT_FILE {}: {}", cert_file, e));
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(path).map_err(|e| unreadable(&e))? {
+        roots
+            .add(certificate.map_err(|e| unreadable(&e))?)
+            .map_err(|e| unreadable(&e))?;
+    }
+    Ok(roots)
+}
+
+fn parse_response(raw: &[u8]) -> io::Result<Response> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+    let head_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = String::from_utf8_lossy(&raw[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(malformed)?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+
+    let body = &raw[head_end + 4..];
+    response.body = if response
+        .header("transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        decode_chunked(body).ok_or_else(malformed)?
+    } else if let Some(length) = response.header("content-length") {
+        let length: usize = length.parse().map_err(|_| malformed())?;
+        body.get(..length).ok_or_else(malformed)?.to_vec()
+    } else {
+        body.to_vec()
+    };
+    Ok(response)
+}
+
+/// Joins the chunks of a `Transfer-Encoding: chunked` body.
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        // Chunk extensions follow a `;`.
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::process::{Command, Stdio};
+    use std::thread::{self, JoinHandle};
+
+    /// Answers one connection per response on a local port, and gives back
+    /// the requests it read.
+    fn serve(responses: Vec<&'static str>) -> (u16, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (port, server)
+    }
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut byte = [0];
+        while !request.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        let head = String::from_utf8(request.clone()).unwrap();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        request.extend(body);
+        String::from_utf8(request).unwrap()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn get_writes_the_body_and_the_status() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, and more",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let mut interpreter = Interpreter::new();
+        let url = format!("http://127.0.0.1:{}/path?q=1", port);
+        let status = get(
+            &mut interpreter,
+            &Streams::new(),
+            &args(&["-H", "X-Test: yes", &url, out.to_str().unwrap()]),
+        );
+        assert_eq!(status, Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello");
+        assert_eq!(interpreter.variable("HTTP_STATUS").as_deref(), Some("200"));
+        let request = &server.join().unwrap()[0];
+        assert!(
+            request.starts_with("GET /path?q=1 HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+        assert!(request.contains("X-Test: yes\r\n"), "{}", request);
+    }
+
+    #[test]
+    fn posts_are_redirected_as_gets_and_errors_fail() {
+        let (port, server) = serve(vec![
+            "HTTP/1.1 303 See Other\r\nLocation: /done\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nno \r\n4\r\nsuch\r\n0\r\n\r\n",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.directory = dir.path().to_path_buf();
+        let url = format!("http://127.0.0.1:{}/form", port);
+        // The relative outfile lands in the shell's directory.
+        let status = run(
+            &mut interpreter,
+            &Streams::new(),
+            "POST",
+            &url,
+            Some("a=1"),
+            &parse_options(&[], "").unwrap().0,
+            Some("body"),
+        );
+        assert_eq!(status, Ok(1));
+        assert_eq!(interpreter.variable("HTTP_STATUS").as_deref(), Some("404"));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("body")).unwrap(),
+            "no such"
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /form "), "{}", requests[0]);
+        assert!(requests[0].contains("Content-Length: 3\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\na=1"), "{}", requests[0]);
+        assert!(requests[1].starts_with("GET /done "), "{}", requests[1]);
+        assert!(!requests[1].contains("Content-Length"), "{}", requests[1]);
+    }
+
+    #[test]
+    fn the_timeout_covers_a_server_that_trickles_its_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream);
+            // Each byte comes well within any one read's timeout.
+            for _ in 0..100 {
+                if stream.write_all(b"H").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let mut interpreter = Interpreter::new();
+        let started = Instant::now();
+        let url = format!("http://127.0.0.1:{}/", port);
+        assert_eq!(
+            get(
+                &mut interpreter,
+                &Streams::new(),
+                &args(&["-t", "0.5", &url])
+            ),
+            Ok(1)
+        );
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(interpreter.variable("HTTP_STATUS").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn urls_and_locations_are_split_and_resolved() {
+        let url = parse_url("https://example.com?x=1").unwrap();
+        assert!(url.https);
+        assert_eq!((url.host, url.port), ("example.com", 443));
+        assert_eq!(url.target, "/?x=1");
+        let url = parse_url("http://[::1]:8080/a").unwrap();
+        assert_eq!(
+            (url.host, url.port, url.authority),
+            ("::1", 8080, "[::1]:8080")
+        );
+        assert!(parse_url("ftp://example.com").is_err());
+        assert_eq!(resolve_location("http://h/a/b", "c"), "http://h/a/c");
+        assert_eq!(resolve_location("http://h/a/b", "/c"), "http://h/c");
+        assert_eq!(resolve_location("http://h", "https://o/"), "https://o/");
+    }
+
+    /// Serves `openssl s_server -www` with a certificate for `localhost`,
+    /// and checks http_get trusts it only when `SSL_CERT_FILE` names it.
+    #[test]
+    fn https_checks_the_certificate_against_ssl_cert_file() {
+        let openssl = |args: &[&str]| Command::new("openssl").args(args).output();
+        if openssl(&["version"]).is_err() {
+            return;
+        }
+        // A certificate authority, and a certificate for `localhost` it
+        // signed: a self-signed certificate is refused as a server's.
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let (ca, ca_key, cert, key) = (
+            path("ca.pem"),
+            path("ca.key"),
+            path("cert.pem"),
+            path("key.pem"),
+        );
+        let (request, extensions) = (path("cert.csr"), path("extensions"));
+        std::fs::write(
+            &extensions,
+            "basicConstraints=CA:FALSE\nsubjectAltName=DNS:localhost\n",
+        )
+        .unwrap();
+        let commands: [&[&str]; 3] = [
+            &[
+                "req",
+                "-x509",
+                "-newkey",
+                "rsa:2048",
+                "-nodes",
+                "-days",
+                "1",
+                "-subj",
+                "/CN=bellos test CA",
+                "-keyout",
+                &ca_key,
+                "-out",
+                &ca,
+            ],
+            &[
+                "req",
+                "-new",
+                "-newkey",
+                "rsa:2048",
+                "-nodes",
+                "-subj",
+                "/CN=localhost",
+                "-keyout",
+                &key,
+                "-out",
+                &request,
+            ],
+            &[
+                "x509",
+                "-req",
+                "-in",
+                &request,
+                "-CA",
+                &ca,
+                "-CAkey",
+                &ca_key,
+                "-days",
+                "1",
+                "-extfile",
+                &extensions,
+                "-out",
+                &cert,
+            ],
+        ];
+        for command in commands {
+            let made = openssl(command).unwrap();
+            assert!(made.status.success(), "{:?}", made);
+        }
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut server = Command::new("openssl")
+            .args(["s_server", "-www", "-quiet", "-accept"])
+            .arg(format!("127.0.0.1:{}", port))
+            .arg("-cert")
+            .arg(&cert)
+            .arg("-key")
+            .arg(&key)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let url = format!("https://localhost:{}/", port);
+        let out = dir.path().join("out");
+        let fetch = |interpreter: &mut Interpreter| {
+            get(
+                interpreter,
+                &Streams::new(),
+                &args(&["-t", "10", &url, out.to_str().unwrap()]),
+            )
+        };
+        let mut untrusting = Interpreter::new();
+        untrusting.export_variable(
+            "SSL_CERT_FILE",
+            dir.path().join("none").display().to_string(),
+        );
+        assert_eq!(fetch(&mut untrusting), Ok(1));
+        assert_eq!(untrusting.variable("HTTP_STATUS").as_deref(), Some(""));
+
+        let mut trusting = Interpreter::new();
+        trusting.export_variable("SSL_CERT_FILE", ca.clone());
+        let status = fetch(&mut trusting);
+        let _ = server.kill();
+        let _ = server.wait();
+        assert_eq!(status, Ok(0));
+        assert_eq!(trusting.variable("HTTP_STATUS").as_deref(), Some("200"));
+        assert!(!std::fs::read(&out).unwrap().is_empty());
+    }
+}
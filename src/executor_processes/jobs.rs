@@ -0,0 +1,1024 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::runtime::Runtime;
+#[cfg(unix)]
+use crate::shell::redirection;
+use std::collections::VecDeque;
+use std::io;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+#[cfg(unix)]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+
+// Signal numbers, for `kill`, `trap` and the 128 plus a signal of `$?`.
+// Without Unix signals they keep Linux's numbers but are never delivered.
+#[cfg(unix)]
+pub use libc::{
+    SIGABRT, SIGALRM, SIGCHLD, SIGCONT, SIGHUP, SIGINT, SIGKILL, SIGPIPE, SIGQUIT, SIGSEGV,
+    SIGSTOP, SIGTERM, SIGTSTP, SIGTTIN, SIGTTOU, SIGUSR1, SIGUSR2,
+};
+#[cfg(not(unix))]
+pub const SIGHUP: i32 = 1;
+#[cfg(not(unix))]
+pub const SIGINT: i32 = 2;
+#[cfg(not(unix))]
+pub const SIGQUIT: i32 = 3;
+#[cfg(not(unix))]
+pub const SIGABRT: i32 = 6;
+#[cfg(not(unix))]
+pub const SIGKILL: i32 = 9;
+#[cfg(not(unix))]
+pub const SIGUSR1: i32 = 10;
+#[cfg(not(unix))]
+pub const SIGSEGV: i32 = 11;
+#[cfg(not(unix))]
+pub const SIGUSR2: i32 = 12;
+#[cfg(not(unix))]
+pub const SIGPIPE: i32 = 13;
+#[cfg(not(unix))]
+pub const SIGALRM: i32 = 14;
+#[cfg(not(unix))]
+pub const SIGTERM: i32 = 15;
+#[cfg(not(unix))]
+pub const SIGCHLD: i32 = 17;
+#[cfg(not(unix))]
+pub const SIGCONT: i32 = 18;
+#[cfg(not(unix))]
+pub const SIGSTOP: i32 = 19;
+#[cfg(not(unix))]
+pub const SIGTSTP: i32 = 20;
+#[cfg(not(unix))]
+pub const SIGTTIN: i32 = 21;
+#[cfg(not(unix))]
+pub const SIGTTOU: i32 = 22;
+
+/// The error for what needs Unix processes and signals.
+pub fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported on this platform", what),
+    )
+}
+
+/// Counts the SIGCHLDs received. Each job table remembers the count it last
+/// scanned at, so shells sharing the process never take each other's
+/// notice that a child exited.
+static CHILD_EXITS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+extern "C" fn handle_sigchld(_signal: libc::c_int) {
+    CHILD_EXITS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Installs the SIGCHLD handler that marks job tables dirty. The handler
+/// only counts the signal; actual reaping happens at safe points.
+#[cfg(unix)]
+pub fn install_sigchld_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigchld as *const () as usize;
+        // Without SA_NOCLDSTOP, jobs that stop or continue count too.
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(SIGCHLD, &action, std::ptr::null_mut());
+    });
+}
+
+#[cfg(not(unix))]
+pub fn install_sigchld_handler() {}
+
+/// The shell's own process group while job control is on, otherwise 0.
+static SHELL_PGID: AtomicI32 = AtomicI32::new(0);
+
+/// Turns on job control if the shell reads commands from a terminal. The
+/// shell waits until it is in the foreground, moves into its own process
+/// group, takes the terminal, and ignores SIGTTOU and SIGTTIN so it can take
+/// the terminal back from its children, and SIGTSTP so Ctrl-Z only stops
+/// them. Returns whether job control is on, which it never is without Unix
+/// process groups.
+#[cfg(unix)]
+pub fn enable_job_control() -> bool {
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return false;
+        }
+        loop {
+            let pgid = libc::getpgrp();
+            if libc::tcgetpgrp(libc::STDIN_FILENO) == pgid {
+                break;
+            }
+            libc::kill(-pgid, SIGTTIN);
+        }
+        libc::signal(SIGTTOU, libc::SIG_IGN);
+        libc::signal(SIGTTIN, libc::SIG_IGN);
+        libc::signal(SIGTSTP, libc::SIG_IGN);
+
+        let pid = libc::getpid();
+        if libc::getpgrp() != pid && libc::setpgid(0, pid) == -1 {
+            return false;
+        }
+        libc::tcsetpgrp(libc::STDIN_FILENO, pid);
+        SHELL_PGID.store(pid, Ordering::SeqCst);
+    }
+    true
+}
+
+#[cfg(not(unix))]
+pub fn enable_job_control() -> bool {
+    false
+}
+
+pub fn job_control() -> bool {
+    SHELL_PGID.load(Ordering::SeqCst) != 0
+}
+
+/// With job control on, makes `command` start a process group of its own,
+/// or join the group `pgid` of an earlier command in the same pipeline, and
+/// restores the terminal signals the shell ignores.
+#[cfg(unix)]
+pub fn set_process_group(command: &mut Command, pgid: u32) {
+    if job_control() {
+        start_in_process_group(command, pgid);
+    }
+}
+
+/// Makes `command` start in the process group `pgid`, or in a new one of
+/// its own for 0, with the terminal signals handled the default way.
+#[cfg(unix)]
+fn start_in_process_group(command: &mut Command, pgid: u32) {
+    let pgid = pgid as libc::pid_t;
+    unsafe {
+        command.pre_exec(move || {
+            libc::setpgid(0, pgid);
+            libc::signal(SIGTTOU, libc::SIG_DFL);
+            libc::signal(SIGTTIN, libc::SIG_DFL);
+            libc::signal(SIGTSTP, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+}
+
+/// Without process groups there is no job control to set one up for.
+#[cfg(not(unix))]
+pub fn set_process_group(_command: &mut Command, _pgid: u32) {}
+
+/// Puts a spawned child into its process group from the shell's side too,
+/// so the group exists before the shell hands it the terminal, whichever
+/// of the two runs first.
+#[cfg(unix)]
+pub fn join_process_group(pid: u32, pgid: u32) {
+    if job_control() {
+        move_to_process_group(pid, pgid);
+    }
+}
+
+/// Moves the child `pid` into the process group `pgid`, or into its own
+/// for 0.
+#[cfg(unix)]
+fn move_to_process_group(pid: u32, pgid: u32) {
+    let pgid = if pgid == 0 { pid } else { pgid };
+    unsafe { libc::setpgid(pid as libc::pid_t, pgid as libc::pid_t) };
+}
+
+#[cfg(not(unix))]
+pub fn join_process_group(_pid: u32, _pgid: u32) {}
+
+/// The terminal handed to a process group by `foreground`, which the
+/// shell takes back when it is dropped.
+pub struct Foreground {
+    #[cfg(unix)]
+    shell: libc::pid_t,
+}
+
+/// Puts the process group `pgid` in the foreground of the terminal until
+/// the returned guard is dropped. Without job control it does nothing.
+#[cfg(unix)]
+pub fn foreground(pgid: u32) -> Foreground {
+    let shell = SHELL_PGID.load(Ordering::SeqCst);
+    if shell != 0 {
+        unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t) };
+    }
+    Foreground { shell }
+}
+
+#[cfg(not(unix))]
+pub fn foreground(_pgid: u32) -> Foreground {
+    Foreground {}
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.shell != 0 {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, self.shell) };
+        }
+    }
+}
+
+/// Runs `wait` with the process group `pgid` in the foreground of the
+/// terminal, taking the terminal back afterwards. Without job control it
+/// only runs `wait`.
+pub fn in_foreground<T>(pgid: u32, wait: impl FnOnce() -> T) -> T {
+    let _terminal = foreground(pgid);
+    wait()
+}
+
+/// Sends `signal` to a job: its whole process group under job control,
+/// otherwise just its process.
+pub fn signal_job(pid: u32, signal: i32) -> io::Result<()> {
+    #[cfg(unix)]
+    if job_control() {
+        return signal_process(-(pid as i32), signal);
+    }
+    signal_process(pid as i32, signal)
+}
+
+/// Sends `signal` to the process `pid`, or the process group `-pid`.
+#[cfg(unix)]
+pub fn signal_process(pid: i32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn signal_process(_pid: i32, _signal: i32) -> io::Result<()> {
+    Err(unsupported("sending signals"))
+}
+
+/// Waits for a command run in the foreground, with the terminal while job
+/// control is on, to exit or to be stopped, as by Ctrl-Z. Returns `None` if
+/// it stopped.
+pub fn wait_foreground_child(child: &mut Child) -> io::Result<Option<ExitStatus>> {
+    #[cfg(unix)]
+    {
+        let pid = child.id();
+        in_foreground(pid, || wait_untraced(pid))
+    }
+    #[cfg(not(unix))]
+    child.wait().map(Some)
+}
+
+/// Waits for a foreground child to exit or to be stopped, as by Ctrl-Z.
+/// Returns `None` if it stopped.
+#[cfg(unix)]
+pub fn wait_untraced(pid: u32) -> io::Result<Option<ExitStatus>> {
+    loop {
+        let mut status = 0;
+        let pid = pid as libc::pid_t;
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        if libc::WIFSTOPPED(status) {
+            return Ok(None);
+        }
+        return Ok(Some(ExitStatus::from_raw(status)));
+    }
+}
+
+/// Waits for `child` to exit without reaping it, so the process group it
+/// leads lives on for the later stages of its pipeline to join. Waiting
+/// for it again reaps it.
+#[cfg(unix)]
+pub fn wait_unreaped(child: &mut Child) -> io::Result<ExitStatus> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        if unsafe { libc::waitid(libc::P_PID, child.id(), &mut info, flags) } == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        let status = unsafe { info.si_status() };
+        // Rebuild the status `waitpid` would have given.
+        let raw = match info.si_code {
+            libc::CLD_EXITED => (status & 0xff) << 8,
+            libc::CLD_DUMPED => status | 0x80,
+            _ => status,
+        };
+        return Ok(ExitStatus::from_raw(raw));
+    }
+}
+
+/// Without process groups there is nothing to keep alive.
+#[cfg(not(unix))]
+pub fn wait_unreaped(child: &mut Child) -> io::Result<ExitStatus> {
+    child.wait()
+}
+
+/// Set in a copy of the shell forked by `fork_subshell`.
+#[cfg(unix)]
+static FORKED: AtomicBool = AtomicBool::new(false);
+
+/// Whether this process is a copy of the shell forked to run a job, which
+/// exits as soon as the job is done.
+pub fn is_forked_copy() -> bool {
+    #[cfg(unix)]
+    return FORKED.load(Ordering::SeqCst);
+    #[cfg(not(unix))]
+    false
+}
+
+/// Forks a copy of the shell that runs `body` and exits with the status it
+/// returns, without the shell's own exit handling, for running compound
+/// commands and pipelines in the background. The copy gets a process group
+/// of its own and no job control, so nothing it runs takes the terminal.
+/// Returns the pid of the copy.
+///
+/// Other threads, as in a program embedding several shells, do not follow
+/// into the copy, and neither would a lock one of them held when it was
+/// made. So the locks the copy needs, those of standard output and error
+/// and of the FIFO copies, are held across the fork, and the allocator
+/// takes care of its own. Without `fork` it always fails.
+#[cfg(unix)]
+pub fn fork_subshell(body: impl FnOnce() -> i32) -> io::Result<u32> {
+    let mut stdout = io::stdout().lock();
+    let stderr = io::stderr().lock();
+    let fifo_copies = redirection::lock_fifo_copies();
+    // Output still buffered would otherwise be written by both processes.
+    let _ = stdout.flush();
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            FORKED.store(true, Ordering::SeqCst);
+            drop((stdout, stderr, fifo_copies));
+            if job_control() {
+                unsafe {
+                    libc::setpgid(0, 0);
+                    libc::signal(SIGTTOU, libc::SIG_DFL);
+                    libc::signal(SIGTTIN, libc::SIG_DFL);
+                    libc::signal(SIGTSTP, libc::SIG_DFL);
+                }
+                SHELL_PGID.store(0, Ordering::SeqCst);
+            }
+            let status = body();
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(status) }
+        }
+        pid => {
+            drop((stdout, stderr, fifo_copies));
+            join_process_group(pid as u32, 0);
+            Ok(pid as u32)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn fork_subshell(_body: impl FnOnce() -> i32) -> io::Result<u32> {
+    Err(unsupported("running compound commands in the background"))
+}
+
+/// The process behind a job: a spawned command, or a copy of the shell
+/// forked by `fork_subshell`.
+pub enum Process {
+    Spawned(Child),
+    Forked(u32),
+}
+
+impl Process {
+    pub fn id(&self) -> u32 {
+        match self {
+            Process::Spawned(child) => child.id(),
+            Process::Forked(pid) => *pid,
+        }
+    }
+
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        match self {
+            Process::Spawned(child) => child.wait(),
+            Process::Forked(pid) => loop {
+                if let Some(status) = wait_pid(*pid, 0)? {
+                    return Ok(status);
+                }
+            },
+        }
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        match self {
+            Process::Spawned(child) => child.kill(),
+            Process::Forked(pid) => signal_process(*pid as i32, SIGKILL),
+        }
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            Process::Spawned(child) => child.try_wait(),
+            #[cfg(unix)]
+            Process::Forked(pid) => wait_pid(*pid, libc::WNOHANG),
+            #[cfg(not(unix))]
+            Process::Forked(pid) => wait_pid(*pid, 0),
+        }
+    }
+}
+
+impl Process {
+    /// Checks without blocking whether the process exited or, under Unix,
+    /// was stopped or continued, as `job_state` tells from the status.
+    fn poll(&mut self) -> io::Result<Option<ExitStatus>> {
+        #[cfg(unix)]
+        return wait_pid(
+            self.id(),
+            libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED,
+        );
+        #[cfg(not(unix))]
+        self.try_wait()
+    }
+}
+
+/// What a status from `Process::poll` says: `Some(true)` if the process
+/// was stopped, `Some(false)` if it was continued, `None` if it exited.
+#[cfg(unix)]
+fn job_state(status: ExitStatus) -> Option<bool> {
+    if status.stopped_signal().is_some() {
+        Some(true)
+    } else if status.continued() {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Without signals a process can only exit.
+#[cfg(not(unix))]
+fn job_state(_status: ExitStatus) -> Option<bool> {
+    None
+}
+
+impl From<Child> for Process {
+    fn from(child: Child) -> Self {
+        Process::Spawned(child)
+    }
+}
+
+/// `waitpid` with `flags`, retried when interrupted. `None` if the process
+/// has not exited yet.
+#[cfg(unix)]
+fn wait_pid(pid: u32, flags: libc::c_int) -> io::Result<Option<ExitStatus>> {
+    loop {
+        let mut status = 0;
+        match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, flags) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Ok(None),
+            _ => return Ok(Some(ExitStatus::from_raw(status))),
+        }
+    }
+}
+
+/// Forked copies only exist where `fork_subshell` can make them.
+#[cfg(not(unix))]
+fn wait_pid(_pid: u32, _flags: i32) -> io::Result<Option<ExitStatus>> {
+    Err(unsupported("waiting for a forked shell"))
+}
+
+/// The notice printed when a foreground job is stopped.
+pub fn stopped_notice(id: usize, command: &str) -> String {
+    format!("[{}]+  {:<24}{}", id, "Stopped", command)
+}
+
+pub struct Job {
+    pub id: usize,
+    pub pid: u32,
+    pub command: String,
+    /// Exit code recorded once the child has been reaped.
+    pub status: Option<i32>,
+    /// Set while the job is stopped by a signal, until it is continued.
+    pub stopped: bool,
+    /// Set by `disown -h`: the job is not sent SIGHUP when the shell exits.
+    pub nohup: bool,
+    process: Process,
+}
+
+impl Job {
+    /// Waits for the job to exit, returning its exit code. Jobs already
+    /// reaped return their recorded status immediately.
+    pub async fn wait(&mut self, runtime: Runtime) -> io::Result<i32> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+        let status = exit_code(runtime.wait(&mut self.process).await?);
+        self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Like `wait`, but returns `None` if the job is stopped again instead
+    /// of exiting. Only the blocking runtime, the one with job control,
+    /// looks out for that.
+    pub async fn wait_foreground(&mut self, runtime: Runtime) -> io::Result<Option<i32>> {
+        if let Some(status) = self.status {
+            return Ok(Some(status));
+        }
+        // Without signals nothing can stop a job.
+        #[cfg(unix)]
+        if runtime == Runtime::Blocking {
+            let Some(status) = wait_untraced(self.pid)? else {
+                self.stopped = true;
+                return Ok(None);
+            };
+            let status = exit_code(status);
+            self.status = Some(status);
+            return Ok(Some(status));
+        }
+        self.wait(runtime).await.map(Some)
+    }
+}
+
+/// The status of a finished process as `$?` reports it: its exit code, or
+/// 128 plus the signal that killed it, so SIGTERM gives 143.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    return status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0));
+    #[cfg(not(unix))]
+    status.code().unwrap_or(1)
+}
+
+/// Background jobs together with the current (`%+`) and previous (`%-`) job
+/// numbers used to resolve job specs.
+///
+/// The table is shared behind a single `Mutex`. Lock holders must never block
+/// on a child while holding it: `wait` and `fg` first `take` the job out of
+/// the table and wait on it after the lock is released, while `reap`,
+/// `check_jobs`, and `jobs` only use non-blocking `try_wait`.
+pub struct JobTable {
+    jobs: Vec<Job>,
+    current: Option<usize>,
+    previous: Option<usize>,
+    /// The value of `CHILD_EXITS` when the jobs were last scanned, if ever.
+    scanned_at: Option<usize>,
+    /// The pids and statuses of the latest jobs `check_jobs` reported and
+    /// removed, so `wait` can still be given their pids.
+    reported: VecDeque<(u32, i32)>,
+}
+
+/// How many reported jobs' statuses are kept for `wait`.
+const REPORTED_STATUSES: usize = 256;
+
+impl Default for JobTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        JobTable {
+            jobs: Vec::new(),
+            current: None,
+            previous: None,
+            scanned_at: None,
+            reported: VecDeque::new(),
+        }
+    }
+
+    /// Registers a background child and returns its job number.
+    pub fn add(&mut self, process: impl Into<Process>, command: String) -> usize {
+        let process = process.into();
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            pid: process.id(),
+            command,
+            status: None,
+            stopped: false,
+            nohup: false,
+            process,
+        });
+        self.previous = self.current;
+        self.current = Some(id);
+        id
+    }
+
+    /// Registers a foreground child that was stopped, making it the current
+    /// job.
+    pub fn add_stopped(&mut self, child: Child, command: String) -> usize {
+        let id = self.add(child, command);
+        if let Some(job) = self.get_mut(id) {
+            job.stopped = true;
+        }
+        id
+    }
+
+    /// Puts back a job taken out with `take`, as the current job.
+    pub fn restore(&mut self, job: Job) {
+        let id = job.id;
+        let index = self.jobs.partition_point(|other| other.id < id);
+        self.jobs.insert(index, job);
+        if self.current != Some(id) {
+            self.previous = self.current;
+            self.current = Some(id);
+        }
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Takes the status of the job with process id `pid` if `check_jobs`
+    /// reported it done, as a `wait` for it does only once.
+    pub fn take_reported(&mut self, pid: u32) -> Option<i32> {
+        let index = self.reported.iter().position(|&(id, _)| id == pid)?;
+        self.reported.remove(index).map(|(_, status)| status)
+    }
+
+    pub fn find_by_pid(&self, pid: u32) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.pid == pid)
+            .map(|job| job.id)
+    }
+
+    /// Removes a job from the table so it can be waited on without holding
+    /// the table lock.
+    pub fn take(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        let job = self.jobs.remove(index);
+        self.forget(id);
+        Some(job)
+    }
+
+    /// Resolves `%N`, `%+`, `%%`, `%-`, and `%prefix` job specs to a job number.
+    pub fn parse_jobspec(&self, spec: &str) -> Result<usize, String> {
+        let no_such_job = || format!("{}: no such job", spec);
+        let body = spec.strip_prefix('%').ok_or_else(no_such_job)?;
+        let id = match body {
+            "" | "+" | "%" => self.current,
+            "-" => self.previous,
+            _ if body.chars().all(|c| c.is_ascii_digit()) => {
+                body.parse().ok().filter(|id| self.get(*id).is_some())
+            }
+            prefix => {
+                let mut matches = self
+                    .jobs
+                    .iter()
+                    .filter(|job| job.command.starts_with(prefix));
+                match (matches.next(), matches.next()) {
+                    (Some(job), None) => Some(job.id),
+                    (Some(_), Some(_)) => return Err(format!("{}: ambiguous job spec", spec)),
+                    _ => None,
+                }
+            }
+        };
+        id.ok_or_else(no_such_job)
+    }
+
+    fn forget(&mut self, id: usize) {
+        if self.current == Some(id) {
+            self.current = self.previous.take();
+        } else if self.previous == Some(id) {
+            self.previous = None;
+        }
+        if self.current.is_none() {
+            self.current = self.jobs.iter().map(|job| job.id).max();
+        }
+        if self.previous.is_none() {
+            self.previous = self
+                .jobs
+                .iter()
+                .map(|job| job.id)
+                .filter(|id| Some(*id) != self.current)
+                .max();
+        }
+    }
+
+    /// Records the exit status of every tracked child that has terminated
+    /// since the last SIGCHLD, and whether the others were stopped or
+    /// continued, without blocking. Finished jobs stay in the table until
+    /// they are reported or waited on.
+    pub fn reap(&mut self) {
+        if !self.child_may_have_exited() {
+            return;
+        }
+        for job in self.jobs.iter_mut().filter(|job| job.status.is_none()) {
+            // A job may have stopped and continued since the last scan.
+            loop {
+                match job.process.poll() {
+                    Ok(Some(status)) => match job_state(status) {
+                        Some(stopped) => job.stopped = stopped,
+                        None => {
+                            job.status = Some(exit_code(status));
+                            break;
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("[{}] Error waiting for job: {}", job.id, e);
+                        job.status = Some(-1);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether a child may have exited since the last scan. Without SIGCHLD
+    /// every call says so.
+    fn child_may_have_exited(&mut self) -> bool {
+        let exits = CHILD_EXITS.load(Ordering::SeqCst);
+        let seen = self.scanned_at.replace(exits);
+        cfg!(not(unix)) || seen != Some(exits)
+    }
+
+    /// Reaps finished jobs, removing them from the table and returning one
+    /// notification line per job. Their statuses are kept for `wait`.
+    pub fn check_jobs(&mut self) -> Vec<String> {
+        self.reap();
+        let mut notifications = Vec::new();
+        let mut finished = Vec::new();
+        self.jobs.retain(|job| match job.status {
+            Some(status) => {
+                notifications.push(job_line(job.id, &state_text(status), &job.command));
+                finished.push((job.id, job.pid, status));
+                false
+            }
+            None => true,
+        });
+        for (id, pid, status) in finished {
+            self.forget(id);
+            if self.reported.len() == REPORTED_STATUSES {
+                self.reported.pop_front();
+            }
+            self.reported.push_back((pid, status));
+        }
+        notifications
+    }
+}
+
+/// A line of `jobs` output or a job notice: the job number, its state and
+/// its command, with the states lined up.
+pub fn job_line(id: usize, state: &str, command: &str) -> String {
+    format!("[{}]  {:<24}{}", id, state, command)
+}
+
+/// Describes a job for `jobs` listings and completion notices.
+pub fn state_text(status: i32) -> String {
+    match status {
+        0 => "Done".to_string(),
+        code if code > 128 => match signal_description(code - 128) {
+            Some(description) => description.to_string(),
+            None => format!("Signal {}", code - 128),
+        },
+        code => format!("Exit {}", code),
+    }
+}
+
+/// How a process killed by `signal` is described, as in `Terminated`.
+fn signal_description(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        SIGHUP => "Hangup",
+        SIGINT => "Interrupt",
+        SIGQUIT => "Quit",
+        SIGABRT => "Aborted",
+        SIGKILL => "Killed",
+        SIGSEGV => "Segmentation fault",
+        SIGPIPE => "Broken pipe",
+        SIGALRM => "Alarm clock",
+        SIGTERM => "Terminated",
+        SIGUSR1 => "User defined signal 1",
+        SIGUSR2 => "User defined signal 2",
+        _ => return None,
+    })
+}
+
+/// Maps a signal name (`TERM`, `SIGTERM`) or number to its signal number.
+pub fn signal_from_name(name: &str) -> Option<i32> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Some(number);
+    }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    Some(match name {
+        "HUP" => SIGHUP,
+        "INT" => SIGINT,
+        "QUIT" => SIGQUIT,
+        "KILL" => SIGKILL,
+        "USR1" => SIGUSR1,
+        "USR2" => SIGUSR2,
+        "PIPE" => SIGPIPE,
+        "ALRM" => SIGALRM,
+        "TERM" => SIGTERM,
+        "CHLD" => SIGCHLD,
+        "CONT" => SIGCONT,
+        "STOP" => SIGSTOP,
+        "TSTP" => SIGTSTP,
+        "TTIN" => SIGTTIN,
+        "TTOU" => SIGTTOU,
+        _ => return None,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::executor_processes::runtime::block_on;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn spawn(script: &str) -> Child {
+        Command::new("sh").args(["-c", script]).spawn().unwrap()
+    }
+
+    /// Calls `done` on the table until it says so, for at most ten seconds.
+    fn poll(table: &mut JobTable, mut done: impl FnMut(&mut JobTable) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done(table) {
+            assert!(Instant::now() < deadline, "jobs did not finish");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn finished_jobs_are_reported_once() {
+        install_sigchld_handler();
+        let mut table = JobTable::new();
+        table.add(spawn("exit 0"), "quick".to_string());
+        table.add(spawn("exit 2"), "failing".to_string());
+        let mut notifications = Vec::new();
+        poll(&mut table, |table| {
+            notifications.extend(table.check_jobs());
+            notifications.len() == 2
+        });
+        notifications.sort();
+        assert_eq!(
+            notifications,
+            [
+                job_line(1, "Done", "quick"),
+                job_line(2, "Exit 2", "failing")
+            ]
+        );
+        assert!(table.jobs().is_empty());
+        assert!(table.check_jobs().is_empty());
+    }
+
+    #[test]
+    fn job_specs_name_jobs_by_number_recency_and_prefix() {
+        install_sigchld_handler();
+        let mut table = JobTable::new();
+        for command in ["sleep 10", "make all", "make test"] {
+            table.add(spawn("sleep 10"), command.to_string());
+        }
+        for (spec, id) in [
+            ("%1", 1),
+            ("%3", 3),
+            ("%", 3),
+            ("%+", 3),
+            ("%%", 3),
+            ("%-", 2),
+            ("%sleep", 1),
+            ("%make a", 2),
+        ] {
+            assert_eq!(table.parse_jobspec(spec), Ok(id), "{}", spec);
+        }
+        for spec in ["%4", "%0", "%x", "1", "sleep"] {
+            assert_eq!(
+                table.parse_jobspec(spec),
+                Err(format!("{}: no such job", spec))
+            );
+        }
+        assert_eq!(
+            table.parse_jobspec("%make"),
+            Err("%make: ambiguous job spec".to_string())
+        );
+
+        let mut third = table.take(3).unwrap();
+        assert_eq!(table.parse_jobspec("%+"), Ok(2));
+        assert_eq!(table.parse_jobspec("%-"), Ok(1));
+        third.process.kill().unwrap();
+        block_on(third.wait(Runtime::Blocking)).unwrap();
+        for id in [1, 2] {
+            let mut job = table.take(id).unwrap();
+            job.process.kill().unwrap();
+            assert_eq!(
+                block_on(job.wait(Runtime::Blocking)).unwrap(),
+                128 + SIGKILL
+            );
+        }
+    }
+
+    fn process_group(pid: u32) -> u32 {
+        unsafe { libc::getpgid(pid as libc::pid_t) as u32 }
+    }
+
+    #[test]
+    fn pipeline_stages_join_the_group_of_the_first() {
+        let mut first = Command::new("sh");
+        first.args(["-c", "exit 4"]);
+        start_in_process_group(&mut first, 0);
+        let mut first = first.spawn().unwrap();
+        let pgid = first.id();
+        assert_ne!(pgid, process_group(std::process::id()));
+
+        // Waited for but not reaped, the first stage keeps its group alive.
+        assert_eq!(exit_code(wait_unreaped(&mut first).unwrap()), 4);
+        assert_eq!(process_group(pgid), pgid);
+        let mut second = Command::new("sleep");
+        second.arg("10");
+        start_in_process_group(&mut second, pgid);
+        let mut second = second.spawn().unwrap();
+        assert_eq!(process_group(second.id()), pgid);
+
+        // A signal to the group reaches every stage.
+        signal_process(-(pgid as i32), SIGKILL).unwrap();
+        assert_eq!(exit_code(second.wait().unwrap()), 128 + SIGKILL);
+        assert_eq!(exit_code(first.wait().unwrap()), 4);
+    }
+
+    #[test]
+    fn unreaped_waits_report_signals_like_reaping_ones() {
+        let mut killed = spawn("kill -TERM $$");
+        assert_eq!(
+            exit_code(wait_unreaped(&mut killed).unwrap()),
+            128 + SIGTERM
+        );
+        assert_eq!(exit_code(killed.wait().unwrap()), 128 + SIGTERM);
+    }
+
+    #[test]
+    fn reaping_tracks_stopped_and_continued_jobs() {
+        install_sigchld_handler();
+        let mut table = JobTable::new();
+        let id = table.add(spawn("sleep 10"), "sleep 10".to_string());
+        let pid = table.get(id).unwrap().pid as libc::pid_t;
+        unsafe { libc::kill(pid, libc::SIGSTOP) };
+        poll(&mut table, |table| {
+            table.reap();
+            table.get(id).unwrap().stopped
+        });
+        unsafe { libc::kill(pid, libc::SIGCONT) };
+        poll(&mut table, |table| {
+            table.reap();
+            !table.get(id).unwrap().stopped
+        });
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        poll(&mut table, |table| {
+            table.reap();
+            table.get(id).unwrap().status.is_some()
+        });
+        assert_eq!(table.get(id).unwrap().status, Some(128 + libc::SIGKILL));
+    }
+
+    #[test]
+    fn reaping_records_statuses_and_leaves_no_zombies() {
+        install_sigchld_handler();
+        let mut table = JobTable::new();
+        let pids: Vec<u32> = (0..5)
+            .map(|status| {
+                let id = table.add(spawn(&format!("exit {}", status)), status.to_string());
+                table.get(id).unwrap().pid
+            })
+            .collect();
+        poll(&mut table, |table| {
+            table.reap();
+            table.jobs().iter().all(|job| job.status.is_some())
+        });
+        for (status, job) in table.jobs().iter().enumerate() {
+            assert_eq!(job.status, Some(status as i32));
+        }
+        for pid in pids {
+            let waited =
+                unsafe { libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), libc::WNOHANG) };
+            assert_eq!(waited, -1, "{} is still a zombie", pid);
+            assert_eq!(
+                io::Error::last_os_error().raw_os_error(),
+                Some(libc::ECHILD)
+            );
+        }
+    }
+}
@@ -1,2 +1,17 @@
+pub mod builtins;
+pub mod condition;
+pub mod date;
 pub mod executor;
+pub mod expr;
+pub mod files;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod jobs;
 pub mod processes;
+pub mod providers;
+pub mod resolver;
+pub mod runtime;
+pub mod streams;
+pub mod testing;
+pub mod text;
+pub mod timeout;
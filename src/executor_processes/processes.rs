@@ -13,142 +13,1208 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::executor_processes::builtins;
+use crate::executor_processes::condition;
+use crate::executor_processes::date;
+use crate::executor_processes::expr;
+use crate::executor_processes::files;
+#[cfg(feature = "http")]
+use crate::executor_processes::http;
+#[cfg(not(unix))]
+use crate::executor_processes::jobs::unsupported;
+use crate::executor_processes::jobs::{
+    exit_code, foreground, install_sigchld_handler, job_control, job_line, signal_from_name,
+    signal_job, signal_process, state_text, stopped_notice, JobTable, Process, SIGCONT, SIGHUP,
+    SIGINT, SIGTERM, SIGTSTP,
+};
+use crate::executor_processes::providers::{
+    FsProvider, InDirectory, Spawner, StdFs, SystemUsers, UserProvider,
+};
+use crate::executor_processes::resolver::{
+    command_extensions, is_executable, path_candidates, resolve_command, spawn_command,
+    suggest_commands, Resolution, PATH_LIST_SEPARATOR,
+};
+use crate::executor_processes::runtime::{Ended, Runtime};
+use crate::executor_processes::streams::{Input, Streams};
+use crate::executor_processes::text;
+use crate::executor_processes::timeout;
 use crate::interpreter_logic::interpreter::Interpreter;
 use crate::interpreter_logic::logic::Logic;
-use crate::utilities::utilities::{ASTNode, RedirectType};
+use crate::parser::parser::is_identifier;
+use crate::shell::expansion::{split_read_fields, DEFAULT_IFS};
+use crate::shell::line_editor;
+use crate::utilities::utilities::{CompletionSpec, ShellOptions};
 use glob::glob;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
-use std::process::{Child, Command, Stdio};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
 use std::sync::{Arc, Mutex};
-use std::thread;
 
 pub struct Processes {
-    background_jobs: Arc<Mutex<Vec<Arc<Mutex<Child>>>>>,
+    background_jobs: Arc<Mutex<JobTable>>,
     pub logic: Logic,
+    /// The files the builtins read and write: `StdFs` unless replaced.
+    pub fs: Box<dyn FsProvider>,
+    /// Runs external commands when set, in place of the shell starting
+    /// them itself with job control.
+    pub spawner: Option<Box<dyn Spawner>>,
+    /// The users whose home directories `~user` expands to: `SystemUsers`
+    /// unless replaced.
+    pub users: Box<dyn UserProvider>,
+    /// Where commands are waited for: the blocking runtime, except while
+    /// `Shell::eval_async` runs a script.
+    pub runtime: Runtime,
+    /// The shell's descriptors, which builtins read and write and commands
+    /// inherit: the process's until redirected.
+    pub streams: Streams,
+}
+
+impl Default for Processes {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Processes {
     pub fn new() -> Self {
+        install_sigchld_handler();
         Processes {
-            background_jobs: Arc::new(Mutex::new(Vec::new())),
+            background_jobs: Arc::new(Mutex::new(JobTable::new())),
             logic: Logic::new(),
+            fs: Box::new(StdFs),
+            spawner: None,
+            users: Box::new(SystemUsers),
+            runtime: Runtime::Blocking,
+            streams: Streams::new(),
         }
     }
 
-    pub fn execute_command(
+    pub async fn execute_command(
         &mut self,
         interpreter: &mut Interpreter,
         name: &str,
         args: &[String],
     ) -> Result<Option<i32>, String> {
         match name {
-            "echo" => self.builtin_echo(interpreter, args),
-            "exit" => std::process::exit(0),
+            ":" => Ok(Some(0)),
+            "echo" => self.builtin_echo(args),
+            "argv" => self.builtin_argv(args),
+            "clear" => self.builtin_clear(args),
+            "exec" => self.builtin_exec(interpreter, args),
             "export" => self.builtin_export(interpreter, args),
+            "declare" => self.builtin_declare(interpreter, args),
+            "cd" => self.builtin_cd(interpreter, args),
+            "set" => self.builtin_set(interpreter, args),
+            "local" => self.builtin_local(interpreter, args),
+            "unset" => self.builtin_unset(interpreter, args),
+            "caller" => self.builtin_caller(interpreter, args),
+            "shopt" => self.builtin_shopt(interpreter, args),
             "jobs" => self.builtin_jobs(),
-            "write" => self.builtin_write(args),
-            "read" => self.builtin_read(args),
-            "append" => self.builtin_append(args),
-            "delete" => self.builtin_delete(args),
-            "[" => self.evaluate_condition(interpreter, args),
+            "kill" => self.builtin_kill(args),
+            "wait" => self.builtin_wait(args).await,
+            "fg" => self.builtin_fg(args).await,
+            "bg" => self.builtin_bg(args),
+            "disown" => self.builtin_disown(args),
+            "write" => self.builtin_write(interpreter, args),
+            "read" => self.builtin_read(interpreter, args),
+            "readfile" => self.builtin_readfile(interpreter, args),
+            "append" => self.builtin_append(interpreter, args),
+            "delete" => self.builtin_delete(interpreter, args),
+            "mkfifo" => self.builtin_mkfifo(interpreter, args),
+            "ln" => files::ln(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "chmod" => files::chmod(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "stat" => files::stat(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "cat" => text::cat(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "head" => text::head(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "tail" => text::tail(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "wc" => text::wc(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            "match" => text::match_lines(&self.fs_in(interpreter), &self.streams, args).map(Some),
+            #[cfg(feature = "http")]
+            "http_get" => http::get(interpreter, &self.streams, args).map(Some),
+            #[cfg(feature = "http")]
+            "http_post" => http::post(interpreter, &self.streams, args).map(Some),
+            "test" | "[" => self.builtin_test(interpreter, name, args),
+            "[[" => self.builtin_double_bracket(interpreter, args),
             "seq" => self.builtin_seq(args),
-            _ => self.execute_external_command(name, args),
+            "split" => self.builtin_split(interpreter, args),
+            "which" => self.builtin_which(interpreter, args),
+            "timeout" => timeout::run(self, interpreter, args).await.map(Some),
+            "expr" => self.builtin_expr(interpreter, args),
+            "date" => date::date(interpreter, &self.streams, args).map(Some),
+            "help" => self.builtin_help(args),
+            "type" => self.builtin_type(interpreter, args),
+            "trap" => self.builtin_trap(interpreter, args),
+            "complete" => self.builtin_complete(interpreter, args),
+            _ => self.execute_external_command(interpreter, name, args).await,
         }
     }
 
-    fn builtin_echo(
+    /// `clear`: clears the terminal screen. Does nothing when standard
+    /// output is not a terminal.
+    fn builtin_clear(&self, args: &[String]) -> Result<Option<i32>, String> {
+        if !args.is_empty() {
+            return Err("Usage: clear".to_string());
+        }
+        let mut stdout = self.streams.out();
+        let is_terminal = stdout.is_terminal();
+        line_editor::clear_screen(&mut stdout, is_terminal).map_err(|e| format!("clear: {}", e))?;
+        Ok(Some(0))
+    }
+
+    /// `echo [args]`: prints the arguments separated by spaces. Writing to a
+    /// closed standard output, as after `exec >&-`, is an error.
+    fn builtin_echo(&self, args: &[String]) -> Result<Option<i32>, String> {
+        writeln!(self.streams.out(), "{}", args.join(" "))
+            .map_err(|e| format!("echo: write error: {}", e))?;
+        Ok(Some(0))
+    }
+
+    /// `export [name[=value] ...]`: sets variables and marks them for the
+    /// environment of commands. Without names, lists the exported variables
+    /// as `export name=value`, sorted by name.
+    fn builtin_export(
         &self,
         interpreter: &mut Interpreter,
         args: &[String],
     ) -> Result<Option<i32>, String> {
-        let expanded_args: Vec<String> = args
-            .iter()
-            .map(|arg| interpreter.expand_variables(arg))
-            .collect();
-        println!("{}", expanded_args.join(" "));
+        if args.is_empty() {
+            for (name, value) in interpreter.command_environment() {
+                let _ = writeln!(self.streams.out(), "export {}={}", name, value);
+            }
+        }
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => interpreter.export_variable(name, value.to_string()),
+                None => {
+                    interpreter.exported.insert(arg.clone());
+                }
+            }
+        }
         Ok(Some(0))
     }
 
-    fn builtin_export(
+    /// `argv [args]`: prints the number of arguments, then each one on its
+    /// own line between angle brackets, to show how a command line expanded.
+    fn builtin_argv(&self, args: &[String]) -> Result<Option<i32>, String> {
+        let mut stdout = self.streams.out();
+        let mut output = format!("argc: {}\n", args.len());
+        for arg in args {
+            output.push_str(&format!("<{}>\n", arg));
+        }
+        stdout
+            .write_all(output.as_bytes())
+            .map_err(|e| format!("argv: write error: {}", e))?;
+        Ok(Some(0))
+    }
+
+    /// `cd [dir | -]`: changes to `dir`, `$HOME` by default, or with `-` to
+    /// `$OLDPWD`, keeping `PWD` and `OLDPWD` up to date. A `dir` found
+    /// through `CDPATH` is printed, as is the one `-` goes to. Only the
+    /// shell's directory changes, never the process's, and `..` leaves a
+    /// symlink the way it was entered, as `PWD` shows.
+    fn builtin_cd(
         &self,
         interpreter: &mut Interpreter,
         args: &[String],
     ) -> Result<Option<i32>, String> {
+        let variable = |name: &str| interpreter.variable(name);
+        let fs = self.fs_in(interpreter);
+        let is_dir = |dir: &str| fs.metadata(Path::new(dir)).is_ok_and(|info| info.is_dir);
+        let (target, print) = match args.first().map(String::as_str) {
+            None => (variable("HOME").ok_or("cd: HOME not set")?, false),
+            Some("-") => (variable("OLDPWD").ok_or("cd: OLDPWD not set")?, true),
+            Some(dir) => match cdpath_directory(dir, variable("CDPATH"), is_dir) {
+                Some(found) => (found, true),
+                None => (dir.to_string(), false),
+            },
+        };
+        let old = variable("PWD").or_else(|| {
+            std::env::current_dir()
+                .ok()
+                .map(|dir| dir.join(&interpreter.directory).display().to_string())
+        });
+
+        match fs.metadata(Path::new(&target)) {
+            Ok(info) if info.is_dir => {}
+            Ok(_) => return Err(format!("cd: {}: Not a directory", target)),
+            Err(e) => return Err(format!("cd: {}: {}", target, e)),
+        }
+        // `PWD` follows the path as given, through symlinks and `..`.
+        let pwd = match &old {
+            _ if cfg!(windows) => interpreter.resolve_path(&target).display().to_string(),
+            Some(old) if !target.starts_with('/') => logical_path(old, &target),
+            _ => logical_path("/", &target),
+        };
+        // A `PWD` that was set to somewhere else cannot be followed.
+        let pwd = if is_dir(&pwd) {
+            pwd
+        } else {
+            interpreter.resolve_path(&target).display().to_string()
+        };
+        interpreter.directory = PathBuf::from(&pwd);
+        if let Some(old) = old {
+            interpreter.export_variable("OLDPWD", old);
+        }
+        if print {
+            let _ = writeln!(self.streams.out(), "{}", pwd);
+        }
+        interpreter.export_variable("PWD", pwd);
+        Ok(Some(0))
+    }
+
+    /// `set [-nvx] [+nvx]`: turns shell options on or off. Without arguments,
+    /// lists the shell variables as `name=value`, sorted by name.
+    fn builtin_set(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        if args.is_empty() {
+            for (name, value) in &interpreter.variables {
+                if is_identifier(name) {
+                    let _ = writeln!(self.streams.out(), "{}={}", name, value);
+                }
+            }
+        }
         for arg in args {
-            let parts: Vec<&str> = arg.splitn(2, '=').collect();
-            if parts.len() == 2 {
-                std::env::set_var(parts[0], parts[1]);
-                interpreter
-                    .variables
-                    .insert(parts[0].to_string(), parts[1].to_string());
+            let enable = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => return Err(format!("set: {}: invalid option", arg)),
+            };
+            for flag in arg.chars().skip(1) {
+                match flag {
+                    'n' => interpreter.options.noexec = enable,
+                    'v' => interpreter.options.verbose = enable,
+                    'x' => interpreter.options.xtrace = enable,
+                    _ => return Err(format!("set: -{}: invalid option", flag)),
+                }
+            }
+            interpreter.update_option_flags();
+        }
+        Ok(Some(0))
+    }
+
+    /// `declare -F`: lists the names of the defined functions, sorted, as
+    /// `declare -f name`.
+    fn builtin_declare(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        if args.len() != 1 || args[0] != "-F" {
+            return Err("Usage: declare -F".to_string());
+        }
+        for name in interpreter.functions.keys() {
+            let _ = writeln!(self.streams.out(), "declare -f {}", name);
+        }
+        Ok(Some(0))
+    }
+
+    /// `local [-n] name[=value] ...`: makes variables local to the function
+    /// being run, or with `-n` references to the variables their values
+    /// name.
+    fn builtin_local(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let (nameref, names) = match args.first().map(String::as_str) {
+            Some("-n") => (true, &args[1..]),
+            _ => (false, args),
+        };
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            interpreter.declare_local(name, value, nameref)?;
+        }
+        Ok(Some(0))
+    }
+
+    /// `unset [-f | -v] name ...`: removes variables, also from the
+    /// environment, or with `-f` functions. Unsetting a dynamic variable like
+    /// `RANDOM` makes it an ordinary one.
+    fn builtin_unset(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let (functions, names) = match args.first().map(String::as_str) {
+            Some("-f") => (true, &args[1..]),
+            Some("-v") => (false, &args[1..]),
+            _ => (false, args),
+        };
+        for name in names {
+            if functions {
+                interpreter.functions.remove(name);
+            } else {
+                interpreter.unset_variable(name);
             }
         }
         Ok(Some(0))
     }
 
+    /// `caller [n]`: describes the call of the function running `n` calls
+    /// up the stack as `line function script`, or just `line script` for the
+    /// current one when `n` is not given. Fails outside of functions.
+    fn builtin_caller(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let depth = match args.first() {
+            Some(n) => Some(
+                n.parse::<usize>()
+                    .map_err(|_| format!("caller: {}: invalid number", n))?,
+            ),
+            None => None,
+        };
+        let stack = &interpreter.call_stack;
+        let Some(index) = stack.len().checked_sub(depth.unwrap_or(0) + 1) else {
+            return Ok(Some(1));
+        };
+        let script = interpreter
+            .variables
+            .get("0")
+            .map_or("bellos", String::as_str);
+        let line = stack[index].call_line;
+        match depth {
+            Some(_) => {
+                let function = index
+                    .checked_sub(1)
+                    .map_or("main", |outer| stack[outer].function.as_str());
+                let _ = writeln!(self.streams.out(), "{} {} {}", line, function, script);
+            }
+            None => {
+                let _ = writeln!(self.streams.out(), "{} {}", line, script);
+            }
+        }
+        Ok(Some(0))
+    }
+
+    /// `shopt [-s | -u] [-q] [name ...]`: sets, unsets or lists `shopt`
+    /// options. Listing with `-s` or `-u` and no names shows only the options
+    /// in that state. `-q` prints nothing, succeeding if every named option
+    /// is on. The flags come in any order, alone or together, as in `-qs`.
+    fn builtin_shopt(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let usage = "Usage: shopt [-s | -u] [-q] [name ...]";
+        let mut value = None;
+        let mut quiet = false;
+        let mut names = args;
+        while let Some((flags, rest)) = names
+            .split_first()
+            .and_then(|(first, rest)| Some((first.strip_prefix('-')?, rest)))
+            .filter(|(flags, _)| !flags.is_empty())
+        {
+            for flag in flags.chars() {
+                match (flag, value) {
+                    ('s', None | Some(true)) => value = Some(true),
+                    ('u', None | Some(false)) => value = Some(false),
+                    ('s' | 'u', _) => {
+                        return Err("shopt: cannot set and unset options at once".to_string())
+                    }
+                    ('q', _) => quiet = true,
+                    _ => return Err(format!("shopt: -{}: invalid option\n{}", flag, usage)),
+                }
+            }
+            names = rest;
+        }
+        if let Some(name) = names
+            .iter()
+            .find(|name| interpreter.options.shopt(name).is_none())
+        {
+            let _ = writeln!(
+                self.streams.err(),
+                "shopt: {}: invalid shell option name",
+                name
+            );
+            return Ok(Some(1));
+        }
+        if let (Some(value), false) = (value, names.is_empty()) {
+            for name in names {
+                interpreter.options.set_shopt(name, value)?;
+            }
+            return Ok(Some(0));
+        }
+        if quiet {
+            let all_on = names
+                .iter()
+                .all(|name| interpreter.options.shopt(name) == Some(true));
+            return Ok(Some(if all_on { 0 } else { 1 }));
+        }
+        let listed: Vec<&str> = if names.is_empty() {
+            ShellOptions::SHOPT_NAMES.to_vec()
+        } else {
+            names.iter().map(String::as_str).collect()
+        };
+        let mut status = 0;
+        for name in listed {
+            let state = interpreter.options.shopt(name).unwrap_or_default();
+            if value.is_some_and(|value| value != state) {
+                continue;
+            }
+            let _ = writeln!(
+                self.streams.out(),
+                "{:<15}\t{}",
+                name,
+                if state { "on" } else { "off" }
+            );
+            if !state {
+                status = 1;
+            }
+        }
+        Ok(Some(if names.is_empty() { 0 } else { status }))
+    }
+
     fn builtin_jobs(&self) -> Result<Option<i32>, String> {
-        let jobs = self.background_jobs.lock().unwrap();
-        for (i, _) in jobs.iter().enumerate() {
-            println!("[{}] Running", i + 1);
+        let mut jobs = self.background_jobs.lock().unwrap();
+        jobs.reap();
+        for job in jobs.jobs() {
+            let state = match job.status {
+                Some(status) => state_text(status),
+                None if job.stopped => "Stopped".to_string(),
+                None => "Running".to_string(),
+            };
+            let _ = writeln!(
+                self.streams.out(),
+                "{}",
+                job_line(job.id, &state, &job.command)
+            );
         }
         Ok(Some(0))
     }
 
-    fn builtin_write(&self, args: &[String]) -> Result<Option<i32>, String> {
-        if args.len() != 2 {
-            return Err("Usage: write <filename> <content>".to_string());
+    fn builtin_kill(&self, args: &[String]) -> Result<Option<i32>, String> {
+        let usage = "Usage: kill [-s SIGNAL | -SIGNAL] pid | %job ...";
+        let mut signal = SIGTERM;
+        let mut targets = args;
+        if let Some(first) = args.first() {
+            let name = if first == "-s" {
+                targets = args.get(2..).ok_or(usage)?;
+                Some(args[1].as_str())
+            } else if let Some(name) = first.strip_prefix('-') {
+                targets = &args[1..];
+                Some(name)
+            } else {
+                None
+            };
+            if let Some(name) = name {
+                signal = signal_from_name(name)
+                    .ok_or_else(|| format!("{}: invalid signal specification", name))?;
+            }
+        }
+        if targets.is_empty() {
+            return Err(usage.to_string());
+        }
+
+        let mut status = 0;
+        for target in targets {
+            let pid = if target.starts_with('%') {
+                let jobs = self.background_jobs.lock().unwrap();
+                match jobs.parse_jobspec(target) {
+                    Ok(id) => jobs.get(id).map(|job| job.pid),
+                    Err(e) => {
+                        let _ = writeln!(self.streams.err(), "kill: {}", e);
+                        None
+                    }
+                }
+            } else {
+                let pid = target.parse::<u32>().ok();
+                if pid.is_none() {
+                    let _ = writeln!(
+                        self.streams.err(),
+                        "kill: {}: arguments must be process or job IDs",
+                        target
+                    );
+                }
+                pid
+            };
+            let Some(pid) = pid else {
+                status = 1;
+                continue;
+            };
+            if let Err(e) = signal_process(pid as i32, signal) {
+                let _ = writeln!(self.streams.err(), "kill: ({}) - {}", pid, e);
+                status = 1;
+            }
+        }
+        Ok(Some(status))
+    }
+
+    async fn builtin_wait(&mut self, args: &[String]) -> Result<Option<i32>, String> {
+        if args.is_empty() {
+            let ids: Vec<usize> = {
+                let jobs = self.background_jobs.lock().unwrap();
+                jobs.jobs().iter().map(|job| job.id).collect()
+            };
+            for id in ids {
+                self.wait_for_job(id).await?;
+            }
+            return Ok(Some(0));
+        }
+
+        let mut status = 0;
+        for target in args {
+            let id = {
+                let mut jobs = self.background_jobs.lock().unwrap();
+                let reported = target.parse().ok().and_then(|pid| jobs.take_reported(pid));
+                if let Some(reported) = reported {
+                    status = reported;
+                    continue;
+                }
+                if target.starts_with('%') {
+                    jobs.parse_jobspec(target)
+                } else {
+                    target
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|pid| jobs.find_by_pid(pid))
+                        .ok_or_else(|| format!("pid {} is not a child of this shell", target))
+                }
+            };
+            status = match id {
+                Ok(id) => self.wait_for_job(id).await?,
+                Err(e) => {
+                    let _ = writeln!(self.streams.err(), "wait: {}", e);
+                    if target.starts_with('%') {
+                        1
+                    } else {
+                        127
+                    }
+                }
+            };
+        }
+        Ok(Some(status))
+    }
+
+    async fn builtin_fg(&mut self, args: &[String]) -> Result<Option<i32>, String> {
+        let id = match self.resolve_jobspec(args.first()) {
+            Ok(id) => id,
+            Err(e) => {
+                let _ = writeln!(self.streams.err(), "fg: {}", e);
+                return Ok(Some(1));
+            }
+        };
+        let Some(mut job) = self.background_jobs.lock().unwrap().take(id) else {
+            return Ok(Some(127));
+        };
+        let _ = writeln!(self.streams.out(), "{}", job.command);
+        job.stopped = false;
+        // Background jobs have a group of their own, led by the job's process.
+        let terminal = foreground(job.pid);
+        let _ = signal_job(job.pid, SIGCONT);
+        let status = job
+            .wait_foreground(self.runtime)
+            .await
+            .map_err(|e| format!("Failed to wait for job {}: {}", id, e))?;
+        drop(terminal);
+        match status {
+            Some(status) => Ok(Some(status)),
+            None => {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "\n{}",
+                    stopped_notice(job.id, &job.command)
+                );
+                self.background_jobs.lock().unwrap().restore(job);
+                Ok(Some(128 + SIGTSTP))
+            }
+        }
+    }
+
+    fn builtin_bg(&self, args: &[String]) -> Result<Option<i32>, String> {
+        let id = match self.resolve_jobspec(args.first()) {
+            Ok(id) => id,
+            Err(e) => {
+                let _ = writeln!(self.streams.err(), "bg: {}", e);
+                return Ok(Some(1));
+            }
+        };
+        if let Some(job) = self.background_jobs.lock().unwrap().get_mut(id) {
+            job.stopped = false;
+            let _ = signal_job(job.pid, SIGCONT);
+            let _ = writeln!(self.streams.out(), "[{}] {} &", job.id, job.command);
+        }
+        Ok(Some(0))
+    }
+
+    /// `disown [-h] [-a] [jobspec ...]`: removes jobs from the job table, so
+    /// the shell forgets them, or with `-h` keeps them but exempts them from
+    /// the SIGHUP sent under `huponexit`. Defaults to the current job, or
+    /// with `-a` to all jobs.
+    fn builtin_disown(&self, args: &[String]) -> Result<Option<i32>, String> {
+        let mut keep = false;
+        let mut all = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-h" => keep = true,
+                "-a" => all = true,
+                "-ah" | "-ha" => (keep, all) = (true, true),
+                _ => specs.push(arg),
+            }
+        }
+
+        let mut jobs = self.background_jobs.lock().unwrap();
+        let ids = if all {
+            jobs.jobs().iter().map(|job| job.id).collect()
+        } else if specs.is_empty() {
+            match jobs.parse_jobspec("%+") {
+                Ok(id) => vec![id],
+                Err(_) => {
+                    let _ = writeln!(self.streams.err(), "disown: current: no such job");
+                    return Ok(Some(1));
+                }
+            }
+        } else {
+            let mut ids = Vec::new();
+            for spec in specs {
+                match jobs.parse_jobspec(spec) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => {
+                        let _ = writeln!(self.streams.err(), "disown: {}", e);
+                        return Ok(Some(1));
+                    }
+                }
+            }
+            ids
+        };
+        for id in ids {
+            if keep {
+                if let Some(job) = jobs.get_mut(id) {
+                    job.nohup = true;
+                }
+            } else {
+                jobs.take(id);
+            }
+        }
+        Ok(Some(0))
+    }
+
+    /// Resolves a job spec argument, defaulting to the current job.
+    fn resolve_jobspec(&self, spec: Option<&String>) -> Result<usize, String> {
+        let jobs = self.background_jobs.lock().unwrap();
+        jobs.parse_jobspec(spec.map_or("%+", |spec| spec.as_str()))
+    }
+
+    /// Blocks until the given job exits, returning its exit code.
+    /// Waits for a job, reporting it if a signal killed it.
+    async fn wait_for_job(&mut self, id: usize) -> Result<i32, String> {
+        let job = self.background_jobs.lock().unwrap().take(id);
+        let Some(mut job) = job else {
+            return Ok(127);
+        };
+        let status = job
+            .wait(self.runtime)
+            .await
+            .map_err(|e| format!("Failed to wait for job {}: {}", id, e))?;
+        if status > 128 {
+            let _ = writeln!(
+                self.streams.err(),
+                "{}",
+                job_line(id, &state_text(status), &job.command)
+            );
+        }
+        Ok(status)
+    }
+
+    pub fn add_job(&self, process: impl Into<Process>, command: String) -> usize {
+        self.background_jobs.lock().unwrap().add(process, command)
+    }
+
+    /// Sends SIGHUP to every job still running, other than those marked
+    /// with `disown -h`. Stopped jobs are continued so they can handle it.
+    pub fn hang_up_jobs(&self) {
+        let mut jobs = self.background_jobs.lock().unwrap();
+        jobs.reap();
+        for job in jobs.jobs() {
+            if job.status.is_none() && !job.nohup {
+                let _ = signal_job(job.pid, SIGHUP);
+                if job.stopped {
+                    let _ = signal_job(job.pid, SIGCONT);
+                }
+            }
+        }
+    }
+
+    fn add_stopped_job(&self, child: Child, command: String) -> usize {
+        self.background_jobs
+            .lock()
+            .unwrap()
+            .add_stopped(child, command)
+    }
+
+    pub fn check_jobs(&self) -> Vec<String> {
+        self.background_jobs.lock().unwrap().check_jobs()
+    }
+
+    /// The builtins' filesystem as seen from the shell's directory.
+    pub fn fs_in<'a>(&'a self, interpreter: &'a Interpreter) -> InDirectory<'a> {
+        InDirectory {
+            fs: &*self.fs,
+            directory: &interpreter.directory,
+        }
+    }
+
+    /// `write file [content]`: replaces the file with `content`, or without
+    /// it with standard input, byte for byte.
+    fn builtin_write(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        if args.is_empty() || args.len() > 2 {
+            return Err("Usage: write <filename> [content]".to_string());
         }
         let filename = &args[0];
-        let content = &args[1];
-        let mut file = File::create(filename)
+        let mut file = self
+            .fs_in(interpreter)
+            .create(Path::new(filename), false)
             .map_err(|e| format!("Failed to create file {}: {}", filename, e))?;
-        file.write_all(content.as_bytes())
+        write_content(&mut file, args.get(1), self.streams.input(0))
             .map_err(|e| format!("Failed to write to file {}: {}", filename, e))?;
         Ok(Some(0))
     }
 
-    fn builtin_read(&self, args: &[String]) -> Result<Option<i32>, String> {
-        if args.len() != 1 {
-            return Err("Usage: read <filename>".to_string());
+    /// Replaces the shell with the given command. Without a command `exec`
+    /// only applies its redirections, which the shell handles.
+    fn builtin_exec(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let Some((name, rest)) = args.split_first() else {
+            return Ok(Some(0));
+        };
+        let mut command = match self.command(interpreter, name) {
+            Ok(command) => command,
+            Err(status) => return Ok(Some(status)),
+        };
+        command.args(rest);
+        // A spawner runs the command instead of it replacing the process;
+        // the shell exits after it.
+        if let Some(result) = self.run_with_spawner(&mut command, None, false) {
+            return result.map(|(status, _)| Some(status));
         }
-        let filename = &args[0];
-        let mut content = String::new();
-        File::open(filename)
-            .map_err(|e| format!("Failed to open file {}: {}", filename, e))?
-            .read_to_string(&mut content)
+        #[cfg(unix)]
+        let error = command.exec();
+        #[cfg(not(unix))]
+        let error = unsupported("replacing the shell with a command");
+        Err(format!("exec: {}: {}", name, error))
+    }
+
+    /// Reads one line from standard input (or `-u FD`) into the named
+    /// variables, or `REPLY` when none are given.
+    fn builtin_read(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let usage = "Usage: read [-r] [-u fd] [name ...]";
+        let mut fd = 0;
+        let mut raw = false;
+        let mut names = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-r" => raw = true,
+                "-u" => {
+                    fd = iter.next().and_then(|fd| fd.parse().ok()).ok_or(usage)?;
+                }
+                _ => names.push(arg.clone()),
+            }
+        }
+        if names.is_empty() {
+            names.push("REPLY".to_string());
+        }
+
+        let Some(line) = read_line_from_fd(&self.streams, fd, raw)? else {
+            for name in &names {
+                interpreter.set_variable(name, String::new());
+            }
+            return Ok(Some(1));
+        };
+        let ifs = interpreter
+            .variables
+            .get("IFS")
+            .map_or(DEFAULT_IFS, String::as_str);
+        let values = split_read_fields(&line, ifs, names.len());
+        for (name, value) in names.into_iter().zip(values) {
+            interpreter.set_variable(&name, value);
+        }
+        Ok(Some(0))
+    }
+
+    /// `readfile [-r] file`: prints a file followed by a newline, or with
+    /// `-r` exactly as it is, so binary files pass through unchanged.
+    fn builtin_readfile(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let (raw, filename) = match args {
+            [filename] => (false, filename),
+            [flag, filename] if flag == "-r" => (true, filename),
+            _ => return Err("Usage: readfile [-r] <filename>".to_string()),
+        };
+        let content = self
+            .fs_in(interpreter)
+            .read(Path::new(filename))
             .map_err(|e| format!("Failed to read file {}: {}", filename, e))?;
-        println!("{}", content);
+        let mut stdout = self.streams.out();
+        stdout
+            .write_all(&content)
+            .and_then(|_| if raw { Ok(()) } else { stdout.write_all(b"\n") })
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("readfile: write error: {}", e))?;
         Ok(Some(0))
     }
 
-    fn builtin_append(&self, args: &[String]) -> Result<Option<i32>, String> {
-        if args.len() != 2 {
-            return Err("Usage: append <filename> <content>".to_string());
+    /// `append file [content]`: adds `content`, or without it standard
+    /// input, to the end of the file.
+    fn builtin_append(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        if args.is_empty() || args.len() > 2 {
+            return Err("Usage: append <filename> [content]".to_string());
         }
         let filename = &args[0];
-        let content = &args[1];
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(filename)
+        let mut file = self
+            .fs_in(interpreter)
+            .create(Path::new(filename), true)
             .map_err(|e| format!("Failed to open file {}: {}", filename, e))?;
-        file.write_all(content.as_bytes())
+        write_content(&mut file, args.get(1), self.streams.input(0))
             .map_err(|e| format!("Failed to append to file {}: {}", filename, e))?;
         Ok(Some(0))
     }
 
-    fn builtin_delete(&self, args: &[String]) -> Result<Option<i32>, String> {
+    fn builtin_delete(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
         if args.len() != 1 {
             return Err("Usage: delete <filename>".to_string());
         }
         let filename = &args[0];
-        std::fs::remove_file(filename)
+        self.fs_in(interpreter)
+            .remove(Path::new(filename))
             .map_err(|e| format!("Failed to delete file {}: {}", filename, e))?;
         Ok(Some(0))
     }
 
+    /// `trap [-p] [action] [condition ...]`: sets the command run on the
+    /// `EXIT`, `ERR` and `DEBUG` conditions. An action of `-` resets the
+    /// conditions and an empty one ignores them. Without an action, lists the
+    /// traps set.
+    fn builtin_trap(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let list = args.first().is_some_and(|arg| arg == "-p");
+        if list || args.is_empty() {
+            let names = &args[args.len().min(1)..];
+            for (condition, action) in &interpreter.traps {
+                if names.is_empty() || names.iter().any(|name| trap_condition(name) == *condition) {
+                    let _ = writeln!(self.streams.out(), "trap -- '{}' {}", action, condition);
+                }
+            }
+            return Ok(Some(0));
+        }
+
+        // A single operand is a condition to reset.
+        let (action, conditions) = match args {
+            [condition] => ("-", std::slice::from_ref(condition)),
+            [action, conditions @ ..] => (action.as_str(), conditions),
+            [] => unreachable!(),
+        };
+        let mut status = 0;
+        for name in conditions {
+            let condition = trap_condition(name);
+            if !matches!(condition.as_str(), "EXIT" | "ERR" | "DEBUG") {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "trap: {}: only EXIT, ERR and DEBUG can be trapped",
+                    name
+                );
+                status = 1;
+            } else if action == "-" {
+                interpreter.traps.remove(&condition);
+            } else {
+                interpreter.traps.insert(condition, action.to_string());
+            }
+        }
+        Ok(Some(status))
+    }
+
+    /// `complete [-W words | -F function | -p | -r] [name ...]`: registers
+    /// how arguments of the named commands are completed, lists the
+    /// registrations with `-p`, or removes them with `-r`.
+    fn builtin_complete(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let usage = "Usage: complete [-W words | -F function | -p | -r] [name ...]";
+        let (option, names) = match args.split_first() {
+            None => ("-p", args),
+            Some((option, names)) => (option.as_str(), names),
+        };
+        let completions = &mut interpreter.completions;
+        let mut status = 0;
+        match option {
+            "-W" | "-F" => {
+                let (value, names) = names.split_first().ok_or(usage)?;
+                if names.is_empty() {
+                    return Err(usage.to_string());
+                }
+                let spec = if option == "-W" {
+                    CompletionSpec::Words(value.clone())
+                } else {
+                    CompletionSpec::Function(value.clone())
+                };
+                for name in names {
+                    completions.insert(name.clone(), spec.clone());
+                }
+            }
+            "-r" if names.is_empty() => completions.clear(),
+            "-r" => {
+                for name in names {
+                    if completions.remove(name).is_none() {
+                        let _ = writeln!(
+                            self.streams.err(),
+                            "complete: {}: no completion specification",
+                            name
+                        );
+                        status = 1;
+                    }
+                }
+            }
+            "-p" => {
+                let listed: Vec<&String> = if names.is_empty() {
+                    completions.keys().collect()
+                } else {
+                    names.iter().collect()
+                };
+                for name in listed {
+                    match completions.get(name) {
+                        Some(CompletionSpec::Words(words)) => {
+                            let _ =
+                                writeln!(self.streams.out(), "complete -W '{}' {}", words, name);
+                        }
+                        Some(CompletionSpec::Function(function)) => {
+                            let _ =
+                                writeln!(self.streams.out(), "complete -F {} {}", function, name);
+                        }
+                        None => {
+                            let _ = writeln!(
+                                self.streams.err(),
+                                "complete: {}: no completion specification",
+                                name
+                            );
+                            status = 1;
+                        }
+                    }
+                }
+            }
+            _ => return Err(usage.to_string()),
+        }
+        Ok(Some(status))
+    }
+
+    /// `help [name ...]`: lists the builtins, or describes the named ones.
+    fn builtin_help(&self, args: &[String]) -> Result<Option<i32>, String> {
+        if args.is_empty() {
+            let _ = write!(self.streams.out(), "{}", builtins::listing());
+            return Ok(Some(0));
+        }
+        let mut status = 0;
+        for name in args {
+            match builtins::find(name) {
+                Some(builtin) => {
+                    let _ = write!(self.streams.out(), "{}", builtin.help());
+                }
+                None => {
+                    let _ = writeln!(
+                        self.streams.err(),
+                        "help: no help topics match `{}'. Try `type {}' to see what it is.",
+                        name,
+                        name
+                    );
+                    status = 1;
+                }
+            }
+        }
+        Ok(Some(status))
+    }
+
+    /// `mkfifo [-m mode] path ...`: creates named pipes, with permissions
+    /// `mode` in octal, by default 666 less the umask.
+    fn builtin_mkfifo(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let usage = "Usage: mkfifo [-m mode] path ...";
+        let (mode, paths) = match args {
+            [flag, mode, paths @ ..] if flag == "-m" => {
+                let mode = u32::from_str_radix(mode, 8)
+                    .map_err(|_| format!("mkfifo: {}: invalid mode", mode))?;
+                (Some(mode), paths)
+            }
+            _ => (None, args),
+        };
+        if paths.is_empty() {
+            return Err(usage.to_string());
+        }
+
+        let fs = self.fs_in(interpreter);
+        let mut status = 0;
+        for path in paths {
+            if let Err(e) = fs.mkfifo(Path::new(path), mode) {
+                let _ = writeln!(self.streams.err(), "mkfifo: {}: {}", path, e);
+                status = 1;
+            }
+        }
+        Ok(Some(status))
+    }
+
+    /// `split name string delimiter`: splits `string` on every occurrence of
+    /// the literal `delimiter`, or into characters if it is empty, storing
+    /// the fields in the indexed array `name`. Unlike `IFS` splitting,
+    /// adjacent delimiters give empty fields, and an empty string gives none.
+    fn builtin_split(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let [name, string, delimiter] = args else {
+            return Err("Usage: split name string delimiter".to_string());
+        };
+        if !is_identifier(name) {
+            return Err(format!("split: {}: not a valid identifier", name));
+        }
+        let fields: Vec<String> = if string.is_empty() {
+            Vec::new()
+        } else if delimiter.is_empty() {
+            string.chars().map(String::from).collect()
+        } else {
+            string.split(delimiter.as_str()).map(String::from).collect()
+        };
+        interpreter.set_array(name, fields);
+        Ok(Some(0))
+    }
+
+    /// `which [-a] [-s] name ...`: prints the path of the executable each
+    /// `name` runs from `PATH`, or with `-a` every executable match in `PATH`
+    /// order. Functions and builtins are not considered. `-s` prints nothing,
+    /// only setting the status, which is 1 if any name was not found.
+    fn builtin_which(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        let usage = "Usage: which [-a] [-s] name ...";
+        let (mut all, mut silent) = (false, false);
+        let mut names = args;
+        while let Some((flags, rest)) = names
+            .split_first()
+            .and_then(|(first, rest)| Some((first.strip_prefix('-')?, rest)))
+            .filter(|(flags, _)| !flags.is_empty())
+        {
+            for flag in flags.chars() {
+                match flag {
+                    'a' => all = true,
+                    's' => silent = true,
+                    _ => return Err(format!("which: -{}: invalid option\n{}", flag, usage)),
+                }
+            }
+            names = rest;
+        }
+        if names.is_empty() {
+            return Err(usage.to_string());
+        }
+
+        let path_var = path_var(interpreter);
+        let extensions = path_extensions(interpreter);
+        let mut status = 0;
+        for name in names {
+            let found: Vec<PathBuf> = if name.contains('/') {
+                Some(PathBuf::from(name))
+                    .filter(|path| is_executable(&interpreter.resolve_path(path), &extensions))
+                    .into_iter()
+                    .collect()
+            } else {
+                path_candidates(name, &path_var, &extensions, &interpreter.directory)
+                    .into_iter()
+                    .filter(|path| is_executable(path, &extensions))
+                    .collect()
+            };
+            if found.is_empty() {
+                status = 1;
+                continue;
+            }
+            if !silent {
+                let shown = if all { found.len() } else { 1 };
+                for path in &found[..shown] {
+                    let _ = writeln!(self.streams.out(), "{}", path.display());
+                }
+            }
+        }
+        Ok(Some(status))
+    }
+
+    /// `type name ...`: tells whether each name runs a builtin, a function
+    /// or a file in PATH, in the order the shell looks for them.
+    fn builtin_type(
+        &self,
+        interpreter: &Interpreter,
+        args: &[String],
+    ) -> Result<Option<i32>, String> {
+        if args.is_empty() {
+            return Err("Usage: type name ...".to_string());
+        }
+        let path_var = path_var(interpreter);
+        let extensions = path_extensions(interpreter);
+        let mut status = 0;
+        for name in args {
+            let in_path = || {
+                if name.contains('/') {
+                    Some(PathBuf::from(name))
+                        .filter(|path| is_executable(&interpreter.resolve_path(path), &extensions))
+                } else {
+                    path_candidates(name, &path_var, &extensions, &interpreter.directory)
+                        .into_iter()
+                        .find(|path| is_executable(path, &extensions))
+                }
+            };
+            if builtins::find(name).is_some() {
+                let _ = writeln!(self.streams.out(), "{} is a shell builtin", name);
+            } else if interpreter.functions.contains_key(name) {
+                let _ = writeln!(self.streams.out(), "{} is a function", name);
+            } else if let Some(path) = in_path() {
+                let _ = writeln!(self.streams.out(), "{} is {}", name, path.display());
+            } else {
+                let _ = writeln!(self.streams.err(), "type: {}: not found", name);
+                status = 1;
+            }
+        }
+        Ok(Some(status))
+    }
+
     fn builtin_seq(&self, args: &[String]) -> Result<Option<i32>, String> {
-        if args.len() < 1 || args.len() > 3 {
+        if args.is_empty() || args.len() > 3 {
             return Err("Usage: seq [START] [STEP] END".to_string());
         }
 
@@ -184,245 +1250,348 @@ impl Processes {
         };
 
         for i in (start..=end).step_by(step as usize) {
-            println!("{}", i);
+            let _ = writeln!(self.streams.out(), "{}", i);
         }
         Ok(Some(0))
     }
 
-    fn execute_external_command(&self, name: &str, args: &[String]) -> Result<Option<i32>, String> {
-        match Command::new(name).args(args).spawn() {
-            Ok(mut child) => {
-                let status = child.wait().map_err(|e| e.to_string())?;
-                Ok(Some(status.code().unwrap_or(0)))
+    /// Resolves an external command against the shell's PATH and prepares it
+    /// to run with the shell's descriptors. When it cannot be run, prints
+    /// the diagnostic and returns the exit status to report instead (127 not
+    /// found, 126 not executable). With a spawner the name is left for it to
+    /// resolve.
+    pub fn command(&self, interpreter: &Interpreter, name: &str) -> Result<Command, i32> {
+        if self.spawner.is_some() {
+            let mut command = Command::new(name);
+            prepare_command(interpreter, &mut command);
+            self.streams.attach(&mut command);
+            return Ok(command);
+        }
+        let path_var = path_var(interpreter);
+        let extensions = path_extensions(interpreter);
+        match resolve_command(name, &path_var, &extensions, &interpreter.directory) {
+            Resolution::Found(path) => {
+                let mut command = spawn_command(&path, name);
+                prepare_command(interpreter, &mut command);
+                self.streams.attach(&mut command);
+                Ok(command)
+            }
+            Resolution::NotExecutable(path) => {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "bellos: {}: Permission denied",
+                    path.display()
+                );
+                Err(126)
+            }
+            Resolution::NotFound => {
+                let _ = writeln!(self.streams.err(), "bellos: {}: command not found", name);
+                let builtins: Vec<&str> =
+                    builtins::available().map(|builtin| builtin.name).collect();
+                let suggestions = suggest_commands(name, &path_var, &builtins);
+                if !suggestions.is_empty() {
+                    let _ = writeln!(
+                        self.streams.err(),
+                        "bellos: did you mean: {}?",
+                        suggestions.join(", ")
+                    );
+                }
+                Err(127)
             }
-            Err(e) => Err(format!("Failed to execute command: {}", e)),
         }
     }
 
-    pub fn execute_redirect(
-        &self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-        direction: RedirectType,
-        target: String,
+    async fn execute_external_command(
+        &mut self,
+        interpreter: &Interpreter,
+        name: &str,
+        args: &[String],
     ) -> Result<Option<i32>, String> {
-        let target = interpreter.expand_variables(&target);
-        match direction {
-            RedirectType::Output => self.execute_output_redirect(interpreter, node, &target),
-            RedirectType::Append => self.execute_append_redirect(interpreter, node, &target),
-            RedirectType::Input => self.execute_input_redirect(interpreter, node, &target),
+        let mut command = match self.command(interpreter, name) {
+            Ok(command) => command,
+            Err(status) => return Ok(Some(status)),
+        };
+        command.args(args);
+        if let Some(result) = self.run_with_spawner(&mut command, None, false) {
+            return result.map(|(status, _)| Some(status));
+        }
+        let ended = self
+            .runtime
+            .run_foreground(command)
+            .await
+            .map_err(|e| format!("Failed to execute command: {}", e))?;
+        match ended {
+            Ended::Exited(status) => {
+                let status = exit_code(status);
+                // Ctrl-C leaves the cursor after the `^C` echoed by the
+                // terminal, so start the next prompt on a new line.
+                if status == 128 + SIGINT && job_control() {
+                    let _ = writeln!(self.streams.out());
+                }
+                Ok(Some(status))
+            }
+            // Stopped by Ctrl-Z: it becomes a job `fg` or `bg` can resume.
+            Ended::Stopped(child) => {
+                let command = std::iter::once(name)
+                    .chain(args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let id = self.add_stopped_job(child, command.clone());
+                let _ = writeln!(self.streams.err(), "\n{}", stopped_notice(id, &command));
+                Ok(Some(128 + SIGTSTP))
+            }
         }
     }
 
-    fn execute_output_redirect(
+    /// Runs `command` through the spawner, if there is one, reporting a
+    /// failure to start it as the shell does its own: status 127 when it
+    /// does not exist.
+    pub fn run_with_spawner(
         &self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        let file = File::create(target).map_err(|e| e.to_string())?;
-        let mut writer = BufWriter::new(file);
-        let result = self.capture_output(interpreter, Box::new(node))?;
-        writer
-            .write_all(result.as_bytes())
-            .map_err(|e| e.to_string())?;
-        Ok(Some(0))
+        command: &mut Command,
+        input: Option<&[u8]>,
+        capture: bool,
+    ) -> Option<Result<(i32, Vec<u8>), String>> {
+        // A spawner writes to the process's standard output: what goes to
+        // the shell's, when it is redirected, is captured and written there.
+        let redirected = !capture && self.streams.is_redirected(1);
+        let result = match self
+            .spawner
+            .as_ref()?
+            .run(command, input, capture || redirected)
+        {
+            Ok((status, output)) if redirected => {
+                let _ = self.streams.out().write_all(&output);
+                Ok((status, Vec::new()))
+            }
+            Ok(result) => Ok(result),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let name = command.get_program().to_string_lossy();
+                let _ = writeln!(self.streams.err(), "bellos: {}: command not found", name);
+                Ok((127, Vec::new()))
+            }
+            Err(e) => Err(format!("Failed to execute command: {}", e)),
+        };
+        Some(result)
     }
 
-    fn execute_append_redirect(
-        &self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        let file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(target)
-            .map_err(|e| e.to_string())?;
-        let mut writer = BufWriter::new(file);
-        let result = self.capture_output(interpreter, Box::new(node))?;
-        writer
-            .write_all(result.as_bytes())
-            .map_err(|e| e.to_string())?;
-        Ok(Some(0))
+    pub fn expand_wildcards(&self, pattern: &str) -> Vec<String> {
+        match glob(pattern) {
+            Ok(paths) => paths
+                .filter_map(Result::ok)
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+            Err(_) => vec![pattern.to_string()],
+        }
     }
 
-    fn execute_input_redirect(
+    /// `test expr` and `[ expr ]`: status 0 if the expression holds, 1 if it
+    /// does not and 2 on a usage error.
+    fn builtin_test(
         &self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-        target: &str,
+        interpreter: &Interpreter,
+        name: &str,
+        args: &[String],
     ) -> Result<Option<i32>, String> {
-        let file = File::open(target).map_err(|e| e.to_string())?;
-        let mut reader = BufReader::new(file);
-        let mut input = String::new();
-        reader
-            .read_to_string(&mut input)
-            .map_err(|e| e.to_string())?;
-        self.execute_with_input(interpreter, node, input)
+        let args = match args.split_last() {
+            _ if name != "[" => args,
+            Some((last, rest)) if last == "]" => rest,
+            _ => {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "{}: [: missing `]'",
+                    interpreter.location()
+                );
+                return Ok(Some(2));
+            }
+        };
+        match condition::evaluate(interpreter, &self.fs_in(interpreter), args) {
+            Ok(result) => Ok(Some(if result { 0 } else { 1 })),
+            Err(e) => {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "{}: {}: {}",
+                    interpreter.location(),
+                    name,
+                    e
+                );
+                Ok(Some(2))
+            }
+        }
     }
 
-    pub fn execute_pipeline(
+    /// `expr args...`: prints the value of the expression. Status 0 if it is
+    /// neither empty nor zero, 1 if it is and 2 on an error.
+    fn builtin_expr(
         &self,
-        interpreter: &mut Interpreter,
-        commands: Vec<ASTNode>,
+        interpreter: &Interpreter,
+        args: &[String],
     ) -> Result<Option<i32>, String> {
-        let mut previous_stdout = None;
-        let mut processes = Vec::new();
-
-        for (i, command) in commands.iter().enumerate() {
-            match command {
-                ASTNode::Command { name, args } => {
-                    let process = self.setup_pipeline_command(
-                        interpreter,
-                        name,
-                        args,
-                        i,
-                        &commands.len(),
-                        &mut previous_stdout,
-                    )?;
-                    processes.push(process);
-                }
-                _ => return Err("Pipeline can only contain commands".to_string()),
+        match expr::evaluate(interpreter, args) {
+            Ok(value) => {
+                let _ = writeln!(self.streams.out(), "{}", value);
+                Ok(Some(if expr::is_true(&value) { 0 } else { 1 }))
+            }
+            Err(e) => {
+                let _ = writeln!(self.streams.err(), "expr: {}", e);
+                Ok(Some(2))
             }
         }
-
-        self.wait_for_processes(processes)
     }
 
-    fn setup_pipeline_command(
+    /// `[[ expr ]]`: like `test`, plus `text =~ regex` matching.
+    fn builtin_double_bracket(
         &self,
         interpreter: &mut Interpreter,
-        name: &str,
         args: &[String],
-        index: usize,
-        total_commands: &usize,
-        previous_stdout: &mut Option<Stdio>,
-    ) -> Result<Child, String> {
-        let mut cmd = Command::new(interpreter.expand_variables(name));
-        for arg in args {
-            cmd.arg(interpreter.expand_variables(arg));
-        }
-
-        if let Some(prev_stdout) = previous_stdout.take() {
-            cmd.stdin(prev_stdout);
-        }
-
-        if index < total_commands - 1 {
-            cmd.stdout(Stdio::piped());
-        }
-
-        let mut child = cmd.spawn().map_err(|e| e.to_string())?;
-
-        if index < total_commands - 1 {
-            *previous_stdout = child.stdout.take().map(Stdio::from);
-        }
-
-        Ok(child)
+    ) -> Result<Option<i32>, String> {
+        let args = match args.split_last() {
+            Some((last, rest)) if last == "]]" => rest,
+            _ => {
+                let _ = writeln!(
+                    self.streams.err(),
+                    "{}: [[: missing `]]'",
+                    interpreter.location()
+                );
+                return Ok(Some(2));
+            }
+        };
+        let result = match args {
+            [text, op, pattern] if op == "=~" => {
+                condition::regex_match(interpreter, text, pattern)?
+            }
+            [not, text, op, pattern] if not == "!" && op == "=~" => {
+                !condition::regex_match(interpreter, text, pattern)?
+            }
+            _ => match condition::evaluate(interpreter, &self.fs_in(interpreter), args) {
+                Ok(result) => result,
+                Err(e) => {
+                    let _ = writeln!(self.streams.err(), "{}: [[: {}", interpreter.location(), e);
+                    return Ok(Some(2));
+                }
+            },
+        };
+        Ok(Some(if result { 0 } else { 1 }))
     }
+}
 
-    fn wait_for_processes(&self, processes: Vec<Child>) -> Result<Option<i32>, String> {
-        let mut last_status = None;
-        for mut process in processes {
-            let status = process.wait().map_err(|e| e.to_string())?;
-            last_status = Some(status.code().unwrap_or(0));
-        }
-        Ok(last_status)
+/// Where `cd dir` goes through `CDPATH`, if `dir` is not a directory below
+/// the current one: the first of `CDPATH`'s entries holding a directory
+/// `dir`. Paths that are absolute or start with `.` or `..` are not looked
+/// up, and an empty entry stands for the current directory, which was
+/// already tried.
+fn cdpath_directory(
+    dir: &str,
+    cdpath: Option<String>,
+    is_dir: impl Fn(&str) -> bool,
+) -> Option<String> {
+    let separators: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+    let bypass = dir.starts_with(separators)
+        || Path::new(dir).is_absolute()
+        || [".", ".."].iter().any(|prefix| {
+            dir.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(separators))
+        });
+    if bypass || is_dir(dir) {
+        return None;
     }
+    cdpath?
+        .split(PATH_LIST_SEPARATOR)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| format!("{}/{}", entry.trim_end_matches(separators), dir))
+        .find(|candidate| is_dir(candidate))
+}
 
-    fn capture_output(
-        &self,
-        interpreter: &mut Interpreter,
-        node: Box<ASTNode>,
-    ) -> Result<String, String> {
-        let old_stdout = io::stdout();
-        let mut handle = old_stdout.lock();
-        let mut buffer = Vec::new();
-        {
-            let mut cursor = Cursor::new(&mut buffer);
-            let result = interpreter.interpret_node(&node)?;
-            writeln!(cursor, "{:?}", result).map_err(|e| e.to_string())?;
-        }
-        handle.write_all(&buffer).map_err(|e| e.to_string())?;
-        String::from_utf8(buffer).map_err(|e| e.to_string())
+/// Writes `content` to `file`, or copies `stdin` when there is none.
+fn write_content(
+    file: &mut impl Write,
+    content: Option<&String>,
+    mut stdin: Input,
+) -> io::Result<()> {
+    match content {
+        Some(content) => file.write_all(content.as_bytes()),
+        None => io::copy(&mut stdin, file).map(|_| ()),
     }
+}
 
-    fn execute_with_input(
-        &self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-        input: String,
-    ) -> Result<Option<i32>, String> {
-        std::env::set_var("BELLOS_INPUT", input);
-        interpreter.interpret_node(&node)
+/// Gives a command the shell's environment and starts it in the shell's
+/// directory.
+pub fn prepare_command(interpreter: &Interpreter, command: &mut Command) {
+    command.env_clear().envs(interpreter.command_environment());
+    if !interpreter.directory.as_os_str().is_empty() {
+        command.current_dir(&interpreter.directory);
     }
+}
 
-    pub fn execute_background(
-        &mut self,
-        interpreter: &mut Interpreter,
-        node: ASTNode,
-    ) -> Result<Option<i32>, String> {
-        let bg_jobs = Arc::clone(&self.background_jobs);
-        let interpreter_clone = interpreter.clone();
-
-        thread::spawn(move || {
-            let mut local_interpreter = interpreter_clone;
-            if let Err(e) = local_interpreter.interpret_node(&node) {
-                eprintln!("Background job error: {}", e);
-            }
-
-            let mut jobs = bg_jobs.lock().unwrap();
-            jobs.retain(|job| {
-                let mut child = job.lock().unwrap();
-                match child.try_wait() {
-                    Ok(Some(_)) => {
-                        println!("Job completed.");
-                        false
-                    }
-                    Ok(None) => {
-                        println!("Job still running.");
-                        true
-                    }
-                    Err(err) => {
-                        eprintln!("Error waiting for job: {}", err);
-                        false
-                    }
-                }
-            });
-        });
+/// The search path: the shell's `PATH`, or the one it was started with.
+fn path_var(interpreter: &Interpreter) -> String {
+    interpreter.variable("PATH").unwrap_or_default()
+}
 
-        let placeholder =
-            Arc::new(Mutex::new(Command::new("sleep").arg("1").spawn().map_err(
-                |e| format!("Failed to create placeholder process: {}", e),
-            )?));
-        self.background_jobs.lock().unwrap().push(placeholder);
+/// The extensions a command name may leave out, by the shell's `PATHEXT`
+/// or the one it was started with.
+fn path_extensions(interpreter: &Interpreter) -> Vec<String> {
+    command_extensions(interpreter.variable("PATHEXT").as_deref())
+}
 
-        Ok(None)
+/// Reads a line from descriptor `fd` one byte at a time so nothing past the
+/// newline is consumed. Returns `None` at end of input with nothing read.
+/// Unless `raw`, a backslash escapes the next character and a trailing
+/// backslash continues the line.
+fn read_line_from_fd(streams: &Streams, fd: i32, raw: bool) -> Result<Option<String>, String> {
+    let mut input = streams.input(fd);
+    let mut bytes = Vec::new();
+    let mut escaped = false;
+    let mut read_any = false;
+    loop {
+        let byte = match read_byte(&mut input) {
+            Ok(Some(byte)) => byte,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("read: {}: {}", fd, e)),
+        };
+        read_any = true;
+        match byte {
+            b'\\' if !raw && !escaped => escaped = true,
+            b'\n' if escaped => escaped = false,
+            b'\n' => return Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+            _ => {
+                escaped = false;
+                bytes.push(byte);
+            }
+        }
     }
+    Ok(read_any.then(|| String::from_utf8_lossy(&bytes).into_owned()))
+}
 
-    pub fn expand_wildcards(&self, pattern: &str) -> Vec<String> {
-        match glob(pattern) {
-            Ok(paths) => paths
-                .filter_map(Result::ok)
-                .map(|path| path.to_string_lossy().into_owned())
-                .collect(),
-            Err(_) => vec![pattern.to_string()],
+/// Reads one byte from `input`, `None` at end of input.
+fn read_byte(input: &mut Input) -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    let count = input.read(std::slice::from_mut(&mut byte))?;
+    Ok((count > 0).then_some(byte))
+}
+
+/// Joins `path` onto the absolute `base`, resolving `.` and `..` by name.
+fn logical_path(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
         }
     }
+    format!("/{}", components.join("/"))
+}
 
-    fn evaluate_condition(
-        &self,
-        interpreter: &mut Interpreter,
-        args: &[String],
-    ) -> Result<Option<i32>, String> {
-        if args.len() != 3 {
-            return Err("Invalid condition syntax".to_string());
-        }
-        let result =
-            self.logic
-                .compare_values(&interpreter.variables, &args[0], &args[1], &args[2])?;
-        Ok(Some(if result { 0 } else { 1 }))
+/// Normalizes a trap condition name: `exit`, `SIGEXIT` and `0` all mean
+/// `EXIT`.
+fn trap_condition(name: &str) -> String {
+    let name = name.to_ascii_uppercase();
+    match name.strip_prefix("SIG").unwrap_or(&name) {
+        "0" => "EXIT".to_string(),
+        name => name.to_string(),
     }
 }
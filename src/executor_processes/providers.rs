@@ -0,0 +1,553 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(unix)]
+use crate::executor_processes::jobs;
+use crate::executor_processes::jobs::exit_code;
+use crate::shell::expansion;
+#[cfg(unix)]
+use crate::shell::redirection;
+use glob::{glob_with, MatchOptions, Pattern};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The filesystem as the shell sees it: the file builtins, the file tests
+/// of `test` and `[[`, glob expansion, redirections and `include` all go
+/// through it, so an embedder can give a shell files of its own. Only the
+/// files external commands open still come from the real filesystem.
+///
+/// The methods past `glob` have defaults, so a provider need only give the
+/// ones it can: the rest fail as unsupported, and redirections work on
+/// temporary copies of its files.
+pub trait FsProvider: Send + Sync {
+    /// Opens a file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>>;
+
+    /// Reads a whole file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.open(path)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// Opens a file for writing: created or emptied, or with `append`
+    /// written after its end, in which case it must already exist.
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Removes a file.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// What `test` needs to know of a file, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo>;
+
+    /// The paths `pattern` matches, unsorted. A `**` component matches any
+    /// number of directories.
+    fn glob(&self, pattern: &str, options: MatchOptions) -> Vec<String>;
+
+    /// What `stat` and `chmod` need to know of a file, following a symlink
+    /// only when `follow` is set.
+    fn stat(&self, _path: &Path, _follow: bool) -> io::Result<FileStatus> {
+        Err(unsupported())
+    }
+
+    /// Sets the permission bits of a file, including setuid, setgid and
+    /// sticky.
+    fn set_mode(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Creates a symbolic link at `link` holding `target` as it is.
+    fn symlink(&self, _target: &Path, _link: &Path) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Creates a hard link to `target` at `link`.
+    fn hard_link(&self, _target: &Path, _link: &Path) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Creates a FIFO, with permissions `mode` if given and otherwise 666
+    /// less the umask.
+    fn mkfifo(&self, _path: &Path, _mode: Option<u32>) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// The one name of a file however it is reached, which `include` uses
+    /// to know the files it has already run.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+
+    /// Opens a file for a redirection, which needs a real descriptor. By
+    /// default that is a temporary copy of the file, whose content is
+    /// written back through `create` when the redirection ends.
+    fn open_redirect(&self, path: &Path, access: Access) -> io::Result<Redirected> {
+        let mut file = tempfile::tempfile()?;
+        let existing = match access {
+            Access::Read => Some(self.read(path)?),
+            Access::ReadWrite => self.read(path).ok(),
+            Access::Write | Access::Append => None,
+        };
+        if let Some(content) = existing {
+            file.write_all(&content)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        let sink = match access {
+            Access::Read => None,
+            Access::Append => Some(
+                self.create(path, true)
+                    .or_else(|_| self.create(path, false))?,
+            ),
+            Access::Write | Access::ReadWrite => Some(self.create(path, false)?),
+        };
+        Ok(Redirected { file, sink })
+    }
+}
+
+/// How a redirection opens its file: `<`, `>`, `>>` or `<>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Append,
+    ReadWrite,
+}
+
+/// A file opened for a redirection. When it is a copy, `sink` takes what
+/// was written to it once the redirection ends.
+pub struct Redirected {
+    pub file: File,
+    pub sink: Option<Box<dyn Write + Send>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+/// A file as `stat` describes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStatus {
+    pub kind: FileKind,
+    /// The permission bits, with setuid, setgid and sticky.
+    pub mode: u32,
+    pub len: u64,
+    /// The modification time in seconds since the epoch.
+    pub modified: i64,
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Operation not supported by this filesystem",
+    )
+}
+
+/// A readable file that `tail` can also read from the end.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo {
+    /// Set for regular files only, not for devices or pipes.
+    pub is_file: bool,
+    pub is_dir: bool,
+    pub len: u64,
+}
+
+/// The real filesystem, which shells use unless given another.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFs;
+
+impl FsProvider for StdFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + Send>> {
+        let file = if append {
+            OpenOptions::new().append(true).open(path)?
+        } else {
+            File::create(path)?
+        };
+        Ok(Box::new(file))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileInfo {
+            is_file: metadata.is_file(),
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+        })
+    }
+
+    fn glob(&self, pattern: &str, options: MatchOptions) -> Vec<String> {
+        if pattern.split('/').any(|component| component == "**") {
+            globstar_paths(pattern, options)
+        } else {
+            glob_paths(pattern, options)
+        }
+    }
+
+    fn stat(&self, path: &Path, follow: bool) -> io::Result<FileStatus> {
+        let metadata = if follow {
+            fs::metadata(path)?
+        } else {
+            fs::symlink_metadata(path)?
+        };
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Directory
+        } else {
+            special_kind(&file_type).unwrap_or(FileKind::File)
+        };
+        #[cfg(unix)]
+        let (mode, modified) = (metadata.mode() & 0o7777, metadata.mtime());
+        // Without Unix modes a file is only writable or read-only.
+        #[cfg(not(unix))]
+        let (mode, modified) = (
+            if metadata.permissions().readonly() {
+                0o444
+            } else {
+                0o666
+            },
+            metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs() as i64),
+        );
+        Ok(FileStatus {
+            kind,
+            mode,
+            len: metadata.len(),
+            modified,
+        })
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    /// Only the write bits mean anything: a file without any is read-only.
+    #[cfg(not(unix))]
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        fs::set_permissions(path, permissions)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        symlink(target, link)
+    }
+
+    fn hard_link(&self, target: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(target, link)
+    }
+
+    #[cfg(unix)]
+    fn mkfifo(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), mode.unwrap_or(0o666) as libc::mode_t) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // An explicit mode is not subject to the umask.
+        match mode {
+            Some(mode) => self.set_mode(path, mode),
+            None => Ok(()),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn open_redirect(&self, path: &Path, access: Access) -> io::Result<Redirected> {
+        // A forked copy of the shell runs just the one job, which may as
+        // well wait for the other end of a FIFO itself. It exits when the
+        // job is done, too soon for a thread to copy its output.
+        #[cfg(unix)]
+        if !jobs::is_forked_copy()
+            && fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+        {
+            let file = match access {
+                Access::Read => redirection::fifo_pipe(path, false)?,
+                Access::Write | Access::Append => redirection::fifo_pipe(path, true)?,
+                Access::ReadWrite => OpenOptions::new().read(true).write(true).open(path)?,
+            };
+            return Ok(Redirected { file, sink: None });
+        }
+        let file = match access {
+            Access::Read => File::open(path)?,
+            Access::Write => File::create(path)?,
+            Access::Append => OpenOptions::new().append(true).create(true).open(path)?,
+            Access::ReadWrite => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?,
+        };
+        Ok(Redirected { file, sink: None })
+    }
+}
+
+/// The kind of a file that is neither a regular file, a directory nor a
+/// symlink, which only Unix has.
+#[cfg(unix)]
+fn special_kind(file_type: &fs::FileType) -> Option<FileKind> {
+    if file_type.is_fifo() {
+        Some(FileKind::Fifo)
+    } else if file_type.is_socket() {
+        Some(FileKind::Socket)
+    } else if file_type.is_char_device() {
+        Some(FileKind::CharDevice)
+    } else if file_type.is_block_device() {
+        Some(FileKind::BlockDevice)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_file_type: &fs::FileType) -> Option<FileKind> {
+    None
+}
+
+/// A filesystem seen from a shell's directory: relative paths are taken
+/// from `directory`, and what globs match is named relative to it again.
+pub struct InDirectory<'a> {
+    pub fs: &'a dyn FsProvider,
+    pub directory: &'a Path,
+}
+
+impl InDirectory<'_> {
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.directory.join(path)
+    }
+}
+
+impl FsProvider for InDirectory<'_> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        self.fs.open(&self.resolve(path))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.fs.read(&self.resolve(path))
+    }
+
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + Send>> {
+        self.fs.create(&self.resolve(path), append)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.fs.remove(&self.resolve(path))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        self.fs.metadata(&self.resolve(path))
+    }
+
+    fn stat(&self, path: &Path, follow: bool) -> io::Result<FileStatus> {
+        self.fs.stat(&self.resolve(path), follow)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.fs.set_mode(&self.resolve(path), mode)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        self.fs.symlink(target, &self.resolve(link))
+    }
+
+    fn hard_link(&self, target: &Path, link: &Path) -> io::Result<()> {
+        self.fs
+            .hard_link(&self.resolve(target), &self.resolve(link))
+    }
+
+    fn mkfifo(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        self.fs.mkfifo(&self.resolve(path), mode)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fs.canonicalize(&self.resolve(path))
+    }
+
+    fn open_redirect(&self, path: &Path, access: Access) -> io::Result<Redirected> {
+        self.fs.open_redirect(&self.resolve(path), access)
+    }
+
+    fn glob(&self, pattern: &str, options: MatchOptions) -> Vec<String> {
+        if self.directory.as_os_str().is_empty() || pattern.starts_with('/') {
+            return self.fs.glob(pattern, options);
+        }
+        let prefix = format!(
+            "{}/",
+            self.directory.display().to_string().trim_end_matches('/')
+        );
+        let escaped = Pattern::escape(&prefix);
+        self.fs
+            .glob(&format!("{}{}", escaped, pattern), options)
+            .into_iter()
+            .filter_map(|path| path.strip_prefix(&prefix).map(str::to_string))
+            .collect()
+    }
+}
+
+fn glob_paths(pattern: &str, match_options: MatchOptions) -> Vec<String> {
+    match glob_with(pattern, match_options) {
+        Ok(paths) => paths
+            .filter_map(Result::ok)
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Matches a pattern containing a `**` component by walking the directory
+/// tree below its literal prefix. Symlinked directories are listed but not
+/// descended into, so links pointing back up the tree cannot loop.
+fn globstar_paths(pattern: &str, match_options: MatchOptions) -> Vec<String> {
+    let Ok(compiled) = Pattern::new(pattern) else {
+        return Vec::new();
+    };
+    let literal: Vec<&str> = pattern
+        .split('/')
+        .take_while(|component| !component.contains(['*', '?', '[']))
+        .collect();
+    let base = match literal.join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base => base,
+    };
+
+    let mut matches = Vec::new();
+    let mut pending = vec![base];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { &dir }) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let path = match dir.as_str() {
+                "" => name.to_string_lossy().into_owned(),
+                "/" => format!("/{}", name.to_string_lossy()),
+                dir => format!("{}/{}", dir, name.to_string_lossy()),
+            };
+            if compiled.matches_with(&path, match_options) {
+                matches.push(path.clone());
+            }
+            let hidden = name.to_string_lossy().starts_with('.');
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir())
+                && !(hidden && match_options.require_literal_leading_dot)
+            {
+                pending.push(path);
+            }
+        }
+    }
+    matches
+}
+
+/// Where `~user` finds the home directories of users, so that an embedder
+/// or a test can say who the users are.
+pub trait UserProvider: Send + Sync {
+    /// The home directory of `user`, or `None` if there is no such user.
+    fn home_directory(&self, user: &str) -> Option<String>;
+}
+
+/// The users of the system, from the password database.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemUsers;
+
+impl UserProvider for SystemUsers {
+    fn home_directory(&self, user: &str) -> Option<String> {
+        expansion::home_directory(user)
+    }
+}
+
+/// Runs the external commands of a shell given one, in place of the
+/// process groups, terminal hand-off and job control the shell otherwise
+/// manages itself: foreground commands, pipeline stages and background
+/// commands, which then finish before the shell goes on. `exec` hands its
+/// command over too, the shell exiting when it is done, and so does
+/// `timeout`, whose limit a spawner is not held to.
+///
+/// Nothing bypasses it, on either runtime: a shell with a spawner never
+/// forks. Background compound commands and functions, and functions run
+/// by `timeout`, run to the end in the shell itself, their variable
+/// changes undone as if they had run in a copy of it.
+pub trait Spawner: Send {
+    /// Runs `command` to completion, with `input` as its standard input
+    /// when given and otherwise the shell's. Returns its exit status and,
+    /// when `capture`, its standard output, which otherwise is the shell's.
+    fn run(
+        &self,
+        command: &mut Command,
+        input: Option<&[u8]>,
+        capture: bool,
+    ) -> io::Result<(i32, Vec<u8>)>;
+}
+
+/// Runs commands as plain child processes of the shell, without job
+/// control: they share its process group and cannot be stopped into jobs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdSpawner;
+
+impl Spawner for StdSpawner {
+    fn run(
+        &self,
+        command: &mut Command,
+        input: Option<&[u8]>,
+        capture: bool,
+    ) -> io::Result<(i32, Vec<u8>)> {
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        if capture {
+            command.stdout(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        Ok((exit_code(output.status), output.stdout))
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What separates the directories of `PATH` and `CDPATH`.
+pub const PATH_LIST_SEPARATOR: char = if cfg!(windows) { ';' } else { ':' };
+
+/// The extensions tried when `PATHEXT` is not set.
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+pub enum Resolution {
+    Found(PathBuf),
+    NotExecutable(PathBuf),
+    NotFound,
+}
+
+/// Whether `name` is a path rather than a command to search for: it has a
+/// slash, or on Windows a backslash.
+pub fn is_path(name: &str) -> bool {
+    name.contains('/') || cfg!(windows) && name.contains('\\')
+}
+
+/// Resolves a command name the way `execvp` would: paths are used as they
+/// are, everything else is searched for in `path_var`. A name without an
+/// extension also matches `extensions`, those of `command_extensions`.
+/// Relative paths, and relative `PATH` entries, are taken from `directory`.
+pub fn resolve_command(
+    name: &str,
+    path_var: &str,
+    extensions: &[String],
+    directory: &Path,
+) -> Resolution {
+    if is_path(name) {
+        let path = &directory.join(name);
+        if let Some(found) = with_extensions(path, extensions)
+            .into_iter()
+            .find(|path| is_executable(path, extensions))
+        {
+            return Resolution::Found(found);
+        }
+        return if !path.exists() {
+            Resolution::NotFound
+        } else {
+            Resolution::NotExecutable(path.to_path_buf())
+        };
+    }
+
+    let mut not_executable = None;
+    for candidate in path_candidates(name, path_var, extensions, directory) {
+        if is_executable(&candidate, extensions) {
+            return Resolution::Found(candidate);
+        }
+        not_executable.get_or_insert(candidate);
+    }
+    not_executable.map_or(Resolution::NotFound, Resolution::NotExecutable)
+}
+
+/// Lists every regular file named `name` in `path_var`, in PATH order,
+/// trying each of `extensions` too when it has none. Relative entries are
+/// taken from `directory`.
+pub fn path_candidates(
+    name: &str,
+    path_var: &str,
+    extensions: &[String],
+    directory: &Path,
+) -> Vec<PathBuf> {
+    path_var
+        .split(PATH_LIST_SEPARATOR)
+        .flat_map(|dir| {
+            let dir = directory.join(if dir.is_empty() { "." } else { dir });
+            with_extensions(&dir.join(name), extensions)
+        })
+        .filter(|candidate| candidate.is_file())
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path, _extensions: &[String]) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Without an execute bit, as on Windows, a file is executable if its
+/// extension is one of `extensions`.
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path, extensions: &[String]) -> bool {
+    has_extension_of(path, extensions) && path.is_file()
+}
+
+/// Whether the extension of `path` is one of `extensions`, in any case.
+#[cfg_attr(unix, allow(dead_code))]
+fn has_extension_of(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|extension| {
+        let extension = format!(".{}", extension.to_string_lossy());
+        extensions
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&extension))
+    })
+}
+
+/// The paths `path` may name as a command: itself, and when it has no
+/// extension, itself with each of `extensions`.
+fn with_extensions(path: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    if path.extension().is_none() {
+        for extension in extensions {
+            let mut name = path.as_os_str().to_owned();
+            name.push(extension);
+            paths.push(PathBuf::from(name));
+        }
+    }
+    paths
+}
+
+/// The extensions a command name may leave out, given the shell's
+/// `PATHEXT`: its own, or the default ones, on Windows, and none elsewhere.
+pub fn command_extensions(pathext_var: Option<&str>) -> Vec<String> {
+    if cfg!(windows) {
+        pathext(pathext_var)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Splits a `PATHEXT` value, or the default one when it is not set.
+fn pathext(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or(DEFAULT_PATHEXT)
+        .split(';')
+        .filter(|extension| !extension.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Prepares the resolved `path` of command `name` to run. Windows runs batch
+/// files through `cmd /C`, since `CreateProcess` only starts executables.
+pub fn spawn_command(path: &Path, name: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let batch = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("bat") || extension.eq_ignore_ascii_case("cmd")
+        });
+        if batch {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(path);
+            return command;
+        }
+        let _ = name;
+        Command::new(path)
+    }
+    #[cfg(not(windows))]
+    {
+        let mut command = Command::new(path);
+        #[cfg(unix)]
+        command.arg0(name);
+        #[cfg(not(unix))]
+        let _ = name;
+        command
+    }
+}
+
+/// Suggests commands from `path_var` and `builtins` within edit distance two
+/// of `name`, closest first.
+pub fn suggest_commands(name: &str, path_var: &str, builtins: &[&str]) -> Vec<String> {
+    let mut names: BTreeSet<String> = builtins.iter().map(|name| name.to_string()).collect();
+    for dir in path_var
+        .split(PATH_LIST_SEPARATOR)
+        .filter(|dir| !dir.is_empty())
+    {
+        if let Ok(entries) = fs::read_dir(dir) {
+            names.extend(
+                entries
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.file_name().into_string().ok()),
+            );
+        }
+    }
+
+    let mut suggestions: Vec<(usize, String)> = names
+        .into_iter()
+        .filter(|candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| (1..=2).contains(distance) && *distance < name.chars().count())
+        .collect();
+    suggestions.sort();
+    suggestions
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pathext_lists_extensions_or_the_default() {
+        assert_eq!(pathext(Some(".EXE;;.Py;")), [".EXE", ".Py"]);
+        assert_eq!(pathext(None), [".COM", ".EXE", ".BAT", ".CMD"]);
+        assert!(pathext(Some("")).is_empty());
+    }
+
+    #[test]
+    fn only_windows_leaves_extensions_out_of_command_names() {
+        let extensions = command_extensions(Some(".PY"));
+        if cfg!(windows) {
+            assert_eq!(extensions, [".PY"]);
+        } else {
+            assert!(extensions.is_empty());
+        }
+    }
+
+    #[test]
+    fn executable_extensions_match_in_any_case() {
+        let extensions = pathext(None);
+        for name in ["tool.exe", "TOOL.EXE", "run.Bat", "dir/run.cmd"] {
+            assert!(has_extension_of(Path::new(name), &extensions), "{}", name);
+        }
+        for name in ["tool", "notes.txt", "tool.exe.txt", ".exe"] {
+            assert!(!has_extension_of(Path::new(name), &extensions), "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_without_an_extension_try_each_one() {
+        let extensions = pathext(Some(".EXE;.BAT"));
+        assert_eq!(
+            with_extensions(Path::new("bin/tool"), &extensions),
+            ["bin/tool", "bin/tool.EXE", "bin/tool.BAT"].map(PathBuf::from)
+        );
+        assert_eq!(
+            with_extensions(Path::new("bin/tool.cmd"), &extensions),
+            [PathBuf::from("bin/tool.cmd")]
+        );
+        assert_eq!(
+            with_extensions(Path::new("bin/tool"), &[]),
+            [PathBuf::from("bin/tool")]
+        );
+    }
+
+    #[test]
+    fn path_search_finds_files_by_pathext_in_path_order() {
+        let root = tempfile::tempdir().unwrap();
+        let (first, second) = (root.path().join("first"), root.path().join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        for file in ["first/tool.BAT", "second/tool.EXE", "second/tool.txt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        fs::create_dir(second.join("tool.CMD")).unwrap();
+
+        let path_var = format!("first{}second", PATH_LIST_SEPARATOR);
+        let extensions = pathext(Some(".EXE;.BAT;.CMD"));
+        assert_eq!(
+            path_candidates("tool", &path_var, &extensions, root.path()),
+            [first.join("tool.BAT"), second.join("tool.EXE")]
+        );
+        assert_eq!(
+            path_candidates("tool.txt", &path_var, &extensions, root.path()),
+            [second.join("tool.txt")]
+        );
+        assert!(path_candidates("tool", &path_var, &[], root.path()).is_empty());
+    }
+
+    #[test]
+    fn suggestions_are_builtins_and_path_commands_one_or_two_edits_away() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["ecco", "ech", "unrelated"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let path_var = dir.path().to_str().unwrap();
+        let builtins = ["echo", "exit", "cd"];
+        assert_eq!(
+            suggest_commands("ech", path_var, &builtins),
+            ["echo", "cd", "ecco"]
+        );
+        assert_eq!(suggest_commands("exti", path_var, &builtins), ["exit"]);
+        assert!(suggest_commands("zzzzzz", path_var, &builtins).is_empty());
+        assert!(suggest_commands("x", path_var, &builtins).is_empty());
+    }
+
+    #[test]
+    fn edit_distance_counts_insertions_deletions_and_substitutions() {
+        assert_eq!(edit_distance("echo", "echo"), 0);
+        assert_eq!(edit_distance("ecoh", "echo"), 2);
+        assert_eq!(edit_distance("eho", "echo"), 1);
+        assert_eq!(edit_distance("", "ls"), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_resolve_to_found_not_executable_or_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tool");
+        fs::write(&tool, "").unwrap();
+        let path_var = dir.path().to_str().unwrap();
+        let resolve = |name| resolve_command(name, path_var, &[], dir.path());
+
+        assert!(matches!(resolve("tool"), Resolution::NotExecutable(path) if path == tool));
+        assert!(matches!(resolve("./tool"), Resolution::NotExecutable(_)));
+        assert!(matches!(resolve("missing"), Resolution::NotFound));
+        assert!(matches!(resolve("./missing"), Resolution::NotFound));
+
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(resolve("tool"), Resolution::Found(path) if path == tool));
+    }
+}
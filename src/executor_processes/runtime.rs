@@ -0,0 +1,251 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! How the shell starts external commands and waits: blocking the thread
+//! running it, or, for `Shell::eval_async`, as futures on a tokio runtime.
+//! The interpreter and the builtins are async code written once for both;
+//! on the blocking runtime nothing they wait for is ever pending, and
+//! `block_on` drives them to the end.
+
+use crate::executor_processes::jobs::{self, Process};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::pin;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// How often a process waited for by polling is checked on.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where a shell waits for the commands it runs and for time to pass.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    /// On the thread running the shell, which blocks until they are done,
+    /// as for `eval` and the `bellos` program.
+    #[default]
+    Blocking,
+    /// On the tokio runtime polling the shell, which goes on with other
+    /// work meanwhile. Commands are started through `tokio::process`, their
+    /// pipes read and written asynchronously, and time kept by tokio's
+    /// timers.
+    #[cfg(feature = "async")]
+    Tokio,
+}
+
+/// How a command run in the foreground ended.
+pub enum Ended {
+    Exited(ExitStatus),
+    /// Stopped, as by Ctrl-Z, and left to become a job.
+    Stopped(Child),
+}
+
+impl Runtime {
+    /// Waits for `duration` to pass.
+    pub async fn sleep(self, duration: Duration) {
+        match self {
+            Runtime::Blocking => thread::sleep(duration),
+            #[cfg(feature = "async")]
+            Runtime::Tokio => tokio::time::sleep(duration).await,
+        }
+    }
+
+    /// Runs `command` in the foreground. On the blocking runtime it gets a
+    /// process group and, under job control, the terminal, and can be
+    /// stopped into a job. A shell on tokio never has job control, so its
+    /// commands only exit.
+    pub async fn run_foreground(self, mut command: Command) -> io::Result<Ended> {
+        match self {
+            Runtime::Blocking => {
+                jobs::set_process_group(&mut command, 0);
+                let mut child = command.spawn()?;
+                jobs::join_process_group(child.id(), 0);
+                Ok(match jobs::wait_foreground_child(&mut child)? {
+                    Some(status) => Ended::Exited(status),
+                    None => Ended::Stopped(child),
+                })
+            }
+            #[cfg(feature = "async")]
+            Runtime::Tokio => {
+                let mut child = tokio::process::Command::from(command).spawn()?;
+                child.wait().await.map(Ended::Exited)
+            }
+        }
+    }
+
+    /// Runs an external stage of a pipeline, with `input`, the output of
+    /// the stage before, as its standard input when there is one, and
+    /// returns its status and, when `capture`, its standard output. Under
+    /// job control the stages share one process group, that of the first,
+    /// which is left in `leader` unreaped so the group outlives it.
+    pub async fn run_stage(
+        self,
+        mut command: Command,
+        input: Option<&[u8]>,
+        capture: bool,
+        leader: &mut Option<Child>,
+    ) -> Result<(ExitStatus, Vec<u8>), String> {
+        // Otherwise the stage keeps the descriptors it was given.
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        if capture {
+            command.stdout(Stdio::piped());
+        }
+        match self {
+            Runtime::Blocking => run_stage_blocking(command, input, leader),
+            #[cfg(feature = "async")]
+            Runtime::Tokio => run_stage_tokio(command, input).await,
+        }
+    }
+
+    /// Waits for `process` to exit. On tokio it is checked on every
+    /// `POLL_INTERVAL`.
+    pub async fn wait(self, process: &mut Process) -> io::Result<ExitStatus> {
+        match self {
+            Runtime::Blocking => process.wait(),
+            #[cfg(feature = "async")]
+            Runtime::Tokio => loop {
+                if let Some(status) = process.try_wait()? {
+                    return Ok(status);
+                }
+                self.sleep(POLL_INTERVAL).await;
+            },
+        }
+    }
+
+    /// Waits for `process` to exit until `deadline`, checking on it every
+    /// `POLL_INTERVAL`. Returns `None` if it is still running then.
+    pub async fn wait_until(
+        self,
+        process: &mut Process,
+        deadline: Instant,
+    ) -> io::Result<Option<ExitStatus>> {
+        loop {
+            if let Some(status) = process.try_wait()? {
+                return Ok(Some(status));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.sleep(POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+}
+
+fn run_stage_blocking(
+    mut command: Command,
+    input: Option<&[u8]>,
+    leader: &mut Option<Child>,
+) -> Result<(ExitStatus, Vec<u8>), String> {
+    let pgid = leader.as_ref().map_or(0, Child::id);
+    jobs::set_process_group(&mut command, pgid);
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to spawn process: {}", e))?;
+    jobs::join_process_group(child.id(), pgid);
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input)
+            .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+    }
+
+    let pgid = if pgid == 0 { child.id() } else { pgid };
+    let result = jobs::in_foreground(pgid, || {
+        let mut output = Vec::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            stdout.read_to_end(&mut output)?;
+        }
+        let status = if leader.is_none() {
+            jobs::wait_unreaped(&mut child)?
+        } else {
+            child.wait()?
+        };
+        io::Result::Ok((status, output))
+    })
+    .map_err(|e| format!("Failed to wait for process: {}", e))?;
+    if leader.is_none() {
+        *leader = Some(child);
+    }
+    Ok(result)
+}
+
+/// Writes the input of a stage while reading its output, so neither side
+/// waits on a full pipe. A stage may exit without reading all its input.
+#[cfg(feature = "async")]
+async fn run_stage_tokio(
+    command: Command,
+    input: Option<&[u8]>,
+) -> Result<(ExitStatus, Vec<u8>), String> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut child = tokio::process::Command::from(command)
+        .spawn()
+        .map_err(|e| format!("Failed to spawn process: {}", e))?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let write = async move {
+        let (Some(input), Some(mut stdin)) = (input, stdin) else {
+            return Ok(());
+        };
+        match stdin.write_all(input).await {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        }
+    };
+    let read = async move {
+        let mut output = Vec::new();
+        if let Some(mut stdout) = stdout {
+            stdout.read_to_end(&mut output).await?;
+        }
+        io::Result::Ok(output)
+    };
+    let (written, output) = tokio::join!(write, read);
+    written.map_err(|e| format!("Failed to write to stdin: {}", e))?;
+    let output = output.map_err(|e| format!("Failed to wait for process: {}", e))?;
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("Failed to wait for process: {}", e))?;
+    Ok((status, output))
+}
+
+/// Wakes the thread blocked in `block_on`.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to the end on this thread, parking it while the future
+/// waits. This is how the synchronous API runs the interpreter, and how a
+/// forked copy of the shell runs its job.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
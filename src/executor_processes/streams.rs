@@ -0,0 +1,361 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The descriptors of a shell. Redirections, those `exec` makes permanent
+//! included, change what its builtins read and write and what the commands
+//! it starts inherit, but never the descriptors of the process, which
+//! other shells running in it share.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+#[cfg(unix)]
+use std::mem::ManuallyDrop;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// What a redirection makes a descriptor.
+#[derive(Debug, Clone)]
+pub enum Descriptor {
+    Open(Arc<File>),
+    /// Closed, as by `>&-`.
+    Closed,
+}
+
+/// The descriptors a shell has redirected. The others are those of the
+/// process.
+#[derive(Debug, Clone, Default)]
+pub struct Streams {
+    fds: BTreeMap<i32, Descriptor>,
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `fd` `descriptor`, or the process's own again for `None`, and
+    /// returns what it was.
+    pub fn set(&mut self, fd: i32, descriptor: Option<Descriptor>) -> Option<Descriptor> {
+        match descriptor {
+            Some(descriptor) => self.fds.insert(fd, descriptor),
+            None => self.fds.remove(&fd),
+        }
+    }
+
+    /// Whether the shell has redirected `fd` away from the process's own.
+    pub fn is_redirected(&self, fd: i32) -> bool {
+        self.fds.contains_key(&fd)
+    }
+
+    pub fn is_open(&self, fd: i32) -> bool {
+        match self.fds.get(&fd) {
+            Some(Descriptor::Open(_)) => true,
+            Some(Descriptor::Closed) => false,
+            None => process_fd_open(fd),
+        }
+    }
+
+    /// The file open on `fd`, for a redirection duplicating it.
+    pub fn file(&self, fd: i32) -> io::Result<Arc<File>> {
+        match self.fds.get(&fd) {
+            Some(Descriptor::Open(file)) => Ok(Arc::clone(file)),
+            Some(Descriptor::Closed) => Err(bad_descriptor()),
+            None => duplicate_process_fd(fd).map(Arc::new),
+        }
+    }
+
+    /// Where builtins write what goes to `fd`.
+    pub fn output(&self, fd: i32) -> Output {
+        match (self.fds.get(&fd), fd) {
+            (Some(Descriptor::Open(file)), _) => Output::File(Arc::clone(file)),
+            (Some(Descriptor::Closed), _) => Output::Closed,
+            (None, 1) => Output::Stdout,
+            (None, 2) => Output::Stderr,
+            (None, _) => self.file(fd).map_or(Output::Closed, Output::File),
+        }
+    }
+
+    /// Standard output.
+    pub fn out(&self) -> Output {
+        self.output(1)
+    }
+
+    /// Standard error.
+    pub fn err(&self) -> Output {
+        self.output(2)
+    }
+
+    /// Where builtins read what comes from `fd`.
+    pub fn input(&self, fd: i32) -> Input {
+        match (self.fds.get(&fd), fd) {
+            (Some(Descriptor::Open(file)), _) => Input::File(Arc::clone(file)),
+            (Some(Descriptor::Closed), _) => Input::Closed,
+            (None, 0) => Input::Stdin,
+            (None, _) => self.file(fd).map_or(Input::Closed, Input::File),
+        }
+    }
+
+    /// Gives `command` the shell's descriptors in place of the process's.
+    /// Standard streams closed in the shell are empty for the command.
+    pub fn attach(&self, command: &mut Command) {
+        // Output the command might write after is written first.
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        let stdio = |fd| match self.fds.get(&fd) {
+            Some(Descriptor::Open(file)) => {
+                Some(file.try_clone().map_or_else(|_| Stdio::null(), Stdio::from))
+            }
+            Some(Descriptor::Closed) => Some(Stdio::null()),
+            None => None,
+        };
+        if let Some(stdin) = stdio(0) {
+            command.stdin(stdin);
+        }
+        if let Some(stdout) = stdio(1) {
+            command.stdout(stdout);
+        }
+        if let Some(stderr) = stdio(2) {
+            command.stderr(stderr);
+        }
+        #[cfg(unix)]
+        self.attach_others(command);
+    }
+
+    /// Puts the descriptors past the standard three in place in the child.
+    /// Each is first copied above all of them, so putting one in place
+    /// never overwrites another still to be.
+    #[cfg(unix)]
+    fn attach_others(&self, command: &mut Command) {
+        let others: Vec<_> = self.fds.range(3..).collect();
+        let Some((&highest, _)) = others.last() else {
+            return;
+        };
+        let mut moves = Vec::new();
+        let mut closes = Vec::new();
+        for (&fd, descriptor) in others {
+            match descriptor {
+                Descriptor::Open(file) => {
+                    let copy = unsafe {
+                        libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, highest + 1)
+                    };
+                    if copy != -1 {
+                        moves.push((fd, unsafe { OwnedFd::from_raw_fd(copy) }));
+                    }
+                }
+                Descriptor::Closed => closes.push(fd),
+            }
+        }
+        unsafe {
+            command.pre_exec(move || {
+                for (fd, copy) in &moves {
+                    if libc::dup2(copy.as_raw_fd(), *fd) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                for fd in &closes {
+                    libc::close(*fd);
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// A descriptor builtins write to. Writing to a closed one fails.
+pub enum Output {
+    /// The process's standard output, through the standard library's buffer.
+    Stdout,
+    Stderr,
+    File(Arc<File>),
+    Closed,
+}
+
+impl Output {
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            Output::Stdout => io::stdout().is_terminal(),
+            Output::Stderr => io::stderr().is_terminal(),
+            Output::File(file) => file.is_terminal(),
+            Output::Closed => false,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            // The standard library quietly drops writes to a closed
+            // descriptor 1 or 2.
+            Output::Stdout if !process_fd_open(1) => Err(bad_descriptor()),
+            Output::Stderr if !process_fd_open(2) => Err(bad_descriptor()),
+            Output::Stdout => io::stdout().write(buffer),
+            Output::Stderr => io::stderr().write(buffer),
+            Output::File(file) => (&**file).write(buffer),
+            Output::Closed => Err(bad_descriptor()),
+        }
+    }
+
+    // Whole writes go to the standard streams under one lock, as from
+    // `println!`, so a line is written at once even when other processes
+    // write to the same descriptor.
+    fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout if process_fd_open(1) => io::stdout().write_all(buffer),
+            Output::Stderr if process_fd_open(2) => io::stderr().write_all(buffer),
+            Output::File(file) => (&**file).write_all(buffer),
+            _ => Err(bad_descriptor()),
+        }
+    }
+
+    fn write_fmt(&mut self, arguments: fmt::Arguments) -> io::Result<()> {
+        match self {
+            Output::Stdout if process_fd_open(1) => io::stdout().write_fmt(arguments),
+            Output::Stderr if process_fd_open(2) => io::stderr().write_fmt(arguments),
+            Output::File(file) => (&**file).write_fmt(arguments),
+            _ => Err(bad_descriptor()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => (&**file).flush(),
+            Output::Closed => Ok(()),
+        }
+    }
+}
+
+/// A descriptor builtins read from. It is read directly, without a buffer,
+/// so a builtin reading a line leaves the rest for the next command.
+pub enum Input {
+    /// The process's standard input.
+    Stdin,
+    File(Arc<File>),
+    Closed,
+}
+
+impl Read for Input {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            // Descriptor 0, which reading must not close.
+            #[cfg(unix)]
+            Input::Stdin => ManuallyDrop::new(unsafe { File::from_raw_fd(0) }).read(buffer),
+            #[cfg(not(unix))]
+            Input::Stdin => io::stdin().read(buffer),
+            Input::File(file) => (&**file).read(buffer),
+            Input::Closed => Err(bad_descriptor()),
+        }
+    }
+}
+
+fn bad_descriptor() -> io::Error {
+    #[cfg(unix)]
+    return io::Error::from_raw_os_error(libc::EBADF);
+    #[cfg(not(unix))]
+    io::Error::new(io::ErrorKind::InvalidInput, "Bad file descriptor")
+}
+
+/// Whether the process has `fd` open. Without Unix descriptors only the
+/// standard three are.
+fn process_fd_open(fd: i32) -> bool {
+    #[cfg(unix)]
+    return unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 };
+    #[cfg(not(unix))]
+    (0..=2).contains(&fd)
+}
+
+/// A close-on-exec copy of the process's `fd`.
+fn duplicate_process_fd(fd: i32) -> io::Result<File> {
+    #[cfg(unix)]
+    {
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+        if copy == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(copy) })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = fd;
+        Err(bad_descriptor())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+
+    fn scratch() -> Arc<File> {
+        Arc::new(tempfile::tempfile().unwrap())
+    }
+
+    fn contents(file: &File) -> String {
+        let mut file = file;
+        let mut text = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn redirected_descriptors_belong_to_the_streams_alone() {
+        let log = scratch();
+        let mut streams = Streams::new();
+        assert!(streams
+            .set(1, Some(Descriptor::Open(Arc::clone(&log))))
+            .is_none());
+        writeln!(streams.out(), "builtin").unwrap();
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo external; echo more >&2"]);
+        streams.set(2, Some(Descriptor::Open(streams.file(1).unwrap())));
+        streams.attach(&mut command);
+        assert!(command.status().unwrap().success());
+        assert_eq!(contents(&log), "builtin\nexternal\nmore\n");
+        // The process's own descriptor 1 is what it was.
+        assert!(Streams::new().is_open(1));
+        assert!(matches!(Streams::new().out(), Output::Stdout));
+    }
+
+    #[test]
+    fn other_descriptors_reach_commands_and_closed_ones_fail() {
+        let (three, four) = (scratch(), scratch());
+        let mut streams = Streams::new();
+        streams.set(3, Some(Descriptor::Open(Arc::clone(&four))));
+        streams.set(4, Some(Descriptor::Open(Arc::clone(&three))));
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo three >&4; echo four >&3"]);
+        streams.attach(&mut command);
+        assert!(command.status().unwrap().success());
+        assert_eq!(
+            (contents(&three), contents(&four)),
+            ("three\n".into(), "four\n".into())
+        );
+
+        streams.set(5, Some(Descriptor::Closed));
+        assert!(!streams.is_open(5));
+        assert!(streams.file(5).is_err());
+        let error = writeln!(streams.output(5), "lost").unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+        assert!(streams.input(5).read(&mut [0]).is_err());
+    }
+}
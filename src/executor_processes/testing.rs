@@ -0,0 +1,275 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::providers::{
+    FileInfo, FileKind, FileStatus, FsProvider, ReadSeek, Spawner, UserProvider,
+};
+use glob::{MatchOptions, Pattern};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// A filesystem held in memory. Directories exist as the parents of the
+/// files in them. Files are 644 until `chmod` changes them, and links and
+/// FIFOs are not supported. Clones share the same files, so one can be
+/// given to a shell and another kept to look at what it wrote.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+    modes: Arc<Mutex<BTreeMap<PathBuf, u32>>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates or replaces a file.
+    pub fn insert(&self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) {
+        let path = key(path.as_ref());
+        self.files.lock().unwrap().insert(path, content.into());
+    }
+
+    /// The content of a file, if there is one.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(&key(path.as_ref())).cloned()
+    }
+
+    /// The paths of all the files, in order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Paths without their `.` components, so `./a` and `a` are one file.
+fn key(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+impl FsProvider for MemoryFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        let content = self.get(path).ok_or_else(not_found)?;
+        Ok(Box::new(Cursor::new(content)))
+    }
+
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + Send>> {
+        let path = key(path);
+        let mut files = self.files.lock().unwrap();
+        if !append {
+            files.insert(path.clone(), Vec::new());
+        } else if !files.contains_key(&path) {
+            return Err(not_found());
+        }
+        Ok(Box::new(MemoryFile {
+            files: Arc::clone(&self.files),
+            path,
+        }))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.modes.lock().unwrap().remove(&key(path));
+        let mut files = self.files.lock().unwrap();
+        files.remove(&key(path)).map(|_| ()).ok_or_else(not_found)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileInfo> {
+        let path = key(path);
+        let files = self.files.lock().unwrap();
+        if let Some(content) = files.get(&path) {
+            return Ok(FileInfo {
+                is_file: true,
+                is_dir: false,
+                len: content.len() as u64,
+            });
+        }
+        let is_dir =
+            path.as_os_str().is_empty() || files.keys().any(|file| file.starts_with(&path));
+        if is_dir {
+            Ok(FileInfo {
+                is_file: false,
+                is_dir,
+                len: 0,
+            })
+        } else {
+            Err(not_found())
+        }
+    }
+
+    fn stat(&self, path: &Path, _follow: bool) -> io::Result<FileStatus> {
+        let info = self.metadata(path)?;
+        let default = if info.is_dir { 0o755 } else { 0o644 };
+        let mode = self.modes.lock().unwrap().get(&key(path)).copied();
+        Ok(FileStatus {
+            kind: if info.is_dir {
+                FileKind::Directory
+            } else {
+                FileKind::File
+            },
+            mode: mode.unwrap_or(default),
+            len: info.len,
+            modified: 0,
+        })
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.metadata(path)?;
+        self.modes.lock().unwrap().insert(key(path), mode);
+        Ok(())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path)?;
+        Ok(key(path))
+    }
+
+    fn glob(&self, pattern: &str, options: MatchOptions) -> Vec<String> {
+        let Ok(pattern) = Pattern::new(pattern.trim_start_matches("./")) else {
+            return Vec::new();
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|path| path.to_string_lossy().into_owned())
+            .filter(|path| pattern.matches_with(path, options))
+            .collect()
+    }
+}
+
+/// A file of a `MemoryFs` open for writing, its writes going straight in.
+struct MemoryFile {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+    path: PathBuf,
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let mut files = self.files.lock().unwrap();
+        files
+            .entry(self.path.clone())
+            .or_default()
+            .extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An external command a `RecordingSpawner` was asked to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    /// What the command was given on standard input, in a pipeline.
+    pub input: Option<Vec<u8>>,
+}
+
+/// The exit status and output of a program run by a `RecordingSpawner`.
+type Response = (i32, Vec<u8>);
+
+/// Records the external commands a shell runs instead of running them.
+/// Each succeeds with no output unless given a response with `respond`.
+/// Clones share the record and the responses.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingSpawner {
+    invocations: Arc<Mutex<Vec<Invocation>>>,
+    responses: Arc<Mutex<HashMap<String, Response>>>,
+}
+
+impl RecordingSpawner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes runs of `program` exit with `status` after writing `output`.
+    pub fn respond(&self, program: &str, status: i32, output: impl Into<Vec<u8>>) {
+        let mut responses = self.responses.lock().unwrap();
+        responses.insert(program.to_string(), (status, output.into()));
+    }
+
+    /// The commands run so far, in order.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+}
+
+impl Spawner for RecordingSpawner {
+    fn run(
+        &self,
+        command: &mut Command,
+        input: Option<&[u8]>,
+        capture: bool,
+    ) -> io::Result<(i32, Vec<u8>)> {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let (status, output) = self
+            .responses
+            .lock()
+            .unwrap()
+            .get(&program)
+            .cloned()
+            .unwrap_or_default();
+        self.invocations.lock().unwrap().push(Invocation {
+            program,
+            args,
+            input: input.map(<[u8]>::to_vec),
+        });
+        if capture {
+            return Ok((status, output));
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        Ok((status, Vec::new()))
+    }
+}
+
+/// Users known only by the home directories given with `insert`. Clones
+/// share the same users.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryUsers {
+    homes: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryUsers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `user`, whose home directory is `home`.
+    pub fn insert(&self, user: &str, home: &str) {
+        let mut homes = self.homes.lock().unwrap();
+        homes.insert(user.to_string(), home.to_string());
+    }
+}
+
+impl UserProvider for MemoryUsers {
+    fn home_directory(&self, user: &str) -> Option<String> {
+        self.homes.lock().unwrap().get(user).cloned()
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::providers::FsProvider;
+use crate::executor_processes::streams::Streams;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The size of the blocks `tail` reads backwards from the end of a file.
+const TAIL_BLOCK: u64 = 8192;
+
+/// `cat [file ...]`: copies files, or standard input for none or `-`, to
+/// standard output.
+pub fn cat(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let mut stdout = streams.out();
+    let mut status = 0;
+    for name in operands(args) {
+        let result =
+            open(fs, streams, name).and_then(|mut input| io::copy(&mut input, &mut stdout));
+        if let Err(e) = result {
+            let _ = writeln!(streams.err(), "cat: {}: {}", name, e);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `head [-n count] [file ...]`: prints the first lines of files, ten by
+/// default, with a `==> name <==` header before each when there are several.
+pub fn head(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let (count, files) = line_count("head", args)?;
+    let files = operands(files);
+    let mut stdout = streams.out();
+    let mut status = 0;
+    for (i, name) in files.iter().enumerate() {
+        if files.len() > 1 {
+            let separator = if i > 0 { "\n" } else { "" };
+            let _ = writeln!(stdout, "{}==> {} <==", separator, name);
+        }
+        let result = open(fs, streams, name).and_then(|input| {
+            let mut input = BufReader::new(input);
+            let mut line = Vec::new();
+            for _ in 0..count {
+                line.clear();
+                if input.read_until(b'\n', &mut line)? == 0 {
+                    break;
+                }
+                stdout.write_all(&line)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            let _ = writeln!(streams.err(), "head: {}: {}", name, e);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `tail [-n count] [file ...]`: prints the last lines of files, ten by
+/// default. Files are read backwards from the end, so only the lines
+/// printed are read, however large the file.
+pub fn tail(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let (count, files) = line_count("tail", args)?;
+    let files = operands(files);
+    let mut stdout = streams.out();
+    let mut status = 0;
+    for (i, name) in files.iter().enumerate() {
+        if files.len() > 1 {
+            let separator = if i > 0 { "\n" } else { "" };
+            let _ = writeln!(stdout, "{}==> {} <==", separator, name);
+        }
+        let result = if *name == "-" {
+            tail_stream(streams.input(0), count)
+        } else {
+            fs.open(Path::new(name))
+                .and_then(|file| tail_file(file, count))
+        };
+        match result.and_then(|lines| stdout.write_all(&lines)) {
+            Ok(()) => {}
+            Err(e) => {
+                let _ = writeln!(streams.err(), "tail: {}: {}", name, e);
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// The last `count` lines of a seekable file, read in blocks from its end.
+/// Pipes and other unseekable files are read through instead.
+fn tail_file(mut file: impl Read + Seek, count: usize) -> io::Result<Vec<u8>> {
+    let Ok(length) = file.seek(SeekFrom::End(0)) else {
+        return tail_stream(file, count);
+    };
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    // Blocks are read backwards and joined once the start is found, so
+    // each byte is scanned and copied only once.
+    let mut blocks = Vec::new();
+    let mut position = length;
+    let mut newlines = 0;
+    let mut start = 0;
+    'scan: while position > 0 {
+        let size = position.min(TAIL_BLOCK);
+        position -= size;
+        let mut block = vec![0; size as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut block)?;
+        // A newline ending the file does not start another line.
+        let end = block.len() - usize::from(blocks.is_empty() && block.last() == Some(&b'\n'));
+        for i in (0..end).rev().filter(|&i| block[i] == b'\n') {
+            newlines += 1;
+            if newlines == count {
+                start = i + 1;
+                blocks.push(block);
+                break 'scan;
+            }
+        }
+        blocks.push(block);
+    }
+    let first = blocks.pop().unwrap_or_default();
+    let mut tail = first[start..].to_vec();
+    for block in blocks.iter().rev() {
+        tail.extend_from_slice(block);
+    }
+    Ok(tail)
+}
+
+/// The last `count` lines of a stream, keeping only that many in memory.
+fn tail_stream(input: impl Read, count: usize) -> io::Result<Vec<u8>> {
+    let mut input = BufReader::new(input);
+    let mut lines = VecDeque::with_capacity(count);
+    loop {
+        let mut line = Vec::new();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        if count > 0 {
+            lines.push_back(line);
+        }
+    }
+    Ok(lines.into_iter().flatten().collect())
+}
+
+/// `wc [-l] [-w] [-m] [-c] [file ...]`: counts lines, words, characters
+/// and bytes, in that order whatever the order of the options. Characters
+/// differ from bytes for multibyte UTF-8 text. Without options it counts
+/// lines, words and bytes. Several files are followed by a total.
+pub fn wc(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let mut selected = [false; 4];
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    let index = "lwmc"
+                        .find(flag)
+                        .ok_or_else(|| format!("wc: -{}: invalid option", flag))?;
+                    selected[index] = true;
+                }
+            }
+            _ => files.push(arg.clone()),
+        }
+    }
+    if !selected.contains(&true) {
+        selected = [true, true, false, true];
+    }
+
+    let files = operands(&files);
+    let mut total = [0; 4];
+    let mut status = 0;
+    let print = |counts: &[usize; 4], name: &str| {
+        let mut line: Vec<String> = (0..4)
+            .filter(|&i| selected[i])
+            .map(|i| format!("{:>7}", counts[i]))
+            .collect();
+        if name != "-" {
+            line.push(name.to_string());
+        }
+        let _ = writeln!(streams.out(), "{}", line.join(" "));
+    };
+    for name in &files {
+        match open(fs, streams, name).and_then(count) {
+            Ok(counts) => {
+                for (sum, n) in total.iter_mut().zip(counts) {
+                    *sum += n;
+                }
+                print(&counts, name);
+            }
+            Err(e) => {
+                let _ = writeln!(streams.err(), "wc: {}: {}", name, e);
+                status = 1;
+            }
+        }
+    }
+    if files.len() > 1 {
+        print(&total, "total");
+    }
+    Ok(status)
+}
+
+/// `match [-v] [-i] [-o] pattern [file ...]`: prints the lines of files, or
+/// of standard input, that match the regular expression `pattern`, prefixed
+/// with the file name when there are several files. The status is 0 if a
+/// line was printed, 1 if none was and 2 on an error.
+pub fn match_lines(fs: &dyn FsProvider, streams: &Streams, args: &[String]) -> Result<i32, String> {
+    let usage = "Usage: match [-v] [-i] [-o] pattern [file ...]";
+    let (mut invert, mut ignore_case, mut only_matching) = (false, false, false);
+    let mut rest = args;
+    while let Some((flags, after)) = rest
+        .split_first()
+        .and_then(|(first, after)| Some((first.strip_prefix('-')?, after)))
+        .filter(|(flags, _)| !flags.is_empty())
+    {
+        for flag in flags.chars() {
+            match flag {
+                'v' => invert = true,
+                'i' => ignore_case = true,
+                'o' => only_matching = true,
+                _ => {
+                    let _ = writeln!(streams.err(), "match: -{}: invalid option\n{}", flag, usage);
+                    return Ok(2);
+                }
+            }
+        }
+        rest = after;
+    }
+    let Some((pattern, files)) = rest.split_first() else {
+        let _ = writeln!(streams.err(), "{}", usage);
+        return Ok(2);
+    };
+    let regex = match RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(e) => {
+            let _ = writeln!(
+                streams.err(),
+                "match: {}: invalid regular expression: {}",
+                pattern,
+                e
+            );
+            return Ok(2);
+        }
+    };
+
+    let filter = LineFilter {
+        regex,
+        invert,
+        only_matching,
+    };
+
+    let files = operands(files);
+    let mut stdout = streams.out();
+    let mut matched = false;
+    let mut failed = false;
+    for name in &files {
+        let prefix = if files.len() > 1 {
+            format!("{}:", name)
+        } else {
+            String::new()
+        };
+        match open(fs, streams, name).and_then(|input| filter.run(input, &prefix, &mut stdout)) {
+            Ok(found) => matched |= found,
+            Err(e) => {
+                let _ = writeln!(streams.err(), "match: {}: {}", name, e);
+                failed = true;
+            }
+        }
+    }
+    Ok(if failed {
+        2
+    } else if matched {
+        0
+    } else {
+        1
+    })
+}
+
+/// How `match` picks lines, and what of them it prints.
+struct LineFilter {
+    regex: Regex,
+    invert: bool,
+    only_matching: bool,
+}
+
+impl LineFilter {
+    /// Copies the lines of `input` the filter picks to `out`, each after
+    /// `prefix`, returning whether there were any.
+    fn run(&self, input: impl Read, prefix: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut input = BufReader::new(input);
+        let mut line = Vec::new();
+        let mut matched = false;
+        loop {
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(matched);
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            if self.regex.is_match(text) == self.invert {
+                continue;
+            }
+            matched = true;
+            if !self.only_matching {
+                out.write_all(prefix.as_bytes())?;
+                out.write_all(&line)?;
+                if line.last() != Some(&b'\n') {
+                    out.write_all(b"\n")?;
+                }
+            } else if !self.invert {
+                for found in self.regex.find_iter(text).filter(|found| !found.is_empty()) {
+                    writeln!(out, "{}{}", prefix, found.as_str())?;
+                }
+            }
+        }
+    }
+}
+
+/// Lines, words, characters and bytes, in the order of `wc`'s `-l`, `-w`,
+/// `-m` and `-c`. Characters are those of UTF-8: every byte but the
+/// continuation bytes of multibyte sequences starts one.
+fn count(input: impl Read) -> io::Result<[usize; 4]> {
+    let mut input = BufReader::new(input);
+    let mut counts = [0; 4];
+    let mut in_word = false;
+    loop {
+        let buffer = input.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        for &byte in buffer {
+            counts[0] += usize::from(byte == b'\n');
+            // Continuation bytes do not start a character.
+            counts[2] += usize::from(byte & 0xc0 != 0x80);
+            counts[3] += 1;
+            let space = byte.is_ascii_whitespace();
+            counts[1] += usize::from(in_word && space);
+            in_word = !space;
+        }
+        let length = buffer.len();
+        input.consume(length);
+    }
+    counts[1] += usize::from(in_word);
+    Ok(counts)
+}
+
+/// Parses `-n count` or `-count` before the files of `head` and `tail`.
+fn line_count<'a>(name: &str, args: &'a [String]) -> Result<(usize, &'a [String]), String> {
+    let invalid = |count: &str| format!("{}: {}: invalid number of lines", name, count);
+    match args {
+        [flag, count, files @ ..] if flag == "-n" => {
+            Ok((count.parse().map_err(|_| invalid(count))?, files))
+        }
+        [flag, files @ ..] if flag.len() > 1 && flag.starts_with('-') => {
+            let count = flag.strip_prefix("-n").unwrap_or(&flag[1..]);
+            Ok((count.parse().map_err(|_| invalid(count))?, files))
+        }
+        _ => Ok((10, args)),
+    }
+}
+
+/// The files to read, standard input standing in for none.
+fn operands(files: &[String]) -> Vec<&str> {
+    if files.is_empty() {
+        vec!["-"]
+    } else {
+        files.iter().map(String::as_str).collect()
+    }
+}
+
+/// Opens a file in `fs`, or standard input for `-`.
+fn open(fs: &dyn FsProvider, streams: &Streams, name: &str) -> io::Result<Box<dyn Read>> {
+    if name == "-" {
+        Ok(Box::new(streams.input(0)))
+    } else {
+        Ok(Box::new(fs.open(Path::new(name))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor_processes::testing::MemoryFs;
+    use std::io::Cursor;
+
+    /// Numbered lines, `1\n` to `count\n`.
+    fn numbered(count: usize) -> Vec<u8> {
+        (1..=count)
+            .map(|i| format!("{}\n", i))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn tail_reads_back_across_blocks() {
+        // Long enough that the last lines span several blocks.
+        let text = numbered(10_000);
+        assert!(text.len() as u64 > 3 * TAIL_BLOCK);
+        let tail = tail_file(Cursor::new(text.clone()), 3000).unwrap();
+        assert_eq!(tail, &text[text.len() - tail.len()..]);
+        assert!(tail.starts_with(b"7001\n"));
+        for count in [1, 1000, 1800, 9999] {
+            assert_eq!(
+                tail_file(Cursor::new(text.clone()), count).unwrap(),
+                tail_stream(&text[..], count).unwrap()
+            );
+        }
+        assert_eq!(tail_file(Cursor::new(text.clone()), 20_000).unwrap(), text);
+        assert_eq!(tail_file(Cursor::new(text), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn tail_of_a_file_and_a_stream_agree() {
+        for text in [&b""[..], b"a", b"a\n", b"a\nb", b"a\n\nb\n\n", b"\n\n\n"] {
+            for count in 0..5 {
+                assert_eq!(
+                    tail_file(Cursor::new(text), count).unwrap(),
+                    tail_stream(text, count).unwrap(),
+                    "{:?} {}",
+                    String::from_utf8_lossy(text),
+                    count
+                );
+            }
+        }
+        assert_eq!(tail_stream(&b"a\nb\nc"[..], 2).unwrap(), b"b\nc");
+    }
+
+    #[test]
+    fn wc_counts_characters_apart_from_bytes() {
+        // Two lines, four words, "é" and "€" taking two and three bytes.
+        let text = "caf\u{e9} costs\n3 \u{20ac}\n";
+        assert_eq!(count(text.as_bytes()).unwrap(), [2, 4, 15, 18]);
+        assert_eq!(count(&b"  no newline  "[..]).unwrap(), [0, 2, 14, 14]);
+        assert_eq!(count(&b""[..]).unwrap(), [0, 0, 0, 0]);
+    }
+
+    const FIXTURE: &str = "Error: disk full\nwarning: low memory\nerror 42 and error 7\nall good";
+
+    /// The lines `match` prints for `flags` and `pattern` over the fixture.
+    fn filtered(flags: &str, pattern: &str) -> (String, bool) {
+        let filter = LineFilter {
+            regex: RegexBuilder::new(pattern)
+                .case_insensitive(flags.contains('i'))
+                .build()
+                .unwrap(),
+            invert: flags.contains('v'),
+            only_matching: flags.contains('o'),
+        };
+        let mut out = Vec::new();
+        let matched = filter.run(FIXTURE.as_bytes(), "", &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), matched)
+    }
+
+    #[test]
+    fn match_flags_select_and_trim_lines() {
+        let cases = [
+            ("", "error", "error 42 and error 7\n"),
+            ("i", "error", "Error: disk full\nerror 42 and error 7\n"),
+            (
+                "v",
+                "error",
+                "Error: disk full\nwarning: low memory\nall good\n",
+            ),
+            ("vi", "error", "warning: low memory\nall good\n"),
+            ("o", "[0-9]+", "42\n7\n"),
+            ("oi", "^e[a-z]+", "Error\nerror\n"),
+            ("ov", "error", ""),
+            ("", "^$", ""),
+        ];
+        for (flags, pattern, expected) in cases {
+            let (out, matched) = filtered(flags, pattern);
+            assert_eq!(out, expected, "-{} {}", flags, pattern);
+            assert_eq!(
+                matched,
+                !flags.is_empty() || pattern != "^$",
+                "-{} {}",
+                flags,
+                pattern
+            );
+        }
+        let mut out = Vec::new();
+        let filter = LineFilter {
+            regex: Regex::new("memory").unwrap(),
+            invert: false,
+            only_matching: false,
+        };
+        filter.run(FIXTURE.as_bytes(), "log:", &mut out).unwrap();
+        assert_eq!(out, b"log:warning: low memory\n");
+    }
+
+    #[test]
+    fn match_status_tells_matches_from_errors() {
+        let fs = MemoryFs::new();
+        fs.insert("/fixture", FIXTURE);
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(
+            match_lines(&fs, &Streams::new(), &args(&["-v", ".", "/fixture"])),
+            Ok(1)
+        );
+        assert_eq!(
+            match_lines(&fs, &Streams::new(), &args(&["^nothing", "/fixture"])),
+            Ok(1)
+        );
+        assert_eq!(
+            match_lines(&fs, &Streams::new(), &args(&["(unclosed", "/fixture"])),
+            Ok(2)
+        );
+        assert_eq!(
+            match_lines(&fs, &Streams::new(), &args(&["-x", "a", "/fixture"])),
+            Ok(2)
+        );
+        assert_eq!(match_lines(&fs, &Streams::new(), &args(&["-i"])), Ok(2));
+        assert_eq!(
+            match_lines(&fs, &Streams::new(), &args(&["a", "/missing"])),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn line_counts_come_before_files() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let files = args(&["a", "b"]);
+        assert_eq!(line_count("head", &files), Ok((10, &files[..])));
+        let with_flag = args(&["-n", "3", "a"]);
+        assert_eq!(line_count("head", &with_flag), Ok((3, &with_flag[2..])));
+        let joined = args(&["-n3", "a"]);
+        assert_eq!(line_count("tail", &joined), Ok((3, &joined[1..])));
+        let short = args(&["-4"]);
+        assert_eq!(line_count("tail", &short), Ok((4, &short[1..])));
+        assert_eq!(
+            line_count("head", &args(&["-n", "x"])),
+            Err("head: x: invalid number of lines".to_string())
+        );
+    }
+}
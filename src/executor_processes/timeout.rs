@@ -0,0 +1,286 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::jobs::{exit_code, fork_subshell, Process};
+#[cfg(unix)]
+use crate::executor_processes::jobs::{foreground, SIGCONT, SIGKILL, SIGTERM};
+use crate::executor_processes::processes::Processes;
+use crate::executor_processes::runtime::Runtime;
+#[cfg(unix)]
+use crate::executor_processes::runtime::POLL_INTERVAL;
+use crate::interpreter_logic::interpreter::Interpreter;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// The status of a command stopped for running out of time.
+const TIMED_OUT: i32 = 124;
+
+/// Parses a duration like `10`, `3.5s`, `2m`, `1h` or `1d`, in seconds
+/// without a suffix.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("{}: invalid duration", text);
+    let (number, unit) = match text.strip_suffix(['s', 'm', 'h', 'd']) {
+        Some(number) => (number, &text[number.len()..]),
+        None => (text, "s"),
+    };
+    let seconds: f64 = number
+        .parse()
+        .ok()
+        .filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)
+        .ok_or_else(invalid)?;
+    let scale = match unit {
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => 1.0,
+    };
+    Duration::try_from_secs_f64(seconds * scale).map_err(|_| invalid())
+}
+
+/// The limits of a `timeout` command line and the command they bound.
+pub struct Limits<'a> {
+    pub duration: Duration,
+    /// How long after SIGTERM to send SIGKILL, if at all.
+    pub grace: Option<Duration>,
+    pub name: &'a str,
+    pub args: &'a [String],
+}
+
+/// Parses the arguments of `timeout [-k grace] duration command [args ...]`.
+pub fn parse_args(args: &[String]) -> Result<Limits<'_>, String> {
+    let usage = "Usage: timeout [-k grace] duration command [args ...]";
+    let (grace, rest) = match args {
+        [flag, grace, rest @ ..] if flag == "-k" => (
+            Some(parse_duration(grace).map_err(|e| format!("timeout: {}", e))?),
+            rest,
+        ),
+        _ => (None, args),
+    };
+    let [duration, name, args @ ..] = rest else {
+        return Err(usage.to_string());
+    };
+    Ok(Limits {
+        duration: parse_duration(duration).map_err(|e| format!("timeout: {}", e))?,
+        grace,
+        name,
+        args,
+    })
+}
+
+/// `timeout [-k grace] duration command [args ...]`: runs an external
+/// command in a process group of its own and, if it is still running after
+/// `duration`, sends the group SIGTERM, then SIGKILL once `grace` has
+/// passed too. The status is 124 if the command timed out, otherwise its
+/// own. A zero duration never times out. Functions are run by the shell,
+/// through `run_forked`. Without Unix signals the command is killed outright
+/// when its time runs out. The command is checked on while it runs, with
+/// the timers of the runtime the shell waits on.
+pub async fn run(
+    processes: &mut Processes,
+    interpreter: &Interpreter,
+    args: &[String],
+) -> Result<i32, String> {
+    let limits = parse_args(args)?;
+    let mut command = match processes.command(interpreter, limits.name) {
+        Ok(command) => command,
+        Err(status) => return Ok(status),
+    };
+    command.args(limits.args);
+    // A spawner runs the command to the end; it has no way to stop it.
+    if let Some(result) = processes.run_with_spawner(&mut command, None, false) {
+        return result.map(|(status, _)| status);
+    }
+    // Signalling a group of its own reaches everything the command starts,
+    // like the stages of a pipeline it runs.
+    #[cfg(unix)]
+    unsafe {
+        command.pre_exec(|| {
+            libc::setpgid(0, 0);
+            libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            libc::signal(libc::SIGTTIN, libc::SIG_DFL);
+            libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+    let child = command
+        .spawn()
+        .map_err(|e| format!("timeout: {}: {}", limits.name, e))?;
+    enforce(&limits, processes.runtime, Process::from(child)).await
+}
+
+/// Runs `body` in a copy of the shell forked into a process group of its
+/// own, within the limits, as `timeout` does for functions. The copy exits
+/// with the status `body` returns.
+pub async fn run_forked(
+    limits: &Limits<'_>,
+    runtime: Runtime,
+    body: impl FnOnce() -> i32,
+) -> Result<i32, String> {
+    let pid = fork_subshell(|| {
+        #[cfg(unix)]
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+        body()
+    })
+    .map_err(|e| format!("timeout: {}: {}", limits.name, e))?;
+    enforce(limits, runtime, Process::Forked(pid)).await
+}
+
+/// Waits for `process` within the limits, signalling its group when they
+/// run out.
+#[cfg(unix)]
+async fn enforce(
+    limits: &Limits<'_>,
+    runtime: Runtime,
+    mut process: Process,
+) -> Result<i32, String> {
+    let pgid = process.id();
+    // Set from the shell's side too, so the group exists before it is
+    // signalled, whichever of the two runs first.
+    unsafe { libc::setpgid(pgid as libc::pid_t, pgid as libc::pid_t) };
+
+    let duration = limits.duration;
+    let deadline = (!duration.is_zero()).then(|| Instant::now() + duration);
+    let _terminal = foreground(pgid);
+    let bounded = async {
+        if let Some(status) = wait_until(runtime, &mut process, deadline).await? {
+            return Ok(exit_code(status));
+        }
+        signal_group(pgid, SIGTERM);
+        let Some(grace) = limits.grace else {
+            runtime.wait(&mut process).await?;
+            return Ok(TIMED_OUT);
+        };
+        let deadline = Instant::now() + grace;
+        let exited = runtime.wait_until(&mut process, deadline).await?.is_some();
+        // The rest of the group, like the commands a function runs, has the
+        // same grace, though the command itself may exit on SIGTERM.
+        while exited && group_exists(pgid) && Instant::now() < deadline {
+            runtime.sleep(POLL_INTERVAL).await;
+        }
+        if group_exists(pgid) {
+            signal_group(pgid, SIGKILL);
+        }
+        if !exited {
+            runtime.wait(&mut process).await?;
+        }
+        io::Result::Ok(TIMED_OUT)
+    };
+    bounded
+        .await
+        .map_err(|e| format!("timeout: {}: {}", limits.name, e))
+}
+
+/// Waits for `process` within the limits and kills it when they run out,
+/// as there is no asking it to stop first.
+#[cfg(not(unix))]
+async fn enforce(
+    limits: &Limits<'_>,
+    runtime: Runtime,
+    mut process: Process,
+) -> Result<i32, String> {
+    let duration = limits.duration;
+    let deadline = (!duration.is_zero()).then(|| Instant::now() + duration);
+    let bounded = async {
+        if let Some(status) = wait_until(runtime, &mut process, deadline).await? {
+            return Ok(exit_code(status));
+        }
+        process.kill()?;
+        runtime.wait(&mut process).await?;
+        io::Result::Ok(TIMED_OUT)
+    };
+    bounded
+        .await
+        .map_err(|e| format!("timeout: {}: {}", limits.name, e))
+}
+
+/// Waits for `process` to exit until `deadline`, or for as long as it
+/// takes without one. Returns `None` if it is still running at the deadline.
+async fn wait_until(
+    runtime: Runtime,
+    process: &mut Process,
+    deadline: Option<Instant>,
+) -> io::Result<Option<ExitStatus>> {
+    match deadline {
+        Some(deadline) => runtime.wait_until(process, deadline).await,
+        None => runtime.wait(process).await.map(Some),
+    }
+}
+
+/// Whether any process is left in the group `pgid`.
+#[cfg(unix)]
+fn group_exists(pgid: u32) -> bool {
+    unsafe { libc::killpg(pgid as libc::pid_t, 0) == 0 }
+}
+
+/// Sends `signal` to the process group `pgid`, continuing it in case it
+/// was stopped.
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: i32) {
+    let pgid = pgid as libc::pid_t;
+    unsafe {
+        libc::killpg(pgid, signal);
+        libc::killpg(pgid, SIGCONT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_an_optional_unit() {
+        let cases = [
+            ("10", 10.0),
+            ("3.5s", 3.5),
+            ("2m", 120.0),
+            ("1h", 3600.0),
+            ("0.5d", 43200.0),
+            ("0", 0.0),
+        ];
+        for (text, seconds) in cases {
+            assert_eq!(parse_duration(text), Ok(Duration::from_secs_f64(seconds)));
+        }
+        for text in ["", "s", "-1", "1x", "inf", "NaN", "1e400"] {
+            assert_eq!(
+                parse_duration(text),
+                Err(format!("{}: invalid duration", text))
+            );
+        }
+    }
+
+    #[test]
+    fn arguments_give_the_limits_and_the_command() {
+        let args: Vec<String> = ["-k", "2", "1m", "f", "a", "b"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let limits = parse_args(&args).unwrap();
+        assert_eq!(limits.grace, Some(Duration::from_secs(2)));
+        assert_eq!(limits.duration, Duration::from_secs(60));
+        assert_eq!(limits.name, "f");
+        assert_eq!(limits.args, ["a", "b"]);
+
+        let limits = parse_args(&args[2..4]).unwrap();
+        assert_eq!((limits.grace, limits.name), (None, "f"));
+        assert!(limits.args.is_empty());
+        assert!(parse_args(&args[2..3]).is_err());
+        assert!(parse_args(&args[..2]).is_err());
+    }
+}
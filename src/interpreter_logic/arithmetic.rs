@@ -0,0 +1,679 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// The result of an arithmetic expression, along with the variables it
+/// assigned, in order, for the caller to store.
+pub struct Evaluation {
+    pub value: i64,
+    pub assignments: Vec<(String, i64)>,
+}
+
+/// Evaluates an integer arithmetic expression, as written inside `$(( ))`
+/// or `(( ))`. `+`, `-`, `*`, `/` and `%` work on 64-bit integers, and the
+/// comparisons `<`, `<=`, `>`, `>=`, `==` and `!=` and the logical `&&`,
+/// `||` and `!` give 1 or 0. Operators have C's precedence, which
+/// parentheses override, and `-` and `+` also prefix operands. `&&` and `||`
+/// skip their right operand once the left one decides the result. Overflow
+/// is an error rather than wrapping around.
+///
+/// Variables are read through `lookup`, with or without a `$`, and unset or
+/// empty ones count as 0. `=`, `+=`, `-=`, `*=`, `/=` and `%=` assign to
+/// them; later reads in the same expression see the new values.
+pub fn evaluate(
+    expression: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Evaluation, String> {
+    let tokens = tokenize(expression)?;
+    let mut parser = ArithmeticParser {
+        tokens: &tokens,
+        position: 0,
+        lookup,
+        assignments: Vec::new(),
+        operands: Vec::new(),
+        operators: Vec::new(),
+        depth: 0,
+        skipping: 0,
+    };
+    let value = if tokens.is_empty() {
+        0
+    } else {
+        parser.expression()?
+    };
+    match tokens.get(parser.position) {
+        Some(token) => Err(format!("syntax error: unexpected {}", token)),
+        None => Ok(Evaluation {
+            value,
+            assignments: parser.assignments,
+        }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithmeticToken {
+    /// A number as written, parsed once any sign before it is known.
+    Number(String),
+    Name(String),
+    Operator(&'static str),
+    /// `=`, or the operator of a compound assignment like `+=`.
+    Assign(char),
+}
+
+impl std::fmt::Display for ArithmeticToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithmeticToken::Number(n) | ArithmeticToken::Name(n) => write!(f, "{}", n),
+            ArithmeticToken::Operator(op) => write!(f, "'{}'", op),
+            ArithmeticToken::Assign('=') => write!(f, "'='"),
+            ArithmeticToken::Assign(op) => write!(f, "'{}='", op),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<ArithmeticToken>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let word_end = |text: &str| {
+            text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(text.len())
+        };
+        if c.is_ascii_digit() {
+            let end = word_end(rest);
+            tokens.push(ArithmeticToken::Number(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let name = rest.strip_prefix('$').unwrap_or(rest);
+            let end = word_end(name);
+            if end == 0 {
+                return Err("syntax error: variable name expected after '$'".to_string());
+            }
+            tokens.push(ArithmeticToken::Name(name[..end].to_string()));
+            rest = &name[end..];
+        } else if let Some(op) = TWO_CHARACTER_OPERATORS
+            .iter()
+            .find(|op| rest.starts_with(*op))
+        {
+            tokens.push(ArithmeticToken::Operator(op));
+            rest = &rest[op.len()..];
+        } else if c == '=' {
+            tokens.push(ArithmeticToken::Assign('='));
+            rest = &rest[1..];
+        } else if let Some(op) = ["(", ")", "<", ">", "!"]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+        {
+            tokens.push(ArithmeticToken::Operator(op));
+            rest = &rest[1..];
+        } else if let Some(op) = ["+", "-", "*", "/", "%"]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+        {
+            match rest[1..].strip_prefix('=') {
+                Some(after) => {
+                    tokens.push(ArithmeticToken::Assign(c));
+                    rest = after;
+                }
+                None => {
+                    tokens.push(ArithmeticToken::Operator(op));
+                    rest = &rest[1..];
+                }
+            }
+        } else {
+            return Err(format!("syntax error: invalid arithmetic operator '{}'", c));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The operators of two characters, matched before those of one so `<=` is
+/// not read as `<` and `=`.
+const TWO_CHARACTER_OPERATORS: [&str; 6] = ["<=", ">=", "==", "!=", "&&", "||"];
+
+/// How deeply parentheses may nest in an expression.
+const MAX_NESTING: usize = 1000;
+
+/// An operator waiting on the stack for its right operand.
+enum Pending {
+    /// A `(` not yet closed.
+    Open,
+    /// A prefix `+`, `-` or `!`.
+    Unary(&'static str),
+    Binary(&'static str),
+    /// `&&` or `||`, and whether its left operand already decided the
+    /// result, so its right one is evaluated only for its syntax.
+    Logical(&'static str, bool),
+    /// An assignment to a variable: `=` or the operator of a compound one.
+    Assign(String, char),
+}
+
+impl Pending {
+    /// How tightly the operator binds, as in C, `None` for `(`, which only
+    /// its `)` resolves.
+    fn precedence(&self) -> Option<u8> {
+        match self {
+            Pending::Open => None,
+            Pending::Assign(..) => Some(0),
+            Pending::Logical("||", _) => Some(1),
+            Pending::Logical(..) => Some(2),
+            Pending::Binary("==" | "!=") => Some(3),
+            Pending::Binary("<" | "<=" | ">" | ">=") => Some(4),
+            Pending::Binary("+" | "-") => Some(5),
+            Pending::Binary(_) => Some(6),
+            Pending::Unary(_) => Some(7),
+        }
+    }
+}
+
+/// An operator precedence evaluation over the tokens. Operands and pending
+/// operators are kept on stacks of their own, so however deeply an
+/// expression nests, the evaluation does not recurse.
+struct ArithmeticParser<'a> {
+    tokens: &'a [ArithmeticToken],
+    position: usize,
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+    assignments: Vec<(String, i64)>,
+    operands: Vec<i64>,
+    operators: Vec<Pending>,
+    /// The number of `(` on the operator stack.
+    depth: usize,
+    /// The number of `&&` and `||` on the operator stack whose right operand
+    /// is skipped. While there are any, nothing is assigned and errors in
+    /// the skipped operand are ignored, as its value is never used.
+    skipping: usize,
+}
+
+impl ArithmeticParser<'_> {
+    /// Evaluates the tokens from the current position up to the first one
+    /// that cannot continue the expression, which is left for the caller.
+    /// Assignments group from the right, so `a = b = 3` sets both, and are
+    /// made as soon as their value is known, so later reads see them.
+    fn expression(&mut self) -> Result<i64, String> {
+        loop {
+            self.operand()?;
+            // Operators after the operand, and the `)` closing groups.
+            loop {
+                match self.tokens.get(self.position) {
+                    Some(ArithmeticToken::Operator(")")) if self.depth > 0 => {
+                        self.position += 1;
+                        self.reduce_while(|_| true)?;
+                        self.operators.pop(); // The `(`
+                        self.depth -= 1;
+                    }
+                    Some(ArithmeticToken::Operator(op @ ("&&" | "||"))) => {
+                        self.position += 1;
+                        let precedence = Pending::Logical(op, false).precedence();
+                        self.reduce_while(|top| top >= precedence)?;
+                        let left = self.operands.last().copied().unwrap_or(0);
+                        let decided = (*op == "&&") == (left == 0);
+                        if decided {
+                            self.skipping += 1;
+                        }
+                        self.operators.push(Pending::Logical(op, decided));
+                        break;
+                    }
+                    Some(ArithmeticToken::Operator(op)) if !matches!(*op, "(" | ")" | "!") => {
+                        self.position += 1;
+                        let pending = Pending::Binary(op);
+                        let precedence = pending.precedence();
+                        // Binary operators group from the left.
+                        self.reduce_while(|top| top >= precedence)?;
+                        self.operators.push(pending);
+                        break;
+                    }
+                    _ => {
+                        if self.depth > 0 {
+                            return Err("syntax error: missing ')'".to_string());
+                        }
+                        self.reduce_while(|_| true)?;
+                        return Ok(self.operands.pop().unwrap_or(0));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads one operand, pushing the prefix operators, assignments and `(`
+    /// before it.
+    fn operand(&mut self) -> Result<(), String> {
+        loop {
+            let token = self.tokens.get(self.position);
+            let next = self.tokens.get(self.position + 1);
+            match (token, next) {
+                // Only a whole expression can be an assignment, not the
+                // operand of another operator.
+                (Some(ArithmeticToken::Name(name)), Some(ArithmeticToken::Assign(op)))
+                    if matches!(
+                        self.operators.last(),
+                        None | Some(Pending::Open | Pending::Assign(..))
+                    ) =>
+                {
+                    self.position += 2;
+                    self.operators.push(Pending::Assign(name.clone(), *op));
+                }
+                // Negating the literal itself lets the most negative number
+                // be written, though its magnitude does not fit.
+                (Some(ArithmeticToken::Operator("-")), Some(ArithmeticToken::Number(n))) => {
+                    self.position += 2;
+                    self.operands.push(parse_number(&format!("-{}", n))?);
+                    return Ok(());
+                }
+                (Some(ArithmeticToken::Operator(op @ ("+" | "-" | "!"))), _) => {
+                    self.position += 1;
+                    self.operators.push(Pending::Unary(op));
+                }
+                (Some(ArithmeticToken::Operator("(")), _) => {
+                    if self.depth == MAX_NESTING {
+                        return Err(format!("parentheses nested more than {} deep", MAX_NESTING));
+                    }
+                    self.position += 1;
+                    self.depth += 1;
+                    self.operators.push(Pending::Open);
+                }
+                (Some(ArithmeticToken::Number(n)), _) => {
+                    self.position += 1;
+                    self.operands.push(parse_number(n)?);
+                    return Ok(());
+                }
+                (Some(ArithmeticToken::Name(name)), _) => {
+                    self.position += 1;
+                    let value = match self.variable(name) {
+                        Err(_) if self.skipping > 0 => 0,
+                        value => value?,
+                    };
+                    self.operands.push(value);
+                    return Ok(());
+                }
+                (Some(token), _) => {
+                    return Err(format!("syntax error: operand expected, found {}", token))
+                }
+                (None, _) => return Err("syntax error: operand expected".to_string()),
+            }
+        }
+    }
+
+    /// Applies the pending operators on top of the stack for as long as
+    /// `applies` accepts their precedence, stopping at any `(`.
+    fn reduce_while(&mut self, applies: impl Fn(Option<u8>) -> bool) -> Result<(), String> {
+        while let Some(top) = self.operators.last() {
+            let precedence = top.precedence();
+            if precedence.is_none() || !applies(precedence) {
+                break;
+            }
+            let Some(pending) = self.operators.pop() else {
+                break;
+            };
+            let right = self.operands.pop().unwrap_or(0);
+            let value = match pending {
+                Pending::Unary("-") => right.checked_neg().ok_or_else(overflow),
+                Pending::Unary("!") => Ok((right == 0) as i64),
+                Pending::Unary(_) => Ok(right),
+                Pending::Binary(op) => {
+                    let left = self.operands.pop().unwrap_or(0);
+                    apply(left, op, right)
+                }
+                Pending::Logical(_, true) => {
+                    self.skipping -= 1;
+                    let left = self.operands.pop().unwrap_or(0);
+                    Ok((left != 0) as i64)
+                }
+                Pending::Logical(_, false) => {
+                    self.operands.pop();
+                    Ok((right != 0) as i64)
+                }
+                Pending::Assign(name, op) => {
+                    let value = match op {
+                        '=' => Ok(right),
+                        op => self
+                            .variable(&name)
+                            .and_then(|current| apply(current, &op.to_string(), right)),
+                    };
+                    if self.skipping == 0 {
+                        if let Ok(value) = value {
+                            self.assignments.push((name, value));
+                        }
+                    }
+                    value
+                }
+                Pending::Open => unreachable!(),
+            };
+            let value = match value {
+                Ok(value) => value,
+                Err(_) if self.skipping > 0 => 0,
+                Err(e) => return Err(e),
+            };
+            self.operands.push(value);
+        }
+        Ok(())
+    }
+
+    /// The value of a variable, as assigned earlier in the expression or
+    /// otherwise as looked up.
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        if let Some((_, value)) = self.assignments.iter().rev().find(|(n, _)| n == name) {
+            return Ok(*value);
+        }
+        match (self.lookup)(name) {
+            Some(value) if !value.trim().is_empty() => parse_number(&value),
+            _ => Ok(0),
+        }
+    }
+}
+
+/// The expression inside `substitution` if it is a `$((...))` arithmetic
+/// expansion. It is one only when the parenthesis opened by the second `(`
+/// closes right before the final `)`, so `$((cd /tmp) && pwd)` stays a
+/// command substitution running a subshell.
+pub fn expansion_body(substitution: &str) -> Option<&str> {
+    let inner = substitution.strip_prefix("$(")?.strip_suffix(')')?;
+    if !inner.starts_with('(') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (i == inner.len() - 1).then(|| &inner[1..i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn apply(left: i64, op: &str, right: i64) -> Result<i64, String> {
+    let result = match op {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" if right == 0 => return Err("Division by zero".to_string()),
+        "/" => left.checked_div(right),
+        "%" if right == 0 => return Err("Modulo by zero".to_string()),
+        "%" => left.checked_rem(right),
+        "<" => Some((left < right) as i64),
+        "<=" => Some((left <= right) as i64),
+        ">" => Some((left > right) as i64),
+        ">=" => Some((left >= right) as i64),
+        "==" => Some((left == right) as i64),
+        _ => Some((left != right) as i64),
+    };
+    result.ok_or_else(overflow)
+}
+
+/// Parses a number as arithmetic reads it, from a literal or a variable's
+/// value, allowing surrounding whitespace and a sign. `0x` starts a
+/// hexadecimal number, and `0o` or a leading zero an octal one. Errors leave
+/// out the expression, which the caller names.
+fn parse_number(text: &str) -> Result<i64, String> {
+    let trimmed = text.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (radix, digits) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if let Some(octal) = digits
+        .strip_prefix("0o")
+        .or_else(|| digits.strip_prefix("0O"))
+    {
+        (8, octal)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("invalid number (error token is \"{}\")", trimmed));
+    }
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(format!(
+            "value too great for base (error token is \"{}\")",
+            trimmed
+        ));
+    }
+    let magnitude = i128::from_str_radix(digits, radix).map_err(|_| overflow())?;
+    let value = if negative { -magnitude } else { magnitude };
+    i64::try_from(value).map_err(|_| overflow())
+}
+
+fn overflow() -> String {
+    "arithmetic overflow".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::Lexer;
+    use crate::utilities::fuzzing::{assert_no_panic, corpus, random_inputs};
+    use crate::utilities::utilities::Token;
+
+    fn value(expression: &str) -> Result<i64, String> {
+        let lookup = |name: &str| match name {
+            "octal" => Some("010".to_string()),
+            "spaced" => Some(" 7 ".to_string()),
+            _ => None,
+        };
+        evaluate(expression, &lookup).map(|evaluation| evaluation.value)
+    }
+
+    #[test]
+    fn literals_are_read_in_their_radix() {
+        let cases = [
+            ("10", 10),
+            ("0xff", 255),
+            ("0XFF", 255),
+            ("0o17", 15),
+            ("017", 15),
+            ("0", 0),
+            ("00", 0),
+            ("octal", 8),
+            ("spaced + 1", 8),
+            ("1 + 0x10 + 010", 25),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn digits_outside_the_radix_are_errors() {
+        assert_eq!(
+            value("08"),
+            Err("value too great for base (error token is \"08\")".to_string())
+        );
+        assert_eq!(
+            value("0o8"),
+            Err("value too great for base (error token is \"0o8\")".to_string())
+        );
+        assert_eq!(
+            value("0x"),
+            Err("invalid number (error token is \"0x\")".to_string())
+        );
+        assert!(value("0xg").is_err());
+    }
+
+    #[test]
+    fn unary_minus_negates_literals_and_expressions() {
+        let cases = [
+            ("-5 + 3", -2),
+            ("- 5", -5),
+            ("--5", 5),
+            ("-(2 * 3)", -6),
+            ("+-0x10", -16),
+            ("2 - -3", 5),
+            ("-octal", -8),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn overflow_is_an_error_at_the_64_bit_bounds() {
+        assert_eq!(value("9223372036854775807"), Ok(i64::MAX));
+        assert_eq!(value("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(value("0x7fffffffffffffff"), Ok(i64::MAX));
+        for expression in [
+            "9223372036854775808",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "-(-9223372036854775808)",
+            "4611686018427387904 * 2",
+            "-9223372036854775808 / -1",
+            "0x10000000000000000",
+        ] {
+            assert_eq!(value(expression), Err(overflow()), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn comparisons_and_logic_give_one_or_zero() {
+        let cases = [
+            ("1 < 2", 1),
+            ("2 <= 1", 0),
+            ("3 > 2", 1),
+            ("3 >= 4", 0),
+            ("1 == 1", 1),
+            ("1 != 1", 0),
+            ("!0", 1),
+            ("!5", 0),
+            ("!!7", 1),
+            ("-!0", -1),
+            ("2 && 3", 1),
+            ("2 && 0", 0),
+            ("0 || 4", 1),
+            ("0 || 0", 0),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn operators_have_c_precedence() {
+        let cases = [
+            ("1 + 2 < 2 * 2", 1),
+            ("1 < 2 == 2 < 3", 1),
+            ("3 > 2 > 1", 0),
+            ("1 == 2 != 1", 1),
+            ("1 || 0 && 0", 1),
+            ("(1 || 0) && 0", 0),
+            ("0 && 0 || 1", 1),
+            ("!1 + 1", 1),
+            ("!(1 + 1)", 0),
+            ("1 < 2 && 3 >= 3", 1),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn logic_skips_the_right_operand_once_decided() {
+        assert_eq!(value("0 && 1 / 0"), Ok(0));
+        assert_eq!(value("1 || 9223372036854775807 + 1"), Ok(1));
+        assert_eq!(value("1 && 1 / 0"), Err("Division by zero".to_string()));
+
+        let lookup = |_: &str| None;
+        let evaluation = evaluate("(1 || (x = 5)) + (0 || (y = 6))", &lookup).unwrap();
+        assert_eq!(evaluation.value, 2);
+        assert_eq!(evaluation.assignments, [("y".to_string(), 6)]);
+        let evaluation = evaluate("0 && (x = 1) || (z = 2)", &lookup).unwrap();
+        assert_eq!(evaluation.value, 1);
+        assert_eq!(evaluation.assignments, [("z".to_string(), 2)]);
+    }
+
+    #[test]
+    fn misplaced_comparison_operators_are_syntax_errors() {
+        for expression in ["1 <", "< 1", "1 < < 2", "1 ! 2", "1 & 2", "1 = = 2"] {
+            assert!(value(expression).is_err(), "{}", expression);
+        }
+    }
+
+    /// Evaluates the arithmetic commands and expansions of `input`, and
+    /// `input` itself as an expression.
+    fn evaluate_all(input: &str) {
+        for token in Lexer::new(input) {
+            if let Token::Arithmetic(expression) = token {
+                let _ = evaluate(&expression, &|_| None);
+            }
+        }
+        let _ = evaluate(input, &|_| None);
+    }
+
+    #[test]
+    fn inputs_that_once_crashed_evaluate_without_panicking() {
+        for input in corpus() {
+            assert_no_panic(&input, evaluate_all);
+        }
+    }
+
+    #[test]
+    fn random_input_evaluates_without_panicking() {
+        for input in random_inputs() {
+            assert_no_panic(&input, evaluate_all);
+        }
+    }
+
+    #[test]
+    fn parentheses_nest_up_to_the_limit() {
+        let nested = |depth: usize| "(".repeat(depth) + "1" + &")".repeat(depth);
+        assert_eq!(value(&nested(MAX_NESTING)), Ok(1));
+        assert_eq!(
+            value(&nested(100_000)),
+            Err(format!("parentheses nested more than {} deep", MAX_NESTING))
+        );
+        assert_eq!(value(&("-".repeat(100_001) + "1")), Ok(-1));
+    }
+
+    #[test]
+    fn nested_parentheses_need_no_spaces() {
+        for (expression, expected) in [
+            ("(1+2)*3", 9),
+            ("((((1))))", 1),
+            ("((2+3)*(4-1))/(1+(1))", 7),
+            ("-(2+1)", -3),
+            ("2*(3-(4-(5-6)))", -4),
+        ] {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+        for expression in ["(1+2", "1+2)", "()", "(1)(2)"] {
+            assert!(value(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn arithmetic_expansions_are_told_from_substitutions_of_subshells() {
+        for (substitution, expected) in [
+            ("$(((1+2)*3))", Some("(1+2)*3")),
+            ("$((((1))))", Some("((1))")),
+            ("$(( (1) ))", Some(" (1) ")),
+            ("$(())", Some("")),
+            ("$((cd /tmp) && pwd)", None),
+            ("$((a) | (b))", None),
+            ("$( (1) )", None),
+            ("$(echo)", None),
+        ] {
+            assert_eq!(expansion_body(substitution), expected, "{}", substitution);
+        }
+    }
+}
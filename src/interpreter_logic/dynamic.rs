@@ -0,0 +1,143 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How one dynamic variable is read and assigned.
+struct Dynamic {
+    name: &'static str,
+    get: fn(&DynamicVariables) -> String,
+    assign: fn(&mut DynamicVariables, &str),
+}
+
+const VARIABLES: &[Dynamic] = &[
+    Dynamic {
+        name: "RANDOM",
+        get: |dynamic| dynamic.next_random().to_string(),
+        // Non-numeric seeds count as 0, as in arithmetic.
+        assign: |dynamic, value| {
+            dynamic
+                .random
+                .store(parse_number(value) as u32, Ordering::Relaxed)
+        },
+    },
+    Dynamic {
+        name: "SECONDS",
+        get: |dynamic| {
+            (dynamic.seconds_base + dynamic.seconds_since.elapsed().as_secs() as i64).to_string()
+        },
+        assign: |dynamic, value| {
+            dynamic.seconds_base = parse_number(value);
+            dynamic.seconds_since = Instant::now();
+        },
+    },
+];
+
+fn parse_number(value: &str) -> i64 {
+    value.trim().parse().unwrap_or(0)
+}
+
+/// Variables whose value is computed each time they are expanded, looked up
+/// before ordinary variables. Assigning to one does what it means for that
+/// variable rather than storing the value. Once unset, a name is an ordinary
+/// variable for the rest of the session.
+pub struct DynamicVariables {
+    /// State of the generator behind `$RANDOM`, atomic so the interpreter
+    /// can be shared with the futures of `Shell::eval_async`.
+    random: AtomicU32,
+    /// `$SECONDS` counts up from `seconds_base` at `seconds_since`.
+    seconds_base: i64,
+    seconds_since: Instant,
+    unset: HashSet<String>,
+}
+
+impl Clone for DynamicVariables {
+    fn clone(&self) -> Self {
+        DynamicVariables {
+            random: AtomicU32::new(self.random.load(Ordering::Relaxed)),
+            seconds_base: self.seconds_base,
+            seconds_since: self.seconds_since,
+            unset: self.unset.clone(),
+        }
+    }
+}
+
+impl Default for DynamicVariables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DynamicVariables {
+    pub fn new() -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos() ^ d.as_secs() as u32);
+        DynamicVariables {
+            random: AtomicU32::new(time ^ std::process::id()),
+            seconds_base: 0,
+            seconds_since: Instant::now(),
+            unset: HashSet::new(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&'static Dynamic> {
+        VARIABLES
+            .iter()
+            .find(|dynamic| dynamic.name == name)
+            .filter(|_| !self.unset.contains(name))
+    }
+
+    pub fn is_dynamic(&self, name: &str) -> bool {
+        self.lookup(name).is_some()
+    }
+
+    /// The current value of `name`, or `None` if it is not dynamic.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.lookup(name).map(|dynamic| (dynamic.get)(self))
+    }
+
+    /// Handles an assignment to `name`, returning false if it is an ordinary
+    /// variable that should be stored as usual.
+    pub fn assign(&mut self, name: &str, value: &str) -> bool {
+        match self.lookup(name) {
+            Some(dynamic) => {
+                (dynamic.assign)(self, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes away the special meaning of `name` for good.
+    pub fn unset(&mut self, name: &str) {
+        if VARIABLES.iter().any(|dynamic| dynamic.name == name) {
+            self.unset.insert(name.to_string());
+        }
+    }
+
+    /// The next value in 0..=32767 from a linear congruential generator.
+    fn next_random(&self) -> u32 {
+        let state = self
+            .random
+            .load(Ordering::Relaxed)
+            .wrapping_mul(1_103_515_245)
+            .wrapping_add(12_345);
+        self.random.store(state, Ordering::Relaxed);
+        (state >> 16) & 0x7fff
+    }
+}
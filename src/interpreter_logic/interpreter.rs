@@ -13,24 +13,164 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::interpreter_logic::logic::Logic;
-use crate::utilities::utilities::ASTNode;
-use std::collections::HashMap;
+use crate::interpreter_logic::logic::{array_elements, element_key, Logic};
+use crate::parser::parser::is_identifier;
+use crate::utilities::utilities::{ASTNode, CompletionSpec, ShellOptions};
+use glob::Pattern;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Interpreter {
-    pub variables: HashMap<String, String>,
-    pub functions: HashMap<String, ASTNode>,
+    pub variables: BTreeMap<String, String>,
+    /// The environment the shell was started with, less what was unset.
+    /// Its names are also the shell's first variables.
+    pub environment: BTreeMap<String, String>,
+    /// The names passed on to the commands the shell starts: those it
+    /// inherited and those given to `export`. Kept per shell, never in the
+    /// process environment, so shells on different threads stay apart.
+    pub exported: BTreeSet<String>,
+    /// The directory `cd` moved to, which relative paths are taken from and
+    /// commands start in. Kept per shell, never as the process's directory;
+    /// empty until the first `cd`, which stands for the process's own.
+    pub directory: PathBuf,
+    pub functions: BTreeMap<String, Arc<ASTNode>>,
     pub logic: Logic,
+    pub options: ShellOptions,
+    /// The script line of the statement being run, or of the one that failed.
+    pub line: usize,
+    /// Commands set by `trap` for the `EXIT`, `ERR` and `DEBUG` conditions.
+    /// An empty command ignores the condition.
+    pub traps: BTreeMap<String, String>,
+    /// Argument completions registered with `complete`, by command name.
+    pub completions: BTreeMap<String, CompletionSpec>,
+    /// The functions being run, outermost first.
+    pub call_stack: Vec<Frame>,
+    /// The call stack as it was when the error being propagated left the
+    /// innermost function, for the error report.
+    pub error_trace: Option<Vec<Frame>>,
+}
+
+/// A function call in progress.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    /// The line the function was called from.
+    pub call_line: usize,
+    /// The variables made local to the call, with the value and name
+    /// reference each had before, restored when it returns.
+    pub locals: Vec<(String, Option<String>, Option<String>)>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let environment: BTreeMap<String, String> = std::env::vars().collect();
+        // Inherited names become variables, so they expand like any other.
+        // `IFS` is never taken from the environment, as in other shells.
+        let mut variables: BTreeMap<String, String> = environment
+            .iter()
+            .filter(|(name, _)| is_identifier(name) && *name != "IFS")
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        // `$?` is 0 until the first command runs.
+        variables.insert("?".to_string(), "0".to_string());
         Interpreter {
-            variables: HashMap::new(),
-            functions: HashMap::new(),
+            variables,
+            exported: environment.keys().cloned().collect(),
+            environment,
+            directory: PathBuf::new(),
+            functions: BTreeMap::new(),
             logic: Logic::new(),
+            options: ShellOptions::default(),
+            line: 0,
+            traps: BTreeMap::new(),
+            completions: BTreeMap::new(),
+            call_stack: Vec::new(),
+            error_trace: None,
+        }
+    }
+
+    /// Sets the variables a shell starts with: `SHLVL`, one more than in the
+    /// environment, and `PWD`, both exported, and the unexported `UID`,
+    /// `HOSTNAME`, `OSTYPE` and `MACHTYPE` describing the system.
+    pub fn init_variables(&mut self) {
+        let ostype = match std::env::consts::OS {
+            os if cfg!(target_env = "gnu") => format!("{}-gnu", os),
+            os => os.to_string(),
+        };
+        let machtype = format!("{}-unknown-{}", std::env::consts::ARCH, ostype);
+        // Only Unix has user IDs.
+        #[cfg(unix)]
+        self.set_variable("UID", unsafe { libc::geteuid() }.to_string());
+        if let Some(hostname) = hostname() {
+            self.set_variable("HOSTNAME", hostname);
         }
+        self.set_variable("OSTYPE", ostype);
+        self.set_variable("MACHTYPE", machtype);
+        // `$$` stays the shell's own process, even in background subshells.
+        self.variables
+            .insert("$".to_string(), std::process::id().to_string());
+        self.variables
+            .entry("0".to_string())
+            .or_insert_with(|| "bellos".to_string());
+        self.update_option_flags();
+
+        let level = self
+            .environment
+            .get("SHLVL")
+            .and_then(|level| level.trim().parse::<i64>().ok())
+            .unwrap_or(0);
+        self.export_variable("SHLVL", (level + 1).max(0).to_string());
+
+        // Keep an inherited `PWD` naming the current directory through
+        // symlinks, as `cd` would have left it.
+        let Ok(current) = std::env::current_dir() else {
+            return;
+        };
+        let pwd = self
+            .environment
+            .get("PWD")
+            .cloned()
+            .filter(|pwd| std::fs::canonicalize(pwd).ok() == current.canonicalize().ok())
+            .unwrap_or_else(|| current.display().to_string());
+        self.export_variable("PWD", pwd);
+    }
+
+    /// `path` as seen from the shell's directory.
+    pub fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.directory.join(path)
+    }
+
+    /// Sets a variable and puts it in the environment of commands.
+    pub fn export_variable(&mut self, name: &str, value: String) {
+        self.set_variable(name, value);
+        self.exported.insert(name.to_string());
+    }
+
+    /// The value of a variable, or of an inherited environment variable.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        let name = self.logic.resolve(name);
+        self.variables
+            .get(name)
+            .or_else(|| self.environment.get(name))
+            .cloned()
+    }
+
+    /// The environment to start commands with: the current values of the
+    /// exported variables.
+    pub fn command_environment(&self) -> BTreeMap<String, String> {
+        self.exported
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.variable(name)?)))
+            .collect()
     }
 
     pub fn interpret(&mut self, nodes: Vec<ASTNode>) -> Result<(), String> {
@@ -53,20 +193,132 @@ impl Interpreter {
             } => self.execute_if(condition, then_block, else_block),
             ASTNode::While { condition, block } => self.execute_while(condition, block),
             ASTNode::For { var, list, block } => self.execute_for(var, list, block),
-            ASTNode::Case { var, cases } => self.execute_case(var, cases),
+            ASTNode::Case { word, cases } => self.execute_case(word, cases),
             ASTNode::Comparison { left, op, right } => self.execute_comparison(left, op, right),
             ASTNode::Expression(expr) => self.execute_expression(expr),
             ASTNode::Function { name, body } => self.define_function(name, body),
+            ASTNode::Line { line, node } => {
+                let previous = self.enter_line(*line);
+                let result = self.interpret_node(node);
+                self.leave_line(previous, &result);
+                result
+            }
             _ => Err(format!("Unsupported node type in Interpreter: {:?}", node)),
         }
     }
 
+    /// Where the running command is, as `script: line N`, to begin the
+    /// messages about it.
+    pub fn location(&self) -> String {
+        let script = self.variables.get("0").map_or("bellos", String::as_str);
+        format!("{}: line {}", script, self.line)
+    }
+
+    /// Takes the call stack recorded for the error being reported, as one
+    /// line per function call, innermost first.
+    pub fn take_error_trace(&mut self) -> Vec<String> {
+        let script = self.variables.get("0").map_or("bellos", String::as_str);
+        let lines = self
+            .error_trace
+            .iter()
+            .flatten()
+            .rev()
+            .map(|frame| {
+                format!(
+                    "    in {} called from {}: line {}",
+                    frame.function, script, frame.call_line
+                )
+            })
+            .collect();
+        self.error_trace = None;
+        lines
+    }
+
+    /// Makes `line` the current line, also seen as `$LINENO`, and returns
+    /// the previous one for `leave_line`.
+    pub fn enter_line(&mut self, line: usize) -> usize {
+        let previous = self.line;
+        self.line = line;
+        self.variables
+            .insert("LINENO".to_string(), line.to_string());
+        previous
+    }
+
+    /// Goes back to the enclosing statement's line once a statement is done.
+    /// After a failure the failing line is kept for the error report.
+    pub fn leave_line<T, E>(&mut self, previous: usize, result: &Result<T, E>) {
+        if result.is_ok() {
+            self.enter_line(previous);
+        }
+    }
+
     fn assignment(&mut self, name: &str, value: &str) -> Result<Option<i32>, String> {
-        let expanded_value = self.expand_variables(value);
-        self.variables.insert(name.to_string(), expanded_value);
+        let expanded_value = self.expand_word(value).into_owned();
+        self.set_variable(name, expanded_value);
         Ok(None)
     }
 
+    /// Assigns a variable, or the one a name reference stands for, letting
+    /// dynamic variables like `RANDOM` handle the assignment themselves.
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        let name = self.logic.resolve(name).to_string();
+        if !self.logic.dynamic.assign(&name, &value) {
+            self.variables.insert(name, value);
+        }
+    }
+
+    /// Makes `name` an indexed array of `elements`, replacing any value it
+    /// had.
+    pub fn set_array(&mut self, name: &str, elements: Vec<String>) {
+        let name = self.logic.resolve(name).to_string();
+        self.remove_array(&name);
+        self.variables.remove(&name);
+        for (i, element) in elements.into_iter().enumerate() {
+            self.variables.insert(element_key(&name, i), element);
+        }
+    }
+
+    /// The elements of the array `name`, in index order. A variable that
+    /// is not an array is one of a single element.
+    pub fn array(&self, name: &str) -> Vec<String> {
+        let name = self.logic.resolve(name);
+        let elements = array_elements(&self.variables, name);
+        if elements.is_empty() {
+            return self.variables.get(name).cloned().into_iter().collect();
+        }
+        elements
+            .into_iter()
+            .map(|(_, value)| value.to_string())
+            .collect()
+    }
+
+    fn remove_array(&mut self, name: &str) {
+        let elements: Vec<usize> = array_elements(&self.variables, name)
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        for index in elements {
+            self.variables.remove(&element_key(name, index));
+        }
+    }
+
+    /// Whether `name` is set, as a dynamic or an ordinary variable.
+    pub fn is_set(&self, name: &str) -> bool {
+        let name = self.logic.resolve(name);
+        self.logic.dynamic.is_dynamic(name) || self.variables.contains_key(name)
+    }
+
+    /// Removes a variable, along with any special meaning of its name.
+    /// Through a name reference, the variable referred to is removed.
+    pub fn unset_variable(&mut self, name: &str) {
+        let name = &self.logic.resolve(name).to_string();
+        self.logic.dynamic.unset(name);
+        self.variables.remove(name);
+        self.remove_array(name);
+        self.environment.remove(name);
+        self.exported.remove(name);
+    }
+
     fn execute_block(&mut self, statements: &[ASTNode]) -> Result<Option<i32>, String> {
         let mut last_result = Ok(None);
         for statement in statements {
@@ -89,7 +341,7 @@ impl Interpreter {
         } else if let Some(else_block) = else_block {
             self.interpret_node(else_block)
         } else {
-            Ok(None)
+            Ok(Some(0))
         }
     }
 
@@ -98,45 +350,53 @@ impl Interpreter {
         condition: &ASTNode,
         block: &ASTNode,
     ) -> Result<Option<i32>, String> {
+        let mut status = None;
         while self.logic.evaluate_condition(&self.variables, condition)? {
-            self.interpret_node(block)?;
+            status = self.interpret_node(block)?;
         }
-        Ok(None)
+        Ok(Some(status.unwrap_or(0)))
     }
 
     fn execute_for(
         &mut self,
         var: &str,
-        list: &[String],
+        list: &Option<Vec<String>>,
         block: &ASTNode,
     ) -> Result<Option<i32>, String> {
-        for item in list {
-            let expanded_item = self.expand_variables(item);
-            self.variables.insert(var.to_string(), expanded_item);
-            self.interpret_node(block)?;
+        let items = match list {
+            Some(list) => list
+                .iter()
+                .map(|item| self.expand_word(item).into_owned())
+                .collect(),
+            None => self.positional_parameters(),
+        };
+        let mut status = None;
+        for item in items {
+            self.set_variable(var, item);
+            status = self.interpret_node(block)?;
         }
-        Ok(None)
+        Ok(Some(status.unwrap_or(0)))
     }
 
     fn execute_case(
         &mut self,
-        var: &ASTNode,
-        cases: &[(ASTNode, ASTNode)],
+        word: &str,
+        cases: &[(Vec<String>, ASTNode)],
     ) -> Result<Option<i32>, String> {
-        let var_str = match var {
-            ASTNode::Expression(expr) => self.expand_variables(expr),
-            _ => return Err("Invalid case variable".to_string()),
-        };
-        for (pattern, block) in cases {
-            let expanded_pattern = match pattern {
-                ASTNode::Expression(expr) => self.expand_variables(expr),
-                _ => return Err("Invalid case pattern".to_string()),
-            };
-            if expanded_pattern == "*" || expanded_pattern == var_str {
-                return self.interpret_node(block);
+        let value = self.expand_word(word).into_owned();
+        for (patterns, block) in cases {
+            for pattern in patterns {
+                let pattern = self.expand_word(pattern);
+                let matched = match Pattern::new(&pattern) {
+                    Ok(glob) => glob.matches(&value),
+                    Err(_) => pattern == value,
+                };
+                if matched {
+                    return self.interpret_node(block);
+                }
             }
         }
-        Ok(None)
+        Ok(Some(0))
     }
 
     fn execute_comparison(
@@ -145,66 +405,220 @@ impl Interpreter {
         op: &str,
         right: &str,
     ) -> Result<Option<i32>, String> {
-        let result = self
-            .logic
-            .compare_values(&self.variables, left, op, right)?;
-        Ok(Some(if result { 0 } else { 1 }))
+        match self.logic.compare_values(&self.variables, left, op, right) {
+            Ok(result) => Ok(Some(if result { 0 } else { 1 })),
+            Err(e) => {
+                eprintln!("{}", e);
+                Ok(Some(2))
+            }
+        }
     }
 
+    /// `(( expr ))`: evaluates the expression, succeeding if it is not zero.
     fn execute_expression(&mut self, expr: &str) -> Result<Option<i32>, String> {
-        let expanded = self.expand_variables(expr);
-        Ok(Some(self.logic.evaluate_arithmetic(&expanded)?))
+        let value = self.evaluate_arithmetic(expr)?;
+        Ok(Some(if value != 0 { 0 } else { 1 }))
+    }
+
+    /// Evaluates an arithmetic expression and stores the variables it
+    /// assigns.
+    pub fn evaluate_arithmetic(&mut self, expr: &str) -> Result<i64, String> {
+        let evaluation = self
+            .logic
+            .evaluate_arithmetic(&self.variables, expr)
+            .map_err(|e| format!("{}: {}", expr.trim(), e))?;
+        for (name, value) in evaluation.assignments {
+            self.set_variable(&name, value.to_string());
+        }
+        Ok(evaluation.value)
     }
 
-    fn define_function(&mut self, name: &str, body: &ASTNode) -> Result<Option<i32>, String> {
-        self.functions.insert(name.to_string(), body.clone());
+    fn define_function(&mut self, name: &str, body: &Arc<ASTNode>) -> Result<Option<i32>, String> {
+        self.functions.insert(name.to_string(), Arc::clone(body));
         Ok(None)
     }
 
-    pub fn expand_variables(&self, input: &str) -> String {
-        self.logic.expand_variables(&self.variables, input)
+    pub fn expand_variables<'a>(&self, input: &'a str) -> Result<Cow<'a, str>, String> {
+        self.logic.try_expand_variables(&self.variables, input)
+    }
+
+    /// Expands a word as written by the user, removing its quotes.
+    pub fn expand_word<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        self.logic.expand_word(&self.variables, word)
     }
 
     pub fn call_function(&mut self, name: &str, args: &[String]) -> Result<Option<i32>, String> {
-        if let Some(function_body) = self.functions.get(name).cloned() {
-            // Save current variables
-            let saved_variables = self.variables.clone();
-
-            // Set up function arguments as variables
-            if let ASTNode::Function { name: _, body } = function_body {
-                if let ASTNode::Block(statements) = *body {
-                    // Assume the first statement is a parameter list
-                    if let Some(ASTNode::Assignment {
-                        name: params,
-                        value: _,
-                    }) = statements.first()
-                    {
-                        let param_names: Vec<&str> = params.split_whitespace().collect();
-                        for (i, param_name) in param_names.iter().enumerate() {
-                            if i < args.len() {
-                                self.variables
-                                    .insert(param_name.to_string(), args[i].clone());
-                            } else {
-                                self.variables.insert(param_name.to_string(), String::new());
-                            }
-                        }
-                    }
-
-                    // Execute function body
-                    let result = self.execute_block(&statements[1..]);
-
-                    // Restore original variables
-                    self.variables = saved_variables;
-
-                    result
-                } else {
-                    Err("Invalid function body".to_string())
-                }
-            } else {
-                Err("Invalid function definition".to_string())
+        let body = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Function '{}' not found", name))?;
+        let saved = self.enter_function(name, args);
+        let result = self.interpret_node(&body);
+        self.leave_function(saved, result.is_err());
+        result
+    }
+
+    /// The positional parameters `$1` to `$#` of the script or function
+    /// running, in order.
+    pub fn positional_parameters(&self) -> Vec<String> {
+        let count = self
+            .variables
+            .get("#")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        (1..=count)
+            .map(|i: usize| {
+                self.variables
+                    .get(&i.to_string())
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Sets `$-` to the letters of the options in effect: `i` for an
+    /// interactive shell and those of `set`. Called whenever they change.
+    pub fn update_option_flags(&mut self) {
+        let flags: String = [
+            ('i', self.options.interactive),
+            ('n', self.options.noexec),
+            ('v', self.options.verbose),
+            ('x', self.options.xtrace),
+        ]
+        .into_iter()
+        .filter_map(|(flag, set)| set.then_some(flag))
+        .collect();
+        self.variables.insert("-".to_string(), flags);
+    }
+
+    /// Makes `args` the positional parameters `$1`, `$2` and so on, with
+    /// `$#`, `$@` and `$*` describing them.
+    pub fn set_positional_parameters(&mut self, args: &[String]) {
+        self.variables.retain(|name, _| !is_positional(name));
+        for (i, arg) in args.iter().enumerate() {
+            self.variables.insert((i + 1).to_string(), arg.clone());
+        }
+        self.variables
+            .insert("#".to_string(), args.len().to_string());
+        self.variables.insert("@".to_string(), args.join(" "));
+        self.variables.insert("*".to_string(), args.join(" "));
+    }
+
+    /// Starts a call to function `name`: pushes its frame and makes `args`
+    /// the positional parameters. Returns the caller's parameters, which
+    /// `leave_function` puts back.
+    pub fn enter_function(&mut self, name: &str, args: &[String]) -> Vec<(String, String)> {
+        let saved = self
+            .variables
+            .iter()
+            .filter(|(name, _)| is_positional(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        self.set_positional_parameters(args);
+        self.call_stack.push(Frame {
+            function: name.to_string(),
+            call_line: self.line,
+            locals: Vec::new(),
+        });
+        saved
+    }
+
+    /// Makes `name` local to the innermost function call, set to `value`
+    /// or empty. With `nameref`, `name` becomes a reference to the variable
+    /// named by `value` instead, so reading and assigning it reads and
+    /// assigns that variable.
+    pub fn declare_local(
+        &mut self,
+        name: &str,
+        value: Option<String>,
+        nameref: bool,
+    ) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("local: `{}': not a valid identifier", name));
+        }
+        let target = match (nameref, &value) {
+            (false, _) => None,
+            (true, Some(target)) if is_identifier(target) => Some(target.clone()),
+            (true, target) => {
+                return Err(format!(
+                    "local: `{}': invalid variable name for name reference",
+                    target.as_deref().unwrap_or_default()
+                ))
             }
-        } else {
-            Err(format!("Function '{}' not found", name))
+        };
+        if let Some(target) = &target {
+            if self.logic.is_circular(name, target) {
+                return Err(format!("local: {}: circular name reference", name));
+            }
+        }
+        let previous = (
+            name.to_string(),
+            self.variables.get(name).cloned(),
+            self.logic.namerefs.get(name).cloned(),
+        );
+        let Some(frame) = self.call_stack.last_mut() else {
+            return Err("local: can only be used in a function".to_string());
+        };
+        if !frame.locals.iter().any(|(local, _, _)| local == name) {
+            frame.locals.push(previous);
+        }
+        match target {
+            Some(target) => {
+                self.variables.remove(name);
+                self.logic.namerefs.insert(name.to_string(), target);
+            }
+            None => {
+                self.logic.namerefs.remove(name);
+                self.set_variable(name, value.unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the innermost function call, recording the call stack if it
+    /// failed and restoring its locals and the caller's positional
+    /// parameters.
+    pub fn leave_function(&mut self, saved: Vec<(String, String)>, failed: bool) {
+        if failed && self.error_trace.is_none() {
+            self.error_trace = Some(self.call_stack.clone());
         }
+        if let Some(frame) = self.call_stack.pop() {
+            for (name, value, nameref) in frame.locals.into_iter().rev() {
+                match value {
+                    Some(value) => self.variables.insert(name.clone(), value),
+                    None => self.variables.remove(&name),
+                };
+                match nameref {
+                    Some(target) => self.logic.namerefs.insert(name, target),
+                    None => self.logic.namerefs.remove(&name),
+                };
+            }
+        }
+        self.variables.retain(|name, _| !is_positional(name));
+        self.variables.extend(saved);
+    }
+}
+
+/// Whether `name` is a positional parameter, or `$#`, `$@` or `$*`, which
+/// describe them.
+fn is_positional(name: &str) -> bool {
+    matches!(name, "#" | "@" | "*")
+        || (name != "0" && !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
     }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..end]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
 }
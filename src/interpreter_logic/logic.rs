@@ -13,71 +13,257 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::interpreter_logic::arithmetic::{self, Evaluation};
+use crate::interpreter_logic::dynamic::DynamicVariables;
+use crate::lexer::lexer::{word_segments, Quoting};
 use crate::utilities::utilities::ASTNode;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// The parameters named by a single punctuation character: `$?`, `$$`,
+/// `$!`, `$#`, `$*`, `$@` and `$-`.
+const SPECIAL_PARAMETERS: &str = "?$!#*@-";
 
 #[derive(Clone)]
-pub struct Logic;
+pub struct Logic {
+    pub dynamic: DynamicVariables,
+    /// Name references made with `local -n`, each naming the variable it
+    /// stands for. They never form a cycle.
+    pub namerefs: BTreeMap<String, String>,
+}
+
+impl Default for Logic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Logic {
     pub fn new() -> Self {
-        Logic
+        Logic {
+            dynamic: DynamicVariables::new(),
+            namerefs: BTreeMap::new(),
+        }
     }
 
-    pub fn expand_variables(&self, variables: &HashMap<String, String>, input: &str) -> String {
-        let mut result = String::new();
-        let mut chars = input.chars().peekable();
-        while let Some(c) = chars.next() {
-            if c == '$' {
-                if chars.peek() == Some(&'(') {
-                    chars.next(); // Consume '('
-                    if chars.peek() == Some(&'(') {
-                        chars.next(); // Consume second '('
-                        let expr = self.extract_arithmetic_expression(&mut chars);
-                        if let Ok(value) = self.evaluate_arithmetic(&expr) {
-                            result.push_str(&value.to_string());
-                        } else {
-                            result.push_str(&format!("$(({})", expr));
-                        }
-                    } else {
-                        let cmd = self.extract_command_substitution(&mut chars);
-                        // For now, we'll just insert the command as-is
-                        result.push_str(&format!("$({})", cmd));
+    /// The variable `name` stands for, following name references.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        let mut name = name;
+        // Bounded, in case a cycle ever slipped through.
+        for _ in 0..=self.namerefs.len() {
+            match self.namerefs.get(name) {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        name
+    }
+
+    /// Whether making `name` refer to `target` would close a cycle of name
+    /// references.
+    pub fn is_circular(&self, name: &str, target: &str) -> bool {
+        let mut current = target;
+        for _ in 0..=self.namerefs.len() {
+            if current == name {
+                return true;
+            }
+            match self.namerefs.get(current) {
+                Some(next) => current = next,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Expands `$` references in `input`, leaving it as it is if an
+    /// arithmetic expansion fails.
+    pub fn expand_variables<'a>(
+        &self,
+        variables: &BTreeMap<String, String>,
+        input: &'a str,
+    ) -> Cow<'a, str> {
+        self.try_expand_variables(variables, input)
+            .unwrap_or(Cow::Borrowed(input))
+    }
+
+    /// Expands `$` references in `input`. Inputs without anything to expand
+    /// are returned borrowed, and untouched spans are copied in bulk. Fails
+    /// when an arithmetic expansion does.
+    pub fn try_expand_variables<'a>(
+        &self,
+        variables: &BTreeMap<String, String>,
+        input: &'a str,
+    ) -> Result<Cow<'a, str>, String> {
+        if !input.contains(['$', '~', '\\']) {
+            return Ok(Cow::Borrowed(input));
+        }
+
+        let mut result = String::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(offset) = rest.find('$') {
+            result.push_str(&rest[..offset]);
+            let after = &rest[offset + 1..];
+            if let Some(inner) = after.strip_prefix("((") {
+                let mut chars = inner.chars();
+                let expr = self.extract_arithmetic_expression(&mut chars);
+                let evaluation = self
+                    .evaluate_arithmetic(variables, &expr)
+                    .map_err(|e| format!("{}: {}", expr.trim(), e))?;
+                result.push_str(&evaluation.value.to_string());
+                rest = chars.as_str();
+            } else if let Some(inner) = after.strip_prefix('(') {
+                let mut chars = inner.chars();
+                let cmd = self.extract_command_substitution(&mut chars);
+                // For now, we'll just insert the command as-is
+                result.push_str(&format!("$({})", cmd));
+                rest = chars.as_str();
+            } else if let Some(inner) = after.strip_prefix('{') {
+                // `${name}` and the array references; anything else is kept
+                // as written.
+                let braced = inner.find('}').map(|end| &inner[..end]);
+                match braced.map(|text| (text, array_reference(text))) {
+                    Some((name, _)) if is_parameter(name) => {
+                        self.push_parameter(&mut result, variables, name, true);
+                        rest = &inner[name.len() + 1..];
                     }
-                } else {
-                    let var_name: String = chars
-                        .by_ref()
-                        .take_while(|&c| c.is_alphanumeric() || c == '_')
-                        .collect();
-                    if let Some(value) = variables.get(&var_name) {
-                        result.push_str(value);
-                    } else {
+                    Some((text, Some(reference))) => {
+                        self.push_array_reference(&mut result, variables, reference)?;
+                        rest = &inner[text.len() + 1..];
+                    }
+                    _ => {
                         result.push('$');
-                        result.push_str(&var_name);
+                        rest = after;
                     }
                 }
             } else {
-                result.push(c);
+                let name_len = match after.chars().next() {
+                    Some(c) if SPECIAL_PARAMETERS.contains(c) || c.is_ascii_digit() => 1,
+                    Some(c) if c.is_alphabetic() || c == '_' => after
+                        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .unwrap_or(after.len()),
+                    // A `$` starting no parameter is an ordinary character.
+                    _ => 0,
+                };
+                if name_len == 0 {
+                    result.push('$');
+                } else {
+                    self.push_parameter(&mut result, variables, &after[..name_len], false);
+                }
+                rest = &after[name_len..];
+            }
+        }
+        result.push_str(rest);
+        Ok(Cow::Owned(result))
+    }
+
+    /// Appends the value of parameter `name` to `result`. Unset special
+    /// and positional parameters are empty, while an unset variable is kept
+    /// as written.
+    fn push_parameter(
+        &self,
+        result: &mut String,
+        variables: &BTreeMap<String, String>,
+        name: &str,
+        braced: bool,
+    ) {
+        let is_variable = name.starts_with(|c: char| c.is_alphabetic() || c == '_');
+        let target = self.resolve(name);
+        if let Some(value) = self.dynamic.get(target) {
+            result.push_str(&value);
+        } else if let Some(value) = variables.get(target) {
+            result.push_str(value);
+        } else if let Some(value) = variables.get(&element_key(target, 0)) {
+            // An array stands for its first element.
+            result.push_str(value);
+        } else if is_variable && braced {
+            result.push_str(&format!("${{{}}}", name));
+        } else if is_variable {
+            result.push('$');
+            result.push_str(name);
+        }
+    }
+
+    /// Appends what an array reference stands for: the element a subscript
+    /// selects, counting back from the end when it is negative, or for `@`
+    /// and `*` every element, separated by spaces. With `#`, the length of
+    /// the element or the number of elements instead. A variable that is not
+    /// an array is one of a single element.
+    fn push_array_reference(
+        &self,
+        result: &mut String,
+        variables: &BTreeMap<String, String>,
+        reference: ArrayReference,
+    ) -> Result<(), String> {
+        let name = self.resolve(reference.name);
+        let mut elements = array_elements(variables, name);
+        if elements.is_empty() {
+            elements.extend(variables.get(name).map(|value| (0, value.as_str())));
+        }
+        if matches!(reference.subscript, "@" | "*") {
+            if reference.length {
+                result.push_str(&elements.len().to_string());
+            } else {
+                let values: Vec<&str> = elements.iter().map(|(_, value)| *value).collect();
+                result.push_str(&values.join(" "));
             }
+            return Ok(());
+        }
+        let index = self
+            .evaluate_arithmetic(variables, reference.subscript)
+            .map_err(|e| format!("{}: {}", reference.subscript.trim(), e))?
+            .value;
+        let index = if index < 0 {
+            let last = elements.last().map_or(0, |(last, _)| *last as i64);
+            last + 1 + index
+        } else {
+            index
+        };
+        let value = elements
+            .iter()
+            .find(|(i, _)| *i as i64 == index)
+            .map_or("", |(_, value)| value);
+        if reference.length {
+            result.push_str(&value.chars().count().to_string());
+        } else {
+            result.push_str(value);
         }
-        result
+        Ok(())
     }
 
-    pub fn extract_arithmetic_expression(
+    /// Expands a word as written, removing its quotes. Variables are
+    /// expanded in double-quoted and unquoted text alike, but not between
+    /// single quotes or after `\$`.
+    pub fn expand_word<'a>(
         &self,
-        chars: &mut std::iter::Peekable<std::str::Chars>,
-    ) -> String {
+        variables: &BTreeMap<String, String>,
+        word: &'a str,
+    ) -> Cow<'a, str> {
+        if !word.contains(['"', '\'']) {
+            return self.expand_variables(variables, word);
+        }
+        let mut result = String::with_capacity(word.len());
+        for (text, quoting) in word_segments(word) {
+            match quoting {
+                Quoting::Literal => result.push_str(&text),
+                _ => result.push_str(&self.expand_variables(variables, &text)),
+            }
+        }
+        Cow::Owned(result)
+    }
+
+    /// Takes the expression of a `$((...))` after its opening `$((`, up to
+    /// and consuming the `))` that closes it.
+    pub fn extract_arithmetic_expression(&self, chars: &mut std::str::Chars) -> String {
         let mut expr = String::new();
-        let mut depth = 2; // We've already consumed "(("
+        let mut depth = 0;
         while let Some(c) = chars.next() {
             match c {
                 '(' => depth += 1,
-                ')' => {
-                    depth -= 1;
-                    if depth == 0 {
-                        break;
-                    }
+                ')' if depth == 0 => {
+                    chars.next(); // The second closing parenthesis
+                    break;
                 }
+                ')' => depth -= 1,
                 _ => {}
             }
             expr.push(c);
@@ -85,23 +271,23 @@ impl Logic {
         expr
     }
 
-    pub fn evaluate_arithmetic(&self, expr: &str) -> Result<i32, String> {
-        let expr = expr.trim();
-        let inner_expr = if expr.starts_with("$((") && expr.ends_with("))") {
-            &expr[3..expr.len() - 2]
-        } else if expr.starts_with("((") && expr.ends_with("))") {
-            &expr[2..expr.len() - 2]
-        } else {
-            expr
+    /// Evaluates an arithmetic expression, reading variables from
+    /// `variables`. The assignments it makes are returned, not applied.
+    pub fn evaluate_arithmetic(
+        &self,
+        variables: &BTreeMap<String, String>,
+        expr: &str,
+    ) -> Result<Evaluation, String> {
+        let lookup = |name: &str| {
+            let name = self.resolve(name);
+            self.dynamic
+                .get(name)
+                .or_else(|| variables.get(name).cloned())
         };
-
-        self.evaluate_arithmetic_expression(inner_expr)
+        arithmetic::evaluate(expr, &lookup)
     }
 
-    fn extract_command_substitution(
-        &self,
-        chars: &mut std::iter::Peekable<std::str::Chars>,
-    ) -> String {
+    fn extract_command_substitution(&self, chars: &mut std::str::Chars) -> String {
         let mut depth = 1;
         let mut cmd = String::new();
         for c in chars.by_ref() {
@@ -120,80 +306,22 @@ impl Logic {
         cmd
     }
 
-    fn evaluate_arithmetic_expression(&self, expr: &str) -> Result<i32, String> {
-        let tokens: Vec<&str> = expr.split_whitespace().collect();
-        if tokens.len() != 3 {
-            return Err("Invalid arithmetic expression".to_string());
-        }
-
-        let left: i32 = self.parse_value(tokens[0])?;
-        let right: i32 = self.parse_value(tokens[2])?;
-
-        match tokens[1] {
-            "+" => Ok(left + right),
-            "-" => Ok(left - right),
-            "*" => Ok(left * right),
-            "/" => {
-                if right != 0 {
-                    Ok(left / right)
-                } else {
-                    Err("Division by zero".to_string())
-                }
-            }
-            "%" => {
-                if right != 0 {
-                    Ok(left % right)
-                } else {
-                    Err("Modulo by zero".to_string())
-                }
-            }
-            _ => Err(format!("Unsupported operation: {}", tokens[1])),
-        }
-    }
-
-    fn parse_value(&self, value: &str) -> Result<i32, String> {
-        value
-            .parse()
-            .map_err(|_| format!("Invalid integer: {}", value))
-    }
-
     pub fn compare_values(
         &self,
-        variables: &HashMap<String, String>,
+        variables: &BTreeMap<String, String>,
         left: &str,
         op: &str,
         right: &str,
     ) -> Result<bool, String> {
-        let left_val = self.expand_variables(variables, left);
-        let right_val = self.expand_variables(variables, right);
-
-        match op {
-            "-eq" => Ok(left_val == right_val),
-            "-ne" => Ok(left_val != right_val),
-            "-lt" => self.compare_numbers(&left_val, &right_val, |a, b| a < b),
-            "-le" => self.compare_numbers(&left_val, &right_val, |a, b| a <= b),
-            "-gt" => self.compare_numbers(&left_val, &right_val, |a, b| a > b),
-            "-ge" => self.compare_numbers(&left_val, &right_val, |a, b| a >= b),
-            _ => Err(format!("Unknown comparison operator: {}", op)),
-        }
-    }
+        let left_val = self.expand_word(variables, left);
+        let right_val = self.expand_word(variables, right);
 
-    fn compare_numbers<F>(&self, left: &str, right: &str, compare: F) -> Result<bool, String>
-    where
-        F: Fn(i32, i32) -> bool,
-    {
-        let left_num = left
-            .parse::<i32>()
-            .map_err(|_| format!("Invalid number: {}", left))?;
-        let right_num = right
-            .parse::<i32>()
-            .map_err(|_| format!("Invalid number: {}", right))?;
-        Ok(compare(left_num, right_num))
+        compare_operands(&left_val, op, &right_val)
     }
 
     pub fn evaluate_condition(
         &self,
-        variables: &HashMap<String, String>,
+        variables: &BTreeMap<String, String>,
         condition: &ASTNode,
     ) -> Result<bool, String> {
         match condition {
@@ -201,10 +329,244 @@ impl Logic {
                 self.compare_values(variables, left, op, right)
             }
             ASTNode::Expression(expr) => {
-                let result = self.evaluate_arithmetic(&self.expand_variables(variables, expr))?;
-                Ok(result != 0)
+                let expr = self.expand_word(variables, expr);
+                Ok(self.evaluate_arithmetic(variables, &expr)?.value != 0)
             }
             _ => Err("Invalid condition".to_string()),
         }
     }
 }
+
+/// Compares two expanded operands with a `test` operator. `=`, `==`, `!=`,
+/// `<` and `>` compare strings; `-eq`, `-ne`, `-lt`, `-le`, `-gt` and `-ge`
+/// compare integers and fail when an operand is not one.
+pub fn compare_operands(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    match op {
+        "=" | "==" => Ok(left == right),
+        "!=" => Ok(left != right),
+        "<" => Ok(left < right),
+        ">" => Ok(left > right),
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            let (left, right) = (parse_integer(left)?, parse_integer(right)?);
+            Ok(match op {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            })
+        }
+        _ => Err(format!("Unknown comparison operator: {}", op)),
+    }
+}
+
+/// Parses an integer operand of `test`, allowing surrounding whitespace and
+/// a sign. Digits are always decimal, so `010` is ten, unlike in arithmetic.
+pub fn parse_integer(operand: &str) -> Result<i64, String> {
+    let trimmed = operand.trim();
+    let digits = trimmed
+        .strip_prefix('-')
+        .or_else(|| trimmed.strip_prefix('+'))
+        .unwrap_or(trimmed);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{}: integer expression expected", operand));
+    }
+    trimmed
+        .strip_prefix('+')
+        .unwrap_or(trimmed)
+        .parse()
+        .map_err(|_| format!("{}: integer out of range", operand))
+}
+
+/// The variable holding element `index` of the array `name`. Arrays are
+/// kept among the other variables, under names no assignment can make.
+pub fn element_key(name: &str, index: usize) -> String {
+    format!("{}[{}]", name, index)
+}
+
+/// The indices and values of the elements of the array `name`, in order.
+pub fn array_elements<'a>(
+    variables: &'a BTreeMap<String, String>,
+    name: &str,
+) -> Vec<(usize, &'a str)> {
+    let prefix = format!("{}[", name);
+    let mut elements: Vec<(usize, &str)> = variables
+        .range(prefix.clone()..)
+        .take_while(|(key, _)| key.starts_with(&prefix))
+        .filter_map(|(key, value)| {
+            let index = key[prefix.len()..].strip_suffix(']')?.parse().ok()?;
+            Some((index, value.as_str()))
+        })
+        .collect();
+    elements.sort_unstable_by_key(|(index, _)| *index);
+    elements
+}
+
+/// A `${name[subscript]}` or `${#name[subscript]}` reference, without the
+/// braces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArrayReference<'a> {
+    /// Whether `#` asks for a length rather than the values.
+    pub length: bool,
+    pub name: &'a str,
+    pub subscript: &'a str,
+}
+
+/// Parses the text between `${` and `}` as an array reference.
+pub fn array_reference(text: &str) -> Option<ArrayReference<'_>> {
+    let (length, text) = match text.strip_prefix('#') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (name, subscript) = text.strip_suffix(']')?.split_once('[')?;
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && is_parameter(name)
+        && !subscript.is_empty();
+    valid.then_some(ArrayReference {
+        length,
+        name,
+        subscript,
+    })
+}
+
+/// Whether `name` can follow `${`: a variable name, any number of digits
+/// for a positional parameter, or a special parameter.
+fn is_parameter(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        Some(c) if c.is_ascii_digit() => chars.all(|c| c.is_ascii_digit()),
+        Some(c) if SPECIAL_PARAMETERS.contains(c) => chars.next().is_none(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(input: &str) -> String {
+        let variables: BTreeMap<String, String> = [
+            ("?", "3"),
+            ("$", "1234"),
+            ("!", "99"),
+            ("#", "2"),
+            ("*", "a b"),
+            ("@", "a b"),
+            ("-", "hv"),
+            ("0", "bellos"),
+            ("1", "a"),
+            ("2", "b"),
+            ("name", "value"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        Logic::new()
+            .expand_variables(&variables, input)
+            .into_owned()
+    }
+
+    #[test]
+    fn special_parameters_expand_alone_and_next_to_text() {
+        let cases = [
+            ("$?", "3"),
+            ("$$", "1234"),
+            ("$!", "99"),
+            ("$#", "2"),
+            ("$*", "a b"),
+            ("$@", "a b"),
+            ("$-", "hv"),
+            ("$0", "bellos"),
+            ("$1", "a"),
+            ("$2", "b"),
+            ("$3", ""),
+            ("status=$?.", "status=3."),
+            ("pid$$pid", "pid1234pid"),
+            ("[$!]", "[99]"),
+            ("$#args", "2args"),
+            ("<$*>", "<a b>"),
+            ("x$@x", "xa bx"),
+            ("-$--", "-hv-"),
+            ("$0:", "bellos:"),
+            ("$?$$$#", "312342"),
+            ("$10", "a0"),
+            ("$1name", "aname"),
+            ("${1}0", "a0"),
+            ("${10}", ""),
+            ("${?}x", "3x"),
+            ("$name.", "value."),
+            ("${name}s", "values"),
+            ("$", "$"),
+            ("a $ b", "a $ b"),
+            ("50%$", "50%$"),
+            ("$%", "$%"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(expand(input), expected, "expanding {:?}", input);
+        }
+    }
+
+    #[test]
+    fn input_without_expansions_is_borrowed() {
+        let logic = Logic::new();
+        let variables = BTreeMap::from([("v".to_string(), "value".to_string())]);
+        for input in ["plain", "", "a-b_c.d/e f", "50% of (x)"] {
+            let expanded = logic.expand_variables(&variables, input);
+            assert!(matches!(expanded, Cow::Borrowed(_)), "{}", input);
+            assert_eq!(expanded, input);
+        }
+        let expanded = logic.expand_variables(&variables, "long literal span $v and tail");
+        assert!(matches!(expanded, Cow::Owned(_)));
+        assert_eq!(expanded, "long literal span value and tail");
+        assert_eq!(expand("é$name é${name}é"), "évalue évalueé");
+    }
+
+    #[test]
+    fn test_reads_integers_as_decimal() {
+        let cases = [
+            ("010", "-eq", "10", true),
+            ("08", "-eq", "8", true),
+            (" 7 ", "-eq", "7", true),
+            ("-5", "-lt", "+3", true),
+            ("-0", "-eq", "0", true),
+            ("9223372036854775807", "-gt", "-9223372036854775808", true),
+            ("010", "-eq", "8", false),
+        ];
+        for (left, op, right, expected) in cases {
+            assert_eq!(
+                compare_operands(left, op, right),
+                Ok(expected),
+                "[ {} {} {} ]",
+                left,
+                op,
+                right
+            );
+        }
+        for operand in ["0x1", "0o7", "", "-", "1.5", "ten"] {
+            assert_eq!(
+                parse_integer(operand),
+                Err(format!("{}: integer expression expected", operand))
+            );
+        }
+        assert!(parse_integer("9223372036854775808").is_err());
+    }
+
+    #[test]
+    fn array_references_name_an_array_and_subscript() {
+        let reference = |length, name, subscript| {
+            Some(ArrayReference {
+                length,
+                name,
+                subscript,
+            })
+        };
+        assert_eq!(array_reference("a[1]"), reference(false, "a", "1"));
+        assert_eq!(array_reference("#list[@]"), reference(true, "list", "@"));
+        assert_eq!(array_reference("_x[i+1]"), reference(false, "_x", "i+1"));
+        for text in ["a", "a[]", "1[0]", "a[1", "[1]", "#[@]", "a b[0]"] {
+            assert_eq!(array_reference(text), None, "{}", text);
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod arithmetic;
+pub mod dynamic;
 pub mod interpreter;
 pub mod logic;
@@ -13,18 +13,44 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::utilities::utilities::{RedirectType, Token};
+use crate::utilities::utilities::{BorrowedToken, RedirectType, Token};
+use std::borrow::Cow;
 
-pub struct Lexer {
-    input: Vec<char>,
+// The lexing errors of input that ends too early, which the interactive
+// shell answers by reading another line.
+const UNTERMINATED_STRING: &str = "Unterminated string";
+const UNTERMINATED_SUBSTITUTION: &str = "Unterminated command substitution";
+const UNTERMINATED_ARITHMETIC: &str = "Unterminated arithmetic command";
+
+pub struct Lexer<'a> {
+    input: &'a str,
     position: usize,
+    /// The body of a heredoc just read, emitted right after its operator.
+    heredoc_body: Option<BorrowedToken<'a>>,
+    /// Where lexing resumes once the line holding heredoc operators ends,
+    /// i.e. just past the last body read.
+    heredoc_resume: Option<usize>,
+    /// Lines skipped over as heredoc bodies, reported as blank lines so the
+    /// parser's line numbers stay right.
+    skipped_lines: usize,
+    /// Newlines inside the words of the current line, like those of a
+    /// multiline string, also reported as blank lines once the line ends.
+    embedded_lines: usize,
+    /// Inside `[[ ... ]]`, where only blanks separate words so operators like
+    /// `=~` and regex characters like `(` and `|` stay part of them.
+    double_bracket: bool,
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
         Lexer {
-            input: input.chars().collect(),
+            input,
             position: 0,
+            heredoc_body: None,
+            heredoc_resume: None,
+            skipped_lines: 0,
+            embedded_lines: 0,
+            double_bracket: false,
         }
     }
 
@@ -36,173 +62,881 @@ impl Lexer {
         tokens
     }
 
+    pub fn tokenize_borrowed(&mut self) -> Vec<BorrowedToken<'a>> {
+        let mut tokens = Vec::new();
+        while let Some(token) = self.next_borrowed_token() {
+            tokens.push(token);
+        }
+        tokens
+    }
+
     fn next_token(&mut self) -> Option<Token> {
+        self.next_borrowed_token().map(BorrowedToken::into_owned)
+    }
+
+    pub fn next_borrowed_token(&mut self) -> Option<BorrowedToken<'a>> {
+        if let Some(body) = self.heredoc_body.take() {
+            return Some(body);
+        }
+        if self.skipped_lines > 0 {
+            self.skipped_lines -= 1;
+            return Some(BorrowedToken::Other(Token::NewLine));
+        }
         self.skip_whitespace();
+        if self.current_char() == '#' {
+            self.skip_comment();
+        }
 
         if self.position >= self.input.len() {
             return None;
         }
+        if self.double_bracket && !matches!(self.current_char(), '\n' | ';') {
+            return Some(self.read_double_bracket_word());
+        }
+        if self.input[self.position..].starts_with("[[") && self.is_blank_at(self.position + 2) {
+            self.position += 2;
+            self.double_bracket = true;
+            return Some(BorrowedToken::Word("[["));
+        }
 
         Some(match self.current_char() {
-            '[' => {
-                self.advance();
-                Token::LeftBracket
-            }
-            ']' => {
-                self.advance();
-                Token::RightBracket
-            }
-            ' ' | '\t' => {
-                self.advance();
-                return self.next_token();
-            }
             '\n' => {
                 self.advance();
-                Token::NewLine
+                self.double_bracket = false;
+                self.skipped_lines = std::mem::take(&mut self.embedded_lines);
+                // A string spanning lines may already have run past the
+                // heredoc bodies.
+                if let Some(resume) = self.heredoc_resume.take().filter(|&r| r > self.position) {
+                    self.skipped_lines += self.input[self.position..resume].matches('\n').count();
+                    self.position = resume;
+                }
+                BorrowedToken::Other(Token::NewLine)
             }
             ';' => {
                 self.advance();
                 if self.current_char() == ';' {
                     self.advance();
-                    Token::DoubleSemicolon
+                    BorrowedToken::Other(Token::DoubleSemicolon)
                 } else {
-                    Token::Semicolon
+                    BorrowedToken::Other(Token::Semicolon)
                 }
             }
             '|' => {
                 self.advance();
-                Token::Pipe
+                if self.current_char() == '|' {
+                    self.advance();
+                    BorrowedToken::Other(Token::Or)
+                } else {
+                    BorrowedToken::Other(Token::Pipe)
+                }
             }
             '&' => {
                 self.advance();
-                Token::Ampersand
-            }
-            '=' => {
+                if self.current_char() == '&' {
+                    self.advance();
+                    return Some(BorrowedToken::Other(Token::And));
+                }
+                if self.current_char() != '>' {
+                    return Some(BorrowedToken::Other(Token::Ampersand));
+                }
                 self.advance();
-                Token::Assignment
+                if self.current_char() == '>' {
+                    self.advance();
+                    BorrowedToken::Other(Token::RedirectAll(RedirectType::Append))
+                } else {
+                    BorrowedToken::Other(Token::RedirectAll(RedirectType::Output))
+                }
             }
+            '(' if self.peek_next() == Some('(') => self.read_arithmetic_command(),
             '(' => {
                 self.advance();
-                Token::LeftParen
+                BorrowedToken::Other(Token::LeftParen)
             }
             ')' => {
                 self.advance();
-                Token::RightParen
+                BorrowedToken::Other(Token::RightParen)
             }
             '>' => {
                 self.advance();
-                if self.current_char() == '>' {
+                let kind = match self.current_char() {
+                    '>' => RedirectType::Append,
+                    '&' => RedirectType::DuplicateOutput,
+                    _ => RedirectType::Output,
+                };
+                if kind != RedirectType::Output {
                     self.advance();
-                    Token::Redirect(RedirectType::Append)
-                } else {
-                    Token::Redirect(RedirectType::Output)
                 }
+                BorrowedToken::Other(Token::Redirect(kind))
             }
             '<' => {
                 self.advance();
-                Token::Redirect(RedirectType::Input)
-            }
-            '"' => self.read_string(),
-            '$' => {
-                if self.peek_next() == Some('(') {
-                    Token::Word(self.read_command_substitution())
+                if self.current_char() == '<' {
+                    self.advance();
+                    if self.current_char() == '<' {
+                        self.advance();
+                        BorrowedToken::Other(Token::Redirect(RedirectType::HereString))
+                    } else {
+                        self.read_heredoc()
+                    }
+                } else if self.current_char() == '&' {
+                    self.advance();
+                    BorrowedToken::Other(Token::Redirect(RedirectType::DuplicateInput))
+                } else if self.current_char() == '>' {
+                    self.advance();
+                    BorrowedToken::Other(Token::Redirect(RedirectType::ReadWrite))
                 } else {
-                    self.read_word()
+                    BorrowedToken::Other(Token::Redirect(RedirectType::Input))
                 }
             }
             _ => self.read_word(),
         })
     }
 
+    /// Returns the character at the current byte position, or `'\0'` past the end.
     fn current_char(&self) -> char {
-        self.input[self.position]
+        self.input[self.position..].chars().next().unwrap_or('\0')
     }
 
+    /// Moves past the current character, staying put at the end of input.
     fn advance(&mut self) {
-        self.position += 1;
+        if let Some(c) = self.input[self.position..].chars().next() {
+            self.position += c.len_utf8();
+        }
     }
 
     fn peek_next(&self) -> Option<char> {
-        self.input.get(self.position + 1).copied()
+        let mut chars = self.input[self.position..].chars();
+        chars.next();
+        chars.next()
     }
 
+    /// Skips blanks, and backslash-newlines, which continue a command on
+    /// the next line.
     fn skip_whitespace(&mut self) {
-        while self.position < self.input.len() && matches!(self.input[self.position], ' ' | '\t') {
+        loop {
+            match self.current_char() {
+                ' ' | '\t' => self.advance(),
+                '\\' if self.peek_next() == Some('\n') => {
+                    self.position += 2;
+                    self.embedded_lines += 1;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Skips a `#` comment up to, but not including, the end of the line.
+    fn skip_comment(&mut self) {
+        while self.position < self.input.len() && self.current_char() != '\n' {
             self.advance();
         }
     }
 
-    fn read_word(&mut self) -> Token {
+    /// Reads a word as written: a run of unquoted text, quoted strings and
+    /// `$(...)` substitutions with nothing separating them, so that
+    /// `pre"fix and"post` is one word. Quotes are kept for expansion to remove.
+    /// `[` and `]` are words like any other: a lone `[` runs the `test`
+    /// builtin, and brackets inside a word are glob patterns or subscripts.
+    fn read_word(&mut self) -> BorrowedToken<'a> {
         let start = self.position;
-        while self.position < self.input.len()
-            && !matches!(
-                self.current_char(),
-                ' ' | '\t' | '\n' | ';' | '|' | '&' | '=' | '(' | ')' | '>' | '<' | '"'
-            )
-        {
+        while self.position < self.input.len() {
+            let skipped = match self.current_char() {
+                ' ' | '\t' | '\n' | ';' | '|' | '&' | '(' | ')' | '>' | '<' => break,
+                '"' => self.skip_string(),
+                '\'' => self.skip_single_quoted(),
+                '$' if self.peek_next() == Some('(') => self.skip_substitution(),
+                '\\' => {
+                    self.skip_escape();
+                    Ok(())
+                }
+                _ => {
+                    self.advance();
+                    Ok(())
+                }
+            };
+            if let Err(e) = skipped {
+                return BorrowedToken::Other(Token::Error(e));
+            }
+        }
+
+        let word = &self.input[start..self.position];
+        self.embedded_lines += word.matches('\n').count();
+        if matches!(self.current_char(), '<' | '>') && word.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(fd) = word.parse() {
+                return BorrowedToken::Other(Token::IoNumber(fd));
+            }
+        }
+        match word {
+            "if" => BorrowedToken::Other(Token::If),
+            "then" => BorrowedToken::Other(Token::Then),
+            "else" => BorrowedToken::Other(Token::Else),
+            "elif" => BorrowedToken::Other(Token::Elif),
+            "fi" => BorrowedToken::Other(Token::Fi),
+            "while" => BorrowedToken::Other(Token::While),
+            "do" => BorrowedToken::Other(Token::Do),
+            "done" => BorrowedToken::Other(Token::Done),
+            "for" => BorrowedToken::Other(Token::For),
+            "in" => BorrowedToken::Other(Token::In),
+            "case" => BorrowedToken::Other(Token::Case),
+            "esac" => BorrowedToken::Other(Token::Esac),
+            "function" => BorrowedToken::Other(Token::Function),
+            "time" => BorrowedToken::Other(Token::Time),
+            "{" => BorrowedToken::Other(Token::LeftBrace),
+            "}" => BorrowedToken::Other(Token::RightBrace),
+            _ => BorrowedToken::Word(word),
+        }
+    }
+
+    /// Reads an `(( expression ))` arithmetic command through its closing
+    /// `))`, with any parentheses inside balanced.
+    fn read_arithmetic_command(&mut self) -> BorrowedToken<'a> {
+        self.position += 2;
+        let start = self.position;
+        let mut depth = 0;
+        while self.position < self.input.len() {
+            match self.current_char() {
+                '(' => depth += 1,
+                ')' if depth == 0 && self.peek_next() == Some(')') => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
             self.advance();
         }
+        if self.position == self.input.len() {
+            return BorrowedToken::Other(Token::Error(UNTERMINATED_ARITHMETIC.to_string()));
+        }
+        let expression = &self.input[start..self.position];
+        self.embedded_lines += expression.matches('\n').count();
+        self.position += 2;
+        BorrowedToken::Other(Token::Arithmetic(expression.to_string()))
+    }
 
-        let word: String = self.input[start..self.position].iter().collect();
-        match word.as_str() {
-            "if" => Token::If,
-            "then" => Token::Then,
-            "else" => Token::Else,
-            "elif" => Token::Elif,
-            "fi" => Token::Fi,
-            "while" => Token::While,
-            "do" => Token::Do,
-            "done" => Token::Done,
-            "for" => Token::For,
-            "in" => Token::In,
-            "case" => Token::Case,
-            "esac" => Token::Esac,
-            "function" => Token::Function,
-            "[" => Token::LeftBracket,
-            "]" => Token::RightBracket,
-            _ => Token::Word(word),
-        }
-    }
-
-    fn read_string(&mut self) -> Token {
-        self.advance(); // Skip opening quote
+    /// Moves past a backslash and the character it quotes.
+    fn skip_escape(&mut self) {
+        self.advance();
+        self.advance();
+    }
+
+    /// Reads a word inside `[[ ... ]]`, ending the construct at `]]`.
+    fn read_double_bracket_word(&mut self) -> BorrowedToken<'a> {
         let start = self.position;
+        while !self.is_blank_at(self.position) {
+            let skipped = match self.current_char() {
+                '"' => self.skip_string(),
+                '\'' => self.skip_single_quoted(),
+                '$' if self.peek_next() == Some('(') => self.skip_substitution(),
+                '\\' => {
+                    self.skip_escape();
+                    Ok(())
+                }
+                _ => {
+                    self.advance();
+                    Ok(())
+                }
+            };
+            if let Err(e) = skipped {
+                return BorrowedToken::Other(Token::Error(e));
+            }
+        }
+        let word = &self.input[start..self.position];
+        self.embedded_lines += word.matches('\n').count();
+        if word == "]]" {
+            self.double_bracket = false;
+        }
+        BorrowedToken::Word(word)
+    }
+
+    /// Whether `position` is at the end of the input or of a word inside
+    /// `[[ ... ]]`.
+    fn is_blank_at(&self, position: usize) -> bool {
+        matches!(
+            self.input[position..].chars().next(),
+            None | Some(' ' | '\t' | '\n' | ';')
+        )
+    }
+
+    /// Skips over a double-quoted string, quotes included. Fails when the
+    /// input ends before the closing quote.
+    fn skip_string(&mut self) -> Result<(), String> {
+        self.advance(); // Skip opening quote
         while self.position < self.input.len() && self.current_char() != '"' {
-            if self.current_char() == '\\' && self.peek_next() == Some('"') {
-                self.advance(); // Skip the backslash
+            if self.current_char() == '$' && self.peek_next() == Some('(') {
+                // Quotes inside a substitution belong to it, not to this string.
+                self.skip_substitution()?;
+                continue;
+            }
+            if self.current_char() == '\\' {
+                self.advance(); // Skip the backslash, escaping what follows
             }
             self.advance();
         }
-        let string: String = self.input[start..self.position].iter().collect();
-        if self.position < self.input.len() {
-            self.advance(); // Skip closing quote
+        if self.position == self.input.len() {
+            return Err(UNTERMINATED_STRING.to_string());
         }
-        Token::String(string)
+        self.advance(); // Skip closing quote
+        Ok(())
     }
 
-    fn read_command_substitution(&mut self) -> String {
-        let mut cmd = String::from("$(");
-        self.advance(); // Skip $
-        self.advance(); // Skip (
-        let mut depth = 1;
+    /// Skips over a single-quoted string, quotes included. Nothing inside
+    /// it is special, not even a backslash. Fails when the input ends before
+    /// the closing quote.
+    fn skip_single_quoted(&mut self) -> Result<(), String> {
+        match self.input[self.position + 1..].find('\'') {
+            Some(end) => {
+                self.position += end + 2;
+                Ok(())
+            }
+            None => {
+                self.position = self.input.len();
+                Err(UNTERMINATED_STRING.to_string())
+            }
+        }
+    }
 
-        while self.position < self.input.len() && depth > 0 {
-            match self.current_char() {
-                '(' => depth += 1,
-                ')' => depth -= 1,
-                _ => {}
+    /// Skips over a `$(...)` substitution. Fails when the input ends before
+    /// its closing parenthesis.
+    fn skip_substitution(&mut self) -> Result<(), String> {
+        match substitution_end(&self.input[self.position..]) {
+            Some(length) => {
+                self.position += length;
+                Ok(())
+            }
+            None => {
+                self.position = self.input.len();
+                Err(UNTERMINATED_SUBSTITUTION.to_string())
             }
-            cmd.push(self.current_char());
+        }
+    }
+
+    /// Reads a heredoc operator's delimiter, after `<<`, and collects its body
+    /// from the lines following the current one (or following the previous
+    /// heredoc's body when a line holds several). The body is borrowed unless
+    /// `<<-` has leading tabs to strip.
+    fn read_heredoc(&mut self) -> BorrowedToken<'a> {
+        let strip_tabs = self.current_char() == '-';
+        if strip_tabs {
             self.advance();
         }
-        cmd
+        self.skip_whitespace();
+        let (delimiter, quoted) = self.read_heredoc_delimiter();
+
+        let start = self.heredoc_resume.unwrap_or_else(|| {
+            self.input[self.position..]
+                .find('\n')
+                .map_or(self.input.len(), |i| self.position + i + 1)
+        });
+        let mut owned = strip_tabs.then(String::new);
+        let mut line_start = start;
+        let (end, resume) = loop {
+            if line_start >= self.input.len() {
+                break (self.input.len(), self.input.len());
+            }
+            let line_end = self.input[line_start..]
+                .find('\n')
+                .map_or(self.input.len(), |i| line_start + i + 1);
+            let line = &self.input[line_start..line_end];
+            let content = if strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if content.trim_end_matches('\n') == delimiter {
+                break (line_start, line_end);
+            }
+            if let Some(body) = owned.as_mut() {
+                body.push_str(content);
+            }
+            line_start = line_end;
+        };
+
+        self.heredoc_resume = Some(resume);
+        self.heredoc_body = Some(BorrowedToken::String(match owned {
+            Some(body) => Cow::Owned(body),
+            None => Cow::Borrowed(&self.input[start..end]),
+        }));
+        BorrowedToken::Other(Token::Redirect(RedirectType::HereDoc {
+            strip_tabs,
+            quoted,
+        }))
+    }
+
+    /// Reads a heredoc delimiter, removing any quoting. Quoting any part of it
+    /// marks the heredoc as quoted.
+    fn read_heredoc_delimiter(&mut self) -> (String, bool) {
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        while self.position < self.input.len()
+            && !matches!(
+                self.current_char(),
+                ' ' | '\t' | '\n' | ';' | '|' | '&' | '(' | ')' | '>' | '<'
+            )
+        {
+            match self.current_char() {
+                quote @ ('\'' | '"') => {
+                    quoted = true;
+                    self.advance();
+                    while self.position < self.input.len()
+                        && !matches!(self.current_char(), '\n')
+                        && self.current_char() != quote
+                    {
+                        delimiter.push(self.current_char());
+                        self.advance();
+                    }
+                    if self.current_char() == quote {
+                        self.advance();
+                    }
+                }
+                '\\' => {
+                    quoted = true;
+                    self.advance();
+                    delimiter.push(self.current_char());
+                    self.advance();
+                }
+                c => {
+                    delimiter.push(c);
+                    self.advance();
+                }
+            }
+        }
+        (delimiter, quoted)
+    }
+}
+
+/// Whether `line` ends with a backslash that is not itself escaped, so the
+/// command goes on to the next line.
+pub fn is_continued(line: &str) -> bool {
+    let backslashes = line.len() - line.trim_end_matches('\\').len();
+    backslashes % 2 == 1
+}
+
+/// Whether `input` ends inside a string, `$(...)` substitution or `((`
+/// arithmetic command, or with a backslash continuing its last line, so
+/// more lines are needed to complete it.
+pub fn is_incomplete(input: &str) -> bool {
+    if is_continued(input.strip_suffix('\n').unwrap_or(input)) {
+        return true;
     }
+    Lexer::new(input).any(|token| {
+        matches!(&token, Token::Error(e)
+            if [UNTERMINATED_STRING, UNTERMINATED_SUBSTITUTION, UNTERMINATED_ARITHMETIC]
+                .contains(&e.as_str()))
+    })
+}
+
+/// How a run of text in a word was quoted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    Unquoted,
+    /// Between double quotes, where variables and substitutions still
+    /// expand.
+    Double,
+    /// Taken as it is: text between single quotes, or what an escape stands
+    /// for.
+    Literal,
 }
 
-impl Iterator for Lexer {
+/// Splits a word as written into runs of unquoted, double-quoted and
+/// literal text, paired with how they were quoted. Quotes are removed;
+/// quotes inside a `$(...)` substitution belong to it. Inside double quotes
+/// `\\`, `\"`, `\$`, `\n` and `\t` are escapes, each its own literal run,
+/// while a backslash before anything else is kept. Outside of quotes a
+/// backslash makes the character after it literal, as the quote in
+/// `'it'\''s'` or the `*` in `expr 2 \* 3`. Outside of single quotes a
+/// backslash-newline is removed, joining the lines. Empty quoted runs are
+/// kept, since `""` is still an argument.
+pub fn word_segments(word: &str) -> Vec<(Cow<'_, str>, Quoting)> {
+    let mut segments = Vec::new();
+    let mut quoting = Quoting::Unquoted;
+    let mut start = 0;
+    let mut position = 0;
+    // Ends the run before `position`, kept even if empty when `keep` is set.
+    let end_run = |segments: &mut Vec<_>, start, position, quoting, keep| {
+        if keep || position > start {
+            segments.push((Cow::Borrowed(&word[start..position]), quoting));
+        }
+    };
+    while let Some(c) = word[position..].chars().next() {
+        match (quoting, c) {
+            (Quoting::Literal, '\'') => {
+                end_run(&mut segments, start, position, quoting, true);
+                quoting = Quoting::Unquoted;
+                position += 1;
+                start = position;
+            }
+            (Quoting::Literal, _) => position += c.len_utf8(),
+            (_, '$') if word[position + 1..].starts_with('(') => {
+                position += substitution_len(&word[position..]);
+            }
+            (Quoting::Unquoted | Quoting::Double, '\\')
+                if word[position + 1..].starts_with('\n') =>
+            {
+                end_run(&mut segments, start, position, quoting, false);
+                position += 2;
+                start = position;
+            }
+            (Quoting::Double, '\\') => {
+                let escaped = word[position + 1..].chars().next();
+                let literal = match escaped {
+                    Some('\\') => "\\",
+                    Some('"') => "\"",
+                    Some('$') => "$",
+                    Some('n') => "\n",
+                    Some('t') => "\t",
+                    _ => {
+                        position += 1 + escaped.map_or(0, char::len_utf8);
+                        continue;
+                    }
+                };
+                end_run(&mut segments, start, position, quoting, false);
+                segments.push((Cow::Borrowed(literal), Quoting::Literal));
+                position += 2;
+                start = position;
+            }
+            (Quoting::Double, '"') => {
+                end_run(&mut segments, start, position, quoting, true);
+                quoting = Quoting::Unquoted;
+                position += 1;
+                start = position;
+            }
+            (Quoting::Unquoted, '\\') if position + 1 < word.len() => {
+                end_run(&mut segments, start, position, quoting, false);
+                let escaped = word[position + 1..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+                let end = position + 1 + escaped;
+                segments.push((Cow::Borrowed(&word[position + 1..end]), Quoting::Literal));
+                position = end;
+                start = position;
+            }
+            (Quoting::Unquoted, '"' | '\'') => {
+                end_run(&mut segments, start, position, quoting, false);
+                quoting = if c == '"' {
+                    Quoting::Double
+                } else {
+                    Quoting::Literal
+                };
+                position += 1;
+                start = position;
+            }
+            _ => position += c.len_utf8(),
+        }
+    }
+    let keep = quoting != Quoting::Unquoted;
+    end_run(&mut segments, start, word.len(), quoting, keep);
+    segments
+}
+
+/// Returns the byte length of the `$(...)` command substitution at the start
+/// of `input`, through its closing parenthesis. Nested parentheses, quotes
+/// and substitutions nested inside double quotes are balanced, so
+/// `$(basename "$(pwd)")` is taken whole. An unterminated substitution runs to
+/// the end of `input`.
+pub fn substitution_len(input: &str) -> usize {
+    substitution_end(input).unwrap_or(input.len())
+}
+
+/// Like `substitution_len`, but `None` for an unterminated substitution.
+fn substitution_end(input: &str) -> Option<usize> {
+    // The parenthesis depth of each substitution open at `position`, and
+    // whether it is inside double quotes, innermost last. Kept on a stack
+    // of its own, so that deeply nested input cannot overflow the real one.
+    let mut open: Vec<(i32, bool)> = vec![(0, false)];
+    let mut position = 0;
+    while let Some(c) = input[position..].chars().next() {
+        position += c.len_utf8();
+        let (depth, in_quotes) = open.last_mut()?;
+        match c {
+            '\\' => {
+                position += input[position..].chars().next().map_or(0, char::len_utf8);
+            }
+            '"' => *in_quotes = !*in_quotes,
+            '\'' if !*in_quotes => {
+                position += input[position..].find('\'')? + 1;
+            }
+            '$' if *in_quotes && input[position..].starts_with('(') => {
+                position += 1;
+                open.push((1, false));
+            }
+            '(' if !*in_quotes => *depth += 1,
+            ')' if !*in_quotes => {
+                *depth -= 1;
+                if *depth == 0 {
+                    open.pop();
+                    if open.is_empty() {
+                        return Some(position);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Iterator for Lexer<'_> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_token()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::fuzzing::{assert_no_panic, corpus, random_inputs, scripts};
+
+    /// Lexes all of `input`, owned and borrowed.
+    fn lex(input: &str) {
+        Lexer::new(input).for_each(drop);
+        Lexer::new(input).tokenize_borrowed();
+        is_incomplete(input);
+    }
+
+    #[test]
+    fn inputs_that_once_crashed_lex_without_panicking() {
+        for input in corpus() {
+            assert_no_panic(&input, lex);
+        }
+    }
+
+    #[test]
+    fn random_input_lexes_without_panicking() {
+        for input in random_inputs() {
+            assert_no_panic(&input, lex);
+        }
+    }
+
+    #[test]
+    fn every_prefix_of_the_scripts_lexes_without_panicking() {
+        let short_corpus = corpus().into_iter().filter(|input| input.len() < 1000);
+        let inputs: Vec<String> = scripts()
+            .into_iter()
+            .map(str::to_string)
+            .chain(short_corpus)
+            .collect();
+        for input in inputs {
+            let cuts = input.char_indices().map(|(i, _)| i).chain([input.len()]);
+            for cut in cuts {
+                let prefix = &input[..cut];
+                assert_no_panic(prefix, lex);
+                assert_no_panic(&format!("{}\n", prefix), lex);
+            }
+        }
+    }
+
+    #[test]
+    fn input_ending_early_gives_tokens_or_errors() {
+        let tokens = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        assert_eq!(
+            tokens("echo a >"),
+            [
+                Token::Word("echo".to_string()),
+                Token::Word("a".to_string()),
+                Token::Redirect(RedirectType::Output),
+            ]
+        );
+        assert_eq!(tokens("a |").last(), Some(&Token::Pipe));
+        for (input, error) in [
+            ("echo \"abc", UNTERMINATED_STRING),
+            ("echo $(ls", UNTERMINATED_SUBSTITUTION),
+            ("echo \"$(ls\"", UNTERMINATED_SUBSTITUTION),
+            ("(( 1 +", UNTERMINATED_ARITHMETIC),
+        ] {
+            assert!(
+                tokens(input).contains(&Token::Error(error.to_string())),
+                "{}",
+                input
+            );
+            assert!(is_incomplete(input), "{}", input);
+        }
+        assert!(!is_incomplete("echo \"abc\" $(ls) $((1))"));
+    }
+
+    #[test]
+    fn single_quotes_keep_their_text_literal() {
+        let tokens = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        assert_eq!(
+            tokens("echo '$X' 'it'\\''s'"),
+            [
+                Token::Word("echo".to_string()),
+                Token::Word("'$X'".to_string()),
+                Token::Word("'it'\\''s'".to_string()),
+            ]
+        );
+        let segments = |word| -> Vec<(String, Quoting)> {
+            word_segments(word)
+                .into_iter()
+                .map(|(text, quoting)| (text.into_owned(), quoting))
+                .collect()
+        };
+        assert_eq!(
+            segments("'$X \\n \"'"),
+            [("$X \\n \"".to_string(), Quoting::Literal)]
+        );
+        let joined: String = segments("'it'\\''s'")
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(joined, "it's");
+        for input in ["echo 'abc", "echo 'it'\\''s", "echo \"a\" 'b\nc"] {
+            assert!(
+                tokens(input).contains(&Token::Error(UNTERMINATED_STRING.to_string())),
+                "{}",
+                input
+            );
+            assert!(is_incomplete(input), "{}", input);
+        }
+        assert!(!is_incomplete("echo 'a\"b' \"c'd\""));
+    }
+
+    #[test]
+    fn double_quotes_replace_their_escapes() {
+        let segments = |word| -> Vec<(String, Quoting)> {
+            word_segments(word)
+                .into_iter()
+                .map(|(text, quoting)| (text.into_owned(), quoting))
+                .collect()
+        };
+        let run = |text: &str, quoting| (text.to_string(), quoting);
+        assert_eq!(
+            segments(r#""a\nb\t\\\"\$X""#),
+            [
+                run("a", Quoting::Double),
+                run("\n", Quoting::Literal),
+                run("b", Quoting::Double),
+                run("\t", Quoting::Literal),
+                run("\\", Quoting::Literal),
+                run("\"", Quoting::Literal),
+                run("$", Quoting::Literal),
+                run("X", Quoting::Double),
+            ]
+        );
+        assert_eq!(segments(r#""a\qb""#), [run("a\\qb", Quoting::Double)]);
+        // An escaped backslash does not escape the closing quote.
+        let tokens = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        assert_eq!(
+            tokens(r#"echo "trailing\\" next"#),
+            [
+                Token::Word("echo".to_string()),
+                Token::Word(r#""trailing\\""#.to_string()),
+                Token::Word("next".to_string()),
+            ]
+        );
+        let joined: String = segments(r#""trailing\\""#)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(joined, "trailing\\");
+        assert!(is_incomplete(r#"echo "trailing\""#));
+    }
+
+    #[test]
+    fn substitutions_in_double_quotes_stay_in_the_word() {
+        let words = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        let word = r#""base: $(basename "$(pwd)") ) \" $(echo ")")""#;
+        assert_eq!(
+            words(&format!("echo {} next", word)),
+            [
+                Token::Word("echo".to_string()),
+                Token::Word(word.to_string()),
+                Token::Word("next".to_string()),
+            ]
+        );
+        let segments: Vec<(String, Quoting)> = word_segments(r#""a $(b "c)") d""#)
+            .into_iter()
+            .map(|(text, quoting)| (text.into_owned(), quoting))
+            .collect();
+        assert_eq!(segments, [("a $(b \"c)\") d".to_string(), Quoting::Double)]);
+    }
+
+    #[test]
+    fn heredocs_record_tab_stripping_and_quoting() {
+        let heredoc = |input: &str| {
+            Lexer::new(input)
+                .find_map(|token| match token {
+                    Token::Redirect(kind @ RedirectType::HereDoc { .. }) => Some(kind),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let kind = |strip_tabs, quoted| RedirectType::HereDoc { strip_tabs, quoted };
+        assert_eq!(heredoc("cat <<EOF\nx\nEOF\n"), kind(false, false));
+        assert_eq!(heredoc("cat <<-EOF\n\tx\n\tEOF\n"), kind(true, false));
+        assert_eq!(heredoc("cat <<'EOF'\nx\nEOF\n"), kind(false, true));
+        assert_eq!(heredoc("cat <<-\\EOF\nx\nEOF\n"), kind(true, true));
+    }
+
+    #[test]
+    fn backslashes_outside_quotes_make_the_next_character_literal() {
+        let tokens = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        let words = ["expr", "\\(", "2", "\\*", "3", "\\)", "\\<", "a\\ b", "\\;"];
+        assert_eq!(
+            tokens(&words.join(" ")),
+            words.map(|word| Token::Word(word.to_string()))
+        );
+        let segments = |word| -> Vec<(String, Quoting)> {
+            word_segments(word)
+                .into_iter()
+                .map(|(text, quoting)| (text.into_owned(), quoting))
+                .collect()
+        };
+        let run = |text: &str, quoting| (text.to_string(), quoting);
+        assert_eq!(
+            segments("a\\ b\\*"),
+            [
+                run("a", Quoting::Unquoted),
+                run(" ", Quoting::Literal),
+                run("b", Quoting::Unquoted),
+                run("*", Quoting::Literal),
+            ]
+        );
+        assert_eq!(
+            segments("\\$x\\é"),
+            [
+                run("$", Quoting::Literal),
+                run("x", Quoting::Unquoted),
+                run("é", Quoting::Literal)
+            ]
+        );
+        assert_eq!(segments("'a\\b'"), [run("a\\b", Quoting::Literal)]);
+        assert_eq!(segments("end\\"), [run("end\\", Quoting::Unquoted)]);
+    }
+
+    #[test]
+    fn brackets_are_words_alone_and_inside_words() {
+        let words = |input: &str| -> Vec<String> {
+            Lexer::new(input)
+                .map(|token| match token {
+                    Token::Word(word) => word,
+                    token => panic!("{:?} is not a word", token),
+                })
+                .collect()
+        };
+        assert_eq!(words("[ $x -eq 1 ]"), ["[", "$x", "-eq", "1", "]"]);
+        assert_eq!(words("[$x -eq 1]"), ["[$x", "-eq", "1]"]);
+        assert_eq!(
+            words("ls file[1] g/[ab]* \"[x]\" ]["),
+            ["ls", "file[1]", "g/[ab]*", "\"[x]\"", "]["]
+        );
+    }
+
+    #[test]
+    fn backslash_newline_continues_the_line() {
+        let tokens = |input: &str| Lexer::new(input).collect::<Vec<Token>>();
+        assert_eq!(tokens("echo a \\\n  b"), tokens("echo a b"));
+        let joined = |word: &str| -> String {
+            word_segments(word)
+                .into_iter()
+                .map(|(text, _)| text)
+                .collect()
+        };
+        assert_eq!(joined("a\\\nb"), "ab");
+        assert_eq!(joined("\"a\\\nb\""), "ab");
+        assert_eq!(joined("'a\\\nb'"), "a\\\nb");
+        assert!(is_incomplete("echo a \\\n"));
+        assert!(is_incomplete("echo a \\"));
+        assert!(!is_incomplete("echo a \\\\\n"));
+    }
+}
@@ -13,68 +13,303 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::utilities::utilities::{ASTNode, Token};
+use crate::lexer::lexer::Lexer;
+use crate::utilities::stack;
+use crate::utilities::utilities::{ASTNode, ListOperator, RedirectType, Redirection, Token};
+use std::iter::Peekable;
+use std::sync::Arc;
 
-pub struct Parser {
-    tokens: Vec<Token>,
-    position: usize,
+/// How deeply compound commands may nest, so that adversarial input is
+/// rejected rather than exhausting memory while it is parsed or run.
+pub const MAX_NESTING: usize = 200;
+
+/// The exit status of input with a syntax error, as in other shells.
+pub const SYNTAX_ERROR_STATUS: i32 = 2;
+
+/// Parses statements from any token source, holding only a single token of
+/// lookahead so scripts never need to be fully tokenized up front.
+pub struct Parser<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    line: usize,
+    /// The number of statements being parsed inside one another.
+    depth: usize,
 }
 
-impl Parser {
+impl Parser<std::vec::IntoIter<Token>> {
     pub fn new(tokens: Vec<Token>) -> Self {
         Parser {
-            tokens,
-            position: 0,
+            tokens: tokens.into_iter().peekable(),
+            line: 1,
+            depth: 0,
+        }
+    }
+}
+
+impl<'a> Parser<Lexer<'a>> {
+    pub fn from_lexer(lexer: Lexer<'a>) -> Self {
+        Parser {
+            tokens: lexer.peekable(),
+            line: 1,
+            depth: 0,
         }
     }
+}
 
+impl<I: Iterator<Item = Token>> Parser<I> {
     pub fn parse(&mut self) -> Result<Vec<ASTNode>, String> {
         let mut nodes = Vec::new();
-        while self.position < self.tokens.len() {
-            self.skip_newlines();
-            if self.position >= self.tokens.len() {
+        while !self.at_end() {
+            self.skip_separators();
+            if self.at_end() {
                 break;
             }
-            nodes.push(self.parse_statement()?);
+            nodes.push(self.parse_line()?);
         }
         Ok(nodes)
     }
 
-    fn current_token(&self) -> Option<&Token> {
-        self.tokens.get(self.position)
+    /// Parses the next top-level statement along with the line it starts on.
+    /// After a syntax error the rest of the offending line is skipped, so
+    /// callers can keep going and report every error in a script. Empty
+    /// statements, such as a lone `;`, are skipped.
+    pub fn next_statement(&mut self) -> Option<(usize, Result<ASTNode, String>)> {
+        self.skip_separators();
+        if self.at_end() {
+            return None;
+        }
+        let line = self.line;
+        let result = self.parse_line();
+        if result.is_err() {
+            while !matches!(self.tokens.peek(), None | Some(Token::NewLine)) {
+                self.tokens.next();
+            }
+        }
+        Some((line, result))
+    }
+
+    /// The line the parser has reached in its input.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.tokens.peek().is_none()
+    }
+
+    fn current_token(&mut self) -> Option<&Token> {
+        self.tokens.peek()
     }
 
     fn consume_token(&mut self) -> Result<(), String> {
-        if self.position < self.tokens.len() {
-            self.position += 1;
-            Ok(())
-        } else {
-            Err("Unexpected end of input".to_string())
+        match self.tokens.next() {
+            Some(_) => Ok(()),
+            None => Err("Unexpected end of input".to_string()),
+        }
+    }
+
+    /// Parses a statement, tagging it with the line it starts on so runtime
+    /// errors can say where they happened. Every nested statement comes
+    /// through here, so this is where nesting is limited.
+    fn parse_line(&mut self) -> Result<ASTNode, String> {
+        if self.depth >= MAX_NESTING {
+            return Err(format!("Commands nested more than {} deep", MAX_NESTING));
         }
+        let line = self.line;
+        self.depth += 1;
+        let statement = stack::grow(|| self.parse_statement());
+        self.depth -= 1;
+        Ok(ASTNode::Line {
+            line,
+            node: Box::new(statement?),
+        })
     }
 
     fn parse_statement(&mut self) -> Result<ASTNode, String> {
-        match self.current_token() {
-            Some(Token::Word(w)) if w == "if" => self.parse_if(),
-            Some(Token::Word(w)) if w == "while" => self.parse_while(),
-            Some(Token::Word(w)) if w == "for" => self.parse_for(),
-            Some(Token::Word(w)) if w == "case" => self.parse_case(),
-            _ => self.parse_command_or_assignment(),
+        if self.current_token() == Some(&Token::Function) {
+            return self.parse_function();
+        }
+        let statement = self.parse_and_or()?;
+        if self.tokens.next_if_eq(&Token::Ampersand).is_some() {
+            Ok(ASTNode::Background(Box::new(statement)))
+        } else {
+            Ok(statement)
+        }
+    }
+
+    /// Parses pipelines joined by `&&` and `||`, which may be followed by
+    /// newlines. The list is kept flat and run left to right, so a long
+    /// chain never nests.
+    fn parse_and_or(&mut self) -> Result<ASTNode, String> {
+        let first = self.parse_timed_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.current_token() {
+                Some(Token::And) => ListOperator::And,
+                Some(Token::Or) => ListOperator::Or,
+                _ => break,
+            };
+            self.consume_token()?;
+            self.skip_newlines();
+            rest.push((op, self.parse_timed_pipeline()?));
+        }
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            Ok(ASTNode::AndOr {
+                first: Box::new(first),
+                rest,
+            })
+        }
+    }
+
+    fn parse_timed_pipeline(&mut self) -> Result<ASTNode, String> {
+        if self.tokens.next_if_eq(&Token::Time).is_some() {
+            Ok(ASTNode::Time(Box::new(self.parse_pipeline()?)))
+        } else {
+            self.parse_pipeline()
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<ASTNode, String> {
+        let first = self.parse_pipeline_stage()?;
+        if self.current_token() != Some(&Token::Pipe) {
+            return Ok(first);
+        }
+        let mut commands = vec![first];
+        while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+            self.skip_newlines();
+            commands.push(self.parse_pipeline_stage()?);
+        }
+        Ok(ASTNode::Pipeline(commands))
+    }
+
+    fn parse_pipeline_stage(&mut self) -> Result<ASTNode, String> {
+        let compound = match self.current_token() {
+            Some(Token::If) => self.parse_if()?,
+            Some(Token::While) => self.parse_while()?,
+            Some(Token::For) => self.parse_for()?,
+            Some(Token::Case) => self.parse_case()?,
+            Some(Token::LeftBrace) => self.parse_brace_group()?,
+            Some(Token::Arithmetic(_)) => match self.tokens.next() {
+                Some(Token::Arithmetic(expression)) => ASTNode::Expression(expression),
+                _ => unreachable!(),
+            },
+            Some(Token::RightBrace) => return Err("Unexpected }".to_string()),
+            // Keywords that only continue or close a compound command.
+            Some(
+                token @ (Token::Then
+                | Token::Elif
+                | Token::Else
+                | Token::Fi
+                | Token::Do
+                | Token::Done
+                | Token::Esac),
+            ) => {
+                let text = keyword_text(token).unwrap_or_default();
+                return Err(format!("Unexpected '{}'", text));
+            }
+            _ => return self.parse_command_or_assignment(),
+        };
+        let mut redirects = Vec::new();
+        while self.parse_redirection(&mut redirects)? {}
+        Ok(Self::with_redirects(compound, redirects))
+    }
+
+    /// Parses a `[fd]op target` redirection if one starts at the current token,
+    /// appending it to `redirects`. `&> file`, `&>> file` and `>& file` are
+    /// desugared into `> file 2>&1` (or `>>`), so the file is opened once and
+    /// both streams share it.
+    fn parse_redirection(&mut self, redirects: &mut Vec<Redirection>) -> Result<bool, String> {
+        if let Some(Token::RedirectAll(kind)) = self.tokens.peek() {
+            let kind = kind.clone();
+            self.tokens.next();
+            let target = self.expect_word()?;
+            Self::push_redirect_all(redirects, kind, target);
+            return Ok(true);
+        }
+        let fd = match self.tokens.peek() {
+            Some(Token::IoNumber(fd)) => {
+                let fd = *fd;
+                self.tokens.next();
+                Some(fd)
+            }
+            _ => None,
+        };
+        let kind = match self.tokens.peek() {
+            Some(Token::Redirect(kind)) => kind.clone(),
+            Some(token) if fd.is_some() => {
+                return Err(format!("Expected redirection operator, found {:?}", token))
+            }
+            _ => return Ok(false),
+        };
+        self.tokens.next();
+        let target = self.expect_word()?;
+        let names_file =
+            target != "-" && !target.contains('$') && !target.bytes().all(|b| b.is_ascii_digit());
+        if fd.is_none() && kind == RedirectType::DuplicateOutput && names_file {
+            Self::push_redirect_all(redirects, RedirectType::Output, target);
+            return Ok(true);
+        }
+        redirects.push(Redirection {
+            fd: fd.unwrap_or_else(|| kind.default_fd()),
+            kind,
+            target,
+        });
+        Ok(true)
+    }
+
+    fn push_redirect_all(redirects: &mut Vec<Redirection>, kind: RedirectType, target: String) {
+        redirects.push(Redirection {
+            fd: 1,
+            kind,
+            target,
+        });
+        redirects.push(Redirection {
+            fd: 2,
+            kind: RedirectType::DuplicateOutput,
+            target: "1".to_string(),
+        });
+    }
+
+    fn with_redirects(node: ASTNode, redirects: Vec<Redirection>) -> ASTNode {
+        if redirects.is_empty() {
+            node
+        } else {
+            ASTNode::Redirect {
+                node: Box::new(node),
+                redirects,
+            }
         }
     }
 
+    /// Parses `if ... then ... [elif ... then ...] ... [else ...] fi`. Each
+    /// `elif` becomes an `if` in the `else` branch of the arm before it.
     fn parse_if(&mut self) -> Result<ASTNode, String> {
         self.consume_token()?; // Consume 'if'
         let condition = self.parse_condition()?;
-        self.expect_token(&Token::Then)?;
-        let then_block = self.parse_block("else", "fi")?;
-        let else_block = if self.current_token_is("else") {
+        let then_block = self.parse_then_block()?;
+        let mut elif_arms = Vec::new();
+        while self.tokens.next_if_eq(&Token::Elif).is_some() {
+            if self.depth + elif_arms.len() >= MAX_NESTING {
+                return Err(format!("Commands nested more than {} deep", MAX_NESTING));
+            }
+            let condition = self.parse_condition()?;
+            elif_arms.push((condition, self.parse_then_block()?));
+        }
+        let mut else_block = if self.current_token_is("else") {
             self.consume_token()?;
-            Some(Box::new(self.parse_block("fi", "fi")?))
+            Some(Box::new(self.parse_block(&["fi"])?))
         } else {
             None
         };
         self.expect_token(&Token::Fi)?;
+        while let Some((condition, then_block)) = elif_arms.pop() {
+            else_block = Some(Box::new(ASTNode::If {
+                condition: Box::new(condition),
+                then_block: Box::new(then_block),
+                else_block,
+            }));
+        }
         Ok(ASTNode::If {
             condition: Box::new(condition),
             then_block: Box::new(then_block),
@@ -82,58 +317,54 @@ impl Parser {
         })
     }
 
+    fn parse_then_block(&mut self) -> Result<ASTNode, String> {
+        self.skip_separators();
+        self.expect_token(&Token::Then)?;
+        self.parse_block(&["elif", "else", "fi"])
+    }
+
+    /// Parses the condition of `if` or `while`, a command whose exit status
+    /// decides. `[ ... ]` is the `[` builtin like any other command.
     fn parse_condition(&mut self) -> Result<ASTNode, String> {
-        self.expect_token(&Token::LeftBracket)?;
-        let left = self.parse_expression()?.to_string();
-        let op = self.expect_word()?;
-        let right = self.parse_expression()?.to_string();
-        self.expect_token(&Token::RightBracket)?;
-        Ok(ASTNode::Comparison { left, op, right })
-    }
-
-    fn parse_expression(&mut self) -> Result<ASTNode, String> {
-        let left = self.expect_word()?;
-        if self.current_token_is("-eq")
-            || self.current_token_is("-ne")
-            || self.current_token_is("-lt")
-            || self.current_token_is("-le")
-            || self.current_token_is("-gt")
-            || self.current_token_is("-ge")
-        {
-            let op = self.expect_word()?;
-            let right = self.expect_word()?;
-            Ok(ASTNode::Comparison { left, op, right })
-        } else {
-            Ok(ASTNode::Expression(left))
-        }
+        self.parse_and_or()
     }
 
+    /// Parses `case word in ... esac`. Each arm is an optional `(`, its
+    /// patterns separated by `|`, a `)` and the commands run on a match,
+    /// ended by `;;` or, for the last arm, by `esac` alone.
     fn parse_case(&mut self) -> Result<ASTNode, String> {
         self.consume_token()?; // Consume 'case'
-        let var = self.parse_expression()?;
+        let word = self.expect_word()?;
+        self.skip_newlines();
         self.expect_token(&Token::In)?;
         let mut cases = Vec::new();
-        while !self.current_token_is("esac") {
-            let pattern = self.parse_expression()?;
+        loop {
+            self.skip_separators();
+            if self.tokens.next_if_eq(&Token::Esac).is_some() {
+                break;
+            }
+            self.tokens.next_if_eq(&Token::LeftParen);
+            let mut patterns = vec![self.expect_word()?];
+            while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+                patterns.push(self.expect_word()?);
+            }
             self.expect_token(&Token::RightParen)?;
-            let block = self.parse_block(";;", "esac")?;
-            cases.push((pattern, block));
-            if self.current_token_is(";;") {
-                self.consume_token()?;
+            let block = self.parse_block(&["esac"])?;
+            cases.push((patterns, block));
+            if self.tokens.next_if_eq(&Token::DoubleSemicolon).is_none() {
+                self.expect_token(&Token::Esac)?;
+                break;
             }
         }
-        self.expect_token(&Token::Esac)?;
-        Ok(ASTNode::Case {
-            var: Box::new(var),
-            cases,
-        })
+        Ok(ASTNode::Case { word, cases })
     }
 
     fn parse_while(&mut self) -> Result<ASTNode, String> {
         self.consume_token()?; // Consume 'while'
         let condition = self.parse_condition()?;
+        self.skip_separators();
         self.expect_token(&Token::Do)?;
-        let block = self.parse_block("done", "done")?;
+        let block = self.parse_block(&["done"])?;
         self.expect_token(&Token::Done)?;
         Ok(ASTNode::While {
             condition: Box::new(condition),
@@ -141,13 +372,19 @@ impl Parser {
         })
     }
 
+    /// Parses `for var in list; do ...; done`, or `for var; do ...; done`
+    /// looping over the positional parameters.
     fn parse_for(&mut self) -> Result<ASTNode, String> {
         self.consume_token()?; // Consume 'for'
         let var = self.expect_word()?;
-        self.expect_token(&Token::In)?;
-        let list = self.parse_list()?;
+        let list = if self.tokens.next_if_eq(&Token::In).is_some() {
+            Some(self.parse_list()?)
+        } else {
+            self.skip_separators();
+            None
+        };
         self.expect_token(&Token::Do)?;
-        let block = self.parse_block("done", "done")?;
+        let block = self.parse_block(&["done"])?;
         self.expect_token(&Token::Done)?;
         Ok(ASTNode::For {
             var,
@@ -156,35 +393,64 @@ impl Parser {
         })
     }
 
+    /// Parses `function name { ...; }` or `function name() { ...; }`. The
+    /// body may span several lines. `name() { ...; }` without the keyword is
+    /// parsed by `parse_command_or_assignment`.
     fn parse_function(&mut self) -> Result<ASTNode, String> {
-        self.position += 1; // Consume 'function'
+        self.consume_token()?; // Consume 'function'
         let name = self.expect_word()?;
+        if self.tokens.next_if_eq(&Token::LeftParen).is_some() {
+            self.expect_token(&Token::RightParen)?;
+        }
+        self.parse_function_body(name)
+    }
+
+    fn parse_function_body(&mut self, name: String) -> Result<ASTNode, String> {
         self.skip_newlines();
-        self.expect_token(&Token::LeftParen)?;
-        self.skip_newlines();
-        let body = Box::new(self.parse_block(")", ")")?);
-        self.expect_token(&Token::RightParen)?;
+        let body = Arc::new(self.parse_brace_group()?);
         Ok(ASTNode::Function { name, body })
     }
 
-    fn parse_block(&mut self, end_token1: &str, end_token2: &str) -> Result<ASTNode, String> {
+    /// Parses `{ ...; }`, whose statements may span several lines. The
+    /// closing brace is required.
+    fn parse_brace_group(&mut self) -> Result<ASTNode, String> {
+        let line = self.line;
+        self.expect_token(&Token::LeftBrace)?;
         let mut statements = Vec::new();
-        while self.position < self.tokens.len()
-            && !self.current_token_is(end_token1)
-            && !self.current_token_is(end_token2)
-        {
-            self.skip_newlines();
-            if self.current_token_is(end_token1) || self.current_token_is(end_token2) {
+        loop {
+            self.skip_separators();
+            match self.tokens.peek() {
+                None => return Err(format!("Unbalanced {{ opened on line {}", line)),
+                Some(Token::RightBrace) => {
+                    self.tokens.next();
+                    return Ok(ASTNode::Block(statements));
+                }
+                _ => statements.push(self.parse_line()?),
+            }
+        }
+    }
+
+    /// Parses statements up to either end keyword, or the `;;` ending a
+    /// `case` arm, which is left for the caller.
+    fn parse_block(&mut self, ends: &[&str]) -> Result<ASTNode, String> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.at_end()
+                || ends.iter().any(|end| self.current_token_is(end))
+                || self.tokens.peek() == Some(&Token::DoubleSemicolon)
+            {
                 break;
             }
-            statements.push(self.parse_statement()?);
+            statements.push(self.parse_line()?);
         }
         Ok(ASTNode::Block(statements))
     }
 
+    #[allow(dead_code)]
     fn parse_command(&mut self) -> Result<ASTNode, String> {
         let mut args = Vec::new();
-        while self.position < self.tokens.len() && !self.is_command_end() {
+        while !self.is_command_end() {
             args.push(self.expect_word()?);
         }
         if args.is_empty() {
@@ -205,120 +471,542 @@ impl Parser {
         }
     }
 
+    /// Parses the words of a `for` list, which ends at a newline or `;`
+    /// rather than at `do`, so `for x in do re mi` loops over three words.
     fn parse_list(&mut self) -> Result<Vec<String>, String> {
         let mut list = Vec::new();
-        while !self.current_token_is("do") {
+        while !self.is_command_end() {
             list.push(self.expect_word()?);
-            self.skip_newlines();
         }
+        self.skip_separators();
         Ok(list)
     }
 
     fn expect_word(&mut self) -> Result<String, String> {
-        if self.position >= self.tokens.len() {
-            return Err("Unexpected end of input".to_string());
-        }
-        match &self.tokens[self.position] {
-            Token::Word(w) | Token::String(w) => {
-                self.position += 1;
-                Ok(w.clone())
+        match self.tokens.peek() {
+            None => Err("Unexpected end of input".to_string()),
+            Some(Token::Word(_)) | Some(Token::String(_)) => match self.tokens.next() {
+                Some(Token::Word(w)) | Some(Token::String(w)) => Ok(w),
+                _ => unreachable!(),
+            },
+            Some(Token::LeftBrace) => {
+                self.tokens.next();
+                Ok("{".to_string())
             }
-            Token::If
-            | Token::Then
-            | Token::Else
-            | Token::Fi
-            | Token::While
-            | Token::Do
-            | Token::Done
-            | Token::For
-            | Token::In
-            | Token::Case
-            | Token::Esac => {
-                let word = format!("{:?}", self.tokens[self.position]);
-                self.position += 1;
-                Ok(word)
+            Some(Token::RightBrace) => {
+                self.tokens.next();
+                Ok("}".to_string())
             }
-            _ => Err(format!(
-                "Expected word, found {:?}",
-                self.tokens[self.position]
-            )),
+            Some(Token::Error(e)) => Err(e.clone()),
+            // Outside of command position a keyword is an ordinary word, so
+            // `echo done` prints `done`.
+            Some(token) => match keyword_text(token) {
+                Some(text) => {
+                    self.tokens.next();
+                    Ok(text.to_string())
+                }
+                None => Err(format!("Expected word, found {:?}", token)),
+            },
         }
     }
 
     fn expect_token(&mut self, expected: &Token) -> Result<(), String> {
-        if self.position >= self.tokens.len() {
-            return Err(format!("Expected {:?}, found end of input", expected));
-        }
-        if self.tokens[self.position] == *expected {
-            self.position += 1;
-            Ok(())
-        } else {
-            Err(format!(
-                "Expected {:?}, found {:?}",
-                expected, self.tokens[self.position]
-            ))
+        match self.tokens.peek() {
+            None => Err(format!("Expected {:?}, found end of input", expected)),
+            Some(token) if token == expected => {
+                self.tokens.next();
+                Ok(())
+            }
+            Some(token) => Err(format!("Expected {:?}, found {:?}", expected, token)),
         }
     }
 
-    fn current_token_is(&self, token: &str) -> bool {
-        if self.position >= self.tokens.len() {
-            return false;
-        }
-        match &self.tokens[self.position] {
-            Token::Word(w) => w.eq_ignore_ascii_case(token),
-            _ => false,
+    /// Whether the current token is the word or keyword `token`, matched
+    /// exactly: keywords are lowercase, so `Done` is an ordinary word.
+    fn current_token_is(&mut self, token: &str) -> bool {
+        match self.tokens.peek() {
+            Some(Token::Word(w)) => w == token,
+            Some(keyword) => keyword_text(keyword) == Some(token),
+            None => false,
         }
     }
 
     fn skip_newlines(&mut self) {
-        while self.position < self.tokens.len() && self.tokens[self.position] == Token::NewLine {
-            self.position += 1;
+        while self.tokens.next_if_eq(&Token::NewLine).is_some() {
+            self.line += 1;
+        }
+    }
+
+    /// Skips the newlines and semicolons between the statements of a block.
+    fn skip_separators(&mut self) {
+        loop {
+            self.skip_newlines();
+            if self.tokens.next_if_eq(&Token::Semicolon).is_none() {
+                break;
+            }
         }
     }
 
+    #[allow(dead_code)]
     fn skip_newlines_and_expect(&mut self, expected: &str) -> Result<(), String> {
         self.skip_newlines();
-        if self.position >= self.tokens.len() {
+        if self.at_end() {
             return Err(format!("Expected {}, found end of input", expected));
         }
         if self.current_token_is(expected) {
-            self.position += 1;
+            self.tokens.next();
             Ok(())
         } else {
             Err(format!(
                 "Expected {}, found {:?}",
-                expected, self.tokens[self.position]
+                expected,
+                self.tokens.peek()
             ))
         }
     }
 
-    fn is_command_end(&self) -> bool {
-        self.position >= self.tokens.len()
-            || matches!(
-                self.tokens[self.position],
-                Token::Semicolon | Token::NewLine
-            )
-            || self.current_token_is("then")
-            || self.current_token_is("do")
-            || self.current_token_is("done")
-            || self.current_token_is("fi")
-            || self.current_token_is("else")
-            || self.current_token_is("elif")
-            || self.current_token_is("esac")
+    fn is_command_end(&mut self) -> bool {
+        matches!(
+            self.tokens.peek(),
+            None | Some(Token::Semicolon)
+                | Some(Token::NewLine)
+                | Some(Token::Ampersand)
+                | Some(Token::Pipe)
+                | Some(Token::And)
+                | Some(Token::Or)
+                | Some(Token::DoubleSemicolon)
+        )
+    }
+
+    /// Recognizes a statement's first word as an assignment: `NAME=value`
+    /// as one word, or `NAME = value` written with spaces, whose `=` and
+    /// value are consumed. An `=` anywhere else is part of an ordinary word.
+    fn parse_assignment(&mut self, word: &str) -> Result<Option<(String, String)>, String> {
+        if let Some((name, value)) = word.split_once('=') {
+            return Ok(is_identifier(name).then(|| (name.to_string(), value.to_string())));
+        }
+        if !is_identifier(word) || !matches!(self.tokens.peek(), Some(Token::Word(w)) if w == "=") {
+            return Ok(None);
+        }
+        self.tokens.next();
+        let value = if self.is_command_end() {
+            String::new()
+        } else {
+            self.expect_word()?
+        };
+        Ok(Some((word.to_string(), value)))
     }
 
     fn parse_command_or_assignment(&mut self) -> Result<ASTNode, String> {
         let name = self.expect_word()?;
-        if self.position < self.tokens.len() && self.tokens[self.position] == Token::Assignment {
-            self.position += 1;
-            let value = self.expect_word()?;
-            Ok(ASTNode::Assignment { name, value })
+        if self.tokens.next_if_eq(&Token::LeftParen).is_some() {
+            self.expect_token(&Token::RightParen)?;
+            return self.parse_function_body(name);
+        }
+        if let Some((name, value)) = self.parse_assignment(&name)? {
+            if self.is_command_end() {
+                return Ok(ASTNode::Assignment { name, value });
+            }
+            // Assignments before a command only last for that command, while
+            // a statement of assignments alone sets them all.
+            Ok(match self.parse_command_or_assignment()? {
+                ASTNode::Environment {
+                    mut assignments,
+                    node,
+                } => {
+                    assignments.insert(0, (name, value));
+                    ASTNode::Environment { assignments, node }
+                }
+                node @ ASTNode::Assignment { .. } => {
+                    ASTNode::Block(vec![ASTNode::Assignment { name, value }, node])
+                }
+                ASTNode::Block(mut statements) => {
+                    statements.insert(0, ASTNode::Assignment { name, value });
+                    ASTNode::Block(statements)
+                }
+                node => ASTNode::Environment {
+                    assignments: vec![(name, value)],
+                    node: Box::new(node),
+                },
+            })
         } else {
             let mut args = Vec::new();
-            while self.position < self.tokens.len() && !self.is_command_end() {
-                args.push(self.expect_word()?);
+            let mut redirects = Vec::new();
+            while !self.is_command_end() {
+                if !self.parse_redirection(&mut redirects)? {
+                    args.push(self.expect_word()?);
+                }
             }
-            Ok(ASTNode::Command { name, args })
+            Ok(Self::with_redirects(
+                ASTNode::Command { name, args },
+                redirects,
+            ))
+        }
+    }
+}
+
+/// Whether `name` can be assigned to: a letter or underscore followed by
+/// letters, digits and underscores.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The text of a keyword token, as written.
+fn keyword_text(token: &Token) -> Option<&'static str> {
+    Some(match token {
+        Token::If => "if",
+        Token::Then => "then",
+        Token::Else => "else",
+        Token::Elif => "elif",
+        Token::Fi => "fi",
+        Token::While => "while",
+        Token::Do => "do",
+        Token::Done => "done",
+        Token::For => "for",
+        Token::In => "in",
+        Token::Case => "case",
+        Token::Esac => "esac",
+        Token::Function => "function",
+        Token::Time => "time",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::fuzzing::{assert_no_panic, corpus, on_thread_stack, random_inputs};
+
+    /// Parses `input` from the lexer, checking that parsing the collected
+    /// tokens with `Parser::new` gives the same result.
+    fn parse(input: &str) -> Result<Vec<ASTNode>, String> {
+        let streamed = Parser::from_lexer(Lexer::new(input)).parse();
+        let collected = Parser::new(Lexer::new(input).tokenize()).parse();
+        assert_eq!(
+            format!("{:?}", streamed),
+            format!("{:?}", collected),
+            "{}",
+            input
+        );
+        streamed
+    }
+
+    fn parse_one(input: &str) -> String {
+        let nodes = parse(input).unwrap();
+        assert_eq!(nodes.len(), 1, "{:?}", nodes);
+        nodes[0].to_string()
+    }
+
+    #[test]
+    fn blank_input_and_stray_separators_parse_to_nothing() {
+        for input in [
+            "",
+            "   ",
+            " \t \n\n",
+            ";",
+            " ; ;\n;",
+            "   # only a comment",
+            "\n  #a\n ;\n",
+        ] {
+            assert!(matches!(parse(input).as_deref(), Ok([])), "{:?}", input);
         }
+        assert_eq!(parse_one("; echo a ;"), "echo a");
+        assert_eq!(parse_one("if true; then :; fi"), "if true; then :; fi");
+    }
+
+    #[test]
+    fn elif_arms_nest_in_else_branches() {
+        assert_eq!(
+            parse_one("if [ $x = 1 ]; then echo one; elif [ $x = 2 ]; then echo two; fi"),
+            "if [ $x = 1 ]; then echo one; else if [ $x = 2 ]; then echo two; fi; fi"
+        );
+        assert_eq!(
+            parse_one("if a\nthen b\nelif c\nthen d\nelif e; then f\nelse\n  g\nfi"),
+            "if a; then b; else if c; then d; else if e; then f; else g; fi; fi; fi"
+        );
+    }
+
+    #[test]
+    fn redirections_keep_their_descriptor_numbers() {
+        assert_eq!(
+            parse_one("cmd 3>&1 1>&2 2>&3 4>&- 5< in 6>> log 7<&0"),
+            "cmd 3>&1 >&2 2>&3 4>&- 5<in 6>>log 7<&0"
+        );
+    }
+
+    #[test]
+    fn ampersand_before_a_redirection_redirects_both_streams() {
+        assert_eq!(parse_one("cmd &> all"), "cmd >all 2>&1");
+        assert_eq!(parse_one("cmd &>> all"), "cmd >>all 2>&1");
+        assert_eq!(parse_one("cmd >& all"), "cmd >all 2>&1");
+        let nodes: Vec<String> = parse("a & b > out")
+            .unwrap()
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(nodes, ["a &", "b >out"]);
+    }
+
+    #[test]
+    fn semicolons_separate_top_level_statements() {
+        let nodes = parse("a; b\n; ; c").unwrap_or_else(|e| panic!("{}", e));
+        let nodes: Vec<String> = nodes
+            .iter()
+            .map(|node| node.to_string().trim_end().to_string())
+            .collect();
+        assert_eq!(nodes, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn here_strings_take_a_word() {
+        assert_eq!(parse_one("read a b <<< \"x y\""), "read a b <<<\"x y\"");
+        assert_eq!(parse_one("cat 3<<<$v"), "cat 3<<<$v");
+        assert!(parse("cat <<<").is_err());
+    }
+
+    #[test]
+    fn function_keyword_takes_a_brace_group_with_or_without_parentheses() {
+        for input in [
+            "function greet { echo hi; echo there; }",
+            "function greet() { echo hi; echo there; }",
+            "function greet\n{\n  echo hi\n  echo there\n}",
+            "function greet ( )\n{ echo hi; echo there\n}",
+        ] {
+            let nodes = parse(input).unwrap_or_else(|e| panic!("{}: {}", input, e));
+            match nodes.as_slice() {
+                [ASTNode::Line { node, .. }] => match node.as_ref() {
+                    ASTNode::Function { name, body } => {
+                        assert_eq!(name, "greet", "{}", input);
+                        assert_eq!(body.to_string(), "{ echo hi; echo there; }", "{}", input);
+                    }
+                    _ => panic!("{}: {:?}", input, node),
+                },
+                _ => panic!("{}: {:?}", input, nodes),
+            }
+        }
+        for input in ["function", "function greet", "function greet ( echo hi; )"] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn brace_groups_must_be_closed() {
+        assert_eq!(
+            parse_one("{ a\n  # comment }\n  if b; then c; fi\n}"),
+            "{ a; if b; then c; fi; }"
+        );
+        assert_eq!(
+            parse("echo a\nf() {\n  echo b\n  { c; }\n").unwrap_err(),
+            "Unbalanced { opened on line 2"
+        );
+        assert!(parse("{ a; }}").is_err());
+    }
+
+    #[test]
+    fn equals_signs_only_assign_in_a_statements_first_words() {
+        let command = |input: &str| match parse(input).unwrap().as_slice() {
+            [ASTNode::Line { node, .. }] => match node.as_ref() {
+                ASTNode::Command { name, args } => (name.clone(), args.clone()),
+                node => panic!("{}: {:?}", input, node),
+            },
+            nodes => panic!("{}: {:?}", input, nodes),
+        };
+        assert_eq!(
+            command("grep name=value file"),
+            (
+                "grep".to_string(),
+                vec!["name=value".to_string(), "file".to_string()]
+            )
+        );
+        assert_eq!(
+            command("curl -d a=b"),
+            (
+                "curl".to_string(),
+                vec!["-d".to_string(), "a=b".to_string()]
+            )
+        );
+        assert_eq!(
+            command("make CC=clang"),
+            ("make".to_string(), vec!["CC=clang".to_string()])
+        );
+        assert_eq!(
+            command("1x=2 a"),
+            ("1x=2".to_string(), vec!["a".to_string()])
+        );
+        for (input, expected) in [
+            ("v=1", ("v", "1")),
+            ("v = 1", ("v", "1")),
+            ("v=a=b", ("v", "a=b")),
+            ("v=", ("v", "")),
+            ("v =", ("v", "")),
+        ] {
+            match parse(input).unwrap().as_slice() {
+                [ASTNode::Line { node, .. }] => match node.as_ref() {
+                    ASTNode::Assignment { name, value } => {
+                        assert_eq!((name.as_str(), value.as_str()), expected, "{}", input)
+                    }
+                    node => panic!("{}: {:?}", input, node),
+                },
+                nodes => panic!("{}: {:?}", input, nodes),
+            }
+        }
+        match parse("CC=clang make CFLAGS=-O2").unwrap().as_slice() {
+            [ASTNode::Line { node, .. }] => match node.as_ref() {
+                ASTNode::Environment { assignments, node } => {
+                    assert_eq!(assignments, &[("CC".to_string(), "clang".to_string())]);
+                    assert_eq!(node.to_string(), "make CFLAGS=-O2");
+                }
+                node => panic!("{:?}", node),
+            },
+            nodes => panic!("{:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn elif_needs_then() {
+        assert!(parse("if a; then b; elif c; fi").is_err());
+        assert!(parse("if a; then b; elif; then c; fi").is_err());
+        assert!(parse("if a; then b; else c; elif d; then e; fi").is_err());
+    }
+
+    #[test]
+    fn closing_keywords_do_not_start_commands() {
+        for input in [
+            "then echo a",
+            "elif true",
+            "fi",
+            "echo a; done",
+            "esac",
+            "do x",
+        ] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+        assert_eq!(parse_one("echo then fi done"), "echo then fi done");
+    }
+
+    #[test]
+    fn keywords_are_only_reserved_where_a_command_starts() {
+        let node = |input: &str| match parse(input).unwrap().as_slice() {
+            [ASTNode::Line { node, .. }] => node.as_ref().clone(),
+            nodes => panic!("{}: {:?}", input, nodes),
+        };
+        let words = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        for (input, args) in [
+            ("echo if then fi", words(&["if", "then", "fi"])),
+            ("echo for in do done", words(&["for", "in", "do", "done"])),
+            (
+                "git stash drop case esac",
+                words(&["stash", "drop", "case", "esac"]),
+            ),
+        ] {
+            match node(input) {
+                ASTNode::Command { args: parsed, .. } => assert_eq!(parsed, args, "{}", input),
+                node => panic!("{}: {:?}", input, node),
+            }
+        }
+        match node("Done If Then") {
+            ASTNode::Command { name, args, .. } => {
+                assert_eq!((name.as_str(), args), ("Done", words(&["If", "Then"])))
+            }
+            node => panic!("{:?}", node),
+        }
+        match node("for In in a b; do echo $In; done") {
+            ASTNode::For { var, .. } => assert_eq!(var, "In"),
+            node => panic!("{:?}", node),
+        }
+        match node("for in in a b; do echo $in; done") {
+            ASTNode::For { var, list, .. } => {
+                assert_eq!((var.as_str(), list), ("in", Some(words(&["a", "b"]))))
+            }
+            node => panic!("{:?}", node),
+        }
+        let conditional = "if true; then echo y; fi";
+        assert!(matches!(node(conditional), ASTNode::If { .. }));
+        assert_eq!(parse_one(conditional), conditional);
+    }
+
+    #[test]
+    fn and_or_lists_run_left_to_right_and_continue_after_operators() {
+        let list = |input: &str| match parse(input).unwrap().as_slice() {
+            [ASTNode::Line { node, .. }] => match node.as_ref() {
+                ASTNode::AndOr { first, rest } => {
+                    let rest: Vec<(ListOperator, String)> = rest
+                        .iter()
+                        .map(|(op, node)| (*op, node.to_string().trim_end().to_string()))
+                        .collect();
+                    (first.to_string().trim_end().to_string(), rest)
+                }
+                node => panic!("{}: {:?}", input, node),
+            },
+            nodes => panic!("{}: {:?}", input, nodes),
+        };
+        let expected = (
+            "a".to_string(),
+            vec![
+                (ListOperator::And, "b".to_string()),
+                (ListOperator::Or, "c | d".to_string()),
+                (ListOperator::And, "e".to_string()),
+            ],
+        );
+        assert_eq!(list("a && b || c | d && e"), expected);
+        assert_eq!(list("a &&\n  b ||\n\n  c | d &&\n e"), expected);
+        assert_eq!(parse("a &&\nb\nc").unwrap().len(), 2);
+        for input in ["a &&", "&& b", "a || || b", "a && ; b"] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+    }
+
+    /// Parses all of `input`, statement by statement, past syntax errors,
+    /// both from the lexer and from the collected tokens, which must agree.
+    fn parse_all(input: &str) {
+        let mut streamed = Parser::from_lexer(Lexer::new(input));
+        let mut collected = Parser::new(Lexer::new(input).tokenize());
+        loop {
+            let statement = streamed.next_statement();
+            assert_eq!(
+                format!("{:?}", statement),
+                format!("{:?}", collected.next_statement())
+            );
+            if statement.is_none() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_that_once_crashed_parse_without_panicking() {
+        for input in corpus() {
+            assert_no_panic(&input, parse_all);
+        }
+    }
+
+    #[test]
+    fn random_input_parses_without_panicking() {
+        for input in random_inputs() {
+            assert_no_panic(&input, parse_all);
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| "{ ".repeat(depth) + ":; " + &"} ".repeat(depth);
+        let ifs = "if a; then ".repeat(5000) + "b; " + &"fi; ".repeat(5000);
+        let (within, past, deep_ifs) = on_thread_stack(|| {
+            (
+                parse(&nested(MAX_NESTING - 1)).is_ok(),
+                parse(&nested(MAX_NESTING)).err(),
+                parse(&ifs).is_err(),
+            )
+        })
+        .unwrap();
+        assert!(within && deep_ifs);
+        assert_eq!(
+            past,
+            Some(format!("Commands nested more than {} deep", MAX_NESTING))
+        );
     }
 }
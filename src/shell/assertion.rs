@@ -0,0 +1,105 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// Expanded words as they would have to be written, quoting those that are
+/// empty or hold blanks so the operands of a failed assertion can be told
+/// apart.
+pub fn expanded_words(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|word| {
+            if word.is_empty() || word.contains([' ', '\t', '\n', '"']) {
+                format!("\"{}\"", word.replace('"', "\\\""))
+            } else {
+                word.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Describes how `left` differs from `right` for `assert_eq`, one line per
+/// entry. Single lines are shown one above the other with a caret under the
+/// first differing character; multiline strings by the lines that differ.
+pub fn difference(left: &str, right: &str) -> Vec<String> {
+    if !left.contains('\n') && !right.contains('\n') {
+        let column = left
+            .chars()
+            .zip(right.chars())
+            .take_while(|(l, r)| l == r)
+            .count();
+        return vec![
+            format!("left:  \"{}\"", left),
+            format!("right: \"{}\"", right),
+            format!("{:>1$}", "^", column + 9),
+        ];
+    }
+
+    let left_lines: Vec<&str> = left.split('\n').collect();
+    let right_lines: Vec<&str> = right.split('\n').collect();
+    let mut lines = Vec::new();
+    for i in 0..left_lines.len().max(right_lines.len()) {
+        let (l, r) = (left_lines.get(i), right_lines.get(i));
+        if l == r {
+            continue;
+        }
+        lines.push(format!("line {}:", i + 1));
+        lines.push(match l {
+            Some(l) => format!("  - {}", l),
+            None => "  - (no line)".to_string(),
+        });
+        lines.push(match r {
+            Some(r) => format!("  + {}", r),
+            None => "  + (no line)".to_string(),
+        });
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expanded_words_quote_only_what_needs_it() {
+        let words = ["[", "a b", "=", "", "say \"hi\"", "]"].map(String::from);
+        assert_eq!(expanded_words(&words), r#"[ "a b" = "" "say \"hi\"" ]"#);
+    }
+
+    #[test]
+    fn single_lines_point_at_the_first_difference() {
+        assert_eq!(
+            difference("abcd", "abXd"),
+            ["left:  \"abcd\"", "right: \"abXd\"", "          ^"]
+        );
+        assert_eq!(difference("ab", "abc")[2], "          ^");
+        assert_eq!(difference("", "x")[2], "        ^");
+    }
+
+    #[test]
+    fn multiline_strings_show_the_lines_that_differ() {
+        assert_eq!(
+            difference("one\ntwo\nthree", "one\n2\nthree\nfour"),
+            [
+                "line 2:",
+                "  - two",
+                "  + 2",
+                "line 4:",
+                "  - (no line)",
+                "  + four"
+            ]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::builtins;
+use crate::executor_processes::resolver::PATH_LIST_SEPARATOR;
+use crate::executor_processes::runtime::block_on;
+use crate::shell::shell::Shell;
+use crate::utilities::utilities::CompletionSpec;
+use std::fs;
+use std::path::Path;
+
+/// The candidates for the last word of `line`, which is being typed. The
+/// command name completes to builtins, functions and programs on `PATH`.
+/// Arguments of a command registered with `complete` complete as it says,
+/// and all others to file names.
+pub fn candidates(shell: &mut Shell, line: &str) -> Vec<String> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    if line.is_empty() || line.ends_with(char::is_whitespace) {
+        words.push("");
+    }
+    let current = words.len() - 1;
+    let word = words[current];
+    if current == 0 {
+        return command_names(shell, word);
+    }
+
+    let mut candidates = match shell.interpreter.completions.get(words[0]).cloned() {
+        Some(CompletionSpec::Words(list)) => {
+            let list = block_on(shell.expand_word(&list)).unwrap_or(list);
+            list.split_whitespace()
+                .filter(|w| w.starts_with(word))
+                .map(String::from)
+                .collect()
+        }
+        Some(CompletionSpec::Function(function)) => {
+            function_candidates(shell, &function, &words, current)
+        }
+        None => file_names(&shell.interpreter.directory, word),
+    };
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Calls a `complete -F` function the way bash does: with the command, the
+/// word being completed and the word before it as arguments, the words of
+/// the line in `COMP_WORDS`, the index of the current one in `COMP_CWORD`
+/// and the line in `COMP_LINE`. The candidates are the words the function
+/// leaves in `COMPREPLY`, separated by whitespace.
+fn function_candidates(
+    shell: &mut Shell,
+    function: &str,
+    words: &[&str],
+    current: usize,
+) -> Vec<String> {
+    let variables = &mut shell.interpreter.variables;
+    variables.insert("COMP_WORDS".to_string(), words.join(" "));
+    variables.insert("COMP_CWORD".to_string(), current.to_string());
+    variables.insert("COMP_LINE".to_string(), words.join(" "));
+    variables.remove("COMPREPLY");
+
+    let args = [words[0], words[current], words[current - 1]].map(String::from);
+    if let Err(e) = block_on(shell.execute_command(function, &args)) {
+        eprintln!("bellos: complete: {}: {}", function, e);
+    }
+
+    let variables = &mut shell.interpreter.variables;
+    for name in ["COMP_WORDS", "COMP_CWORD", "COMP_LINE"] {
+        variables.remove(name);
+    }
+    variables
+        .remove("COMPREPLY")
+        .map(|reply| reply.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn command_names(shell: &Shell, prefix: &str) -> Vec<String> {
+    if prefix.contains('/') {
+        return file_names(&shell.interpreter.directory, prefix);
+    }
+    let mut names: Vec<String> = builtins::available()
+        .map(|builtin| builtin.name)
+        .chain(shell.interpreter.functions.keys().map(String::as_str))
+        .filter(|name| name.starts_with(prefix))
+        .map(String::from)
+        .collect();
+    let path = shell.interpreter.variable("PATH").unwrap_or_default();
+    for dir in path
+        .split(PATH_LIST_SEPARATOR)
+        .filter(|dir| !dir.is_empty())
+    {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        names.extend(
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name.starts_with(prefix)),
+        );
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Paths starting with `prefix`, as seen from `directory`, with a `/` after
+/// directories. Hidden files are only offered once the prefix names them
+/// with a leading dot.
+pub fn file_names(directory: &Path, prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(slash) => (&prefix[..=slash], &prefix[slash + 1..]),
+        None => ("", prefix),
+    };
+    let Ok(entries) = fs::read_dir(directory.join(if dir.is_empty() { "." } else { dir })) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|entry| {
+            entry.starts_with(name) && (name.starts_with('.') || !entry.starts_with('.'))
+        })
+        .map(|entry| {
+            let path = format!("{}{}", dir, entry);
+            if directory.join(&path).is_dir() {
+                path + "/"
+            } else {
+                path
+            }
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_lists_complete_the_arguments_of_their_command() {
+        let mut shell = Shell::new();
+        shell
+            .run("actions='start stop status'\ncomplete -W '$actions restart' myctl\n")
+            .unwrap();
+        assert_eq!(
+            candidates(&mut shell, "myctl st"),
+            ["start", "status", "stop"]
+        );
+        assert_eq!(candidates(&mut shell, "myctl re"), ["restart"]);
+        assert!(candidates(&mut shell, "myctl x").is_empty());
+        assert_eq!(candidates(&mut shell, "myctl start ").len(), 4);
+
+        shell.run("complete -r myctl\n").unwrap();
+        let directory = shell.interpreter.directory.clone();
+        assert_eq!(
+            candidates(&mut shell, "myctl st"),
+            file_names(&directory, "st")
+        );
+    }
+
+    #[test]
+    fn functions_complete_from_the_words_of_the_line() {
+        let mut shell = Shell::new();
+        shell
+            .run(
+                "_hosts() {\n\
+                 \x20 seen=\"$1|$2|$3|$COMP_WORDS|$COMP_CWORD\"\n\
+                 \x20 COMPREPLY=\"$2-one $2-two\"\n\
+                 }\n\
+                 complete -F _hosts ssh\n",
+            )
+            .unwrap();
+        assert_eq!(
+            candidates(&mut shell, "ssh -p 22 web"),
+            ["web-one", "web-two"]
+        );
+        let seen = shell.interpreter.variable("seen");
+        assert_eq!(seen.as_deref(), Some("ssh|web|22|ssh -p 22 web|3"));
+        for name in ["COMPREPLY", "COMP_WORDS", "COMP_CWORD", "COMP_LINE"] {
+            assert!(!shell.interpreter.is_set(name), "{}", name);
+        }
+    }
+}
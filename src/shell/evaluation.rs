@@ -0,0 +1,88 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `Shell::eval_async`: runs a script as a future on a tokio runtime, for
+//! embedders driving many shells from one executor.
+
+use crate::executor_processes::runtime::Runtime;
+use crate::shell::shell::Shell;
+
+impl Shell {
+    /// Runs `input` as `eval` does, resolving to the same result. External
+    /// commands are started through `tokio::process` and waited for, with
+    /// the pipes between them, without blocking the runtime, and `timeout`
+    /// and `wait` keep time with its timers; the interpreter itself runs
+    /// synchronously whenever the future is polled. It must be polled
+    /// within a tokio runtime with timers enabled.
+    pub async fn eval_async(&mut self, input: &str) -> Result<i32, String> {
+        self.processes.runtime = Runtime::Tokio;
+        let result = self.evaluate(input).await;
+        self.processes.runtime = Runtime::Blocking;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn variable(shell: &Shell, name: &str) -> Option<String> {
+        shell.interpreter.variables.get(name).cloned()
+    }
+
+    #[tokio::test]
+    async fn scripts_keep_their_state() {
+        let mut shell = Shell::new();
+        let result = shell.eval_async("first=one; false").await;
+        assert_eq!(result, Ok(1));
+        assert_eq!(shell.eval_async("test \"$first\" = one").await, Ok(0));
+        assert_eq!(variable(&shell, "first").as_deref(), Some("one"));
+        assert_eq!(shell.processes.runtime, Runtime::Blocking);
+    }
+
+    #[tokio::test]
+    async fn exit_ends_the_script_not_the_program() {
+        let mut shell = Shell::new();
+        let result = shell
+            .eval_async("trap 'after=yes' EXIT; exit 3; never=ran")
+            .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(variable(&shell, "after").as_deref(), Some("yes"));
+        assert_eq!(variable(&shell, "never"), None);
+        assert_eq!(shell.eval_async(":").await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn syntax_errors_run_nothing() {
+        let mut shell = Shell::new();
+        let result = shell.eval_async("ran=yes; if true; then").await;
+        assert!(result.is_err());
+        assert_eq!(variable(&shell, "ran"), None);
+        assert_eq!(shell.last_status(), 2);
+    }
+
+    #[tokio::test]
+    async fn shells_on_one_thread_wait_together() {
+        let (mut first, mut second) = (Shell::new(), Shell::new());
+        let start = Instant::now();
+        let (one, two) = tokio::join!(
+            first.eval_async("sleep 0.5; sleep 0.5"),
+            second.eval_async("sleep 0.5; sleep 0.5"),
+        );
+        assert_eq!((one, two), (Ok(0), Ok(0)));
+        assert!(start.elapsed() < Duration::from_millis(1800));
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::providers::FsProvider;
+use crate::interpreter_logic::logic::array_reference;
+use crate::lexer::lexer::substitution_len;
+use crate::utilities::utilities::ShellOptions;
+use glob::{MatchOptions, Pattern};
+use std::borrow::Cow;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+
+/// The separators used for field splitting when `IFS` is unset.
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// Collects the fields a word expands to. Quoted text always belongs to a
+/// field, even when empty, while unquoted text that expands to nothing
+/// produces no field at all. Fields with glob characters in unquoted text
+/// are expanded to the paths they match.
+#[derive(Default)]
+pub struct Fields {
+    fields: Vec<Field>,
+    current: Option<Field>,
+}
+
+#[derive(Default)]
+struct Field {
+    text: String,
+    /// `text` as a glob pattern, with quoted text escaped.
+    pattern: String,
+    has_glob: bool,
+}
+
+impl Fields {
+    /// Appends quoted text to the current field.
+    pub fn push_quoted(&mut self, text: &str) {
+        let field = self.current.get_or_insert_with(Field::default);
+        field.text.push_str(text);
+        field.pattern.push_str(&Pattern::escape(text));
+    }
+
+    /// Appends unquoted text that is not subject to splitting.
+    pub fn push_unsplit(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.push_unquoted(text);
+        }
+    }
+
+    /// Appends unquoted text split on the characters of `ifs`. Separators at
+    /// either end finish the field being built, so `a$(echo " b ")c` gives
+    /// the three fields `a`, `b` and `c`.
+    pub fn push_split(&mut self, text: &str, ifs: &str) {
+        let is_separator = |c: char| ifs.contains(c);
+        if text.starts_with(is_separator) {
+            self.end_field();
+        }
+        for (i, word) in text
+            .split(is_separator)
+            .filter(|word| !word.is_empty())
+            .enumerate()
+        {
+            if i > 0 {
+                self.end_field();
+            }
+            self.push_unquoted(word);
+        }
+        if text.ends_with(is_separator) {
+            self.end_field();
+        }
+    }
+
+    fn push_unquoted(&mut self, text: &str) {
+        let field = self.current.get_or_insert_with(Field::default);
+        field.text.push_str(text);
+        field.pattern.push_str(text);
+        field.has_glob |= text.contains(['*', '?', '[']);
+    }
+
+    /// Finishes the field being built, so that what follows starts a new one.
+    pub fn end_field(&mut self) {
+        if let Some(field) = self.current.take() {
+            self.fields.push(field);
+        }
+    }
+
+    pub fn finish(mut self, options: &ShellOptions, fs: &dyn FsProvider) -> Vec<String> {
+        self.end_field();
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for field in self.fields {
+            if field.has_glob {
+                fields.extend(expand_glob(field, options, fs));
+            } else {
+                fields.push(field.text);
+            }
+        }
+        fields
+    }
+}
+
+/// Splits a line read by `read` into at most `count` fields. Runs of the
+/// whitespace in `ifs` separate fields and are trimmed from both ends, and
+/// each other `ifs` character ends a field by itself, so `a::b` with `IFS=:`
+/// has an empty middle field. The last field takes the rest of the line.
+pub fn split_read_fields(line: &str, ifs: &str, count: usize) -> Vec<String> {
+    let is_space = |c: char| c.is_whitespace() && ifs.contains(c);
+    let is_delimiter = |c: char| !c.is_whitespace() && ifs.contains(c);
+    let mut rest = line.trim_matches(is_space);
+    let mut fields = Vec::with_capacity(count);
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.find(|c: char| ifs.contains(c)).unwrap_or(rest.len());
+        fields.push(rest[..end].to_string());
+        rest = rest[end..].trim_start_matches(is_space);
+        if let Some(after) = rest.strip_prefix(is_delimiter) {
+            rest = after.trim_start_matches(is_space);
+        }
+    }
+    fields.push(rest.to_string());
+    fields.resize(count, String::new());
+    fields
+}
+
+/// Expands a field to the paths its pattern matches in `fs`, in sorted
+/// order. With no matches the field is kept as written, or dropped under
+/// `nullglob`.
+fn expand_glob(field: Field, options: &ShellOptions, fs: &dyn FsProvider) -> Vec<String> {
+    let match_options = MatchOptions {
+        case_sensitive: !options.nocaseglob,
+        require_literal_separator: true,
+        require_literal_leading_dot: !options.dotglob,
+    };
+    let mut matches = if options.globstar {
+        fs.glob(&field.pattern, match_options)
+    } else {
+        fs.glob(&collapse_globstars(&field.pattern), match_options)
+    };
+    matches.sort();
+    matches.dedup();
+    if !matches.is_empty() {
+        matches
+    } else if options.nullglob {
+        Vec::new()
+    } else {
+        vec![field.text]
+    }
+}
+
+/// Turns every run of `*` into a single one, so `**` means `*` when
+/// `globstar` is off.
+fn collapse_globstars(pattern: &str) -> Cow<'_, str> {
+    if !pattern.contains("**") {
+        return Cow::Borrowed(pattern);
+    }
+    let mut collapsed = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if !(c == '*' && collapsed.ends_with('*')) {
+            collapsed.push(c);
+        }
+    }
+    Cow::Owned(collapsed)
+}
+
+/// Splits double-quoted text around each `$@`, `${@}` or `${name[@]}`
+/// outside of `$(...)`, whose items go as fields of their own. Each part of
+/// the text comes with the list that follows it, `@` for the positional
+/// parameters or else the name of an array; the last part has none. Text
+/// without a list is a single part.
+pub fn split_at_lists(text: &str) -> Vec<(&str, Option<&str>)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut position = 0;
+    while let Some(offset) = text[position..].find('$') {
+        let at = position + offset;
+        let after = &text[at + 1..];
+        let braced = after
+            .strip_prefix('{')
+            .and_then(|inner| Some(&inner[..inner.find('}')?]));
+        let array = braced
+            .and_then(array_reference)
+            .filter(|reference| !reference.length && reference.subscript == "@");
+        let (length, list) = if after.starts_with('@') {
+            (2, "@")
+        } else if braced == Some("@") {
+            (4, "@")
+        } else if let (Some(braced), Some(array)) = (braced, array) {
+            (braced.len() + 3, array.name)
+        } else if after.starts_with('(') {
+            position = at + substitution_len(&text[at..]);
+            continue;
+        } else {
+            // `$$` is a parameter of its own, not the start of `$@`.
+            position = at + 1 + usize::from(after.starts_with('$'));
+            continue;
+        };
+        parts.push((&text[start..at], Some(list)));
+        start = at + length;
+        position = start;
+    }
+    parts.push((&text[start..], None));
+    parts
+}
+
+/// Finds the start of the first `$(` command substitution or `$((`
+/// arithmetic expansion in `text`.
+pub fn find_substitution(text: &str) -> Option<usize> {
+    text.find("$(")
+}
+
+/// Splits a word as written into the `~` prefix it starts with, if any, and
+/// the rest: the prefix runs up to the first `/`, or on Windows `\`, and is
+/// not one if any of it is quoted or expanded.
+pub fn tilde_prefix(word: &str) -> Option<(&str, &str)> {
+    let separators: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+    let rest = word.strip_prefix('~')?;
+    let end = rest.find(separators).unwrap_or(rest.len());
+    let prefix = &rest[..end];
+    if prefix.contains(['"', '\'', '\\', '$']) {
+        return None;
+    }
+    Some((prefix, &rest[end..]))
+}
+
+/// The home directory of `user` from the password database, or `None` if
+/// there is no such user.
+#[cfg(unix)]
+pub fn home_directory(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    passwd_home(|entry, buffer, length, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), entry, buffer, length, result)
+    })
+}
+
+/// The home directory of the user running the shell, from the password
+/// database.
+#[cfg(unix)]
+pub fn current_user_home() -> Option<String> {
+    let uid = unsafe { libc::getuid() };
+    passwd_home(|entry, buffer, length, result| unsafe {
+        libc::getpwuid_r(uid, entry, buffer, length, result)
+    })
+}
+
+/// Runs a `getpw*_r` lookup, growing its buffer as needed, and returns the
+/// home directory of the entry found.
+#[cfg(unix)]
+fn passwd_home(
+    lookup: impl Fn(&mut libc::passwd, *mut libc::c_char, usize, &mut *mut libc::passwd) -> libc::c_int,
+) -> Option<String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        match lookup(&mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) {
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => break,
+            _ => return None,
+        }
+    }
+    let home = unsafe { CStr::from_ptr(entry.pw_dir) };
+    Some(home.to_string_lossy().into_owned())
+}
+
+/// Without a password database, as on Windows, other users' profiles are
+/// assumed to sit next to the current one, as they do under `C:\Users`.
+#[cfg(not(unix))]
+pub fn home_directory(user: &str) -> Option<String> {
+    let profiles = current_user_home()?;
+    let home = std::path::Path::new(&profiles).parent()?.join(user);
+    home.is_dir().then(|| home.display().to_string())
+}
+
+#[cfg(not(unix))]
+pub fn current_user_home() -> Option<String> {
+    std::env::var("USERPROFILE").ok()
+}
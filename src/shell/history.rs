@@ -0,0 +1,89 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// The lines entered in interactive mode, numbered from 1.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<String>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if !line.trim().is_empty() {
+            self.entries.push(line.to_string());
+        }
+    }
+
+    /// Replaces the newest entry, as `fc` does with itself once it knows the
+    /// commands it runs.
+    pub fn replace_last(&mut self, line: &str) {
+        self.entries.pop();
+        self.add(line);
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The entries before the newest, which is the line being run.
+    pub fn previous(&self) -> &[String] {
+        &self.entries[..self.entries.len().saturating_sub(1)]
+    }
+
+    /// Resolves a history specification against the entries before the
+    /// newest, returning an index into them: a positive number is an entry
+    /// number, a negative one counts back from the most recent entry, and
+    /// anything else is the most recent entry starting with it.
+    pub fn find(&self, spec: &str) -> Result<usize, String> {
+        let entries = self.previous();
+        let out_of_range = || format!("fc: {}: history specification out of range", spec);
+        match spec.parse::<i64>() {
+            Ok(number) if number > 0 => {
+                let index = number as usize - 1;
+                (index < entries.len())
+                    .then_some(index)
+                    .ok_or_else(out_of_range)
+            }
+            Ok(number) if number < 0 => entries
+                .len()
+                .checked_sub(number.unsigned_abs() as usize)
+                .ok_or_else(out_of_range),
+            Ok(_) => entries.len().checked_sub(1).ok_or_else(out_of_range),
+            Err(_) => entries
+                .iter()
+                .rposition(|entry| entry.starts_with(spec))
+                .ok_or_else(|| format!("fc: {}: no command found", spec)),
+        }
+    }
+
+    /// Like `find`, but numbers out of range are moved into it, as listing
+    /// needs.
+    pub fn find_clamped(&self, spec: &str) -> Result<usize, String> {
+        let last = self.previous().len().checked_sub(1);
+        match (spec.parse::<i64>(), last) {
+            (_, None) => Err("fc: no history".to_string()),
+            (Ok(number), Some(last)) if number > 0 => Ok((number as usize - 1).min(last)),
+            (Ok(number), Some(last)) if number < 0 => {
+                Ok((last + 1).saturating_sub(number.unsigned_abs() as usize))
+            }
+            _ => self.find(spec),
+        }
+    }
+}
@@ -0,0 +1,839 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(not(unix))]
+use crate::executor_processes::jobs;
+#[cfg(unix)]
+use crate::shell::completion;
+use crate::shell::shell::Shell;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+
+/// The escape sequences terminals send for the keys the editor handles:
+/// the arrows, Home, End and Delete.
+const KEY_SEQUENCES: &[&[u8]] = &[
+    b"\x1b[A", b"\x1b[B", b"\x1b[C", b"\x1b[D", b"\x1b[H", b"\x1b[F", b"\x1bOA", b"\x1bOB",
+    b"\x1bOC", b"\x1bOD", b"\x1bOH", b"\x1bOF", b"\x1b[1~", b"\x1b[3~", b"\x1b[4~",
+];
+
+/// How long, in milliseconds, the editor waits for a key before looking
+/// for finished jobs anyway.
+#[cfg(unix)]
+const JOB_CHECK_INTERVAL: libc::c_int = 1000;
+
+/// Moves the cursor home and clears the screen and the scrollback, as
+/// `clear` does.
+pub const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J\x1b[3J";
+
+/// Clears the screen `out` shows, if it is a terminal: anything else, like
+/// a file or a pipe, should not get escape sequences written into it.
+pub fn clear_screen(out: &mut impl Write, is_terminal: bool) -> io::Result<()> {
+    if !is_terminal {
+        return Ok(());
+    }
+    out.write_all(CLEAR_SCREEN.as_bytes())?;
+    out.flush()
+}
+
+/// What a key sequence bound with `bind` does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    /// Text typed in as if by the user.
+    Macro(String),
+    /// `bind -x`: a command run without disturbing the line being edited.
+    Command(String),
+}
+
+/// The key sequences bound with `bind`, consulted before the editor's own
+/// handling of keys.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: BTreeMap<Vec<u8>, Binding>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, sequence: Vec<u8>, binding: Binding) {
+        self.bindings.insert(sequence, binding);
+    }
+
+    /// Removes the binding of `sequence`, returning whether there was one.
+    pub fn remove(&mut self, sequence: &[u8]) -> bool {
+        self.bindings.remove(sequence).is_some()
+    }
+
+    pub fn get(&self, sequence: &[u8]) -> Option<&Binding> {
+        self.bindings.get(sequence)
+    }
+
+    /// The bindings in order of their key sequences.
+    pub fn bindings(&self) -> impl Iterator<Item = (&Vec<u8>, &Binding)> {
+        self.bindings.iter()
+    }
+
+    /// Whether a longer bound sequence starts with `sequence`, so more keys
+    /// are needed to tell which is meant.
+    fn extends(&self, sequence: &[u8]) -> bool {
+        self.bindings
+            .keys()
+            .any(|bound| bound.len() > sequence.len() && bound.starts_with(sequence))
+    }
+}
+
+/// Parses a binding as `bind` takes it, `"keyseq": value`, into the key
+/// sequence and the value with its quotes removed. Escapes in the value are
+/// only decoded for macros, not for the commands of `bind -x`.
+pub fn parse_binding(text: &str, command: bool) -> Result<(Vec<u8>, String), String> {
+    let invalid = || format!("{}: expected \"keyseq\": value", text);
+    let text = text.trim();
+    let inner = text.strip_prefix('"').ok_or_else(invalid)?;
+    let end = closing_quote(inner).ok_or_else(invalid)?;
+    let sequence = parse_key_sequence(&inner[..end])?;
+    if sequence.is_empty() {
+        return Err(invalid());
+    }
+    let value = inner[end + 1..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(invalid)?
+        .trim();
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => {
+            let end = closing_quote(quoted).ok_or_else(invalid)?;
+            if command {
+                quoted[..end].replace("\\\"", "\"")
+            } else {
+                let bytes = parse_key_sequence(&quoted[..end])?;
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        }
+        None => value.to_string(),
+    };
+    Ok((sequence, value))
+}
+
+/// The position of the first `"` in `text` not escaped with a backslash.
+fn closing_quote(text: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Decodes readline's key sequence notation: `\C-x` for Control-x, `\M-x`
+/// and `\e` for Meta and Escape, the C escapes `\a \b \d \f \n \r \t \v`,
+/// octal `\nnn` and hexadecimal `\xHH` bytes, and `\\`, `\"` and `\'`.
+pub fn parse_key_sequence(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("{}: invalid key sequence", text);
+    let mut bytes = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let escape = chars.next().ok_or_else(invalid)?;
+        match escape {
+            'C' | 'M' if chars.peek() == Some(&'-') => {
+                chars.next();
+                let mut key = chars.next().ok_or_else(invalid)?;
+                if key == '\\' {
+                    key = match chars.next().ok_or_else(invalid)? {
+                        'e' => '\x1b',
+                        other => other,
+                    };
+                }
+                if !key.is_ascii() {
+                    return Err(invalid());
+                }
+                if escape == 'M' {
+                    bytes.push(0x1b);
+                    bytes.push(key as u8);
+                } else {
+                    bytes.push(control(key as u8));
+                }
+            }
+            'e' => bytes.push(0x1b),
+            'a' => bytes.push(0x07),
+            'b' => bytes.push(0x08),
+            'd' => bytes.push(0x7f),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            'x' => {
+                let mut value = 0;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    if digits == 2 {
+                        break;
+                    }
+                    value = value * 16 + digit;
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err(invalid());
+                }
+                bytes.push(value as u8);
+            }
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            other => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// The byte Control and `key` send, `\C-?` being Delete.
+fn control(key: u8) -> u8 {
+    if key == b'?' {
+        0x7f
+    } else {
+        key.to_ascii_uppercase() & 0x1f
+    }
+}
+
+/// Writes bytes back in the notation `parse_key_sequence` reads.
+pub fn format_key_sequence(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\x1b' => text.push_str("\\e"),
+                '\n' => text.push_str("\\n"),
+                '\t' => text.push_str("\\t"),
+                '\r' => text.push_str("\\r"),
+                '\x7f' => text.push_str("\\C-?"),
+                '"' => text.push_str("\\\""),
+                '\\' => text.push_str("\\\\"),
+                c if (c as u32) < 0x20 => {
+                    text.push_str("\\C-");
+                    text.push((c as u8 | 0x60) as char);
+                }
+                c => text.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            text.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    text
+}
+
+/// What the terminal side of the editor has to do after a key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// The line is finished.
+    Accept(String),
+    /// Control-D on an empty line: end of input.
+    Eof,
+    /// Control-C: the line is dropped and a new one started.
+    Cancel,
+    /// Tab: complete the word before the cursor.
+    Complete,
+    /// A `bind -x` command to run.
+    Execute(String),
+    /// Control-L: clear the screen and redraw the line.
+    ClearScreen,
+}
+
+/// The line being edited and the keys read towards the next action. It
+/// only changes state, leaving the terminal to `read_line`, so it can be
+/// driven one byte at a time.
+#[derive(Debug, Clone, Default)]
+pub struct EditorState {
+    line: String,
+    /// A byte offset into `line`, always at a character boundary.
+    cursor: usize,
+    /// The bytes of a key sequence read so far.
+    pending: Vec<u8>,
+    /// The bytes of a multibyte character read so far.
+    partial: Vec<u8>,
+    /// The history entry shown, if browsing with the arrows.
+    history_index: Option<usize>,
+    /// The line as it was before browsing the history.
+    saved: String,
+}
+
+impl EditorState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replaces the line, putting the cursor at `cursor` or as close before
+    /// it as a character boundary allows.
+    pub fn set_line(&mut self, line: &str, cursor: usize) {
+        self.line = line.to_string();
+        let mut cursor = cursor.min(self.line.len());
+        while !self.line.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+        self.cursor = cursor;
+    }
+
+    /// Reads one byte of input. Bound sequences take precedence over the
+    /// editor's own keys; a byte that could still start one is held until
+    /// the sequence is complete.
+    pub fn feed(&mut self, byte: u8, keymap: &Keymap, history: &[String]) -> Option<Action> {
+        self.pending.push(byte);
+        if let Some(binding) = keymap.get(&self.pending).cloned() {
+            self.pending.clear();
+            return match binding {
+                Binding::Macro(text) => self.type_text(text.as_bytes(), history),
+                Binding::Command(command) => Some(Action::Execute(command)),
+            };
+        }
+        let incomplete = keymap.extends(&self.pending)
+            || KEY_SEQUENCES
+                .iter()
+                .any(|known| known.len() > self.pending.len() && known.starts_with(&self.pending));
+        if incomplete {
+            return None;
+        }
+        let sequence = std::mem::take(&mut self.pending);
+        self.key(&sequence, history)
+    }
+
+    /// Types `text` in with the editor's own keys, ignoring bindings so a
+    /// macro cannot expand itself.
+    fn type_text(&mut self, text: &[u8], history: &[String]) -> Option<Action> {
+        for &byte in text {
+            if let Some(action) = self.key(&[byte], history) {
+                return Some(action);
+            }
+        }
+        None
+    }
+
+    /// Handles a complete key sequence that is not bound.
+    fn key(&mut self, sequence: &[u8], history: &[String]) -> Option<Action> {
+        match sequence {
+            b"\r" | b"\n" => {
+                let line = std::mem::take(&mut self.line);
+                *self = Self::new();
+                return Some(Action::Accept(line));
+            }
+            b"\x03" => {
+                *self = Self::new();
+                return Some(Action::Cancel);
+            }
+            b"\x04" if self.line.is_empty() => return Some(Action::Eof),
+            b"\x04" | b"\x1b[3~" => {
+                if let Some(c) = self.line[self.cursor..].chars().next() {
+                    self.line
+                        .replace_range(self.cursor..self.cursor + c.len_utf8(), "");
+                }
+            }
+            b"\t" => return Some(Action::Complete),
+            b"\x0c" => return Some(Action::ClearScreen),
+            b"\x01" | b"\x1b[H" | b"\x1bOH" | b"\x1b[1~" => self.cursor = 0,
+            b"\x05" | b"\x1b[F" | b"\x1bOF" | b"\x1b[4~" => self.cursor = self.line.len(),
+            b"\x02" | b"\x1b[D" | b"\x1bOD" => self.cursor = self.previous_boundary(),
+            b"\x06" | b"\x1b[C" | b"\x1bOC" => {
+                if let Some(c) = self.line[self.cursor..].chars().next() {
+                    self.cursor += c.len_utf8();
+                }
+            }
+            b"\x7f" | b"\x08" => {
+                let start = self.previous_boundary();
+                self.line.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            b"\x0b" => self.line.truncate(self.cursor),
+            b"\x15" => {
+                self.line.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            b"\x17" => {
+                let before = self.line[..self.cursor].trim_end_matches([' ', '\t']);
+                let start = before.rfind([' ', '\t']).map_or(0, |i| i + 1);
+                self.line.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            b"\x10" | b"\x1b[A" | b"\x1bOA" => self.history_back(history),
+            b"\x0e" | b"\x1b[B" | b"\x1bOB" => self.history_forward(history),
+            [byte] if *byte >= 0x80 => {
+                self.partial.push(*byte);
+                match std::str::from_utf8(&self.partial) {
+                    Ok(text) => {
+                        let text = text.to_string();
+                        self.partial.clear();
+                        self.insert(&text);
+                    }
+                    Err(e) if e.error_len().is_some() => self.partial.clear(),
+                    Err(_) => {}
+                }
+            }
+            [byte] if (0x20..0x7f).contains(byte) => self.insert(&(*byte as char).to_string()),
+            // Anything else, like an unknown escape sequence, is ignored.
+            _ => {}
+        }
+        None
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.line.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.line[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn history_back(&mut self, history: &[String]) {
+        let index = match self.history_index {
+            None if history.is_empty() => return,
+            None => {
+                self.saved = self.line.clone();
+                history.len() - 1
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.history_index = Some(index);
+        self.line = history[index].clone();
+        self.cursor = self.line.len();
+    }
+
+    fn history_forward(&mut self, history: &[String]) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+        if index + 1 < history.len() {
+            self.history_index = Some(index + 1);
+            self.line = history[index + 1].clone();
+        } else {
+            self.history_index = None;
+            self.line = std::mem::take(&mut self.saved);
+        }
+        self.cursor = self.line.len();
+    }
+}
+
+/// The terminal settings of standard input while the editor reads a line,
+/// restored when dropped.
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+    raw: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// Turns off line buffering, echo and the signal keys, so the editor
+    /// sees every key as it is pressed. Output processing stays on.
+    fn enable() -> io::Result<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        let mode = RawMode { original, raw };
+        mode.apply()?;
+        Ok(mode)
+    }
+
+    fn apply(&self) -> io::Result<()> {
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.raw) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn restore(&self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Whether standard input is a terminal the editor can read from. Only
+/// Unix terminals can be put in raw mode.
+pub fn is_terminal() -> bool {
+    cfg!(unix) && io::stdin().is_terminal()
+}
+
+/// Reads a line from the terminal after showing `prompt`, with editing,
+/// history, completion and the bindings of `bind`. Returns `None` at the
+/// end of input.
+#[cfg(unix)]
+pub fn read_line(shell: &mut Shell, prompt: &str) -> io::Result<Option<String>> {
+    let raw = RawMode::enable()?;
+    let mut state = EditorState::new();
+    let mut stdout = io::stdout();
+    write!(stdout, "{}", prompt)?;
+    stdout.flush()?;
+    loop {
+        let notices = wait_for_input(shell)?;
+        if !notices.is_empty() {
+            // Job notices go above the line being edited, which is then
+            // drawn again below them.
+            write!(stdout, "\r\x1b[K")?;
+            for notice in notices {
+                write!(stdout, "{}\r\n", notice)?;
+            }
+            redraw(&mut stdout, prompt, &state)?;
+            continue;
+        }
+        let Some(byte) = read_byte()? else {
+            write!(stdout, "\r\n")?;
+            return Ok((!state.line().is_empty()).then(|| state.line().to_string()));
+        };
+        let action = state.feed(byte, &shell.keymap, shell.history.entries());
+        match action {
+            None => {}
+            Some(Action::Accept(line)) => {
+                write!(stdout, "\r\n")?;
+                return Ok(Some(line));
+            }
+            Some(Action::Eof) => {
+                write!(stdout, "\r\n")?;
+                return Ok(None);
+            }
+            Some(Action::Cancel) => write!(stdout, "^C\r\n")?,
+            Some(Action::Complete) => {
+                if let Some(listing) = complete(shell, &mut state) {
+                    write!(stdout, "\r\n{}\r\n", listing)?;
+                }
+            }
+            Some(Action::Execute(command)) => {
+                write!(stdout, "\r\n")?;
+                stdout.flush()?;
+                raw.restore();
+                execute_binding(shell, &mut state, &command);
+                raw.apply()?;
+            }
+            Some(Action::ClearScreen) => clear_screen(&mut stdout, true)?,
+        }
+        redraw(&mut stdout, prompt, &state)?;
+    }
+}
+
+#[cfg(not(unix))]
+pub fn read_line(_shell: &mut Shell, _prompt: &str) -> io::Result<Option<String>> {
+    Err(jobs::unsupported("the line editor"))
+}
+
+/// Waits until standard input can be read, returning early with the
+/// notices of background jobs that finish in the meantime. SIGCHLD cuts
+/// the wait short; the timeout covers one arriving just before it starts.
+#[cfg(unix)]
+fn wait_for_input(shell: &Shell) -> io::Result<Vec<String>> {
+    let mut input = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let notices = shell.processes.check_jobs();
+        if !notices.is_empty() {
+            return Ok(notices);
+        }
+        match unsafe { libc::poll(&mut input, 1, JOB_CHECK_INTERVAL) } {
+            0 => {}
+            ready if ready > 0 => return Ok(Vec::new()),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        let count = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut _, 1) };
+        match count {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+/// Shows the prompt and the line, with the cursor where it belongs.
+#[cfg(unix)]
+fn redraw(stdout: &mut io::Stdout, prompt: &str, state: &EditorState) -> io::Result<()> {
+    write!(stdout, "\r{}{}\x1b[K", prompt, state.line())?;
+    let after = state.line()[state.cursor()..].chars().count();
+    if after > 0 {
+        write!(stdout, "\x1b[{}D", after)?;
+    }
+    stdout.flush()
+}
+
+/// Completes the word before the cursor: a single candidate replaces it,
+/// several extend it as far as they agree. When they agree no further, they
+/// are returned for listing.
+#[cfg(unix)]
+fn complete(shell: &mut Shell, state: &mut EditorState) -> Option<String> {
+    let line = state.line().to_string();
+    let cursor = state.cursor();
+    let before = &line[..cursor];
+    let start = before.rfind([' ', '\t']).map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let candidates = completion::candidates(shell, before);
+    let replacement = match candidates.as_slice() {
+        [] => return None,
+        [only] if only.ends_with('/') => only.clone(),
+        [only] => format!("{} ", only),
+        [first, rest @ ..] => {
+            let common = rest.iter().fold(first.as_str(), |common, candidate| {
+                let length = common
+                    .char_indices()
+                    .zip(candidate.chars())
+                    .take_while(|((_, a), b)| a == b)
+                    .last()
+                    .map_or(0, |((i, a), _)| i + a.len_utf8());
+                &common[..length]
+            });
+            if common.len() <= word.len() {
+                return Some(candidates.join("  "));
+            }
+            common.to_string()
+        }
+    };
+    let mut completed = line[..start].to_string();
+    completed.push_str(&replacement);
+    let new_cursor = completed.len();
+    completed.push_str(&line[cursor..]);
+    state.set_line(&completed, new_cursor);
+    None
+}
+
+/// Runs a `bind -x` command with the line in `READLINE_LINE` and the cursor
+/// in `READLINE_POINT`, taking back whatever the command leaves in them.
+#[cfg(unix)]
+fn execute_binding(shell: &mut Shell, state: &mut EditorState, command: &str) {
+    let variables = &mut shell.interpreter.variables;
+    variables.insert("READLINE_LINE".to_string(), state.line().to_string());
+    variables.insert("READLINE_POINT".to_string(), state.cursor().to_string());
+    if let Err(e) = shell.run(command) {
+        eprintln!("bellos: bind: {}: {}", command, e);
+    }
+    let _ = io::stdout().flush();
+    let variables = &mut shell.interpreter.variables;
+    let line = variables.remove("READLINE_LINE").unwrap_or_default();
+    let point = variables
+        .remove("READLINE_POINT")
+        .and_then(|point| point.parse().ok())
+        .unwrap_or(line.len());
+    state.set_line(&line, point);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `bytes` one at a time, returning the last action.
+    fn feed_all(state: &mut EditorState, keymap: &Keymap, bytes: &[u8]) -> Option<Action> {
+        let mut last = None;
+        for &byte in bytes {
+            last = state.feed(byte, keymap, &[]);
+        }
+        last
+    }
+
+    #[test]
+    fn bound_macros_type_their_text_at_the_cursor() {
+        let mut keymap = Keymap::new();
+        let (sequence, text) = parse_binding(r#""\C-t": "hello ""#, false).unwrap();
+        keymap.bind(sequence, Binding::Macro(text));
+        let (sequence, text) = parse_binding(r#""\ex": "\C-a# ""#, false).unwrap();
+        keymap.bind(sequence, Binding::Macro(text));
+
+        let mut state = EditorState::new();
+        assert_eq!(feed_all(&mut state, &keymap, b"world\x01\x14"), None);
+        assert_eq!(state.line(), "hello world");
+        assert_eq!(state.cursor(), 6);
+
+        // The escape is held until the bound sequence is complete, and the
+        // macro's own keys are the editor's.
+        assert_eq!(state.feed(0x1b, &keymap, &[]), None);
+        assert_eq!(state.line(), "hello world");
+        assert_eq!(state.feed(b'x', &keymap, &[]), None);
+        assert_eq!(state.line(), "# hello world");
+        assert_eq!(
+            feed_all(&mut state, &keymap, b"\r"),
+            Some(Action::Accept("# hello world".to_string()))
+        );
+    }
+
+    #[test]
+    fn unbound_prefixes_fall_back_to_the_editor_keys() {
+        let mut keymap = Keymap::new();
+        keymap.bind(b"\x1bxy".to_vec(), Binding::Macro("never".to_string()));
+        let mut state = EditorState::new();
+        feed_all(&mut state, &keymap, b"ab\x1b[D");
+        assert_eq!(state.cursor(), 1);
+        feed_all(&mut state, &keymap, b"\x1bxz");
+        assert_eq!(state.line(), "ab");
+    }
+
+    #[test]
+    fn bound_commands_are_dispatched_and_the_line_is_put_back() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![0x18], Binding::Command("true".to_string()));
+        keymap.bind(
+            vec![0x07],
+            Binding::Command("READLINE_LINE=\"[$READLINE_LINE]\"; READLINE_POINT=1".to_string()),
+        );
+        let mut state = EditorState::new();
+        assert_eq!(
+            feed_all(&mut state, &keymap, b"make all\x02\x18"),
+            Some(Action::Execute("true".to_string()))
+        );
+        assert_eq!((state.line(), state.cursor()), ("make all", 7));
+
+        #[cfg(unix)]
+        {
+            let mut shell = Shell::new();
+            execute_binding(&mut shell, &mut state, "true");
+            assert_eq!((state.line(), state.cursor()), ("make all", 7));
+            let Some(Action::Execute(command)) = state.feed(0x07, &keymap, &[]) else {
+                panic!("\\C-g is bound to a command");
+            };
+            execute_binding(&mut shell, &mut state, &command);
+            assert_eq!((state.line(), state.cursor()), ("[make all]", 1));
+            assert_eq!(shell.interpreter.variable("READLINE_LINE"), None);
+        }
+    }
+
+    #[test]
+    fn key_sequences_decode_readline_notation() {
+        assert_eq!(parse_key_sequence(r"\C-x\C-r"), Ok(vec![0x18, 0x12]));
+        assert_eq!(parse_key_sequence(r"\C-?"), Ok(vec![0x7f]));
+        assert_eq!(parse_key_sequence(r"\C-\e"), Ok(vec![0x1b]));
+        assert_eq!(parse_key_sequence(r"\e[A"), Ok(b"\x1b[A".to_vec()));
+        assert_eq!(parse_key_sequence(r"\M-f"), Ok(b"\x1bf".to_vec()));
+        assert_eq!(
+            parse_key_sequence(r"\t\n\\\x41\101"),
+            Ok(b"\t\n\\AA".to_vec())
+        );
+        assert!(parse_key_sequence(r"ab\").is_err());
+        assert!(parse_key_sequence(r"\C-").is_err());
+        assert!(parse_key_sequence(r"\xg").is_err());
+        for text in [r"\C-x\C-r", r"\e[A", r"\C-?", r#"a\"b\\"#] {
+            let bytes = parse_key_sequence(text).unwrap();
+            assert_eq!(format_key_sequence(&bytes), text);
+        }
+    }
+
+    #[test]
+    fn bindings_need_a_quoted_sequence_and_a_value() {
+        assert_eq!(
+            parse_binding(r#""\C-x": "a\tb""#, false),
+            Ok((vec![0x18], "a\tb".to_string()))
+        );
+        assert_eq!(
+            parse_binding(r#""\C-x": "echo \"\t\"""#, true),
+            Ok((vec![0x18], "echo \"\\t\"".to_string()))
+        );
+        assert_eq!(
+            parse_binding(r#""\ea" : unquoted"#, false),
+            Ok((b"\x1ba".to_vec(), "unquoted".to_string()))
+        );
+        for text in [r#"\C-x: "a""#, r#""\C-x" "a""#, r#""": "a""#, r#""\C-x: a"#] {
+            assert!(parse_binding(text, false).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn clearing_writes_the_sequence_only_to_terminals() {
+        let mut terminal = Vec::new();
+        clear_screen(&mut terminal, true).unwrap();
+        assert_eq!(terminal, b"\x1b[H\x1b[2J\x1b[3J");
+        let mut file = Vec::new();
+        clear_screen(&mut file, false).unwrap();
+        assert!(file.is_empty());
+    }
+
+    #[test]
+    fn control_l_keeps_the_line_being_edited() {
+        let keymap = Keymap::new();
+        let mut state = EditorState::new();
+        feed_all(&mut state, &keymap, b"echo hi\x02\x02");
+        assert_eq!(state.feed(0x0c, &keymap, &[]), Some(Action::ClearScreen));
+        assert_eq!((state.line(), state.cursor()), ("echo hi", 5));
+        assert_eq!(
+            feed_all(&mut state, &keymap, b"!\r"),
+            Some(Action::Accept("echo !hi".to_string()))
+        );
+    }
+
+    #[test]
+    fn removed_bindings_no_longer_apply() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![0x14], Binding::Macro("x".to_string()));
+        assert!(keymap.remove(&[0x14]));
+        assert!(!keymap.remove(&[0x14]));
+        let mut state = EditorState::new();
+        feed_all(&mut state, &keymap, b"a\x14");
+        assert_eq!(state.line(), "a");
+    }
+}
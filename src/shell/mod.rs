@@ -1 +1,9 @@
+pub mod assertion;
+pub mod completion;
+#[cfg(feature = "async")]
+pub mod evaluation;
+pub mod expansion;
+pub mod history;
+pub mod line_editor;
+pub mod redirection;
 pub mod shell;
@@ -0,0 +1,403 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::executor_processes::jobs;
+use crate::executor_processes::providers::FsProvider;
+#[cfg(unix)]
+use crate::executor_processes::providers::{Access, Redirected};
+#[cfg(unix)]
+use crate::executor_processes::streams::Descriptor;
+use crate::executor_processes::streams::Streams;
+use crate::utilities::utilities::{RedirectType, Redirection};
+use std::fs::File;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// The threads copying between pipes and FIFOs, which the shell waits for
+/// before it exits so nothing written to a FIFO is lost.
+static FIFO_COPIES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// What a descriptor of the shell was before a redirection replaced it:
+/// `None` if it was the process's own. A file the filesystem only gave a
+/// copy of is kept with where its content goes.
+pub struct SavedFd {
+    fd: i32,
+    #[cfg(unix)]
+    previous: Option<Descriptor>,
+    copy: Option<(File, Box<dyn Write + Send>)>,
+}
+
+/// Applies redirections to the shell's descriptors in `streams` in order,
+/// so builtins and spawned children alike see them. `targets` holds the
+/// expanded target of each redirection, and files are opened through `fs`.
+/// On failure everything applied so far is undone.
+#[cfg(unix)]
+pub fn apply(
+    redirects: &[Redirection],
+    targets: &[String],
+    fs: &dyn FsProvider,
+    streams: &mut Streams,
+) -> Result<Vec<SavedFd>, String> {
+    flush_std_streams();
+    let mut saved = Vec::new();
+    for (redirect, target) in redirects.iter().zip(targets) {
+        match apply_one(redirect, target, fs, streams) {
+            Ok(state) => saved.push(state),
+            Err(e) => {
+                restore(saved, streams);
+                return Err(e);
+            }
+        }
+    }
+    Ok(saved)
+}
+
+/// Redirections need Unix descriptors, so there are none to apply.
+#[cfg(not(unix))]
+pub fn apply(
+    redirects: &[Redirection],
+    _targets: &[String],
+    _fs: &dyn FsProvider,
+    _streams: &mut Streams,
+) -> Result<Vec<SavedFd>, String> {
+    if !redirects.is_empty() {
+        return Err("redirections are not supported on this platform".to_string());
+    }
+    Ok(Vec::new())
+}
+
+/// A redirection making `fd` a duplicate of `file`'s descriptor, as
+/// `fd>&N` or `fd<&N` would, for the shell's own plumbing. Without Unix
+/// descriptors `apply` turns it down like any other.
+pub fn duplicate(fd: i32, kind: RedirectType, file: &File) -> Redirection {
+    #[cfg(unix)]
+    let target = file.as_raw_fd().to_string();
+    #[cfg(not(unix))]
+    let target = {
+        let _ = file;
+        "-".to_string()
+    };
+    Redirection { fd, kind, target }
+}
+
+/// Puts the shell's descriptors back the way they were before `apply`, and
+/// writes what went to copies of files back to the files.
+pub fn restore(saved: Vec<SavedFd>, streams: &mut Streams) {
+    flush_std_streams();
+    for state in saved.into_iter().rev() {
+        #[cfg(unix)]
+        streams.set(state.fd, state.previous);
+        if let Some((mut file, mut sink)) = state.copy {
+            let written = file
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| io::copy(&mut file, &mut sink))
+                .and_then(|_| sink.flush());
+            if let Err(e) = written {
+                let _ = writeln!(streams.err(), "bellos: {}: {}", state.fd, e);
+            }
+        }
+    }
+}
+
+/// Makes redirections permanent, as `exec` without a command does. What
+/// goes to a copy of a file from then on never reaches the file.
+pub fn persist(saved: Vec<SavedFd>) {
+    drop(saved);
+}
+
+#[cfg(unix)]
+fn apply_one(
+    redirect: &Redirection,
+    target: &str,
+    fs: &dyn FsProvider,
+    streams: &mut Streams,
+) -> Result<SavedFd, String> {
+    let fd = redirect.fd;
+    let duplicates = matches!(
+        redirect.kind,
+        RedirectType::DuplicateInput | RedirectType::DuplicateOutput
+    );
+    let duplicate = match target {
+        _ if !duplicates => None,
+        "-" => None,
+        number => {
+            let other = number
+                .parse::<i32>()
+                .map_err(|_| format!("{}: ambiguous redirect", number))?;
+            let file = streams
+                .file(other)
+                .map_err(|_| format!("{}: Bad file descriptor", other))?;
+            Some(file)
+        }
+    };
+
+    let socket = match redirect.kind {
+        RedirectType::Input
+        | RedirectType::Output
+        | RedirectType::Append
+        | RedirectType::ReadWrite => network_address(target),
+        _ => None,
+    };
+    let access = match redirect.kind {
+        RedirectType::Input => Some(Access::Read),
+        RedirectType::Output => Some(Access::Write),
+        RedirectType::Append => Some(Access::Append),
+        RedirectType::ReadWrite => Some(Access::ReadWrite),
+        _ => None,
+    };
+    let plain = |file| Redirected { file, sink: None };
+    let opened = match (socket, access) {
+        (Some((protocol, host, port)), _) => {
+            connect(protocol, host, port).map(|file| Some(plain(file)))
+        }
+        (None, Some(access)) => fs.open_redirect(Path::new(target), access).map(Some),
+        (None, None) => match redirect.kind {
+            RedirectType::HereDoc { .. } => heredoc_file(target).map(|file| Some(plain(file))),
+            RedirectType::HereString => {
+                heredoc_file(&format!("{}\n", target)).map(|file| Some(plain(file)))
+            }
+            _ => Ok(None),
+        },
+    };
+    let (descriptor, copy) = match opened {
+        Ok(Some(Redirected { file, sink })) => {
+            // Keep a descriptor of a copy to read back what was written.
+            let copy = match sink {
+                Some(sink) => {
+                    let kept = file.try_clone().map_err(|e| format!("{}: {}", target, e))?;
+                    Some((kept, sink))
+                }
+                None => None,
+            };
+            (Descriptor::Open(Arc::new(file)), copy)
+        }
+        Ok(None) => match duplicate {
+            Some(file) => (Descriptor::Open(file), None),
+            None => (Descriptor::Closed, None),
+        },
+        Err(e) => return Err(format!("{}: {}", target, e)),
+    };
+    let previous = streams.set(fd, Some(descriptor));
+    Ok(SavedFd { fd, previous, copy })
+}
+
+/// Splits a `/dev/tcp/HOST/PORT` or `/dev/udp/HOST/PORT` pseudo-path into
+/// its protocol, host and port.
+#[cfg(unix)]
+fn network_address(target: &str) -> Option<(&str, &str, &str)> {
+    let rest = target.strip_prefix("/dev/")?;
+    let (protocol, rest) = rest.split_once('/')?;
+    let (host, port) = rest.split_once('/')?;
+    let valid = matches!(protocol, "tcp" | "udp") && !host.is_empty() && !port.contains('/');
+    valid.then_some((protocol, host, port))
+}
+
+/// Opens a TCP connection, or a connected UDP socket, as a file whose
+/// descriptor redirections can use like any other.
+#[cfg(unix)]
+fn connect(protocol: &str, host: &str, port: &str) -> io::Result<File> {
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+    let fd = if protocol == "tcp" {
+        TcpStream::connect((host, port))?.into_raw_fd()
+    } else {
+        let address = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        socket.into_raw_fd()
+    };
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Connects to a FIFO through a pipe with a thread copying between the two.
+/// Opening a FIFO blocks until its other end is opened too; this way only
+/// the thread waits, and a command reading or writing the returned end is
+/// what blocks, not the shell setting up its redirections.
+#[cfg(unix)]
+pub(crate) fn fifo_pipe(path: &Path, write: bool) -> io::Result<File> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read_end, write_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    let path = path.to_path_buf();
+    // The thread keeps the other end of the pipe.
+    let (mut pipe, local) = if write {
+        (read_end, write_end)
+    } else {
+        (write_end, read_end)
+    };
+    let copy = std::thread::spawn(move || {
+        let opened = if write {
+            OpenOptions::new().write(true).open(&path)
+        } else {
+            File::open(&path)
+        };
+        let result = opened.and_then(|mut fifo| {
+            if write {
+                io::copy(&mut pipe, &mut fifo)
+            } else {
+                io::copy(&mut fifo, &mut pipe)
+            }
+        });
+        if let Err(e) = result {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("bellos: {}: {}", path.display(), e);
+            }
+        }
+    });
+    let mut copies = FIFO_COPIES.lock().unwrap_or_else(|e| e.into_inner());
+    copies.retain(|copy| !copy.is_finished());
+    copies.push(copy);
+    Ok(local)
+}
+
+/// Holds the list of FIFO copies, so no other thread has it locked while
+/// the shell forks.
+pub fn lock_fifo_copies() -> MutexGuard<'static, Vec<JoinHandle<()>>> {
+    FIFO_COPIES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Waits for the copies to and from FIFOs still going on, as a shell
+/// writing to a FIFO directly would until its reader came. A forked copy of
+/// the shell has none: their threads stayed behind in the shell.
+pub fn finish_fifo_copies() {
+    if jobs::is_forked_copy() {
+        return;
+    }
+    let copies = std::mem::take(&mut *FIFO_COPIES.lock().unwrap_or_else(|e| e.into_inner()));
+    for copy in copies {
+        let _ = copy.join();
+    }
+}
+
+/// Stores a heredoc body in an unlinked temporary file, rewound for reading.
+/// Unlike a pipe this cannot block however large the body is.
+#[cfg(unix)]
+fn heredoc_file(body: &str) -> io::Result<File> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(body.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+pub fn flush_std_streams() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::executor_processes::streams::{Input, Output};
+    use crate::executor_processes::testing::MemoryFs;
+    use std::io::Read;
+
+    const INPUT: i32 = 3;
+    const OUTPUT: i32 = 4;
+    const APPENDED: i32 = 5;
+
+    fn redirect(fd: i32, kind: RedirectType, target: &str) -> Redirection {
+        Redirection {
+            fd,
+            kind,
+            target: target.to_string(),
+        }
+    }
+
+    #[test]
+    fn redirections_open_files_through_the_filesystem() {
+        let fs = MemoryFs::new();
+        fs.insert("in", "from memory\n");
+        fs.insert("log", "first\n");
+        let redirects = [
+            redirect(INPUT, RedirectType::Input, "in"),
+            redirect(OUTPUT, RedirectType::Output, "out"),
+            redirect(APPENDED, RedirectType::Append, "log"),
+        ];
+        let targets: Vec<String> = redirects.iter().map(|r| r.target.clone()).collect();
+        let mut streams = Streams::new();
+        let saved = apply(&redirects, &targets, &fs, &mut streams).unwrap();
+
+        let mut input = String::new();
+        streams.input(INPUT).read_to_string(&mut input).unwrap();
+        streams.output(OUTPUT).write_all(b"written\n").unwrap();
+        streams.output(APPENDED).write_all(b"second\n").unwrap();
+        restore(saved, &mut streams);
+
+        assert_eq!(input, "from memory\n");
+        assert_eq!(fs.get("out").as_deref(), Some(&b"written\n"[..]));
+        assert_eq!(fs.get("log").as_deref(), Some(&b"first\nsecond\n"[..]));
+        assert!(!Path::new("out").exists());
+        assert!(streams.output(OUTPUT).write_all(b"lost").is_err());
+    }
+
+    #[test]
+    fn missing_input_files_fail_and_undo_the_redirections() {
+        let fs = MemoryFs::new();
+        let redirects = [
+            redirect(1, RedirectType::Output, "made"),
+            redirect(0, RedirectType::Input, "missing"),
+        ];
+        let targets: Vec<String> = redirects.iter().map(|r| r.target.clone()).collect();
+        let mut streams = Streams::new();
+        let error = apply(&redirects, &targets, &fs, &mut streams)
+            .err()
+            .unwrap();
+        assert!(error.starts_with("missing: "), "{}", error);
+        assert!(matches!(streams.out(), Output::Stdout));
+        assert!(matches!(streams.input(0), Input::Stdin));
+    }
+
+    #[test]
+    fn network_paths_name_a_protocol_host_and_port() {
+        assert_eq!(
+            network_address("/dev/tcp/example.com/80"),
+            Some(("tcp", "example.com", "80"))
+        );
+        assert_eq!(
+            network_address("/dev/udp/::1/53"),
+            Some(("udp", "::1", "53"))
+        );
+        for target in [
+            "/dev/tcp//80",
+            "/dev/sctp/host/1",
+            "/dev/tcp/host",
+            "/dev/tcp/a/1/2",
+        ] {
+            assert_eq!(network_address(target), None, "{}", target);
+        }
+    }
+}
@@ -13,260 +13,3035 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::executor_processes::builtins;
+use crate::executor_processes::jobs;
+use crate::executor_processes::processes::{self, Processes};
+use crate::executor_processes::providers::{FsProvider, InDirectory};
+use crate::executor_processes::resolver::PATH_LIST_SEPARATOR;
+use crate::executor_processes::runtime::{block_on, Runtime};
+use crate::executor_processes::timeout;
+use crate::interpreter_logic::arithmetic;
 use crate::interpreter_logic::interpreter::Interpreter;
-use crate::lexer::lexer::Lexer;
-use crate::parser::parser::Parser;
-use crate::utilities::utilities::{ASTNode, RedirectType};
-use std::io::{self, Write};
-use std::process::{Command, Stdio};
+use crate::lexer::lexer::{substitution_len, word_segments, Lexer, Quoting};
+use crate::parser::parser::{Parser, MAX_NESTING, SYNTAX_ERROR_STATUS};
+use crate::shell::assertion;
+use crate::shell::expansion::{
+    current_user_home, find_substitution, split_at_lists, tilde_prefix, Fields, DEFAULT_IFS,
+};
+use crate::shell::history::History;
+use crate::shell::line_editor::{self, Binding, Keymap};
+use crate::shell::redirection;
+use crate::utilities::stack;
+use crate::utilities::utilities::{ASTNode, Interrupt, ListOperator, RedirectType, Redirection};
+use glob::Pattern;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Child;
+use std::time::{Duration, Instant};
 
 pub struct Shell {
     pub interpreter: Interpreter,
+    pub processes: Processes,
+    /// The status of the last command substitution run while expanding the
+    /// current command.
+    substitution_status: Option<i32>,
+    /// The number of command substitutions running inside one another.
+    substitution_depth: usize,
+    /// Set while a trap runs, so traps do not trigger themselves.
+    in_trap: bool,
+    /// The number of conditions running, like those of `if` and all but the
+    /// last command of `&&` and `||` lists, whose failures do not trigger
+    /// `ERR`.
+    conditions: usize,
+    /// The lines entered in interactive mode, for `fc`.
+    pub history: History,
+    /// The key bindings of the line editor, set with `bind`.
+    pub keymap: Keymap,
+    /// The canonical paths of the libraries sourced by `include`, in order.
+    included: Vec<PathBuf>,
+    /// The files being sourced by `include`, innermost last.
+    including: Vec<PathBuf>,
+    /// Set for a login shell, which runs `~/.bellos_logout`, or the file
+    /// named by `BELLOS_LOGOUT`, when it ends.
+    pub login: bool,
+}
+
+// Embedders run shells on threads of their own: keep `Shell` `Send`.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Shell>();
+};
+
+/// A node running, as returned by `interpret_node`. Boxed, as nodes run
+/// the nodes inside them.
+pub type Running<'a> = Pin<Box<dyn Future<Output = Result<Option<i32>, Interrupt>> + Send + 'a>>;
+
+/// How deeply functions may call one another, so that runaway recursion
+/// fails rather than exhausting memory.
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// The profile every login shell sources, before the user's own.
+const SYSTEM_PROFILE: &str = "/etc/bellos_profile";
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Shell {
     pub fn new() -> Self {
         Shell {
             interpreter: Interpreter::new(),
+            processes: Processes::new(),
+            substitution_status: None,
+            substitution_depth: 0,
+            in_trap: false,
+            conditions: 0,
+            history: History::new(),
+            keymap: Keymap::new(),
+            included: Vec::new(),
+            including: Vec::new(),
+            login: false,
         }
     }
 
+    /// Parses and runs `input`. Nothing runs if it has a syntax error, which
+    /// is returned, and `$?` is then 2.
     pub fn run(&mut self, input: &str) -> Result<(), String> {
-        let mut lexer = Lexer::new(input.to_string());
-        let tokens = lexer.tokenize();
-        let mut parser = Parser::new(tokens);
-        let ast = parser.parse()?;
-        self.interpret(ast)
+        self.processes.runtime = Runtime::Blocking;
+        block_on(self.run_async(input))
+    }
+
+    /// `run` for commands the shell runs itself, as `fc` does.
+    async fn run_async(&mut self, input: &str) -> Result<(), String> {
+        let ast = self.parse(input)?;
+        self.interpret(ast).await
+    }
+
+    /// Parses and runs `input` for a program embedding the shell, returning
+    /// the status of the last command. Unlike `run`, `exit` ends only
+    /// `input`, with its status, after `shutdown`, and leaves the program
+    /// running.
+    pub fn eval(&mut self, input: &str) -> Result<i32, String> {
+        self.processes.runtime = Runtime::Blocking;
+        block_on(self.evaluate(input))
+    }
+
+    /// What `eval` and `eval_async` run, on the runtime they set.
+    pub(crate) async fn evaluate(&mut self, input: &str) -> Result<i32, String> {
+        let ast = self.parse(input)?;
+        if let Some(status) = self.interpret_until_exit(ast).await {
+            return Ok(self.wind_up(status).await);
+        }
+        Ok(self.last_status())
+    }
+
+    /// Parses `input`, setting `$?` to 2 on a syntax error.
+    fn parse(&mut self, input: &str) -> Result<Vec<ASTNode>, String> {
+        let mut parser = Parser::from_lexer(Lexer::new(input));
+        parser.parse().inspect_err(|_| {
+            self.interpreter
+                .variables
+                .insert("?".to_string(), SYNTAX_ERROR_STATUS.to_string());
+        })
     }
 
-    pub fn interpret(&mut self, nodes: Vec<ASTNode>) -> Result<(), String> {
+    /// Runs `$PROMPT_COMMAND`, if set, before a prompt is shown. Errors are
+    /// reported without ending the session, and `$?` still holds the status
+    /// of the last command the user ran.
+    pub fn run_prompt_command(&mut self) {
+        let Some(command) = self.interpreter.variables.get("PROMPT_COMMAND").cloned() else {
+            return;
+        };
+        let status = self.interpreter.variables.get("?").cloned();
+        if let Err(e) = self.run(&command) {
+            let _ = writeln!(
+                self.processes.streams.err(),
+                "bellos: PROMPT_COMMAND: {}",
+                e
+            );
+        }
+        if let Some(status) = status {
+            self.interpreter.variables.insert("?".to_string(), status);
+        }
+    }
+
+    /// Prints a notification to stderr for every background job that has
+    /// finished since the last check.
+    pub fn check_jobs(&self) {
+        for notification in self.processes.check_jobs() {
+            let _ = writeln!(self.processes.streams.err(), "{}", notification);
+        }
+    }
+
+    pub async fn interpret(&mut self, nodes: Vec<ASTNode>) -> Result<(), String> {
+        if let Some(status) = self.interpret_until_exit(nodes).await {
+            let status = self.wind_up(status).await;
+            std::process::exit(status)
+        }
+        Ok(())
+    }
+
+    /// Runs `nodes` in order, reporting errors and carrying on after them,
+    /// until one of them runs `exit`, whose status is returned.
+    async fn interpret_until_exit(&mut self, nodes: Vec<ASTNode>) -> Option<i32> {
         for node in nodes {
-            if let Err(e) = self.interpret_node(&node) {
-                eprintln!("Error executing command: {}", e);
+            match self.interpret_node(&node).await {
+                Ok(_) => {}
+                Err(Interrupt::Exit(status)) => return Some(status),
+                Err(e) => {
+                    let _ = writeln!(
+                        self.processes.streams.err(),
+                        "Error executing command: {}",
+                        e
+                    );
+                    for frame in self.interpreter.take_error_trace() {
+                        let _ = writeln!(self.processes.streams.err(), "{}", frame);
+                    }
+                }
             }
         }
+        None
+    }
+
+    /// Runs a node, recording its exit status in `$?`. Simple commands are
+    /// preceded by the `DEBUG` trap, and failing ones followed by `ERR`.
+    pub fn interpret_node<'a>(&'a mut self, node: &'a ASTNode) -> Running<'a> {
+        let mut running: Running<'a> = Box::pin(async move {
+            let simple = matches!(node, ASTNode::Command { .. } | ASTNode::Pipeline(_));
+            if simple {
+                self.run_trap("DEBUG").await?;
+            }
+            let result = match node {
+                ASTNode::Command { name, args } => self.execute_command(name, args).await,
+                ASTNode::Pipeline(commands) => self.execute_pipeline(commands).await,
+                ASTNode::Redirect { node, redirects } => {
+                    self.execute_redirect(node, redirects).await
+                }
+                ASTNode::AndOr { first, rest } => self.execute_and_or(first, rest).await,
+                ASTNode::Background(node) => self.execute_background(node).await,
+                ASTNode::Time(node) => self.execute_time(node).await,
+                ASTNode::Assignment { name, value } => self.assignment(name, value).await,
+                ASTNode::Environment { assignments, node } => {
+                    self.execute_with_environment(assignments, node).await
+                }
+                ASTNode::Block(statements) => {
+                    let mut status = None;
+                    for statement in statements {
+                        status = self.interpret_node(statement).await?;
+                    }
+                    Ok(status)
+                }
+                ASTNode::If {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    self.execute_if(condition, then_block, else_block.as_deref())
+                        .await
+                }
+                ASTNode::While { condition, block } => self.execute_while(condition, block).await,
+                ASTNode::For { var, list, block } => self.execute_for(var, list, block).await,
+                ASTNode::Case { word, cases } => self.execute_case(word, cases).await,
+                ASTNode::Line { line, node } => {
+                    let previous = self.interpreter.enter_line(*line);
+                    let result = self.interpret_node(node).await;
+                    self.interpreter.leave_line(previous, &result);
+                    result
+                }
+                _ => Ok(self.interpreter.interpret_node(node)?),
+            };
+            // A command that fails with an error, like `cd` to a missing
+            // directory, has status 1.
+            let status = match result {
+                Ok(Some(status)) => Some(status),
+                Err(Interrupt::Error(_)) => Some(1),
+                _ => None,
+            };
+            if let Some(status) = status {
+                self.interpreter
+                    .variables
+                    .insert("?".to_string(), status.to_string());
+                let command = simple || matches!(node, ASTNode::Assignment { .. });
+                if status != 0 && command && self.conditions == 0 {
+                    self.run_trap("ERR").await?;
+                }
+            }
+            result
+        });
+        // Nodes are polled inside the nodes around them, as deeply as
+        // commands nest.
+        Box::pin(std::future::poll_fn(move |cx| {
+            stack::grow(|| running.as_mut().poll(cx))
+        }))
+    }
+
+    /// Runs the command trapped for `condition`, if any, in the current
+    /// shell. `$?` is preserved, and `$LINENO` stays that of the command
+    /// that triggered the trap. Errors in it are reported; it fails only
+    /// with the `Interrupt::Exit` of an `exit` it runs, which ends the
+    /// shell as one outside it would.
+    pub async fn run_trap(&mut self, condition: &str) -> Result<(), Interrupt> {
+        if self.in_trap {
+            return Ok(());
+        }
+        let Some(action) = self.interpreter.traps.get(condition).cloned() else {
+            return Ok(());
+        };
+        let nodes = match Parser::from_lexer(Lexer::new(&action)).parse() {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                let _ = writeln!(
+                    self.processes.streams.err(),
+                    "bellos: trap: {}: {}",
+                    condition,
+                    e
+                );
+                return Ok(());
+            }
+        };
+        self.in_trap = true;
+        let status = self.interpreter.variables.get("?").cloned();
+        for node in &nodes {
+            let node = match node {
+                ASTNode::Line { node, .. } => node,
+                node => node,
+            };
+            match self.interpret_node(node).await {
+                Ok(_) => {}
+                Err(Interrupt::Exit(status)) => {
+                    self.in_trap = false;
+                    return Err(Interrupt::Exit(status));
+                }
+                Err(e) => {
+                    let _ = writeln!(
+                        self.processes.streams.err(),
+                        "bellos: trap: {}: {}",
+                        condition,
+                        e
+                    );
+                }
+            }
+        }
+        if let Some(status) = status {
+            self.interpreter.variables.insert("?".to_string(), status);
+        }
+        self.in_trap = false;
         Ok(())
     }
 
-    pub fn interpret_node(&mut self, node: &ASTNode) -> Result<Option<i32>, String> {
-        match node {
-            ASTNode::Command { name, args } => self.execute_command(name, args),
-            ASTNode::Pipeline(commands) => self.execute_pipeline(commands),
-            ASTNode::Redirect {
-                node,
-                direction,
-                target,
-            } => self.execute_redirect(node, direction, target),
-            ASTNode::Background(node) => self.execute_background(node),
-            _ => self.interpreter.interpret_node(node),
+    /// Terminates the shell after `shutdown`.
+    pub fn exit(&mut self, status: i32) -> ! {
+        let status = block_on(self.wind_up(status));
+        std::process::exit(status)
+    }
+
+    /// What happens before the shell ends with `status`: `$?` is set to
+    /// it, `shutdown` runs and what the shell wrote is flushed. Returns the
+    /// status the shell ends with, that of an `exit` in the logout file or
+    /// `EXIT` trap if one ran.
+    async fn wind_up(&mut self, status: i32) -> i32 {
+        self.interpreter
+            .variables
+            .insert("?".to_string(), status.to_string());
+        let status = self.shutdown_async().await.unwrap_or(status);
+        self.interpreter
+            .variables
+            .insert("?".to_string(), status.to_string());
+        redirection::flush_std_streams();
+        status
+    }
+
+    /// Does what has to happen however the shell ends: runs
+    /// `~/.bellos_logout` in a login shell, then the `EXIT` trap if it has
+    /// not run yet, then under `huponexit` sends SIGHUP to the jobs still
+    /// running, except those marked with `disown -h`. Returns the status of
+    /// an `exit` run by the logout file or the trap, which the shell should
+    /// end with instead.
+    pub fn shutdown(&mut self) -> Option<i32> {
+        block_on(self.shutdown_async())
+    }
+
+    async fn shutdown_async(&mut self) -> Option<i32> {
+        let mut exited = None;
+        if std::mem::take(&mut self.login) {
+            if let Some(logout) = self.startup_file("BELLOS_LOGOUT", ".bellos_logout") {
+                exited = self.source_startup_file(&logout).await;
+            }
         }
+        if let Err(Interrupt::Exit(status)) = self.run_trap("EXIT").await {
+            exited = Some(status);
+        }
+        self.interpreter.traps.remove("EXIT");
+        if self.interpreter.options.huponexit {
+            self.processes.hang_up_jobs();
+        }
+        redirection::finish_fifo_copies();
+        exited
     }
 
-    pub fn execute_command(&mut self, name: &str, args: &[String]) -> Result<Option<i32>, String> {
-        let expanded_name = self.interpreter.expand_variables(name);
-        let expanded_args: Vec<String> = args
-            .iter()
-            .map(|arg| self.interpreter.expand_variables(arg))
-            .collect();
+    /// Starts a login shell: marks it as one and sources
+    /// `/etc/bellos_profile`, then `~/.bellos_profile`, skipping those that
+    /// do not exist. `BELLOS_SYSTEM_PROFILE` and `BELLOS_PROFILE` name
+    /// other files to source instead. `exit` in one terminates the shell.
+    pub fn start_login(&mut self) {
+        self.login = true;
+        let profiles = [
+            Some(
+                self.interpreter
+                    .variable("BELLOS_SYSTEM_PROFILE")
+                    .map_or_else(|| PathBuf::from(SYSTEM_PROFILE), PathBuf::from),
+            ),
+            self.startup_file("BELLOS_PROFILE", ".bellos_profile"),
+        ];
+        for profile in profiles.into_iter().flatten() {
+            if let Some(status) = block_on(self.source_startup_file(&profile)) {
+                self.exit(status);
+            }
+        }
+    }
 
-        let output = Command::new(&expanded_name)
-            .args(&expanded_args)
-            .output()
-            .map_err(|e| format!("Failed to execute command: {}", e))?;
+    /// Prepares an interactive shell: sources `~/.bellosrc`, or the file
+    /// `BELLOS_RC` names instead, if it exists. `exit` in it terminates the
+    /// shell.
+    pub fn start_interactive(&mut self) {
+        let Some(rc) = self.startup_file("BELLOS_RC", ".bellosrc") else {
+            return;
+        };
+        if let Some(status) = block_on(self.source_startup_file(&rc)) {
+            self.exit(status);
+        }
+    }
 
-        io::stdout()
-            .write_all(&output.stdout)
-            .map_err(|e| e.to_string())?;
-        io::stderr()
-            .write_all(&output.stderr)
-            .map_err(|e| e.to_string())?;
+    /// The startup file named by `variable`, or else the file `name` in the
+    /// user's home directory.
+    fn startup_file(&self, variable: &str, name: &str) -> Option<PathBuf> {
+        match self.interpreter.variable(variable) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => self.home_file(name),
+        }
+    }
 
-        Ok(Some(output.status.code().unwrap_or(-1)))
+    /// The file `name` in the user's home directory.
+    fn home_file(&self, name: &str) -> Option<PathBuf> {
+        self.interpreter
+            .variable("HOME")
+            .or_else(current_user_home)
+            .map(|home| Path::new(&home).join(name))
     }
 
-    pub fn execute_pipeline(&mut self, commands: &[ASTNode]) -> Result<Option<i32>, String> {
-        let mut last_output = Vec::new();
-        let mut last_exit_code = None;
+    /// Sources a profile or logout file if it exists. A `return` ends only
+    /// the file; the status of an `exit`, which ends the shell, is
+    /// returned.
+    async fn source_startup_file(&mut self, path: &Path) -> Option<i32> {
+        let fs = self.processes.fs_in(&self.interpreter);
+        if !fs.metadata(path).is_ok_and(|info| info.is_file) {
+            return None;
+        }
+        match self.source_file(path).await {
+            Ok(_) | Err(Interrupt::Return(_)) => {}
+            Err(Interrupt::Exit(status)) => return Some(status),
+            Err(e) => {
+                let _ = writeln!(self.processes.streams.err(), "{}: {}", path.display(), e);
+            }
+        }
+        None
+    }
 
-        for (i, command) in commands.iter().enumerate() {
-            if let ASTNode::Command { name, args } = command {
-                let expanded_name = self.interpreter.expand_variables(name);
-                let expanded_args: Vec<String> = args
-                    .iter()
-                    .map(|arg| self.interpreter.expand_variables(arg))
-                    .collect();
+    /// `exit [n]`: ends the shell with status `n`, by default that of the
+    /// last command. The top level terminates the shell once the `exit`
+    /// has unwound to it.
+    fn builtin_exit(&self, args: &[String]) -> Interrupt {
+        Interrupt::Exit(self.status_argument("exit", args))
+    }
 
-                let mut process = Command::new(&expanded_name);
-                process.args(&expanded_args);
+    /// `return [n]`: ends the current function with status `n`, by default
+    /// that of the last command.
+    fn builtin_return(&self, args: &[String]) -> Interrupt {
+        Interrupt::Return(self.status_argument("return", args))
+    }
 
-                if i == 0 {
-                    process.stdin(Stdio::inherit());
-                } else {
-                    process.stdin(Stdio::piped());
+    /// The status given to `exit` or `return`, or `$?` without one.
+    fn status_argument(&self, name: &str, args: &[String]) -> i32 {
+        let status = match args.first() {
+            Some(n) => n.parse::<i32>().unwrap_or_else(|_| {
+                let _ = writeln!(
+                    self.processes.streams.err(),
+                    "bellos: {}: {}: numeric argument required",
+                    name,
+                    n
+                );
+                2
+            }),
+            None => self.last_status(),
+        };
+        status & 0xff
+    }
+
+    /// The status of the last command, `$?`.
+    pub fn last_status(&self) -> i32 {
+        self.interpreter
+            .variables
+            .get("?")
+            .and_then(|status| status.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// `assert [-k] command [args]` runs a command, usually a test like
+    /// `[ "$x" -eq 5 ]`, and `assert_eq [-k] left right` compares two strings.
+    /// On failure the assertion is reported with its line, its words as
+    /// `written` and their values, then the script exits with status 1, or
+    /// with `-k` the assertion just returns 1.
+    async fn builtin_assert(
+        &mut self,
+        name: &str,
+        written: &[String],
+        args: &[String],
+    ) -> Result<Option<i32>, Interrupt> {
+        let keep_going = args.first().is_some_and(|arg| arg == "-k");
+        let args = &args[usize::from(keep_going)..];
+        let written = match written.split_first() {
+            Some((flag, rest)) if keep_going && flag == "-k" => rest,
+            _ => written,
+        };
+
+        let details = if name == "assert_eq" {
+            let [left, right] = args else {
+                return Err("Usage: assert_eq [-k] left right".to_string().into());
+            };
+            if left == right {
+                return Ok(Some(0));
+            }
+            assertion::difference(left, right)
+        } else {
+            let Some((command, command_args)) = args.split_first() else {
+                return Err("Usage: assert [-k] command [args]".to_string().into());
+            };
+            let status = self.run_command(command, command_args).await?.unwrap_or(0);
+            if status == 0 {
+                return Ok(Some(0));
+            }
+            let mut details = vec![format!("expanded: {}", assertion::expanded_words(args))];
+            if !matches!(command.as_str(), "[" | "[[" | "test") {
+                details.push(format!("status: {}", status));
+            }
+            details
+        };
+
+        let text = match name {
+            "assert_eq" => format!("assert_eq {}", written.join(" ")),
+            _ => written.join(" "),
+        };
+        let _ = writeln!(
+            self.processes.streams.err(),
+            "{}: assertion failed: {}",
+            self.interpreter.location(),
+            text
+        );
+        for detail in details {
+            let _ = writeln!(self.processes.streams.err(), "  {}", detail);
+        }
+        if keep_going {
+            Ok(Some(1))
+        } else {
+            Err(Interrupt::Exit(1))
+        }
+    }
+
+    /// `include file` sources a library at most once per session, looking
+    /// for it in the directories of `BELLOS_PATH`, then in that of the script
+    /// including it, unless its name holds a `/`. `include -l` lists the
+    /// files included so far.
+    async fn builtin_include(&mut self, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        let name = match args {
+            [flag] if flag == "-l" => {
+                for path in &self.included {
+                    let _ = writeln!(self.processes.streams.out(), "{}", path.display());
                 }
+                return Ok(Some(0));
+            }
+            [name] => name,
+            _ => return Err("Usage: include file | include -l".to_string().into()),
+        };
+
+        let candidates: Vec<PathBuf> = if name.contains('/') {
+            vec![self.interpreter.resolve_path(name)]
+        } else {
+            self.include_path()
+                .into_iter()
+                .map(|dir| dir.join(name))
+                .collect()
+        };
+        let fs = self.processes.fs_in(&self.interpreter);
+        let Some(path) = candidates
+            .iter()
+            .find(|path| fs.metadata(path).is_ok_and(|info| info.is_file))
+        else {
+            let searched: Vec<String> = candidates
+                .iter()
+                .filter_map(|path| path.parent())
+                .map(|dir| dir.display().to_string())
+                .collect();
+            return Err(format!(
+                "include: {}: not found (searched {})",
+                name,
+                searched.join(", ")
+            )
+            .into());
+        };
+        let path = fs
+            .canonicalize(path)
+            .map_err(|e| format!("include: {}: {}", path.display(), e))?;
+        if self.included.contains(&path) {
+            return Ok(Some(0));
+        }
+        // Guarding before sourcing keeps libraries that include each other
+        // from looping.
+        self.included.push(path.clone());
+        self.including.push(path.clone());
+        let result = self.source_file(&path).await;
+        self.including.pop();
+        result
+    }
+
+    /// The directories `include` searches: those of `BELLOS_PATH`, taken
+    /// from the shell's directory and an empty one meaning that directory
+    /// itself, then that of the file including, or of the script.
+    fn include_path(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self
+            .interpreter
+            .variables
+            .get("BELLOS_PATH")
+            .filter(|path| !path.is_empty())
+            .map(|path| {
+                path.split(PATH_LIST_SEPARATOR)
+                    .map(|dir| {
+                        self.interpreter
+                            .resolve_path(if dir.is_empty() { "." } else { dir })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let including = match self.including.last() {
+            Some(file) => Some(file.clone()),
+            None => self.interpreter.variables.get("0").map(PathBuf::from),
+        };
+        if let Some(dir) = including.as_deref().and_then(Path::parent) {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            if !dirs.iter().any(|known| known == dir) {
+                dirs.push(dir.to_path_buf());
+            }
+        }
+        dirs
+    }
 
-                if i == commands.len() - 1 {
-                    process.stdout(Stdio::inherit());
+    /// Runs the statements of a file in the current shell, reporting errors
+    /// with the file's name and line the way a script's are, and going on
+    /// after them. `return` ends the file early. The status is that of the
+    /// last statement run.
+    async fn source_file(&mut self, path: &Path) -> Result<Option<i32>, Interrupt> {
+        let source = self
+            .processes
+            .fs_in(&self.interpreter)
+            .read(path)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .map_err(|e| format!("include: {}: {}", path.display(), e))?;
+        let line = self.interpreter.line;
+        let mut parser = Parser::from_lexer(Lexer::new(&source));
+        let mut status = Some(0);
+        let mut result = Ok(());
+        while let Some((statement_line, statement)) = parser.next_statement() {
+            let node = match statement {
+                Ok(node) => node,
+                Err(e) => {
+                    let _ = writeln!(
+                        self.processes.streams.err(),
+                        "{}: line {}: syntax error: {}",
+                        path.display(),
+                        statement_line,
+                        e
+                    );
+                    status = Some(SYNTAX_ERROR_STATUS);
+                    break;
+                }
+            };
+            match self.interpret_node(&node).await {
+                Ok(node_status) => status = node_status.or(status),
+                Err(Interrupt::Return(returned)) => {
+                    status = Some(returned);
+                    break;
+                }
+                Err(Interrupt::Error(e)) => {
+                    let _ = writeln!(
+                        self.processes.streams.err(),
+                        "{}: line {}: {}",
+                        path.display(),
+                        self.interpreter.line,
+                        e
+                    );
+                    for frame in self.interpreter.take_error_trace() {
+                        let _ = writeln!(self.processes.streams.err(), "{}", frame);
+                    }
+                    status = Some(1);
+                }
+                Err(interrupt) => {
+                    result = Err(interrupt);
+                    break;
+                }
+            }
+        }
+        self.interpreter.enter_line(line);
+        result.map(|()| status)
+    }
+
+    /// `fc -l [-n] [-r] [first [last]]` lists history entries, by default
+    /// the last 16, `fc -s [old=new] [spec]` runs an entry again, by default
+    /// the last, with every `old` replaced by `new`, and `fc [first [last]]`
+    /// opens entries, by default the last, in `$FCEDIT` or `$EDITOR` and
+    /// runs what is saved. Commands run again are printed first and take
+    /// the place of the `fc` in the history.
+    async fn builtin_fc(&mut self, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        let usage =
+            "Usage: fc -l [-nr] [first [last]] | fc -s [old=new] [spec] | fc [first [last]]";
+        let (mut list, mut numbered, mut reverse, mut again) = (false, true, false, false);
+        let mut operands = args;
+        while let Some((flags, rest)) = operands
+            .split_first()
+            .and_then(|(first, rest)| Some((first.strip_prefix('-')?, rest)))
+            .filter(|(flags, _)| !flags.is_empty() && flags.parse::<u64>().is_err())
+        {
+            for flag in flags.chars() {
+                match flag {
+                    'l' => list = true,
+                    'n' => numbered = false,
+                    'r' => reverse = true,
+                    's' => again = true,
+                    _ => return Err(format!("fc: -{}: invalid option\n{}", flag, usage).into()),
+                }
+            }
+            operands = rest;
+        }
+
+        if again {
+            let (substitution, spec) = match operands {
+                [] => (None, None),
+                [first] if first.contains('=') => (first.split_once('='), None),
+                [spec] => (None, Some(spec)),
+                [first, spec] if first.contains('=') => (first.split_once('='), Some(spec)),
+                _ => return Err(usage.to_string().into()),
+            };
+            let index = self.history.find(spec.map_or("-1", String::as_str))?;
+            let mut command = self.history.previous()[index].clone();
+            if let Some((old, new)) = substitution.filter(|(old, _)| !old.is_empty()) {
+                command = command.replace(old, new);
+            }
+            return self.run_again(&command).await;
+        }
+
+        let (first, last) = match operands {
+            [] if list => (
+                self.history.find_clamped("-16")?,
+                self.history.find_clamped("-1")?,
+            ),
+            [] => {
+                let last = self.history.find("-1")?;
+                (last, last)
+            }
+            [first] if list => (
+                self.history.find_clamped(first)?,
+                self.history.find_clamped("-1")?,
+            ),
+            [first] => {
+                let first = self.history.find(first)?;
+                (first, first)
+            }
+            [first, last] if list => (
+                self.history.find_clamped(first)?,
+                self.history.find_clamped(last)?,
+            ),
+            [first, last] => (self.history.find(first)?, self.history.find(last)?),
+            _ => return Err(usage.to_string().into()),
+        };
+        let mut selected: Vec<(usize, &String)> = self.history.previous()
+            [first.min(last)..=first.max(last)]
+            .iter()
+            .enumerate()
+            .map(|(offset, entry)| (first.min(last) + offset + 1, entry))
+            .collect();
+        if reverse != (first > last) {
+            selected.reverse();
+        }
+
+        if list {
+            for (number, entry) in selected {
+                if numbered {
+                    let _ = writeln!(self.processes.streams.out(), "{}\t{}", number, entry);
                 } else {
-                    process.stdout(Stdio::piped());
+                    let _ = writeln!(self.processes.streams.out(), "\t{}", entry);
                 }
+            }
+            return Ok(Some(0));
+        }
 
-                let mut child = process
-                    .spawn()
-                    .map_err(|e| format!("Failed to spawn process: {}", e))?;
+        let text: String = selected
+            .iter()
+            .map(|(_, entry)| format!("{}\n", entry))
+            .collect();
+        let edited = self.edit(&text)?;
+        if edited.trim().is_empty() {
+            return Ok(Some(0));
+        }
+        self.run_again(edited.trim_end_matches('\n')).await
+    }
 
-                if i > 0 {
-                    if let Some(mut stdin) = child.stdin.take() {
-                        stdin
-                            .write_all(&last_output)
-                            .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+    /// `bind '"keyseq": text'` makes a key sequence of the line editor type
+    /// text, `bind -x '"keyseq": command'` makes it run a command, `bind -p`
+    /// lists the bindings and `bind -r keyseq` removes one.
+    fn builtin_bind(&mut self, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        let usage = "Usage: bind '\"keyseq\": text' | bind -x '\"keyseq\": command' | bind -p | bind -r keyseq";
+        match args {
+            [] => Err(usage.to_string().into()),
+            [flag] if flag == "-p" => {
+                let mut out = self.processes.streams.out();
+                for (sequence, binding) in self.keymap.bindings() {
+                    let sequence = line_editor::format_key_sequence(sequence);
+                    match binding {
+                        Binding::Macro(text) => {
+                            let text = line_editor::format_key_sequence(text.as_bytes());
+                            let _ = writeln!(out, "\"{}\": \"{}\"", sequence, text);
+                        }
+                        Binding::Command(command) => {
+                            let command = command.replace('"', "\\\"");
+                            let _ = writeln!(out, "-x \"{}\": \"{}\"", sequence, command);
+                        }
+                    }
+                }
+                Ok(Some(0))
+            }
+            [flag, sequences @ ..] if flag == "-r" && !sequences.is_empty() => {
+                let mut status = 0;
+                for text in sequences {
+                    let text = text
+                        .strip_prefix('"')
+                        .and_then(|text| text.strip_suffix('"'))
+                        .unwrap_or(text);
+                    let sequence = line_editor::parse_key_sequence(text)
+                        .map_err(|e| format!("bind: {}", e))?;
+                    if !self.keymap.remove(&sequence) {
+                        let _ = writeln!(self.processes.streams.err(), "bind: {}: not bound", text);
+                        status = 1;
                     }
                 }
+                Ok(Some(status))
+            }
+            [flag, bindings @ ..] if flag == "-x" && !bindings.is_empty() => {
+                for text in bindings {
+                    let (sequence, command) = line_editor::parse_binding(text, true)
+                        .map_err(|e| format!("bind: {}", e))?;
+                    self.keymap.bind(sequence, Binding::Command(command));
+                }
+                Ok(Some(0))
+            }
+            [flag, ..] if flag.starts_with('-') => {
+                Err(format!("bind: {}: invalid option\n{}", flag, usage).into())
+            }
+            bindings => {
+                for text in bindings {
+                    let (sequence, text) = line_editor::parse_binding(text, false)
+                        .map_err(|e| format!("bind: {}", e))?;
+                    self.keymap.bind(sequence, Binding::Macro(text));
+                }
+                Ok(Some(0))
+            }
+        }
+    }
+
+    /// Opens `text` in `$FCEDIT`, `$EDITOR` or `vi`, through a temporary
+    /// file, returning what the editor saved.
+    fn edit(&mut self, text: &str) -> Result<String, String> {
+        let editor = ["FCEDIT", "EDITOR"]
+            .iter()
+            .find_map(|name| self.interpreter.variable(name))
+            .filter(|editor| !editor.trim().is_empty())
+            .unwrap_or_else(|| "vi".to_string());
+        let mut file = tempfile::Builder::new()
+            .prefix("bellos-fc")
+            .suffix(".bellos")
+            .tempfile()
+            .map_err(|e| format!("fc: {}", e))?;
+        file.write_all(text.as_bytes())
+            .and_then(|()| file.flush())
+            .map_err(|e| format!("fc: {}", e))?;
+
+        // The editor may be given with arguments, like `code --wait`.
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+        redirection::flush_std_streams();
+        let mut command = std::process::Command::new(program);
+        processes::prepare_command(&self.interpreter, &mut command);
+        let status = command
+            .args(words)
+            .arg(file.path())
+            .status()
+            .map_err(|e| format!("fc: {}: {}", program, e))?;
+        if !status.success() {
+            return Err(format!(
+                "fc: {}: exited with status {}",
+                editor,
+                jobs::exit_code(status)
+            ));
+        }
+        fs::read_to_string(file.path()).map_err(|e| format!("fc: {}", e))
+    }
+
+    /// Prints and runs commands taken from the history, putting them there
+    /// in place of the `fc` that ran them.
+    async fn run_again(&mut self, commands: &str) -> Result<Option<i32>, Interrupt> {
+        let _ = writeln!(self.processes.streams.err(), "{}", commands);
+        self.history.replace_last(commands);
+        self.run(commands)?;
+        Ok(Some(self.last_status()))
+    }
+
+    /// `break [n]` and `continue [n]`: leave, or go on to the next iteration
+    /// of, the n-th enclosing loop.
+    fn builtin_loop_control(&self, name: &str, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        let count = match args.first() {
+            None => 1,
+            Some(n) => match n.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    let _ = writeln!(
+                        self.processes.streams.err(),
+                        "bellos: {}: {}: loop count out of range",
+                        name,
+                        n
+                    );
+                    return Ok(Some(1));
+                }
+            },
+        };
+        Err(if name == "break" {
+            Interrupt::Break(count)
+        } else {
+            Interrupt::Continue(count)
+        })
+    }
 
-                let output = child
-                    .wait_with_output()
-                    .map_err(|e| format!("Failed to wait for process: {}", e))?;
+    /// Expands a word as written into the fields it stands for: quotes are
+    /// removed, escapes replaced, variables and `$(...)` substitutions
+    /// outside of single quotes expanded, the output of unquoted
+    /// substitutions split on `IFS`, and unquoted glob patterns replaced by
+    /// the paths they match.
+    pub async fn expand_fields(&mut self, word: &str) -> Result<Vec<String>, String> {
+        let mut fields = Fields::default();
+        let mut word = word;
+        if let Some((home, rest)) = self.expand_tilde(word) {
+            // Like quoted text, a home directory is neither split nor globbed.
+            fields.push_quoted(&home);
+            word = rest;
+        }
+        for (text, quoting) in word_segments(word) {
+            if quoting == Quoting::Double {
+                let parts = split_at_lists(&text);
+                if parts.len() > 1 {
+                    self.push_lists(&mut fields, &parts).await?;
+                    continue;
+                }
+            }
+            if quoting != Quoting::Unquoted {
+                fields.push_quoted(&self.expand_segment(&text, quoting).await?);
+                continue;
+            }
+            let mut rest: &str = &text;
+            while let Some(start) = find_substitution(rest) {
+                fields.push_unsplit(&self.interpreter.expand_variables(&rest[..start])?);
+                let end = start + substitution_len(&rest[start..]);
+                let output = self.substitute(&rest[start..end]).await?;
+                let ifs = self.interpreter.variables.get("IFS").map(String::as_str);
+                fields.push_split(&output, ifs.unwrap_or(DEFAULT_IFS));
+                rest = &rest[end..];
+            }
+            fields.push_unsplit(&self.interpreter.expand_variables(rest)?);
+        }
+        Ok(fields.finish(
+            &self.interpreter.options,
+            &self.processes.fs_in(&self.interpreter),
+        ))
+    }
 
-                last_output = output.stdout;
-                last_exit_code = Some(output.status.code().unwrap_or(-1));
+    /// Adds double-quoted text split around `"$@"` and `"${name[@]}"` to
+    /// `fields`: each positional parameter or array element is a field of
+    /// its own, the first and last joined to the text before and after them.
+    /// Without items, and without other text, a list adds no field at all.
+    async fn push_lists(
+        &mut self,
+        fields: &mut Fields,
+        parts: &[(&str, Option<&str>)],
+    ) -> Result<(), String> {
+        for (part, list) in parts {
+            let text = self.expand_text(part).await?;
+            if !text.is_empty() {
+                fields.push_quoted(&text);
+            }
+            let items = match list {
+                Some("@") => self.interpreter.positional_parameters(),
+                Some(name) => self.interpreter.array(name),
+                None => break,
+            };
+            for (j, parameter) in items.iter().enumerate() {
+                if j > 0 {
+                    fields.end_field();
+                }
+                fields.push_quoted(parameter);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands a word as written into a single string, as for assignment
+    /// values and redirection targets, where no splitting happens.
+    pub async fn expand_word(&mut self, word: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut word = word;
+        if let Some((home, rest)) = self.expand_tilde(word) {
+            result = home;
+            word = rest;
+        }
+        for (text, quoting) in word_segments(word) {
+            result.push_str(&self.expand_segment(&text, quoting).await?);
+        }
+        Ok(result)
+    }
+
+    /// Expands the `~` prefix of a word as written, returning the directory
+    /// it stands for and the rest of the word: `~` is `$HOME`, `~user` the
+    /// home directory of `user`, and `~+` and `~-` are `$PWD` and `$OLDPWD`.
+    /// A prefix naming no user or unset variable is left as it is.
+    fn expand_tilde<'a>(&self, word: &'a str) -> Option<(String, &'a str)> {
+        let (prefix, rest) = tilde_prefix(word)?;
+        let variable = |name: &str| self.interpreter.variable(name);
+        let directory = match prefix {
+            "" => variable("HOME").or_else(current_user_home),
+            "+" => variable("PWD"),
+            "-" => variable("OLDPWD"),
+            user => self.processes.users.home_directory(user),
+        }?;
+        Some((directory, rest))
+    }
+
+    /// Expands a run of a word as `word_segments` splits it: literal text,
+    /// single-quoted or escaped, is taken as it is, anything else goes
+    /// through `expand_text`.
+    async fn expand_segment(&mut self, text: &str, quoting: Quoting) -> Result<String, String> {
+        match quoting {
+            Quoting::Literal => Ok(text.to_string()),
+            _ => self.expand_text(text).await,
+        }
+    }
+
+    /// Expands variables and `$(...)` substitutions in `text`, which has no
+    /// quotes of its own. Substituted output is never expanded again.
+    async fn expand_text(&mut self, text: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(start) = find_substitution(rest) {
+            result.push_str(&self.interpreter.expand_variables(&rest[..start])?);
+            let end = start + substitution_len(&rest[start..]);
+            result.push_str(&self.substitute(&rest[start..end]).await?);
+            rest = &rest[end..];
+        }
+        result.push_str(&self.interpreter.expand_variables(rest)?);
+        Ok(result)
+    }
+
+    /// Expands a command's name and arguments into its argument vector.
+    /// The regex after `=~` in `[[ ... ]]` is one word, its quoted parts
+    /// matching literally.
+    async fn expand_command(&mut self, name: &str, args: &[String]) -> Result<Vec<String>, String> {
+        let mut argv = self.expand_fields(name).await?;
+        let regex = args
+            .iter()
+            .position(|arg| arg == "=~")
+            .filter(|_| name == "[[")
+            .map(|op| op + 1);
+        for (i, arg) in args.iter().enumerate() {
+            if Some(i) == regex {
+                argv.push(self.expand_regex(arg).await?);
             } else {
-                return Err("Invalid command in pipeline".to_string());
+                argv.extend(self.expand_fields(arg).await?);
+            }
+        }
+        Ok(argv)
+    }
+
+    async fn expand_regex(&mut self, word: &str) -> Result<String, String> {
+        let mut regex = String::new();
+        for (text, quoting) in word_segments(word) {
+            let text = self.expand_segment(&text, quoting).await?;
+            regex.push_str(&match quoting {
+                Quoting::Unquoted => text,
+                _ => regex::escape(&text),
+            });
+        }
+        Ok(regex)
+    }
+
+    /// Runs the `$(...)` substitution spanning `substitution`, or evaluates
+    /// the `$((...))` arithmetic expansion, storing what it assigns.
+    async fn substitute(&mut self, substitution: &str) -> Result<String, String> {
+        if let Some(expr) = arithmetic::expansion_body(substitution) {
+            return Ok(self.interpreter.evaluate_arithmetic(expr)?.to_string());
+        }
+        let command = &substitution[2..];
+        self.command_substitution(command.strip_suffix(')').unwrap_or(command))
+            .await
+    }
+
+    /// Runs `command` with stdout captured and returns the output minus its
+    /// trailing newlines, the only place output is trimmed: pipelines and
+    /// redirections pass it on as written. Like a subshell, variable changes made by the
+    /// command do not outlive it; its exit status is kept for the command
+    /// being expanded.
+    async fn command_substitution(&mut self, command: &str) -> Result<String, String> {
+        // Each substitution parses and runs its command anew, so nesting in
+        // them escapes the parser's limit.
+        if self.substitution_depth >= MAX_NESTING {
+            return Err(format!(
+                "Command substitutions nested more than {} deep",
+                MAX_NESTING
+            ));
+        }
+        let mut capture = tempfile::tempfile().map_err(|e| e.to_string())?;
+        let redirect = [redirection::duplicate(
+            1,
+            RedirectType::DuplicateOutput,
+            &capture,
+        )];
+        let saved = self.redirect(&redirect, &[redirect[0].target.clone()])?;
+
+        let variables = self.interpreter.variables.clone();
+        let line = self.interpreter.line;
+        let mut status = 0;
+        let mut parser = Parser::from_lexer(Lexer::new(command));
+        self.substitution_depth += 1;
+        while let Some((_, statement)) = parser.next_statement() {
+            let result = match statement {
+                Ok(node) => self.interpret_node(&node).await,
+                Err(e) => Err(Interrupt::from(e)),
+            };
+            match result {
+                Ok(result) => status = result.unwrap_or(status),
+                // `exit` only ends the substitution.
+                Err(Interrupt::Exit(code)) => {
+                    status = code;
+                    break;
+                }
+                Err(e) => {
+                    let _ = writeln!(self.processes.streams.err(), "bellos: {}", e);
+                    for frame in self.interpreter.take_error_trace() {
+                        let _ = writeln!(self.processes.streams.err(), "{}", frame);
+                    }
+                    status = 1;
+                }
             }
         }
+        self.substitution_depth -= 1;
+        self.interpreter.variables = variables;
+        self.interpreter.line = line;
+        self.substitution_status = Some(status);
+        redirection::restore(saved, &mut self.processes.streams);
 
-        Ok(last_exit_code)
+        let mut bytes = Vec::new();
+        capture
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| capture.read_to_end(&mut bytes))
+            .map_err(|e| format!("command substitution: {}", e))?;
+        // Variables hold text, so bytes that are not UTF-8 become U+FFFD.
+        let mut output = String::from_utf8_lossy(&bytes).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        Ok(output)
     }
 
-    pub fn execute_redirect(
+    /// Assigns a variable. The status is that of the last command
+    /// substitution in the value, so `x=$(false)` fails.
+    async fn assignment(&mut self, name: &str, value: &str) -> Result<Option<i32>, Interrupt> {
+        self.substitution_status = None;
+        let value = self.expand_word(value).await?;
+        self.interpreter.set_variable(name, value);
+        Ok(Some(self.substitution_status.unwrap_or(0)))
+    }
+
+    /// Runs `node` with variables assigned for its duration only, both in
+    /// the shell and in the environment of the commands it starts.
+    async fn execute_with_environment(
         &mut self,
+        assignments: &[(String, String)],
         node: &ASTNode,
-        direction: &RedirectType,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        let expanded_target = self.interpreter.expand_variables(target);
-        match direction {
-            RedirectType::Input => self.execute_input_redirect(node, &expanded_target),
-            RedirectType::Output => self.execute_output_redirect(node, &expanded_target),
-            RedirectType::Append => self.execute_append_redirect(node, &expanded_target),
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut values = Vec::new();
+        for (_, value) in assignments {
+            values.push(self.expand_word(value).await?);
+        }
+        let mut saved = Vec::new();
+        for ((name, _), value) in assignments.iter().zip(values) {
+            saved.push((
+                name,
+                self.interpreter.variables.get(name).cloned(),
+                self.interpreter.exported.contains(name),
+            ));
+            self.interpreter.export_variable(name, value);
+        }
+
+        let result = self.interpret_node(node).await;
+
+        for (name, variable, exported) in saved.into_iter().rev() {
+            match variable {
+                Some(value) => self.interpreter.variables.insert(name.clone(), value),
+                None => self.interpreter.variables.remove(name),
+            };
+            if !exported {
+                self.interpreter.exported.remove(name);
+            }
+        }
+        result
+    }
+
+    /// Evaluates the condition of `if` or `while`: a comparison, or a
+    /// command, including `(( expr ))`, that holds when it succeeds.
+    async fn evaluate_condition(&mut self, condition: &ASTNode) -> Result<bool, Interrupt> {
+        match condition {
+            ASTNode::Comparison { .. } => Ok(self
+                .interpreter
+                .logic
+                .evaluate_condition(&self.interpreter.variables, condition)?),
+            _ => Ok(self.as_condition(condition).await?.unwrap_or(0) == 0),
+        }
+    }
+
+    /// Runs `node` as a condition, whose failures do not trigger `ERR`.
+    async fn as_condition(&mut self, node: &ASTNode) -> Result<Option<i32>, Interrupt> {
+        self.conditions += 1;
+        let result = self.interpret_node(node).await;
+        self.conditions -= 1;
+        result
+    }
+
+    /// Runs an and-or list left to right, skipping each command whose
+    /// operator does not hold for the status of the last one run. A command
+    /// failing with an error, like `cd` to a missing directory, is reported
+    /// and has status 1, so `cd dir || exit` still reaches `exit`.
+    async fn execute_and_or(
+        &mut self,
+        first: &ASTNode,
+        rest: &[(ListOperator, ASTNode)],
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut status = self.run_list_operand(first, !rest.is_empty()).await?;
+        for (i, (op, node)) in rest.iter().enumerate() {
+            if op.runs_after(status) {
+                status = self.run_list_operand(node, i + 1 < rest.len()).await?;
+            }
         }
+        Ok(Some(status))
     }
 
-    fn execute_input_redirect(
+    /// Runs a command of an and-or list, as a condition unless it is the
+    /// last.
+    async fn run_list_operand(
         &mut self,
         node: &ASTNode,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        if let ASTNode::Command { name, args } = node {
-            let expanded_name = self.interpreter.expand_variables(name);
-            let expanded_args: Vec<String> = args
-                .iter()
-                .map(|arg| self.interpreter.expand_variables(arg))
-                .collect();
+        condition: bool,
+    ) -> Result<i32, Interrupt> {
+        let result = if condition {
+            self.as_condition(node).await
+        } else {
+            self.interpret_node(node).await
+        };
+        match result {
+            Ok(status) => Ok(status.unwrap_or(0)),
+            Err(Interrupt::Error(e)) => {
+                let _ = writeln!(self.processes.streams.err(), "bellos: {}", e);
+                for frame in self.interpreter.take_error_trace() {
+                    let _ = writeln!(self.processes.streams.err(), "{}", frame);
+                }
+                Ok(1)
+            }
+            Err(interrupt) => Err(interrupt),
+        }
+    }
 
-            let input = std::fs::File::open(target)
-                .map_err(|e| format!("Failed to open input file '{}': {}", target, e))?;
+    async fn execute_if(
+        &mut self,
+        condition: &ASTNode,
+        then_block: &ASTNode,
+        else_block: Option<&ASTNode>,
+    ) -> Result<Option<i32>, Interrupt> {
+        if self.evaluate_condition(condition).await? {
+            self.interpret_node(then_block).await
+        } else if let Some(else_block) = else_block {
+            self.interpret_node(else_block).await
+        } else {
+            // No branch ran: the failed condition is not the status.
+            Ok(Some(0))
+        }
+    }
 
-            let output = std::process::Command::new(&expanded_name)
-                .args(&expanded_args)
-                .stdin(input)
-                .output()
-                .map_err(|e| format!("Failed to execute command: {}", e))?;
+    /// Runs a `while` loop. Its status is that of the last command of the
+    /// block, or 0 if the block never ran.
+    async fn execute_while(
+        &mut self,
+        condition: &ASTNode,
+        block: &ASTNode,
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut status = None;
+        while self.evaluate_condition(condition).await? {
+            match self.interpret_node(block).await {
+                Ok(result) => status = result,
+                Err(interrupt) => {
+                    if loop_control(interrupt)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(Some(status.unwrap_or(0)))
+    }
 
-            io::stdout()
-                .write_all(&output.stdout)
-                .map_err(|e| e.to_string())?;
-            io::stderr()
-                .write_all(&output.stderr)
-                .map_err(|e| e.to_string())?;
+    /// Runs a `for` loop. Its status is that of the last command of the
+    /// block, or 0 if there was nothing to loop over.
+    async fn execute_for(
+        &mut self,
+        var: &str,
+        list: &Option<Vec<String>>,
+        block: &ASTNode,
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut items = Vec::new();
+        match list {
+            Some(list) => {
+                for word in list {
+                    items.extend(self.expand_fields(word).await?);
+                }
+            }
+            None => items = self.interpreter.positional_parameters(),
+        }
+        let mut status = None;
+        for item in items {
+            self.interpreter.set_variable(var, item);
+            match self.interpret_node(block).await {
+                Ok(result) => status = result,
+                Err(interrupt) => {
+                    if loop_control(interrupt)? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(Some(status.unwrap_or(0)))
+    }
 
-            Ok(Some(output.status.code().unwrap_or(-1)))
-        } else {
-            Err("Invalid command for input redirection".to_string())
+    /// Runs the block of the first `case` arm with a pattern matching the
+    /// word. Patterns are globs, their quoted parts matching literally.
+    async fn execute_case(
+        &mut self,
+        word: &str,
+        cases: &[(Vec<String>, ASTNode)],
+    ) -> Result<Option<i32>, Interrupt> {
+        let value = self.expand_word(word).await?;
+        for (patterns, block) in cases {
+            for pattern in patterns {
+                let mut glob = String::new();
+                for (text, quoting) in word_segments(pattern) {
+                    let text = self.expand_segment(&text, quoting).await?;
+                    glob.push_str(&match quoting {
+                        Quoting::Unquoted => text,
+                        _ => Pattern::escape(&text),
+                    });
+                }
+                let matched = match Pattern::new(&glob) {
+                    Ok(glob) => glob.matches(&value),
+                    Err(_) => glob == value,
+                };
+                if matched {
+                    return self.interpret_node(block).await;
+                }
+            }
+        }
+        Ok(Some(0))
+    }
+
+    pub async fn execute_command(
+        &mut self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Option<i32>, Interrupt> {
+        self.substitution_status = None;
+        let argv = self.expand_command(name, args).await?;
+        // A command that expands to nothing, like `$(true)`, only runs its
+        // substitutions.
+        let Some((command, command_args)) = argv.split_first() else {
+            return Ok(Some(self.substitution_status.unwrap_or(0)));
+        };
+        if self.interpreter.options.xtrace {
+            let _ = writeln!(self.processes.streams.err(), "+ {}", argv.join(" "));
+        }
+        // Assertions report their words as written along with their values.
+        match command.as_str() {
+            "assert" | "assert_eq" if command == name => {
+                self.builtin_assert(command, args, command_args).await
+            }
+            _ => self.run_command(command, command_args).await,
+        }
+    }
+
+    /// Runs an expanded command: a shell builtin, a function, or a builtin
+    /// or external command of `Processes`.
+    async fn run_command(&mut self, name: &str, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        match name {
+            "exit" => return Err(self.builtin_exit(args)),
+            "return" => return Err(self.builtin_return(args)),
+            "break" | "continue" => return self.builtin_loop_control(name, args),
+            "assert" | "assert_eq" => return Box::pin(self.builtin_assert(name, args, args)).await,
+            "include" => return self.builtin_include(args).await,
+            "fc" => return self.builtin_fc(args).await,
+            "bind" => return self.builtin_bind(args),
+            "timeout" => return self.builtin_timeout(args).await,
+            "exec" if !args.is_empty() && self.processes.spawner.is_some() => {
+                let status = self
+                    .processes
+                    .execute_command(&mut self.interpreter, name, args)
+                    .await?;
+                return Err(Interrupt::Exit(status.unwrap_or(0)));
+            }
+            _ => {}
+        }
+        if self.is_function(name) {
+            return self.call_function(name, args).await;
+        }
+        Ok(self
+            .processes
+            .execute_command(&mut self.interpreter, name, args)
+            .await?)
+    }
+
+    /// `timeout` runs a function in a forked copy of the shell, which it can
+    /// stop like any other process. Other commands are left to `Processes`.
+    async fn builtin_timeout(&mut self, args: &[String]) -> Result<Option<i32>, Interrupt> {
+        let limits = timeout::parse_args(args)?;
+        if !self.is_function(limits.name) {
+            return Ok(self
+                .processes
+                .execute_command(&mut self.interpreter, "timeout", args)
+                .await?);
+        }
+        // Like the commands a spawner runs, the function is not held to
+        // the limit.
+        if self.processes.spawner.is_some() {
+            let call = async |shell: &mut Self| shell.call_function(limits.name, limits.args).await;
+            return Ok(Some(self.run_in_place(call).await));
+        }
+        let runtime = self.processes.runtime;
+        let status = timeout::run_forked(&limits, runtime, || {
+            self.processes.runtime = Runtime::Blocking;
+            subshell_status(block_on(self.call_function(limits.name, limits.args)))
+        })
+        .await?;
+        Ok(Some(status))
+    }
+
+    /// Whether `name` runs a function: one is defined and no builtin has the
+    /// name.
+    fn is_function(&self, name: &str) -> bool {
+        self.interpreter.functions.contains_key(name) && builtins::find(name).is_none()
+    }
+
+    /// Calls a function in the current shell with `args` as its positional
+    /// parameters. Its status is that of the last command it ran.
+    pub async fn call_function(
+        &mut self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Option<i32>, Interrupt> {
+        let Some(body) = self.interpreter.functions.get(name).cloned() else {
+            return Err(format!("Function '{}' not found", name).into());
+        };
+        if self.interpreter.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err(format!(
+                "{}: maximum function nesting level exceeded ({})",
+                name, MAX_CALL_DEPTH
+            )
+            .into());
+        }
+        let saved = self.interpreter.enter_function(name, args);
+        let result = match self.interpret_node(&body).await {
+            Err(Interrupt::Return(status)) => Ok(Some(status)),
+            // Loops outside the function are out of reach of its `break`
+            // and `continue`.
+            Err(Interrupt::Break(_) | Interrupt::Continue(_)) => Ok(None),
+            result => result,
+        };
+        let failed = matches!(result, Err(Interrupt::Error(_)));
+        self.interpreter.leave_function(saved, failed);
+        Ok(Some(result?.unwrap_or(0)))
+    }
+
+    /// Runs a function, builtin or compound command as a pipeline stage in
+    /// the shell itself, reading `input` if it follows another stage and
+    /// returning its output, as raw bytes, if another stage follows.
+    async fn run_stage_in_shell(
+        &mut self,
+        input: Option<&[u8]>,
+        capture: bool,
+        stage: impl AsyncFnOnce(&mut Self) -> Result<Option<i32>, Interrupt>,
+    ) -> Result<(Option<i32>, Vec<u8>), Interrupt> {
+        let temporary = |contents: &[u8]| -> io::Result<File> {
+            let mut file = tempfile::tempfile()?;
+            file.write_all(contents)?;
+            file.seek(SeekFrom::Start(0))?;
+            Ok(file)
+        };
+        let stdin = input
+            .map(temporary)
+            .transpose()
+            .map_err(|e| format!("pipeline: {}", e))?;
+        let stdout = capture
+            .then(|| temporary(&[]))
+            .transpose()
+            .map_err(|e| format!("pipeline: {}", e))?;
+        let mut redirects = Vec::new();
+        if let Some(file) = &stdin {
+            redirects.push(redirection::duplicate(
+                0,
+                RedirectType::DuplicateInput,
+                file,
+            ));
+        }
+        if let Some(file) = &stdout {
+            redirects.push(redirection::duplicate(
+                1,
+                RedirectType::DuplicateOutput,
+                file,
+            ));
+        }
+        let targets: Vec<String> = redirects.iter().map(|r| r.target.clone()).collect();
+        let saved = self.redirect(&redirects, &targets)?;
+        let result = match stage(self).await {
+            // Like a subshell, a stage that exits only ends itself.
+            Err(Interrupt::Exit(status)) => Ok(Some(status)),
+            result => result,
+        };
+        redirection::restore(saved, &mut self.processes.streams);
+
+        let mut output = Vec::new();
+        if let Some(mut file) = stdout {
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.read_to_end(&mut output))
+                .map_err(|e| format!("pipeline: {}", e))?;
+        }
+        Ok((result?, output))
+    }
+
+    pub async fn execute_pipeline(
+        &mut self,
+        commands: &[ASTNode],
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut leader = None;
+        let result = self.run_pipeline_stages(commands, &mut leader).await;
+        if let Some(mut leader) = leader {
+            let _ = leader.wait();
         }
+        result
     }
 
-    fn execute_output_redirect(
+    /// Runs the stages of a pipeline in order. External commands share one
+    /// process group, that of the first, which is left in `leader` unreaped
+    /// so the group outlives it.
+    async fn run_pipeline_stages(
+        &mut self,
+        commands: &[ASTNode],
+        leader: &mut Option<Child>,
+    ) -> Result<Option<i32>, Interrupt> {
+        let mut last_output = Vec::new();
+        let mut last_exit_code = None;
+
+        for (i, command) in commands.iter().enumerate() {
+            if let ASTNode::Command { name, args } = command {
+                let argv = self.expand_command(name, args).await?;
+                let Some((expanded_name, expanded_args)) = argv.split_first() else {
+                    continue;
+                };
+                let last = i == commands.len() - 1;
+                if self.is_function(expanded_name) || builtins::find(expanded_name).is_some() {
+                    let input = (i > 0).then_some(last_output.as_slice());
+                    let (status, output) = self
+                        .run_stage_in_shell(input, !last, async |shell| {
+                            shell.run_command(expanded_name, expanded_args).await
+                        })
+                        .await?;
+                    last_output = output;
+                    last_exit_code = Some(status.unwrap_or(0));
+                    continue;
+                }
+
+                let mut process = match self.processes.command(&self.interpreter, expanded_name) {
+                    Ok(process) => process,
+                    Err(status) => return Ok(Some(status)),
+                };
+                process.args(expanded_args);
+
+                let input = (i > 0).then_some(last_output.as_slice());
+                if let Some(result) = self.processes.run_with_spawner(&mut process, input, !last) {
+                    let (status, output) = result?;
+                    last_output = output;
+                    last_exit_code = Some(status);
+                    continue;
+                }
+
+                let (status, output) = self
+                    .processes
+                    .runtime
+                    .run_stage(process, input, !last, leader)
+                    .await?;
+                last_output = output;
+                last_exit_code = Some(jobs::exit_code(status));
+            } else {
+                // Redirected and compound stages run in the shell too, their
+                // own redirections applying inside the pipeline's.
+                let last = i == commands.len() - 1;
+                let input = (i > 0).then_some(last_output.as_slice());
+                let (status, output) = self
+                    .run_stage_in_shell(input, !last, async |shell| {
+                        shell.interpret_node(command).await
+                    })
+                    .await?;
+                last_output = output;
+                last_exit_code = Some(status.unwrap_or(0));
+            }
+        }
+
+        Ok(last_exit_code)
+    }
+
+    /// Runs `node` with its redirections applied to the shell's descriptors,
+    /// restoring them afterwards. `exec` without a command keeps them.
+    pub async fn execute_redirect(
         &mut self,
         node: &ASTNode,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        if let ASTNode::Command { name, args } = node {
-            let expanded_name = self.interpreter.expand_variables(name);
-            let expanded_args: Vec<String> = args
-                .iter()
-                .map(|arg| self.interpreter.expand_variables(arg))
-                .collect();
+        redirects: &[Redirection],
+    ) -> Result<Option<i32>, Interrupt> {
+        let targets = self.redirect_targets(redirects).await?;
+        let saved = self.redirect(redirects, &targets)?;
 
-            let output_file = std::fs::File::create(target)
-                .map_err(|e| format!("Failed to create output file '{}': {}", target, e))?;
+        if matches!(node, ASTNode::Command { name, args } if name == "exec" && args.is_empty()) {
+            redirection::persist(saved);
+            return Ok(Some(0));
+        }
 
-            let output = std::process::Command::new(&expanded_name)
-                .args(&expanded_args)
-                .stdout(output_file)
-                .output()
-                .map_err(|e| format!("Failed to execute command: {}", e))?;
+        let result = self.interpret_node(node).await;
+        redirection::restore(saved, &mut self.processes.streams);
+        result
+    }
 
-            io::stderr()
-                .write_all(&output.stderr)
-                .map_err(|e| e.to_string())?;
+    /// Applies redirections to the shell's descriptors, opening files from
+    /// its directory.
+    fn redirect(
+        &mut self,
+        redirects: &[Redirection],
+        targets: &[String],
+    ) -> Result<Vec<redirection::SavedFd>, String> {
+        let fs = InDirectory {
+            fs: &*self.processes.fs,
+            directory: &self.interpreter.directory,
+        };
+        redirection::apply(redirects, targets, &fs, &mut self.processes.streams)
+    }
 
-            Ok(Some(output.status.code().unwrap_or(-1)))
-        } else {
-            Err("Invalid command for output redirection".to_string())
+    /// Expands the target of each redirection: heredoc bodies unless their
+    /// delimiter was quoted, and other targets as words.
+    async fn redirect_targets(&mut self, redirects: &[Redirection]) -> Result<Vec<String>, String> {
+        let mut targets = Vec::new();
+        for redirect in redirects {
+            targets.push(match redirect.kind {
+                RedirectType::HereDoc { quoted: true, .. } => redirect.target.clone(),
+                RedirectType::HereDoc { quoted: false, .. } => {
+                    self.expand_text(&redirect.target).await?
+                }
+                _ => self.expand_word(&redirect.target).await?,
+            });
+        }
+        Ok(targets)
+    }
+
+    pub async fn execute_background(&mut self, node: &ASTNode) -> Result<Option<i32>, Interrupt> {
+        let (command, redirects) = match node {
+            ASTNode::Redirect { node, redirects } => (node.as_ref(), redirects.as_slice()),
+            node => (node, &[][..]),
+        };
+        let ASTNode::Command { name, args } = command else {
+            return self
+                .execute_in_subshell(node, async |shell| shell.interpret_node(node).await)
+                .await;
+        };
+        let argv = self.expand_command(name, args).await?;
+        let Some((expanded_name, expanded_args)) = argv.split_first() else {
+            return Ok(Some(0));
+        };
+        // Functions and builtins need the shell: they run in a copy of it,
+        // with the words already expanded here.
+        if self.is_function(expanded_name) || builtins::find(expanded_name).is_some() {
+            let targets = self.redirect_targets(redirects).await?;
+            return self
+                .execute_in_subshell(node, async |shell| {
+                    shell.redirect(redirects, &targets)?;
+                    shell.run_command(expanded_name, expanded_args).await
+                })
+                .await;
+        }
+
+        // The job inherits its redirections with the shell's descriptors.
+        let targets = self.redirect_targets(redirects).await?;
+        let saved = self.redirect(redirects, &targets)?;
+        let mut command = match self.processes.command(&self.interpreter, expanded_name) {
+            Ok(command) => command,
+            Err(status) => {
+                redirection::restore(saved, &mut self.processes.streams);
+                return Ok(Some(status));
+            }
+        };
+        command.args(expanded_args);
+        // A spawner cannot leave a command running: it finishes first.
+        if let Some(result) = self.processes.run_with_spawner(&mut command, None, false) {
+            redirection::restore(saved, &mut self.processes.streams);
+            return Ok(Some(result?.0));
         }
+        // Background jobs get a process group but never the terminal.
+        jobs::set_process_group(&mut command, 0);
+        let child = command.spawn();
+        redirection::restore(saved, &mut self.processes.streams);
+        let child = child.map_err(|e| format!("Failed to spawn background process: {}", e))?;
+        jobs::join_process_group(child.id(), 0);
+        let pid = child.id();
+        self.start_job(child, pid, node);
+        Ok(Some(0))
     }
 
-    fn execute_append_redirect(
+    /// Runs `job` in the background, in a forked copy of the shell: it sees
+    /// the variables, functions, directory and environment as they are
+    /// now, and what either shell changes afterwards does not reach the
+    /// other. `node` is what `jobs` shows. A shell given a spawner runs
+    /// `job` with `run_in_place` instead, to the end.
+    async fn execute_in_subshell(
         &mut self,
         node: &ASTNode,
-        target: &str,
-    ) -> Result<Option<i32>, String> {
-        if let ASTNode::Command { name, args } = node {
-            let expanded_name = self.interpreter.expand_variables(name);
-            let expanded_args: Vec<String> = args
-                .iter()
-                .map(|arg| self.interpreter.expand_variables(arg))
-                .collect();
+        job: impl AsyncFnOnce(&mut Self) -> Result<Option<i32>, Interrupt>,
+    ) -> Result<Option<i32>, Interrupt> {
+        if self.processes.spawner.is_some() {
+            return Ok(Some(self.run_in_place(job).await));
+        }
+        // The copy waits for what it runs itself, whatever runtime the
+        // shell was on.
+        let pid = jobs::fork_subshell(|| {
+            self.processes.runtime = Runtime::Blocking;
+            subshell_status(block_on(job(self)))
+        })
+        .map_err(|e| format!("Failed to start background job: {}", e))?;
+        self.start_job(jobs::Process::Forked(pid), pid, node);
+        Ok(Some(0))
+    }
 
-            let output_file = std::fs::OpenOptions::new()
-                .write(true)
-                .append(true)
-                .open(target)
-                .map_err(|e| format!("Failed to open file '{}' for appending: {}", target, e))?;
+    /// Makes the process `pid` started for `node` a job, and `$!`. An
+    /// interactive shell tells its number and process ID on stderr, as
+    /// `[1] 4242`.
+    fn start_job(&mut self, process: impl Into<jobs::Process>, pid: u32, node: &ASTNode) {
+        self.interpreter
+            .variables
+            .insert("!".to_string(), pid.to_string());
+        let id = self
+            .processes
+            .add_job(process, node.to_string().trim_end().to_string());
+        if self.interpreter.options.interactive {
+            let _ = writeln!(self.processes.streams.err(), "[{}] {}", id, pid);
+        }
+    }
 
-            let output = std::process::Command::new(&expanded_name)
-                .args(&expanded_args)
-                .stdout(output_file)
-                .output()
-                .map_err(|e| format!("Failed to execute command: {}", e))?;
+    /// Runs `job` in the shell itself where a shell without a spawner
+    /// would fork a copy to run it, so that every command it runs goes
+    /// through the spawner. As in a copy, the variables it changes are
+    /// restored afterwards and `exit` ends only the job.
+    async fn run_in_place(
+        &mut self,
+        job: impl AsyncFnOnce(&mut Self) -> Result<Option<i32>, Interrupt>,
+    ) -> i32 {
+        let variables = self.interpreter.variables.clone();
+        let result = job(self).await;
+        self.interpreter.variables = variables;
+        match result {
+            Ok(status) => status.unwrap_or(0),
+            Err(Interrupt::Exit(status)) => status,
+            Err(e) => {
+                let _ = writeln!(self.processes.streams.err(), "bellos: {}", e);
+                1
+            }
+        }
+    }
 
-            io::stderr()
-                .write_all(&output.stderr)
-                .map_err(|e| e.to_string())?;
+    /// Runs a node and reports its real, user, and system time to stderr.
+    /// CPU times cover both the shell and its children.
+    pub async fn execute_time(&mut self, node: &ASTNode) -> Result<Option<i32>, Interrupt> {
+        let start_cpu = cpu_times();
+        let start = Instant::now();
+        let result = self.interpret_node(node).await;
+        let real = start.elapsed();
 
-            Ok(Some(output.status.code().unwrap_or(-1)))
-        } else {
-            Err("Invalid command for append redirection".to_string())
+        let _ = writeln!(self.processes.streams.err());
+        let _ = writeln!(
+            self.processes.streams.err(),
+            "real\t{}",
+            format_duration(real)
+        );
+        if let (Some((start_user, start_sys)), Some((end_user, end_sys))) = (start_cpu, cpu_times())
+        {
+            let _ = writeln!(
+                self.processes.streams.err(),
+                "user\t{}",
+                format_duration(end_user.saturating_sub(start_user))
+            );
+            let _ = writeln!(
+                self.processes.streams.err(),
+                "sys\t{}",
+                format_duration(end_sys.saturating_sub(start_sys))
+            );
         }
+        result
     }
+}
 
-    pub fn execute_background(&mut self, node: &ASTNode) -> Result<Option<i32>, String> {
-        if let ASTNode::Command { name, args } = node {
-            let expanded_name = self.interpreter.expand_variables(name);
-            let expanded_args: Vec<String> = args
-                .iter()
-                .map(|arg| self.interpreter.expand_variables(arg))
-                .collect();
+/// Handles an interrupt reaching a loop: `Ok(true)` if the loop stops,
+/// `Ok(false)` if it goes on with the next iteration, and the interrupt to
+/// pass on to the enclosing context otherwise.
+fn loop_control(interrupt: Interrupt) -> Result<bool, Interrupt> {
+    match interrupt {
+        Interrupt::Break(1) => Ok(true),
+        Interrupt::Continue(1) => Ok(false),
+        Interrupt::Break(n) => Err(Interrupt::Break(n - 1)),
+        Interrupt::Continue(n) => Err(Interrupt::Continue(n - 1)),
+        interrupt => Err(interrupt),
+    }
+}
 
-            let child = Command::new(&expanded_name)
-                .args(&expanded_args)
-                .spawn()
-                .map_err(|e| format!("Failed to spawn background process: {}", e))?;
+/// The user and system time of the shell and the commands it has waited
+/// for, which only Unix keeps.
+#[cfg(unix)]
+fn cpu_times() -> Option<(Duration, Duration)> {
+    let mut user = Duration::ZERO;
+    let mut sys = Duration::ZERO;
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+            return None;
+        }
+        user += timeval_duration(usage.ru_utime);
+        sys += timeval_duration(usage.ru_stime);
+    }
+    Some((user, sys))
+}
 
-            println!("Started background process with PID: {}", child.id());
-            Ok(Some(0))
-        } else {
-            Err("Invalid command for background execution".to_string())
+#[cfg(not(unix))]
+fn cpu_times() -> Option<(Duration, Duration)> {
+    None
+}
+
+#[cfg(unix)]
+fn timeval_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    let minutes = (seconds / 60.0).floor();
+    format!("{}m{:.3}s", minutes, seconds - minutes * 60.0)
+}
+
+/// The status a forked copy of the shell exits with after running a job
+/// with `result`.
+fn subshell_status(result: Result<Option<i32>, Interrupt>) -> i32 {
+    match result {
+        Ok(status) => status.unwrap_or(0),
+        Err(Interrupt::Exit(status)) => status,
+        Err(e) => {
+            eprintln!("bellos: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor_processes::providers::{SystemUsers, UserProvider};
+    use crate::executor_processes::testing::{MemoryFs, MemoryUsers, RecordingSpawner};
+    use crate::utilities::fuzzing::on_thread_stack;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Runs `script` in a new shell with `args` as its positional
+    /// parameters, returning the shell to inspect its variables.
+    fn run(script: &str, args: &[&str]) -> Shell {
+        let mut shell = Shell::new();
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        shell.interpreter.set_positional_parameters(&args);
+        shell.run(script).unwrap();
+        shell
+    }
+
+    fn variable(shell: &Shell, name: &str) -> Option<String> {
+        shell.interpreter.variable(name)
+    }
+
+    #[test]
+    fn eval_returns_the_last_status_and_exit_ends_only_the_script() {
+        let mut shell = Shell::new();
+        assert_eq!(shell.eval("true; false"), Ok(1));
+        assert_eq!(
+            shell.eval("trap 'after=yes' EXIT; exit 4; never=ran"),
+            Ok(4)
+        );
+        assert_eq!(variable(&shell, "after").as_deref(), Some("yes"));
+        assert_eq!(variable(&shell, "never"), None);
+        assert!(shell.eval("if true; then").is_err());
+        assert_eq!(shell.last_status(), 2);
+    }
+
+    #[test]
+    fn exit_in_a_trap_ends_only_the_script() {
+        let mut shell = Shell::new();
+        assert_eq!(shell.eval("trap 'exit 5' ERR; false; never=ran"), Ok(5));
+        assert_eq!(variable(&shell, "never"), None);
+        assert_eq!(shell.eval("trap - ERR; trap 'exit 7' EXIT; exit 3"), Ok(7));
+        assert_eq!(shell.last_status(), 7);
+        // The shell is still there to run more.
+        assert_eq!(shell.eval("trap 'exit 6' DEBUG; true; never=ran"), Ok(6));
+        assert_eq!(variable(&shell, "never"), None);
+    }
+
+    #[test]
+    fn case_runs_the_arm_matching_the_first_argument() {
+        let script = "case $1 in\n\
+                      \x20 start) action=starting ;;\n\
+                      \x20 stop | halt) action=stopping ;;\n\
+                      \x20 re*) action=restarting ;;\n\
+                      \x20 *) action=unknown ;;\n\
+                      esac\n";
+        for (arg, action) in [
+            ("start", "starting"),
+            ("halt", "stopping"),
+            ("reload", "restarting"),
+            ("bogus", "unknown"),
+        ] {
+            let shell = run(script, &[arg]);
+            assert_eq!(
+                variable(&shell, "action").as_deref(),
+                Some(action),
+                "{}",
+                arg
+            );
+        }
+    }
+
+    #[test]
+    fn keywords_in_other_cases_are_ordinary_words() {
+        let shell = run(
+            "seen=; calls=\n\
+             Done() { calls=\"$calls<$*>\"; }\n\
+             Done If Then\n\
+             for In in a b; do seen=\"$seen$In\"; done\n\
+             for Do in x; do Done $Do; done\n",
+            &[],
+        );
+        assert_eq!(variable(&shell, "calls").as_deref(), Some("<If Then><x>"));
+        assert_eq!(variable(&shell, "seen").as_deref(), Some("ab"));
+        assert_eq!(variable(&shell, "In").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn for_without_in_loops_over_the_frames_arguments() {
+        let shell = run(
+            "seen=; count=0; outer=\n\
+             each() { for a; do seen=\"$seen<$a>\"; count=$((count + 1)); done; }\n\
+             each \"first one\" second third\n\
+             for a do outer=\"$outer<$a>\"; done\n\
+             none=0; each_none() { for a\n do none=1; done; }; each_none\n",
+            &["script arg"],
+        );
+        assert_eq!(
+            variable(&shell, "seen").as_deref(),
+            Some("<first one><second><third>")
+        );
+        assert_eq!(variable(&shell, "count").as_deref(), Some("3"));
+        assert_eq!(variable(&shell, "outer").as_deref(), Some("<script arg>"));
+        assert_eq!(variable(&shell, "none").as_deref(), Some("0"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn concurrent_shells_pass_their_own_exports_to_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let workers: Vec<_> = ["hello", "bonjour"]
+            .into_iter()
+            .map(|greeting| {
+                let output = dir.path().join(greeting);
+                std::thread::spawn(move || {
+                    let mut shell = Shell::new();
+                    let script = format!(
+                        "export GREETING={greeting} ONLY_{greeting}=1\n\
+                         hidden=yes\n\
+                         for i in 1 2 3 4 5; do\n\
+                         /bin/sh -c 'echo \"$GREETING ${{ONLY_hello-}}${{ONLY_bonjour-}} ${{hidden-unset}}\" >> \"$0\"' {}\n\
+                         done\n",
+                        output.display()
+                    );
+                    shell.run(&script).unwrap();
+                    output
+                })
+            })
+            .collect();
+        for (worker, greeting) in workers.into_iter().zip(["hello", "bonjour"]) {
+            let output = worker.join().unwrap();
+            let expected = format!("{} 1 unset\n", greeting).repeat(5);
+            assert_eq!(fs::read_to_string(output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn name_references_write_through_and_reject_cycles() {
+        let mut shell = run(
+            "result=; fields=\n\
+             get() { local -n out=$1; out=\"value of $2\"; }\n\
+             get result key\n\
+             fill() { local -n into=$1; split into \"$2\" ,; inner=${#into[@]}; }\n\
+             fill fields \"a,b c,d\"\n",
+            &[],
+        );
+        assert_eq!(variable(&shell, "result").as_deref(), Some("value of key"));
+        assert_eq!(shell.interpreter.array("fields"), ["a", "b c", "d"]);
+        assert_eq!(variable(&shell, "inner").as_deref(), Some("3"));
+        assert_eq!(variable(&shell, "out"), None);
+        assert_eq!(variable(&shell, "into"), None);
+
+        assert_eq!(
+            shell.eval("itself() { local -n me=me; reached=yes; }; itself"),
+            Ok(1)
+        );
+        assert_eq!(
+            shell.eval("chain() { local -n x=y; local -n y=x; reached=yes; }; chain"),
+            Ok(1)
+        );
+        assert_eq!(variable(&shell, "reached"), None);
+    }
+
+    #[test]
+    fn and_or_lists_short_circuit_on_the_last_status() {
+        let shell = run(
+            "true && a=ran\n\
+             false && b=ran\n\
+             false && c=skipped || c=fallback\n\
+             true || d=ran\n\
+             true && false || e=either\n\
+             if false || true && true; then f=then; else f=else; fi\n\
+             if true && false; then g=then; else g=else; fi\n",
+            &[],
+        );
+        let values: Vec<_> = ["a", "b", "c", "d", "e", "f", "g"]
+            .iter()
+            .map(|name| variable(&shell, name).unwrap_or_default())
+            .collect();
+        assert_eq!(
+            values,
+            ["ran", "", "fallback", "", "either", "then", "else"]
+        );
+
+        let mut shell = Shell::new();
+        assert_eq!(
+            shell.eval("cd /missing/directory || exit 4\nnever=ran"),
+            Ok(4)
+        );
+        assert_eq!(variable(&shell, "never"), None);
+        assert_eq!(shell.eval("cd / || exit 4\nafter=ran"), Ok(0));
+        assert_eq!(variable(&shell, "after").as_deref(), Some("ran"));
+    }
+
+    #[test]
+    fn case_subjects_and_patterns_are_full_words() {
+        let script = "ext=txt\n\
+                      case \"$1\"\n\
+                      in\n\
+                      \x20 \"my \"*.$ext) kind=report ;;\n\
+                      \x20 \"a*\") kind=star ;;\n\
+                      \x20 $(echo a)?c | [0-9]*) kind=short ;;\n\
+                      \x20 *) kind=other ;;\n\
+                      esac\n";
+        for (arg, kind) in [
+            ("my report.txt", "report"),
+            ("my report.pdf", "other"),
+            ("a*", "star"),
+            ("abc", "short"),
+            ("42", "short"),
+            ("a long", "other"),
+        ] {
+            let shell = run(script, &[arg]);
+            assert_eq!(variable(&shell, "kind").as_deref(), Some(kind), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn elif_runs_the_first_arm_whose_condition_holds() {
+        let script = "if [ $1 = 1 ]; then r=one\n\
+                      elif [ $1 = 2 ]; then r=two\n\
+                      elif [ $1 = 3 ]\nthen\n  r=three\n\
+                      else r=other; fi\n";
+        for (arg, r) in [("1", "one"), ("2", "two"), ("3", "three"), ("4", "other")] {
+            assert_eq!(variable(&run(script, &[arg]), "r").as_deref(), Some(r));
+        }
+        let shell = run("if false; then r=a; elif false; then r=b; fi", &[]);
+        assert_eq!(variable(&shell, "r"), None);
+    }
+
+    #[test]
+    fn status_is_zero_before_the_first_command() {
+        let shell = run("s=$?\nfor x in; do :; done\nt=$?", &[]);
+        assert_eq!(variable(&shell, "s").as_deref(), Some("0"));
+        assert_eq!(variable(&shell, "t").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn quoted_all_parameters_expand_to_one_field_each() {
+        let mut shell = Shell::new();
+        let fields = |shell: &mut Shell, word: &str| block_on(shell.expand_fields(word)).unwrap();
+        assert_eq!(fields(&mut shell, "\"$@\""), Vec::<String>::new());
+        assert_eq!(fields(&mut shell, "\"${@}\""), Vec::<String>::new());
+        assert_eq!(fields(&mut shell, "\"<$@>\""), ["<>"]);
+        let parameters = ["a b", "", "c"].map(String::from);
+        shell.interpreter.set_positional_parameters(&parameters);
+        assert_eq!(fields(&mut shell, "\"$@\""), ["a b", "", "c"]);
+        assert_eq!(fields(&mut shell, "x\"<$@>\"y"), ["x<a b", "", "c>y"]);
+        assert_eq!(fields(&mut shell, "\"$*\""), ["a b  c"]);
+        assert_eq!(fields(&mut shell, "\"$$@\"").len(), 1);
+        assert_eq!(fields(&mut shell, "'$@'"), ["$@"]);
+    }
+
+    #[test]
+    fn adjacent_quoted_and_unquoted_parts_join_into_one_word() {
+        let mut shell = run("name=arch\nvalue=a\"b c\"$name'd'\n", &[]);
+        assert_eq!(variable(&shell, "value").as_deref(), Some("ab carchd"));
+        let fields = |shell: &mut Shell, word: &str| block_on(shell.expand_fields(word)).unwrap();
+        assert_eq!(fields(&mut shell, "pre\"fix and\"post"), ["prefix andpost"]);
+        assert_eq!(fields(&mut shell, "\"$name\".tar.gz"), ["arch.tar.gz"]);
+        assert_eq!(fields(&mut shell, "$name\"\"-'$name'"), ["arch-$name"]);
+        assert_eq!(fields(&mut shell, "\"\"''"), [""]);
+    }
+
+    #[test]
+    fn test_and_brackets_check_variables_and_strings_alike() {
+        let script = "empty=\nset=value\n\
+                      $1 -v set $2; a=$?\n\
+                      $1 -v empty $2; b=$?\n\
+                      $1 -v missing $2; c=$?\n\
+                      $1 -n \"$empty\" $2; d=$?\n\
+                      $1 -z \"$empty\" $2; e=$?\n\
+                      $1 -n \"$set\" $2; f=$?\n\
+                      $1 -z \"$set\" $2; g=$?\n\
+                      $1 1 -eq $2; h=$?\n\
+                      statuses=\"$a $b $c $d $e $f $g $h\"\n";
+        for args in [["test", ""], ["[", "]"]] {
+            let shell = run(script, &args);
+            assert_eq!(
+                variable(&shell, "statuses").as_deref(),
+                Some("0 0 1 1 0 0 1 2"),
+                "{}",
+                args[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_and_brackets_choose_the_form_by_argument_count() {
+        let cases = [
+            ("", 1),
+            ("word", 0),
+            ("''", 1),
+            ("-n word", 0),
+            ("-z word", 1),
+            ("! ''", 0),
+            ("! word", 1),
+            ("-q word", 2),
+            ("a = a", 0),
+            ("a != a", 1),
+            ("! -z word", 0),
+            ("a b c", 2),
+            ("! a = b", 0),
+            ("! a = a", 1),
+            ("a b c d", 2),
+            ("a b c d e", 2),
+        ];
+        let script: String = std::iter::once("statuses=\n".to_string())
+            .chain(cases.iter().map(|(expression, _)| {
+                format!("$1 {} $2; statuses=\"$statuses $?\"\n", expression)
+            }))
+            .collect();
+        let expected: String = cases
+            .iter()
+            .map(|(_, status)| format!(" {}", status))
+            .collect();
+        for args in [["test", ""], ["[", "]"]] {
+            let shell = run(&script, &args);
+            assert_eq!(
+                variable(&shell, "statuses"),
+                Some(expected.clone()),
+                "{}",
+                args[0]
+            );
+        }
+    }
+
+    #[test]
+    fn functions_defined_with_the_keyword_are_callable() {
+        let shell = run(
+            "function plain { first=$1; }\n\
+             function parens() {\n\
+             \x20 second=\"$1 $2\"\n\
+             \x20 return 3\n\
+             }\n\
+             plain one\n\
+             parens two three; status=$?\n",
+            &[],
+        );
+        assert_eq!(variable(&shell, "first").as_deref(), Some("one"));
+        assert_eq!(variable(&shell, "second").as_deref(), Some("two three"));
+        assert_eq!(variable(&shell, "status").as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn function_bodies_hold_loops_conditions_and_comments() {
+        let shell = run(
+            "evens=\n\
+             count() {\n\
+             \x20 # count up to $1, noting the even numbers\n\
+             \x20 local i=0\n\
+             \x20 while [ $i -lt $1 ]; do\n\
+             \x20   i=$((i + 1))\n\
+             \x20   if [ $((i % 2)) -eq 0 ]; then # even\n\
+             \x20     evens=\"$evens $i\"\n\
+             \x20   fi\n\
+             \x20 done\n\
+             \x20 { total=$i; }\n\
+             }\n\
+             count 5\n",
+            &[],
+        );
+        assert_eq!(variable(&shell, "evens").as_deref(), Some(" 2 4"));
+        assert_eq!(variable(&shell, "total").as_deref(), Some("5"));
+        assert_eq!(variable(&shell, "i"), None);
+    }
+
+    #[test]
+    fn arithmetic_expansions_nest_parentheses_against_their_delimiters() {
+        let shell = run(
+            "a=$(((1+2)*3)) b=$((((((1))))+1)) c=$(( ((2+3)*(4-1))/(1+(1)) ))\n\
+             d=$((-(2+1))) e=$(( (7) ))x f=\"$(((1)))\"\n",
+            &[],
+        );
+        let values: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+            .iter()
+            .map(|name| variable(&shell, name).unwrap_or_default())
+            .collect();
+        assert_eq!(values, ["9", "2", "7", "-3", "7x", "1"]);
+    }
+
+    #[test]
+    fn nesting_limits_hold_on_a_thread_stack() {
+        let ifs = |depth: usize| "if true; then ".repeat(depth) + "x=1; " + &"fi; ".repeat(depth);
+        let substitutions =
+            |depth: usize| "x=".to_string() + &"$(echo ".repeat(depth) + "s" + &")".repeat(depth);
+        let calls = "f() { n=$((n+1)); if ((n < 2000)); then f; fi; }; n=0; f";
+        let results = on_thread_stack(|| {
+            let eval = |script: &str| {
+                let mut shell = Shell::new();
+                (shell.eval(script), variable(&shell, "x"))
+            };
+            [
+                eval(&ifs(MAX_NESTING - 1)),
+                eval(&ifs(MAX_NESTING)),
+                eval(&substitutions(MAX_NESTING)),
+                eval(&substitutions(MAX_NESTING + 1)),
+                eval(calls),
+            ]
+        })
+        .unwrap();
+        let [within, past, substituted, past_substitutions, recursed] = results;
+        assert_eq!(within, (Ok(0), Some("1".to_string())));
+        assert!(past.0.is_err() && past.1.is_none());
+        assert_eq!(substituted, (Ok(0), Some("s".to_string())));
+        assert_eq!(past_substitutions, (Ok(0), Some(String::new())));
+        assert_eq!(recursed.0, Ok(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn substitutions_drop_only_trailing_newlines() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let shell = run(
+            &format!(
+                "cd {}\n\
+                 file=$(pwd)/file\n\
+                 lines=$(printf 'a\\n\\n\\nb\\n\\n')\n\
+                 blank=$(printf '\\n\\n\\n')\n\
+                 builtin=$(echo; echo x; echo; echo)\n\
+                 printf 'kept\\n\\n' > written\n",
+                dir.display()
+            ),
+            &[],
+        );
+        let file = dir.join("file").display().to_string();
+        assert_eq!(variable(&shell, "file"), Some(file));
+        assert_eq!(variable(&shell, "lines").as_deref(), Some("a\n\n\nb"));
+        assert_eq!(variable(&shell, "blank").as_deref(), Some(""));
+        assert_eq!(variable(&shell, "builtin").as_deref(), Some("\nx"));
+        assert_eq!(fs::read_to_string(dir.join("written")).unwrap(), "kept\n\n");
+    }
+
+    #[test]
+    fn numeric_comparisons_parse_integers_and_reject_the_rest() {
+        let script = "$1 5 -eq 05 $2; a=$?\n\
+                      $1 ' 7 ' -eq 7 $2; b=$?\n\
+                      $1 -3 -lt 2 $2; c=$?\n\
+                      $1 3 -ne 03 $2; d=$?\n\
+                      $1 abc -lt 5 $2; e=$?\n\
+                      $1 10 -eq 10.0 $2; f=$?\n\
+                      $1 5 = 05 $2; g=$?\n\
+                      statuses=\"$a $b $c $d $e $f $g\"\n";
+        for args in [["test", ""], ["[", "]"], ["[[", "]]"]] {
+            let shell = run(script, &args);
+            assert_eq!(
+                variable(&shell, "statuses").as_deref(),
+                Some("0 0 0 1 2 2 1"),
+                "{}",
+                args[0]
+            );
+        }
+    }
+
+    #[test]
+    fn regex_matches_store_their_groups_in_bash_rematch() {
+        let shell = run(
+            "line='42:hello world'\n\
+             if [[ $line =~ ^([0-9]+):(.*)$ ]]; then\n\
+             \x20 whole=${BASH_REMATCH[0]} number=${BASH_REMATCH[1]} text=${BASH_REMATCH[2]}\n\
+             fi\n\
+             [[ abc =~ ^[0-9]+$ ]]; missed=$? left=${#BASH_REMATCH[@]}\n\
+             [[ ab =~ a(x)?(b) ]]; optional=\"[${BASH_REMATCH[1]}] ${BASH_REMATCH[2]}\"\n",
+            &[],
+        );
+        assert_eq!(variable(&shell, "whole").as_deref(), Some("42:hello world"));
+        assert_eq!(variable(&shell, "number").as_deref(), Some("42"));
+        assert_eq!(variable(&shell, "text").as_deref(), Some("hello world"));
+        assert_eq!(variable(&shell, "missed").as_deref(), Some("1"));
+        assert_eq!(variable(&shell, "left").as_deref(), Some("0"));
+        assert_eq!(variable(&shell, "optional").as_deref(), Some("[] b"));
+
+        let mut shell = Shell::new();
+        assert_eq!(shell.eval("[[ abc =~ ( ]]"), Ok(1));
+    }
+
+    #[test]
+    fn random_is_reproducible_after_seeding_and_in_range() {
+        let shell = run(
+            "RANDOM=42; a=\"$RANDOM $RANDOM $RANDOM\"\n\
+             RANDOM=42; b=\"$RANDOM $RANDOM $RANDOM\"\n\
+             RANDOM=7; c=\"$RANDOM $RANDOM $RANDOM\"\n\
+             many=\"$RANDOM\"; i=0\n\
+             while [ $i -lt 200 ]; do many=\"$many $RANDOM\"; i=$((i + 1)); done\n\
+             unset RANDOM; [ -v RANDOM ]; after=$?; RANDOM=5; plain=\"$RANDOM $RANDOM\"\n",
+            &[],
+        );
+        let a = variable(&shell, "a").unwrap();
+        assert_eq!(variable(&shell, "b"), Some(a.clone()));
+        assert_ne!(variable(&shell, "c"), Some(a));
+        let many = variable(&shell, "many").unwrap();
+        let values: Vec<u32> = many.split(' ').map(|n| n.parse().unwrap()).collect();
+        assert!(values.iter().all(|&value| value <= 32767), "{}", many);
+        assert!(values.windows(2).any(|pair| pair[0] != pair[1]));
+        assert_eq!(variable(&shell, "after").as_deref(), Some("1"));
+        assert_eq!(variable(&shell, "plain").as_deref(), Some("5 5"));
+    }
+
+    #[test]
+    fn err_trap_runs_for_failures_that_are_not_tested() {
+        let shell = run(
+            "failed=\n\
+             trap 'failed=\"$failed $?@$LINENO\"' ERR\n\
+             true\n\
+             false\n\
+             if false; then :; fi\n\
+             while false; do :; done\n\
+             false || true\n\
+             false && true\n\
+             true && false\n\
+             f() { false; }\n\
+             if f; then :; fi\n\
+             f\n\
+             x=$((1 / 0))\n",
+            &[],
+        );
+        assert_eq!(
+            variable(&shell, "failed").as_deref(),
+            Some(" 1@4 1@9 1@10 1@12 1@13")
+        );
+    }
+
+    #[test]
+    fn quoted_and_escaped_regex_parts_match_literally() {
+        let mut shell = Shell::new();
+        for (script, status) in [
+            ("[[ a.b =~ ^a\\.b$ ]]", 0),
+            ("[[ axb =~ ^a\\.b$ ]]", 1),
+            ("[[ axb =~ \"a.b\" ]]", 1),
+            ("[[ a.b =~ ^'a.b'$ ]]", 0),
+            ("r='^a.b$'; [[ axb =~ $r ]]", 0),
+            ("r='a b'; [[ 'xa by' =~ $r ]]", 0),
+            ("expr 2 \\* 3 \\> 5", 0),
+        ] {
+            assert_eq!(shell.eval(script), Ok(status), "{}", script);
+        }
+    }
+
+    #[test]
+    fn compound_commands_whose_body_never_ran_succeed() {
+        for script in [
+            "false; if false; then :; fi",
+            "false; if false; then :; elif false; then :; fi",
+            "while false; do :; done",
+            "false; case a in b) false ;; esac",
+        ] {
+            let shell = run(script, &[]);
+            assert_eq!(shell.last_status(), 0, "{}", script);
+        }
+        assert_eq!(run("if true; then false; fi", &[]).last_status(), 1);
+        assert_eq!(run("for x in a; do false; done", &[]).last_status(), 1);
+        let shell = run("if false; then :; fi && r=ran", &[]);
+        assert_eq!(variable(&shell, "r").as_deref(), Some("ran"));
+    }
+
+    #[test]
+    fn syntax_errors_run_nothing_and_set_status_two() {
+        let mut shell = Shell::new();
+        assert!(shell.run("r=ran\nif true; then").is_err());
+        assert_eq!(variable(&shell, "r"), None);
+        assert_eq!(shell.last_status(), SYNTAX_ERROR_STATUS);
+    }
+
+    #[test]
+    fn background_jobs_fork_beside_threads_holding_the_output_locks() {
+        // The test harness runs this test on a thread of its own, and
+        // another one keeps taking the locks the forked copies need.
+        let done = Arc::new(AtomicBool::new(false));
+        let contender = std::thread::spawn({
+            let done = Arc::clone(&done);
+            move || {
+                while !done.load(Ordering::SeqCst) {
+                    let _out = io::stdout().lock();
+                    let _err = io::stderr().lock();
+                }
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let mut shell = Shell::new();
+        shell
+            .interpreter
+            .set_variable("dir", dir.path().display().to_string());
+        let status = shell.eval(
+            "f() { echo \"f $1\" > \"$dir/f$1\"; }\n\
+             for i in 1 2 3 4 5; do f $i & done\n\
+             { echo group > \"$dir/group\"; } &\n\
+             timeout 5 f 6\n\
+             wait\n",
+        );
+        done.store(true, Ordering::SeqCst);
+        contender.join().unwrap();
+        assert_eq!(status, Ok(0));
+        for i in 1..=6 {
+            let written = std::fs::read_to_string(dir.path().join(format!("f{}", i)));
+            assert_eq!(written.unwrap(), format!("f {}\n", i));
+        }
+        let group = std::fs::read_to_string(dir.path().join("group")).unwrap();
+        assert_eq!(group, "group\n");
+    }
+
+    #[test]
+    fn functions_are_defined_when_their_definition_runs() {
+        let script = "if [ \"$1\" = linux ]; then\n\
+                      \x20 helper() { picked=linux; }\n\
+                      else\n\
+                      \x20 helper() { picked=other; }\n\
+                      fi\n\
+                      helper\n\
+                      outer() { inner() { nested=yes; }; }\n\
+                      inner; before=$?\n\
+                      outer; inner; after=$?\n\
+                      greet() { greeting=first; }\n\
+                      greet; first=$greeting\n\
+                      greet() { greeting=second; }\n\
+                      greet; second=$greeting\n";
+        for (platform, picked) in [("linux", "linux"), ("bsd", "other")] {
+            let shell = run(script, &[platform]);
+            assert_eq!(variable(&shell, "picked").as_deref(), Some(picked));
+            assert_eq!(variable(&shell, "before").as_deref(), Some("127"));
+            assert_eq!(variable(&shell, "after").as_deref(), Some("0"));
+            assert_eq!(variable(&shell, "nested").as_deref(), Some("yes"));
+            assert_eq!(variable(&shell, "first").as_deref(), Some("first"));
+            assert_eq!(variable(&shell, "second").as_deref(), Some("second"));
+        }
+
+        // Redefining a function drops its old body.
+        let mut shell = run("greet() { greeting=first; }", &[]);
+        let old = std::sync::Arc::clone(&shell.interpreter.functions["greet"]);
+        shell.run("greet() { greeting=second; }").unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&old), 1);
+    }
+
+    #[test]
+    fn copies_share_function_bodies_and_keep_their_own_definitions() {
+        let mut shell = run("f() { r=first; }", &[]);
+        let snapshot = shell.interpreter.clone();
+        assert!(std::sync::Arc::ptr_eq(
+            &shell.interpreter.functions["f"],
+            &snapshot.functions["f"]
+        ));
+
+        shell.run("f() { r=second; }; f").unwrap();
+        assert_eq!(variable(&shell, "r").as_deref(), Some("second"));
+        let mut copy = Shell::new();
+        copy.interpreter = snapshot;
+        copy.run("f").unwrap();
+        assert_eq!(variable(&copy, "r").as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn arithmetic_commands_compare_and_combine() {
+        let shell = run(
+            "i=0; n=0\nwhile (( i < 5 && n != 3 )); do (( i += 1 )); (( n = n + 1 )); done\nif (( i >= 3 )) && (( ! (i > 3) )); then r=yes; fi",
+            &[],
+        );
+        assert_eq!(variable(&shell, "i").as_deref(), Some("3"));
+        assert_eq!(variable(&shell, "r").as_deref(), Some("yes"));
+    }
+
+    #[test]
+    fn split_stores_an_indexed_array() {
+        let shell = run(
+            "split dirs /usr/bin:/bin::/opt/x:y ':'\n\
+             count=${#dirs[@]} first=$dirs second=${dirs[1]} last=${dirs[-1]} fourth=${dirs[1+2]}\n\
+             listed=\"${dirs[*]}\" length=${#dirs[0]}\n\
+             n=0; for d in \"${dirs[@]}\"; do n=$((n + 1)); done\n\
+             split trailing a:b: ':'; trailing_count=${#trailing[@]}\n\
+             split empty '' ':'; empty_count=${#empty[@]}\n\
+             split chars h\u{e9}y ''; chars_count=${#chars[@]} middle=${chars[1]}",
+            &[],
+        );
+        let cases = [
+            ("count", "5"),
+            ("first", "/usr/bin"),
+            ("second", "/bin"),
+            ("last", "y"),
+            ("fourth", "/opt/x"),
+            ("listed", "/usr/bin /bin  /opt/x y"),
+            ("length", "8"),
+            ("n", "5"),
+            ("trailing_count", "3"),
+            ("empty_count", "0"),
+            ("chars_count", "3"),
+            ("middle", "\u{e9}"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                variable(&shell, name).as_deref(),
+                Some(expected),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn quoted_arrays_expand_to_one_field_per_element() {
+        let mut shell = run("split words 'a b:c::d' ':'\nsplit none '' ':'", &[]);
+        assert_eq!(
+            block_on(shell.expand_fields("\"<${words[@]}>\"")).unwrap(),
+            ["<a b", "c", "", "d>"]
+        );
+        assert_eq!(
+            block_on(shell.expand_fields("\"${none[@]}\"")).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            block_on(shell.expand_fields("\"${words[*]}\"")).unwrap(),
+            ["a b c  d"]
+        );
+        assert_eq!(
+            block_on(shell.expand_fields("\"$#${none[@]}\"")).unwrap(),
+            ["0"]
+        );
+    }
+
+    #[test]
+    fn splitting_again_or_unsetting_replaces_the_array() {
+        let shell = run(
+            "split list a:b:c ':'\nsplit list z ':'\nafter_split=${#list[@]}/${list[1]}/\n\
+             unset list\nafter_unset=${#list[@]}",
+            &[],
+        );
+        assert_eq!(variable(&shell, "after_split").as_deref(), Some("1//"));
+        assert_eq!(variable(&shell, "after_unset").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn shells_on_different_threads_keep_their_own_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let started_in = std::env::current_dir().unwrap();
+        let workers: Vec<_> = ["one", "two"]
+            .into_iter()
+            .map(|name| {
+                let dir = root.path().join(name);
+                fs::create_dir(&dir).unwrap();
+                std::thread::spawn(move || {
+                    let script = format!(
+                        "cd {}\nwrite made {}\nsh -c 'pwd > where'\nfound=\n\
+                         for f in *; do found=\"$found$f \"; done\n\
+                         if [ -f made ]; then seen=yes; fi\ncd ..\nback=$PWD\n",
+                        dir.display(),
+                        name
+                    );
+                    let shell = run(&script, &[]);
+                    (dir, variable(&shell, "found"), variable(&shell, "seen"))
+                })
+            })
+            .collect();
+        for (worker, name) in workers.into_iter().zip(["one", "two"]) {
+            let (dir, found, seen) = worker.join().unwrap();
+            assert_eq!(fs::read_to_string(dir.join("made")).unwrap(), name);
+            let shown = fs::read_to_string(dir.join("where")).unwrap();
+            assert_eq!(
+                Path::new(shown.trim_end()).canonicalize().unwrap(),
+                dir.canonicalize().unwrap()
+            );
+            assert_eq!(found.as_deref(), Some("made where "));
+            assert_eq!(seen.as_deref(), Some("yes"));
+        }
+        assert_eq!(std::env::current_dir().unwrap(), started_in);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exec_redirections_hold_for_the_commands_after_them() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("input"), "one\ntwo\nthree\nfour\n").unwrap();
+        let mut shell = Shell::new();
+        shell.interpreter.directory = dir.path().to_path_buf();
+        let status = shell.eval(
+            "exec > build.log 2>&1\n\
+             echo builtin\n\
+             sh -c 'echo external; echo external-error >&2'\n\
+             cat missing\n\
+             exec 3< input\n\
+             read -u 3 first\n\
+             read second <&3\n\
+             sh -c 'read third; echo \"$third\"' <&3\n\
+             cat <&3\n\
+             echo \"$first $second\"\n",
+        );
+        assert_eq!(status, Ok(0));
+        let log = fs::read_to_string(dir.path().join("build.log")).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines[..3], ["builtin", "external", "external-error"]);
+        assert!(lines[3].starts_with("cat: missing: "), "{}", log);
+        assert_eq!(lines[4..], ["three", "four", "one two"]);
+        // The process's own descriptors were never touched.
+        #[cfg(target_os = "linux")]
+        assert_ne!(
+            fs::read_link("/proc/self/fd/1").unwrap(),
+            dir.path().join("build.log")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn closed_output_fails_cleanly() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut shell = Shell::new();
+        shell.interpreter.directory = dir.path().to_path_buf();
+        let status = shell.eval(
+            "exec 2> errors\n\
+             exec >&-\n\
+             echo lost; echoed=$?\n\
+             sh -c 'echo lost too'; external=$?\n\
+             echo still >&4",
+        );
+        assert_eq!(status, Ok(1));
+        assert_eq!(variable(&shell, "echoed").as_deref(), Some("1"));
+        assert_eq!(variable(&shell, "external").as_deref(), Some("0"));
+        let errors = fs::read_to_string(dir.path().join("errors")).unwrap();
+        assert!(
+            errors.contains("echo: write error: Bad file descriptor"),
+            "{}",
+            errors
+        );
+        assert!(errors.contains("4: Bad file descriptor"), "{}", errors);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shells_on_different_threads_redirect_their_own_output() {
+        let root = tempfile::tempdir().unwrap();
+        let workers: Vec<_> = ["one", "two"]
+            .into_iter()
+            .map(|name| {
+                let log = root.path().join(name);
+                std::thread::spawn(move || {
+                    let mut shell = Shell::new();
+                    let script = format!(
+                        "exec > {}\n\
+                         for i in 1 2 3 4 5; do echo {name}; sh -c 'echo {name}'; done\n",
+                        log.display()
+                    );
+                    assert_eq!(shell.eval(&script), Ok(0));
+                    fs::read_to_string(&log).unwrap()
+                })
+            })
+            .collect();
+        for (worker, name) in workers.into_iter().zip(["one", "two"]) {
+            assert_eq!(worker.join().unwrap(), format!("{}\n", name).repeat(10));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn cd_moves_the_shell_and_leaves_symlinks_by_name() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("real/inner")).unwrap();
+        std::os::unix::fs::symlink(root.join("real/inner"), root.join("link")).unwrap();
+        let shell = run(
+            &format!(
+                "cd {}\ncd link\ninside=$PWD\ncd ..\noutside=$PWD\ncd missing\n",
+                root.display()
+            ),
+            &[],
+        );
+        let pwd = |name| variable(&shell, name).map(PathBuf::from);
+        assert_eq!(pwd("inside"), Some(root.join("link")));
+        assert_eq!(pwd("outside"), Some(root.clone()));
+        assert_eq!(shell.interpreter.directory, root);
+        assert_ne!(shell.last_status(), 0);
+    }
+
+    #[test]
+    fn shopt_toggles_nullglob_and_dotglob() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["a.txt", ".hidden.txt", "b.txt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let mut shell = run(&format!("cd {}\n", root.path().display()), &[]);
+        let fields = |shell: &mut Shell, word: &str| block_on(shell.expand_fields(word)).unwrap();
+        assert_eq!(fields(&mut shell, "*.txt"), ["a.txt", "b.txt"]);
+        assert_eq!(fields(&mut shell, "*.log"), ["*.log"]);
+
+        assert_eq!(shell.eval("shopt -s nullglob dotglob"), Ok(0));
+        assert_eq!(
+            fields(&mut shell, "*.txt"),
+            [".hidden.txt", "a.txt", "b.txt"]
+        );
+        assert!(fields(&mut shell, "*.log").is_empty());
+        assert_eq!(fields(&mut shell, "'*.log'"), ["*.log"]);
+
+        assert_eq!(shell.eval("shopt -u nullglob"), Ok(0));
+        assert_eq!(fields(&mut shell, "*.log"), ["*.log"]);
+        assert_eq!(shell.eval("shopt -q dotglob"), Ok(0));
+        assert_eq!(shell.eval("shopt -q nullglob"), Ok(1));
+        assert_ne!(shell.eval("shopt -s nosuchoption"), Ok(0));
+    }
+
+    #[test]
+    fn shopt_flags_come_in_any_order_and_nocaseglob_ignores_case() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["Notes.TXT", "a.txt", ".hidden.txt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let mut shell = run(&format!("cd {}\n", root.path().display()), &[]);
+        let fields = |shell: &mut Shell, word: &str| block_on(shell.expand_fields(word)).unwrap();
+        assert_eq!(fields(&mut shell, "*.txt"), ["a.txt"]);
+        assert_eq!(fields(&mut shell, "[n]*"), ["[n]*"]);
+
+        assert_eq!(shell.eval("shopt -s -q nocaseglob"), Ok(0));
+        assert_eq!(fields(&mut shell, "*.txt"), ["Notes.TXT", "a.txt"]);
+        assert_eq!(fields(&mut shell, "[n]*"), ["Notes.TXT"]);
+        assert_eq!(shell.eval("shopt -q -s dotglob"), Ok(0));
+        assert_eq!(
+            fields(&mut shell, "*.txt"),
+            [".hidden.txt", "Notes.TXT", "a.txt"]
+        );
+        assert_eq!(shell.eval("shopt -qs nullglob"), Ok(0));
+        assert!(fields(&mut shell, "*.log").is_empty());
+        assert_eq!(shell.eval("shopt -q nocaseglob dotglob nullglob"), Ok(0));
+
+        assert_eq!(shell.eval("shopt -uq nocaseglob dotglob"), Ok(0));
+        assert_eq!(fields(&mut shell, "*.txt"), ["a.txt"]);
+        assert_eq!(shell.eval("shopt -q nocaseglob"), Ok(1));
+        assert_eq!(shell.eval("shopt -qu nullglob; shopt -q nullglob"), Ok(1));
+        assert_eq!(shell.eval("shopt -q extglob"), Ok(1));
+        assert_eq!(shell.eval("shopt -s extglob; shopt -q extglob"), Ok(0));
+        assert_ne!(shell.eval("shopt -su dotglob"), Ok(0));
+        assert_ne!(shell.eval("shopt -x dotglob"), Ok(0));
+        assert_eq!(shell.eval("shopt -q dotglob"), Ok(1));
+    }
+
+    #[test]
+    fn globstar_matches_at_any_depth_only_when_set() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src/a/b", "src/c"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
         }
+        for file in [
+            "src/top.rs",
+            "src/a/one.rs",
+            "src/a/b/two.rs",
+            "src/c/three.rs",
+        ] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.path().join("src"), root.path().join("src/a/loop"))
+            .unwrap();
+        let mut shell = run(&format!("cd {}\n", root.path().display()), &[]);
+        let fields = |shell: &mut Shell, word: &str| block_on(shell.expand_fields(word)).unwrap();
+        assert_eq!(
+            fields(&mut shell, "src/**/*.rs"),
+            ["src/a/one.rs", "src/c/three.rs"]
+        );
+
+        assert_eq!(shell.eval("shopt -s globstar"), Ok(0));
+        let matches = fields(&mut shell, "src/**/*.rs");
+        assert_eq!(
+            matches,
+            [
+                "src/a/b/two.rs",
+                "src/a/one.rs",
+                "src/c/three.rs",
+                "src/top.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn automatic_variables_follow_the_clock_lines_and_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let mut shell = run(
+            &format!(
+                "SECONDS=100\nstart=$SECONDS\nfirst=$LINENO\n\n\
+                 if true; then\n  nested=$LINENO\nfi\ncd {}\n",
+                root.display()
+            ),
+            &[],
+        );
+        assert_eq!(variable(&shell, "start").as_deref(), Some("100"));
+        assert_eq!(variable(&shell, "first").as_deref(), Some("3"));
+        assert_eq!(variable(&shell, "nested").as_deref(), Some("6"));
+        let pwd = |shell: &Shell, name| variable(shell, name).map(PathBuf::from);
+        assert_eq!(pwd(&shell, "PWD"), Some(root.clone()));
+
+        std::thread::sleep(Duration::from_millis(1100));
+        shell.run("later=$SECONDS\ncd ..\n").unwrap();
+        let later: u64 = variable(&shell, "later").unwrap().parse().unwrap();
+        assert!((101..110).contains(&later), "{}", later);
+        assert_eq!(pwd(&shell, "OLDPWD"), Some(root.clone()));
+        assert_eq!(pwd(&shell, "PWD").as_deref(), root.parent());
+    }
+
+    #[test]
+    fn tildes_expand_to_home_directories_and_directory_variables() {
+        let users = MemoryUsers::new();
+        users.insert("alice", "/home/alice");
+        let mut shell = Shell::new();
+        shell.processes.users = Box::new(users);
+        shell
+            .eval(
+                "HOME=/home/me PWD=/work OLDPWD=/before joined=\n\
+                 for word in ~ ~/notes ~alice ~alice/logs ~bob/logs ~+ ~-/x '~alice' ~\"alice\"; do\n\
+                 joined=\"$joined $word\"\n\
+                 done\n",
+            )
+            .unwrap();
+        assert_eq!(
+            variable(&shell, "joined").as_deref(),
+            Some(
+                " /home/me /home/me/notes /home/alice /home/alice/logs ~bob/logs \
+                 /work /before/x ~alice ~alice"
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_users_are_looked_up_in_the_password_database() {
+        let output = std::process::Command::new("id")
+            .arg("-un")
+            .output()
+            .unwrap();
+        let user = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(SystemUsers.home_directory(user.trim()), current_user_home());
+        assert!(current_user_home().is_some());
+        assert_eq!(SystemUsers.home_directory("no-such-user-xyz"), None);
+    }
+
+    /// A shell with files only in `fs` that records the commands it would
+    /// run with `spawner`.
+    fn sandboxed(fs: &MemoryFs, spawner: &RecordingSpawner) -> Shell {
+        let mut shell = Shell::new();
+        shell.processes.fs = Box::new(fs.clone());
+        shell.processes.spawner = Some(Box::new(spawner.clone()));
+        shell
+    }
+
+    #[test]
+    fn include_and_file_builtins_use_the_shells_filesystem() {
+        let fs = MemoryFs::new();
+        fs.insert("lib/helpers.bellos", "from_lib=yes\n");
+        fs.insert("script.sh", "echo hi\n");
+        let mut shell = sandboxed(&fs, &RecordingSpawner::new());
+        shell
+            .run(
+                "include ./lib/helpers.bellos\n\
+                 chmod u+x,g-r script.sh\n\
+                 ln -s script.sh link\nlinked=$?\n\
+                 mkfifo pipe\npiped=$?\n",
+            )
+            .unwrap();
+        assert_eq!(variable(&shell, "from_lib").as_deref(), Some("yes"));
+        let status = fs.stat(Path::new("script.sh"), false).unwrap();
+        assert_eq!(status.mode, 0o704);
+        // Links and FIFOs are not supported in memory, and never reach disk.
+        assert_eq!(variable(&shell, "linked").as_deref(), Some("1"));
+        assert_eq!(variable(&shell, "piped").as_deref(), Some("1"));
+        assert!(!Path::new("link").exists() && !Path::new("pipe").exists());
+    }
+
+    #[test]
+    fn include_searches_bellos_path_in_order_and_runs_files_once() {
+        let fs = MemoryFs::new();
+        fs.insert("/first/util.bellos", "from=first; loads=$((loads + 1))\n");
+        fs.insert("/second/util.bellos", "from=second\n");
+        fs.insert("/second/only.bellos", "only=second; include util.bellos\n");
+        let mut shell = sandboxed(&fs, &RecordingSpawner::new());
+        shell
+            .run(
+                "BELLOS_PATH=/first:/second; loads=0\n\
+                 include util.bellos\n\
+                 include util.bellos\n\
+                 include only.bellos\n\
+                 include /first/util.bellos\n",
+            )
+            .unwrap();
+        assert_eq!(variable(&shell, "from").as_deref(), Some("first"));
+        assert_eq!(variable(&shell, "only").as_deref(), Some("second"));
+        assert_eq!(variable(&shell, "loads").as_deref(), Some("1"));
+        assert_eq!(
+            shell.included,
+            [
+                PathBuf::from("/first/util.bellos"),
+                PathBuf::from("/second/only.bellos")
+            ]
+        );
+        assert_eq!(shell.eval("include -l"), Ok(0));
+
+        match block_on(shell.builtin_include(&["missing.bellos".to_string()])) {
+            Err(Interrupt::Error(message)) => assert!(
+                message.starts_with("include: missing.bellos: not found (searched /first, /second"),
+                "{}",
+                message
+            ),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(shell.eval("include missing.bellos"), Ok(1));
+    }
+
+    #[test]
+    fn exec_and_timeout_hand_commands_to_the_spawner() {
+        let spawner = RecordingSpawner::new();
+        spawner.respond("finish", 3, "");
+        let mut shell = sandboxed(&MemoryFs::new(), &spawner);
+        let start = Instant::now();
+        shell.run("timeout 0.1 sleep 5\nlimited=$?\n").unwrap();
+        assert_eq!(variable(&shell, "limited").as_deref(), Some("0"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let nodes = Parser::from_lexer(Lexer::new("exec finish now"))
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            block_on(shell.interpret_node(&nodes[0])),
+            Err(Interrupt::Exit(3))
+        ));
+        let ran: Vec<_> = spawner
+            .invocations()
+            .into_iter()
+            .map(|invocation| (invocation.program, invocation.args))
+            .collect();
+        assert_eq!(
+            ran,
+            [
+                ("sleep".to_string(), vec!["5".to_string()]),
+                ("finish".to_string(), vec!["now".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn background_jobs_and_timed_functions_run_in_place_with_a_spawner() {
+        let spawner = RecordingSpawner::new();
+        let mut shell = sandboxed(&MemoryFs::new(), &spawner);
+        shell
+            .run(
+                "f() { worker \"$@\"; x=changed; exit 4; }\n\
+                 x=kept\n\
+                 { worker brace; x=changed; } &\n\
+                 f function &\n\
+                 timeout 5 f limited; limited=$?\n\
+                 wait\n",
+            )
+            .unwrap();
+        // Had the jobs run in forked copies, their commands would have
+        // been recorded there and not here.
+        let ran: Vec<_> = spawner
+            .invocations()
+            .into_iter()
+            .map(|invocation| invocation.args.join(" "))
+            .collect();
+        assert_eq!(ran, ["brace", "function", "limited"]);
+        assert_eq!(variable(&shell, "x").as_deref(), Some("kept"));
+        assert_eq!(variable(&shell, "limited").as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn durations_are_formatted_as_minutes_and_seconds() {
+        assert_eq!(format_duration(Duration::ZERO), "0m0.000s");
+        assert_eq!(format_duration(Duration::from_millis(1_250)), "0m1.250s");
+        assert_eq!(format_duration(Duration::from_millis(125_500)), "2m5.500s");
     }
 }
@@ -0,0 +1,128 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Input for the tests that feed the lexer, parser and arithmetic evaluator
+//! adversarial text: what used to overflow the stack, and random text built
+//! from shell fragments, the same for the same seed.
+
+use std::thread;
+
+/// The pieces random input is built from.
+const FRAGMENTS: &[&str] = &[
+    "echo", " ", " ", "\t", "\n", ";", ";;", "|", "&", ">", ">>", "<", "<<EOF\n", "EOF\n", "2>&1",
+    "&>", "<>", "(", ")", "((", "))", "{", "}", "[[", "]]", "[", "]", "if", "then", "elif", "else",
+    "fi", "while", "do", "done", "for", "in", "case", "esac", "function", "time", "f()", "x=",
+    "\"", "'", "\\", "$", "$(", "$((", "${", "$x", "$1", "#", "*", "?", "~", "a", "1", "-", "+",
+    "=", "é", "\\'", "&&", "||",
+];
+
+/// The stack size of a thread spawned with the defaults, like an
+/// embedder's worker or tokio's.
+const THREAD_STACK: usize = 2 << 20;
+
+/// How many random inputs a test tries, unless `BELLOS_FUZZ_ITERATIONS`
+/// says otherwise.
+const ITERATIONS: usize = 10_000;
+
+/// The seed of the random inputs, unless `BELLOS_FUZZ_SEED` gives another.
+const SEED: u64 = 0x5eed;
+
+/// Input that overflowed the stack before nesting was limited, or made the
+/// lexer panic.
+pub fn corpus() -> Vec<String> {
+    vec![
+        "cat <<E \"\nE\n\n\n\"\necho after\n".to_string(),
+        "if true; then\n".repeat(5000) + "echo deep\n" + &"fi\n".repeat(5000),
+        "while false; do\n".repeat(5000) + &"done\n".repeat(5000),
+        "for x in a; do\n".repeat(5000) + &"done\n".repeat(5000),
+        "{ ".repeat(5000) + "echo b; " + &"} ".repeat(5000),
+        "f() { ".repeat(5000) + ":; " + &"}; ".repeat(5000),
+        "case a in a) ".repeat(3000) + "echo c;; " + &"esac;; ".repeat(2999) + "esac\n",
+        "echo $((".to_string() + &"(".repeat(100_000) + "1" + &")".repeat(100_000) + "))\n",
+        "(( ".to_string() + &"(".repeat(100_000) + "1" + &")".repeat(100_000) + " ))\n",
+        "echo $((".to_string() + &"-".repeat(100_000) + "1))\n",
+        "echo ".to_string() + &"$(".repeat(3000) + "echo s" + &")".repeat(3000) + "\n",
+        "echo \"".to_string() + &"$(echo \"".repeat(200_000) + "s" + &"\")".repeat(200_000),
+        "((((((((\n".to_string(),
+        "echo \"".to_string() + &"x".repeat(10_000_000) + "\n",
+    ]
+}
+
+/// Scripts and operator fragments whose every prefix is lexed, as input
+/// cut off anywhere, like a line typed so far, must not crash the shell.
+pub fn scripts() -> Vec<&'static str> {
+    vec![
+        include_str!("../../bellos_scripts/basic_math.bellos"),
+        include_str!("../../bellos_scripts/control_structures.bellos"),
+        include_str!("../../bellos_scripts/expansions.bellos"),
+        include_str!("../../bellos_scripts/file_operations.bellos"),
+        include_str!("../../bellos_scripts/functions.bellos"),
+        include_str!("../../bellos_scripts/hello_world.bellos"),
+        include_str!("../../bellos_scripts/string_manipulation.bellos"),
+        "a >> b 2>&1 &> c <> d <&0 >&- | e && f || g & h; i;; ( j ) { k; }",
+        "cat <<EOF <<-'END' <<\\X <<< \"$v\"\nbody $x\nEOF\n\tquoted\nEND\nx\nX\n",
+        "echo \"a $(b \"c\" $((1 + (2))) ${d:-e} `f`) 'g'\" 'h\\' \\\" $'i' [[ j =~ (k|l) ]]",
+        "echo é \"é\" 'é' $é ${é} é=1 \\é",
+    ]
+}
+
+/// Random inputs of up to 64 fragments each, the same for the same seed.
+/// Longer runs can be had with `BELLOS_FUZZ_ITERATIONS=1000000 cargo test
+/// without_panicking`.
+pub fn random_inputs() -> impl Iterator<Item = String> {
+    let setting = |name: &str| std::env::var(name).ok()?.parse().ok();
+    let count = setting("BELLOS_FUZZ_ITERATIONS").unwrap_or(ITERATIONS as u64) as usize;
+    let mut rng = Rng(setting("BELLOS_FUZZ_SEED").unwrap_or(SEED) | 1);
+    (0..count).map(move |_| {
+        let length = 1 + rng.below(64);
+        (0..length)
+            .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())])
+            .collect()
+    })
+}
+
+/// Runs `check` on `input`, failing with the start of `input` if it
+/// panics, as the whole of it may be megabytes long.
+pub fn assert_no_panic(input: &str, check: impl Fn(&str) + Sync) {
+    if on_thread_stack(|| check(input)).is_err() {
+        let shown: String = input.chars().take(200).collect();
+        panic!("panicked on {:?}", shown);
+    }
+}
+
+/// Runs `f` with the stack of a thread spawned with the defaults, the
+/// smallest the nesting limits must hold on, whatever `RUST_MIN_STACK` says
+/// of test threads. Returns what `f` does, or `Err` if it panics.
+pub fn on_thread_stack<T: Send>(f: impl FnOnce() -> T + Send) -> thread::Result<T> {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(THREAD_STACK)
+            .spawn_scoped(scope, f)
+            .unwrap()
+            .join()
+    })
+}
+
+/// A xorshift generator, enough to make runs repeatable from a seed.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -1 +1,4 @@
+#[cfg(test)]
+pub mod fuzzing;
+pub mod stack;
 pub mod utilities;
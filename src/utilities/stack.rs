@@ -0,0 +1,30 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Stack for the recursion of parsing and running nested commands. An
+//! unoptimized build uses tens of KiB for each level, so instead of nesting
+//! limits small enough for any thread, the stack grows where it runs low,
+//! and the limits hold on a 2 MiB worker thread as on an 8 MiB main one.
+
+/// How much stack must remain to recurse a level further in place.
+const RED_ZONE: usize = 256 << 10;
+
+/// The size of each stack allocated once less than `RED_ZONE` remains.
+const GROWTH: usize = 2 << 20;
+
+/// Runs `f`, on a newly allocated stack if the current one runs low.
+pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, GROWTH, f)
+}
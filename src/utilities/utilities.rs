@@ -13,18 +13,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Word(String),
+    /// The body of a heredoc. Words, quoted or not, are `Word`s.
     String(String),
-    Assignment,
     Pipe,
     Redirect(RedirectType),
+    /// `&>` or `&>>`, redirecting stdout and stderr together.
+    RedirectAll(RedirectType),
+    /// A descriptor number written directly before a redirection operator.
+    IoNumber(i32),
     Semicolon,
     NewLine,
     Ampersand,
+    /// `&&`, running the next command only if the last one succeeded.
+    And,
+    /// `||`, running the next command only if the last one failed.
+    Or,
     LeftParen,
     RightParen,
+    /// `(( expression ))`, an arithmetic command, holding the expression.
+    Arithmetic(String),
+    /// `{` and `}` written as words of their own, delimiting brace groups and
+    /// function bodies.
+    LeftBrace,
+    RightBrace,
     If,
     Then,
     Else,
@@ -37,10 +55,107 @@ pub enum Token {
     Case,
     Esac,
     Function,
+    Time,
     Elif,
-    LeftBracket,
-    RightBracket,
     DoubleSemicolon,
+    /// Input the lexer could not read, such as an unterminated string,
+    /// which the parser reports as a syntax error.
+    Error(String),
+}
+
+/// Behavior toggles controlled by command-line flags, `set` and `shopt`.
+#[derive(Debug, Clone, Default)]
+pub struct ShellOptions {
+    /// `-n`: parse input without executing it.
+    pub noexec: bool,
+    /// `-v`: echo input lines to stderr as they are read.
+    pub verbose: bool,
+    /// `-x`: print each command to stderr, after expansion, before it runs.
+    pub xtrace: bool,
+    /// Set while the shell reads commands from the user, which `$-` shows
+    /// as `i`.
+    pub interactive: bool,
+    /// `shopt -s dotglob`: glob patterns match names starting with a dot.
+    pub dotglob: bool,
+    /// `shopt -s huponexit`: send SIGHUP to running jobs when the shell
+    /// exits. Otherwise they keep running.
+    pub huponexit: bool,
+    /// `shopt -s globstar`: a `**` path component matches any number of
+    /// directories. Otherwise it means the same as `*`.
+    pub globstar: bool,
+    /// `shopt -s nullglob`: a glob pattern matching nothing expands to
+    /// nothing instead of itself.
+    pub nullglob: bool,
+    /// `shopt -s nocaseglob`: glob patterns match names regardless of case.
+    pub nocaseglob: bool,
+    /// `shopt -s extglob`: accepted for scripts that set it, but extended
+    /// patterns like `@(a|b)` are not supported yet.
+    pub extglob: bool,
+}
+
+impl ShellOptions {
+    /// The options managed by `shopt`, in listing order.
+    pub const SHOPT_NAMES: &'static [&'static str] = &[
+        "dotglob",
+        "extglob",
+        "globstar",
+        "huponexit",
+        "nocaseglob",
+        "nullglob",
+    ];
+
+    /// The state of the `shopt` option `name`, or `None` if there is none.
+    pub fn shopt(&self, name: &str) -> Option<bool> {
+        match name {
+            "dotglob" => Some(self.dotglob),
+            "extglob" => Some(self.extglob),
+            "globstar" => Some(self.globstar),
+            "huponexit" => Some(self.huponexit),
+            "nocaseglob" => Some(self.nocaseglob),
+            "nullglob" => Some(self.nullglob),
+            _ => None,
+        }
+    }
+
+    pub fn set_shopt(&mut self, name: &str, value: bool) -> Result<(), String> {
+        match name {
+            "dotglob" => self.dotglob = value,
+            "extglob" => self.extglob = value,
+            "globstar" => self.globstar = value,
+            "huponexit" => self.huponexit = value,
+            "nocaseglob" => self.nocaseglob = value,
+            "nullglob" => self.nullglob = value,
+            _ => return Err(format!("{}: invalid shell option name", name)),
+        }
+        Ok(())
+    }
+}
+
+/// How arguments of a command are completed, as registered by `complete`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionSpec {
+    /// `-W list`: the words of `list`, expanded when completing.
+    Words(String),
+    /// `-F function`: whatever the function leaves in `COMPREPLY`.
+    Function(String),
+}
+
+/// A token whose text borrows from the lexer input where possible.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedToken<'a> {
+    Word(&'a str),
+    String(Cow<'a, str>),
+    Other(Token),
+}
+
+impl BorrowedToken<'_> {
+    pub fn into_owned(self) -> Token {
+        match self {
+            BorrowedToken::Word(w) => Token::Word(w.to_string()),
+            BorrowedToken::String(s) => Token::String(s.into_owned()),
+            BorrowedToken::Other(token) => token,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -48,6 +163,18 @@ pub enum RedirectType {
     Input,
     Output,
     Append,
+    /// `<>`, opening the target for both reading and writing.
+    ReadWrite,
+    DuplicateInput,
+    DuplicateOutput,
+    /// `<<` or, with `strip_tabs`, `<<-`. A `quoted` delimiter disables
+    /// expansion of the body.
+    HereDoc {
+        strip_tabs: bool,
+        quoted: bool,
+    },
+    /// `<<<`, giving the expanded word and a newline as input.
+    HereString,
 }
 
 impl RedirectType {
@@ -56,6 +183,63 @@ impl RedirectType {
             RedirectType::Output => ">",
             RedirectType::Append => ">>",
             RedirectType::Input => "<",
+            RedirectType::ReadWrite => "<>",
+            RedirectType::DuplicateInput => "<&",
+            RedirectType::DuplicateOutput => ">&",
+            RedirectType::HereDoc {
+                strip_tabs: false, ..
+            } => "<<",
+            RedirectType::HereDoc {
+                strip_tabs: true, ..
+            } => "<<-",
+            RedirectType::HereString => "<<<",
+        }
+    }
+
+    /// The descriptor redirected when no number precedes the operator.
+    pub fn default_fd(&self) -> i32 {
+        match self {
+            RedirectType::Input
+            | RedirectType::ReadWrite
+            | RedirectType::DuplicateInput
+            | RedirectType::HereDoc { .. }
+            | RedirectType::HereString => 0,
+            RedirectType::Output | RedirectType::Append | RedirectType::DuplicateOutput => 1,
+        }
+    }
+}
+
+/// A single `[fd]op target` redirection. For the duplicate kinds the target
+/// names a descriptor number or `-` to close `fd`, for heredocs it is the
+/// body and for here-strings the word; otherwise it is a path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirection {
+    pub fd: i32,
+    pub kind: RedirectType,
+    pub target: String,
+}
+
+/// The operator joining two commands of an and-or list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListOperator {
+    And,
+    Or,
+}
+
+impl ListOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListOperator::And => "&&",
+            ListOperator::Or => "||",
+        }
+    }
+
+    /// Whether the command after this operator runs, given the status of
+    /// the one before it.
+    pub fn runs_after(&self, status: i32) -> bool {
+        match self {
+            ListOperator::And => status == 0,
+            ListOperator::Or => status != 0,
         }
     }
 }
@@ -70,11 +254,16 @@ pub enum ASTNode {
         name: String,
         value: String,
     },
+    /// `NAME=value ... command`: assignments that only last while `node`
+    /// runs, and are in its environment.
+    Environment {
+        assignments: Vec<(String, String)>,
+        node: Box<ASTNode>,
+    },
     Pipeline(Vec<ASTNode>),
     Redirect {
         node: Box<ASTNode>,
-        direction: RedirectType,
-        target: String,
+        redirects: Vec<Redirection>,
     },
     Block(Vec<ASTNode>),
     If {
@@ -86,9 +275,11 @@ pub enum ASTNode {
         condition: Box<ASTNode>,
         block: Box<ASTNode>,
     },
+    /// `for var in list; do ...; done`. Without `in`, the list is `None`
+    /// and the loop goes over the positional parameters when it runs.
     For {
         var: String,
-        list: Vec<String>,
+        list: Option<Vec<String>>,
         block: Box<ASTNode>,
     },
     Comparison {
@@ -96,35 +287,131 @@ pub enum ASTNode {
         op: String,
         right: String,
     },
+    /// `case word in pattern | pattern) ... ;; esac`: each arm's patterns,
+    /// as written, with the block run when one matches.
     Case {
-        var: Box<ASTNode>,
-        cases: Vec<(ASTNode, ASTNode)>,
+        word: String,
+        cases: Vec<(Vec<String>, ASTNode)>,
     },
+    /// A function definition. The body is shared with the function table,
+    /// so defining and calling functions never copies it.
     Function {
         name: String,
-        body: Box<ASTNode>,
+        body: Arc<ASTNode>,
+    },
+    /// `first && ... || ...`: each of `rest` runs or is skipped in turn,
+    /// depending on its operator and the status of the command before it.
+    AndOr {
+        first: Box<ASTNode>,
+        rest: Vec<(ListOperator, ASTNode)>,
     },
     Background(Box<ASTNode>),
+    Time(Box<ASTNode>),
     Expression(String),
+    /// A statement tagged with the script line it starts on.
+    Line {
+        line: usize,
+        node: Box<ASTNode>,
+    },
 }
 
-impl ASTNode {
-    pub fn is_empty_command(&self) -> bool {
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ASTNode::Command { name, args } => name.is_empty() && args.is_empty(),
-            _ => false,
+            ASTNode::Command { name, args } => write!(f, "{} {}", name, args.join(" ")),
+            ASTNode::Assignment { name, value } => write!(f, "{}={}", name, value),
+            ASTNode::Expression(expr) => write!(f, "{}", expr),
+            ASTNode::Line { node, .. } => write!(f, "{}", node),
+            ASTNode::Redirect { node, redirects } => {
+                write!(f, "{}", node.to_string().trim_end())?;
+                for redirect in redirects {
+                    write!(f, " ")?;
+                    if redirect.fd != redirect.kind.default_fd() {
+                        write!(f, "{}", redirect.fd)?;
+                    }
+                    match redirect.kind {
+                        RedirectType::HereDoc { .. } => write!(f, "{}", redirect.kind.as_str())?,
+                        _ => write!(f, "{}{}", redirect.kind.as_str(), redirect.target)?,
+                    }
+                }
+                Ok(())
+            }
+            ASTNode::Environment { assignments, node } => {
+                for (name, value) in assignments {
+                    write!(f, "{}={} ", name, value)?;
+                }
+                write!(f, "{}", node)
+            }
+            ASTNode::Pipeline(commands) => {
+                let commands: Vec<String> = commands
+                    .iter()
+                    .map(|command| command.to_string().trim_end().to_string())
+                    .collect();
+                write!(f, "{}", commands.join(" | "))
+            }
+            ASTNode::Block(_) => write!(f, "{{ {}; }}", statements(self)),
+            ASTNode::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                write!(
+                    f,
+                    "if {}; then {}; ",
+                    statements(condition),
+                    statements(then_block)
+                )?;
+                if let Some(else_block) = else_block {
+                    write!(f, "else {}; ", statements(else_block))?;
+                }
+                write!(f, "fi")
+            }
+            ASTNode::While { condition, block } => {
+                write!(
+                    f,
+                    "while {}; do {}; done",
+                    statements(condition),
+                    statements(block)
+                )
+            }
+            ASTNode::For { var, list, block } => {
+                write!(f, "for {}", var)?;
+                if let Some(list) = list {
+                    write!(f, " in {}", list.join(" "))?;
+                }
+                write!(f, "; do {}; done", statements(block))
+            }
+            ASTNode::Case { word, cases } => {
+                write!(f, "case {} in ", word)?;
+                for (patterns, block) in cases {
+                    write!(f, "{}) {};; ", patterns.join(" | "), statements(block))?;
+                }
+                write!(f, "esac")
+            }
+            ASTNode::AndOr { first, rest } => {
+                write!(f, "{}", first.to_string().trim_end())?;
+                for (op, node) in rest {
+                    write!(f, " {} {}", op.as_str(), node.to_string().trim_end())?;
+                }
+                Ok(())
+            }
+            ASTNode::Background(node) => write!(f, "{} &", node.to_string().trim_end()),
+            ASTNode::Time(node) => write!(f, "time {}", node),
+            _ => write!(f, "{:?}", self),
         }
     }
 }
 
-impl ToString for ASTNode {
-    fn to_string(&self) -> String {
-        match self {
-            ASTNode::Command { name, args } => format!("{} {}", name, args.join(" ")),
-            ASTNode::Assignment { name, value } => format!("{}={}", name, value),
-            ASTNode::Expression(expr) => expr.clone(),
-            _ => format!("{:?}", self),
-        }
+/// The commands of a block separated by `;`, or a single command, as they
+/// appear inside a compound command.
+fn statements(node: &ASTNode) -> String {
+    match node {
+        ASTNode::Block(nodes) => nodes
+            .iter()
+            .map(|node| node.to_string().trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("; "),
+        node => node.to_string().trim_end().to_string(),
     }
 }
 
@@ -142,3 +429,37 @@ impl PartialEq<String> for ASTNode {
         self == other.as_str()
     }
 }
+
+/// Why a statement stopped before finishing: an error, or `exit`, `return`,
+/// `break` or `continue` unwinding to the context that handles it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Interrupt {
+    Error(String),
+    /// `exit n`: ends the shell at the top level, or just the command
+    /// substitution or pipeline stage it is run in.
+    Exit(i32),
+    /// `return n`: ends the innermost function call.
+    Return(i32),
+    /// `break n`: leaves the n-th enclosing loop.
+    Break(usize),
+    /// `continue n`: goes on to the next iteration of the n-th enclosing loop.
+    Continue(usize),
+}
+
+impl From<String> for Interrupt {
+    fn from(error: String) -> Self {
+        Interrupt::Error(error)
+    }
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interrupt::Error(error) => write!(f, "{}", error),
+            Interrupt::Exit(status) => write!(f, "exit {}", status),
+            Interrupt::Return(_) => write!(f, "return: can only return from a function"),
+            Interrupt::Break(_) => write!(f, "break: only meaningful in a loop"),
+            Interrupt::Continue(_) => write!(f, "continue: only meaningful in a loop"),
+        }
+    }
+}
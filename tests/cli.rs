@@ -0,0 +1,1778 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs the bellos binary on scripts, as users and the kernel do. Only
+//! Unix runs a script by its `#!` line.
+#![cfg(unix)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
+
+const BELLOS: &str = env!("CARGO_BIN_EXE_bellos");
+
+/// Writes an executable script named `name` into `dir`.
+fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, body).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn extensionless_shebang_script_runs_by_argument_and_directly() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = format!("#!{}\nargv \"$@\"\necho \"count $#\"\n", BELLOS);
+    let path = script(dir.path(), "greet", &body);
+    let expected = "argc: 2\n<a b>\n<c>\ncount 2\n";
+
+    let by_argument = Command::new(BELLOS)
+        .arg(&path)
+        .args(["a b", "c"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&by_argument), expected);
+    assert!(by_argument.status.success());
+
+    let direct = Command::new(&path).args(["a b", "c"]).output().unwrap();
+    assert_eq!(stdout(&direct), expected);
+    assert!(direct.status.success());
+
+    let no_arguments = Command::new(&path).output().unwrap();
+    assert_eq!(stdout(&no_arguments), "argc: 0\ncount 0\n");
+}
+
+#[test]
+fn options_end_at_double_dash() {
+    let dir = tempfile::tempdir().unwrap();
+    script(dir.path(), "-n", "argv \"$@\"\n");
+    let output = Command::new(BELLOS)
+        .current_dir(dir.path())
+        .args(["--", "-n", "-v"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "argc: 1\n<-v>\n");
+}
+
+#[test]
+fn verbose_mode_echoes_each_line_before_it_runs() {
+    let source = "x=1\n# comment\necho \"$x\" \\\n  more\n\nset -x\necho $-\n";
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(dir.path(), "script", source);
+    let output = Command::new(BELLOS).arg("-v").arg(&path).output().unwrap();
+    assert_eq!(stdout(&output), "1 more\nvx\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, format!("{}+ echo vx\n", source));
+}
+
+#[test]
+fn output_to_a_reader_that_went_away_fails_without_a_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "echo first\nsleep 0.2\necho lost\nreadfile script\necho done > marker\n",
+    );
+    let mut child = Command::new(BELLOS)
+        .arg(&path)
+        .current_dir(dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first = [0; 6];
+    std::io::Read::read_exact(child.stdout.as_mut().unwrap(), &mut first).unwrap();
+    assert_eq!(&first, b"first\n");
+    // The reader goes away, as `head -1` does after its line.
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("echo: write error: Broken pipe"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("marker")).unwrap(),
+        "done\n"
+    );
+}
+
+/// Runs `body` as a script file, returning its output and exit status.
+fn run_script(body: &str) -> Output {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(dir.path(), "script", body);
+    Command::new(BELLOS).arg(&path).output().unwrap()
+}
+
+#[test]
+fn exit_status_is_that_of_the_last_command() {
+    let output = run_script("/bin/sh -c 'exit 7'\necho $?\nnonexistent_command_xyz\necho $?\n");
+    assert_eq!(stdout(&output), "7\n127\n");
+    assert_eq!(run_script("exit 3\n").status.code(), Some(3));
+    assert_eq!(run_script("/bin/sh -c 'exit 5'\n").status.code(), Some(5));
+    assert_eq!(
+        run_script("false\nif false; then :; fi\n").status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        run_script("while false; do :; done\n").status.code(),
+        Some(0)
+    );
+}
+
+#[test]
+fn syntax_error_exits_with_status_two() {
+    let output = run_script("echo before\nif true; then\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("syntax error"));
+}
+
+#[test]
+fn running_stops_at_the_first_syntax_error() {
+    let output = run_script("echo one\nfi\necho two\ndone\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "one\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2: syntax error"), "{}", stderr);
+    assert!(!stderr.contains("line 4"), "{}", stderr);
+}
+
+#[test]
+fn noexec_reports_every_syntax_error_and_runs_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("marker");
+    let body = format!("touch {}\nfi\necho two\ndone\n", marker.display());
+    let path = script(dir.path(), "script", &body);
+    let output = Command::new(BELLOS).arg("-n").arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2: syntax error"), "{}", stderr);
+    assert!(stderr.contains("line 4: syntax error"), "{}", stderr);
+    assert!(!marker.exists());
+}
+
+#[test]
+fn included_file_stops_at_its_first_syntax_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let library = script(dir.path(), "library", "echo a\nfi\necho b\n");
+    let body = format!("include {}\necho status $?\n", library.display());
+    let path = script(dir.path(), "script", &body);
+    let output = Command::new(BELLOS).arg(&path).output().unwrap();
+    assert_eq!(stdout(&output), "a\nstatus 2\n");
+}
+
+#[test]
+fn text_builtins_read_files_standard_input_and_pipes() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "one\ntwo\nthree\n").unwrap();
+    fs::write(dir.path().join("b"), "caf\u{e9}\n").unwrap();
+    let body = format!(
+        "cd {}\n\
+         cat a - b < b\n\
+         head -n 2 a\n\
+         tail -n 1 a b\n\
+         echo piped | cat\n\
+         cat a | head -n 1\n\
+         cat a | tail -2\n\
+         wc -c -l -m b\n\
+         wc a b\n\
+         cat a | wc -w\n\
+         cat missing\n\
+         echo status $?\n",
+        dir.path().display()
+    );
+    let output = run_script(&body);
+    let expected = "one\ntwo\nthree\ncaf\u{e9}\ncaf\u{e9}\n\
+                    one\ntwo\n\
+                    ==> a <==\nthree\n\n==> b <==\ncaf\u{e9}\n\
+                    piped\n\
+                    one\n\
+                    two\nthree\n\
+                    \x20     1       5       6 b\n\
+                    \x20     3       3      14 a\n\
+                    \x20     1       1       6 b\n\
+                    \x20     4       4      20 total\n\
+                    \x20     3\n\
+                    status 1\n";
+    assert_eq!(stdout(&output), expected);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cat: missing"));
+}
+
+#[test]
+fn help_and_type_describe_commands() {
+    let body = format!(
+        "help wc\nhelp nope\nf() {{ :; }}\ntype cd f {} nope\necho $?\n",
+        BELLOS
+    );
+    let output = run_script(&body);
+    let stdout = stdout(&output);
+    assert!(
+        stdout.starts_with("wc: wc [-l] [-w] [-m] [-c] [file ...]\n"),
+        "{}",
+        stdout
+    );
+    let types = format!(
+        "cd is a shell builtin\nf is a function\n{} is {}\n1\n",
+        BELLOS, BELLOS
+    );
+    assert!(stdout.ends_with(&types), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Try `type nope'"), "{}", stderr);
+    assert!(stderr.contains("type: nope: not found"), "{}", stderr);
+}
+
+#[test]
+fn read_splits_here_strings_on_ifs() {
+    let output = run_script(
+        "read first rest <<< \"alpha beta gamma\"\n\
+         echo \"[$first] [$rest]\"\n\
+         read a b c <<< '  one   two  '\n\
+         echo \"[$a] [$b] [$c]\"\n\
+         IFS=: read user pass shell <<< root:secret:/bin/sh:extra\n\
+         echo \"[$user] [$pass] [$shell]\"\n\
+         read x y <<< 'after:the:prefix'\n\
+         echo \"[$x] [$y]\"\n\
+         IFS=,\n\
+         read p q <<< 'left,right,more'\n\
+         echo \"[$p] [$q]\"\n\
+         v=word\n\
+         cat <<< \"$v  kept\"\n\
+         cat <<< '$v'\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "[alpha] [beta gamma]\n\
+         [one] [two] []\n\
+         [root] [secret] [/bin/sh:extra]\n\
+         [after:the:prefix] []\n\
+         [left] [right,more]\n\
+         word  kept\n\
+         $v\n"
+    );
+}
+
+#[test]
+fn timeout_bounds_functions_and_external_commands() {
+    let start = std::time::Instant::now();
+    let output = run_script(
+        "slow() { sleep 5; echo never; }\n\
+         quick() { echo \"quick $1\"; return 3; }\n\
+         stubborn() { sh -c 'trap \"\" TERM; sleep 5'; }\n\
+         x=1\n\
+         bump() { x=2; }\n\
+         timeout 0.2 slow\n\
+         echo \"slow $?\"\n\
+         timeout 5 quick arg\n\
+         echo \"quick $?\"\n\
+         timeout -k 0.2 0.2 stubborn\n\
+         echo \"stubborn $?\"\n\
+         timeout 5 bump\n\
+         echo \"x $x\"\n\
+         timeout 0.2 sleep 5\n\
+         echo \"sleep $?\"\n\
+         timeout 5 sh -c 'exit 4'\n\
+         echo \"sh $?\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "slow 124\nquick arg\nquick 3\nstubborn 124\nx 1\nsleep 124\nsh 4\n"
+    );
+    assert!(start.elapsed() < std::time::Duration::from_secs(4));
+}
+
+#[test]
+fn fifos_carry_data_from_every_kind_of_writer() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = dir.path().join("p");
+    let out = dir.path().join("out");
+    let body = format!(
+        "mkfifo {p}\n\
+         say() {{ echo \"function $1\"; }}\n\
+         echo builtin > {p} &\n\
+         cat < {p}\n\
+         {{ echo group; }} > {p} &\n\
+         cat < {p}\n\
+         say writer > {p} &\n\
+         cat < {p}\n\
+         /bin/echo external > {p} &\n\
+         cat < {p}\n\
+         wait\n\
+         cat < {p} > {out} &\n\
+         echo last > {p}\n",
+        p = fifo.display(),
+        out = out.display()
+    );
+    let path = script(dir.path(), "script", &body);
+    // A writer that never meets its reader blocks forever, so bound the run.
+    let output = Command::new("timeout")
+        .arg("10")
+        .arg(BELLOS)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let transferred: Vec<_> = stdout(&output).lines().map(str::to_string).collect();
+    assert_eq!(
+        transferred,
+        ["builtin", "group", "function writer", "external"]
+    );
+    // The background reader may still be finishing after the shell exits.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while fs::read_to_string(&out).unwrap_or_default() != "last\n"
+        && std::time::Instant::now() < deadline
+    {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert_eq!(fs::read_to_string(&out).unwrap(), "last\n");
+}
+
+#[test]
+fn commands_that_cannot_run_report_127_or_126() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("plain"), "echo never\n").unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "PATH=/nonexistent\n\
+         ech hi\n\
+         echo \"missing $?\"\n\
+         ./plain\n\
+         echo \"plain $?\"\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(dir.path())
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "missing 127\nplain 126\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines[0], "bellos: ech: command not found");
+    assert!(
+        lines[1].starts_with("bellos: did you mean: echo"),
+        "{}",
+        stderr
+    );
+    assert!(
+        lines[2].ends_with("/plain: Permission denied"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn numbered_descriptors_redirect_to_files_and_each_other() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("data"), "one\ntwo\n").unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "echo to-three 3> three >&3\n\
+         exec 3< data\n\
+         read -u 3 first\n\
+         read -u 3 second\n\
+         exec 3<&-\n\
+         echo \"$first $second\"\n\
+         read -u 3 closed\n\
+         echo \"closed $?\"\n\
+         sh -c 'echo out; echo err >&2' 3>&1 1>&2 2>&3\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(dir.path())
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "one two\nclosed 1\nerr\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("read: 3: Bad file descriptor"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.ends_with("\nout\n"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("three")).unwrap(),
+        "to-three\n"
+    );
+}
+
+#[test]
+fn both_streams_redirect_to_one_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "sh -c 'echo out; echo err >&2; echo out2' &> all\n\
+         sh -c 'echo appended >&2' &>> all\n\
+         sh -c 'echo replaced; echo replaced-err >&2' >& both\n\
+         true &\n\
+         wait $!\n\
+         echo \"background $?\"\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(dir.path())
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(stdout(&output).ends_with("background 0\n"));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    let read = |name| fs::read_to_string(dir.path().join(name)).unwrap();
+    assert_eq!(read("all"), "out\nerr\nout2\nappended\n");
+    assert_eq!(read("both"), "replaced\nreplaced-err\n");
+}
+
+#[test]
+fn heredocs_strip_tabs_and_quoted_delimiters_keep_the_body() {
+    let output = run_script(
+        "i=0\n\
+         while [ $i -lt 2 ]; do\n\
+         \tcat <<-EOF\n\
+         \t\tindented $i\n\
+         \tEOF\n\
+         \ti=$((i + 1))\n\
+         done\n\
+         cat <<'EOF'\n\
+         $HOME $(echo no) `x`\n\
+         EOF\n\
+         cat <<\"EOF\"\n\
+         \t$i\n\
+         EOF\n\
+         cat <<EOF\n\
+         \t$i\n\
+         EOF\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "indented 0\nindented 1\n$HOME $(echo no) `x`\n\t$i\n\t2\n"
+    );
+}
+
+#[test]
+fn quoted_substitutions_nest_and_stay_one_argument() {
+    let dir = tempfile::tempdir().unwrap();
+    let inner = dir.path().join("a dir");
+    fs::create_dir(&inner).unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "count() { echo \"$# [$1]\"; }\n\
+         echo \"base: $(basename \"$(pwd)\")\"\n\
+         count \"$(echo \"two  spaces\")\"\n\
+         count \"$(echo \"$(echo \"in (parens) and \\\")\\\"\")\")\"\n\
+         count $(echo \"two  words\")\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(&inner)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        stdout(&output),
+        "base: a dir\n1 [two  spaces]\n1 [in (parens) and \")\"]\n2 [two]\n"
+    );
+}
+
+#[test]
+fn substitutions_split_unless_quoted_and_set_the_status() {
+    let output = run_script(
+        "count() { echo \"$#\"; }\n\
+         count $(printf 'a b\\nc\\n\\n')\n\
+         count \"$(printf 'a b\\nc\\n\\n')\"\n\
+         for word in $(echo one two); do echo \"<$word>\"; done\n\
+         x=$(false)\n\
+         echo \"assigned $?\"\n\
+         count $(sh -c 'exit 3')\n\
+         echo \"after $?\"\n\
+         $(sh -c 'exit 3')\n\
+         echo \"alone $?\"\n\
+         count \"$(true)\"\n\
+         echo \"quoted $?\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "3\n1\n<one>\n<two>\nassigned 1\n0\nafter 0\nalone 3\n1\nquoted 0\n"
+    );
+}
+
+#[test]
+fn shopt_lists_options_and_rejects_unknown_ones() {
+    let output = run_script("shopt -s nullglob\nshopt\nshopt -s bogus\necho \"status $?\"\n");
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"nullglob       \ton"), "{}", stdout);
+    assert!(lines.contains(&"dotglob        \toff"), "{}", stdout);
+    assert_eq!(lines.last(), Some(&"status 1"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "shopt: bogus: invalid shell option name\n"
+    );
+}
+
+#[test]
+fn runtime_errors_name_the_line_they_happened_on() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "echo \"at $LINENO\"\n\
+         for i in 1; do\n\
+         \x20 if true; then\n\
+         \x20   x=$((1 / 0))\n\
+         \x20 fi\n\
+         done\n\
+         while true; do\n\
+         \x20 [[ 1 -lt ]]\n\
+         \x20 [ a = b\n\
+         \x20 break\n\
+         done\n\
+         echo \"at $LINENO\"\n",
+    );
+    let output = Command::new(BELLOS).arg(&path).output().unwrap();
+    assert_eq!(stdout(&output), "at 1\nat 12\n");
+    let path = path.display();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{path}: line 4: 1 / 0: Division by zero\n\
+             {path}: line 8: [[: 1: unary operator expected\n\
+             {path}: line 9: [: missing `]'\n"
+        )
+    );
+}
+
+#[test]
+fn errors_in_functions_print_the_call_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "inner() {\n\
+         \x20 caller\n\
+         \x20 caller 0\n\
+         \x20 caller 1\n\
+         \x20 caller 2\n\
+         \x20 caller 3 || echo \"no frame $?\"\n\
+         \x20 y=$((2 / 0))\n\
+         }\n\
+         middle() { inner; }\n\
+         outer() {\n\
+         \x20 middle\n\
+         }\n\
+         outer\n\
+         caller || echo \"top $?\"\n",
+    );
+    let output = Command::new(BELLOS).arg(&path).output().unwrap();
+    let path = path.display();
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "9 {path}\n\
+             9 middle {path}\n\
+             11 outer {path}\n\
+             13 main {path}\n\
+             no frame 1\n\
+             top 1\n"
+        )
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{path}: line 7: 2 / 0: Division by zero\n\
+             \x20   in inner called from {path}: line 9\n\
+             \x20   in middle called from {path}: line 11\n\
+             \x20   in outer called from {path}: line 13\n"
+        )
+    );
+}
+
+#[test]
+fn shlvl_counts_nested_shells() {
+    let dir = tempfile::tempdir().unwrap();
+    script(dir.path(), "inner", "echo \"inner $SHLVL\"\n");
+    let outer = script(
+        dir.path(),
+        "outer",
+        "echo \"outer $SHLVL\"\n\"$1\" \"$2\"\n",
+    );
+    let output = Command::new(BELLOS)
+        .env("SHLVL", "4")
+        .arg(&outer)
+        .arg(BELLOS)
+        .arg(dir.path().join("inner"))
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "outer 5\ninner 6\n");
+    let unset = Command::new(BELLOS)
+        .env_remove("SHLVL")
+        .arg(dir.path().join("inner"))
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&unset), "inner 1\n");
+}
+
+#[test]
+fn identity_variables_are_set_before_the_profile_and_not_exported() {
+    let home = tempfile::tempdir().unwrap();
+    fs::write(
+        home.path().join(".bellos_profile"),
+        "profile=\"$UID $OSTYPE\"\n",
+    )
+    .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "echo \"$profile\"\n\
+         echo \"$MACHTYPE\"\n\
+         [ \"$UID\" = \"$(id -u)\" ] && echo same uid\n\
+         env | grep -c -e ^UID= -e ^OSTYPE= -e ^MACHTYPE=\n",
+    );
+    let run = || {
+        let output = Command::new(BELLOS)
+            .env("HOME", home.path())
+            .arg("-l")
+            .arg(&path)
+            .output()
+            .unwrap();
+        stdout(&output)
+    };
+    let first = run();
+    let lines: Vec<&str> = first.lines().collect();
+    let (uid, ostype) = lines[0].split_once(' ').unwrap();
+    assert!(uid.parse::<u32>().is_ok(), "{}", first);
+    assert!(!ostype.is_empty());
+    assert!(lines[1].ends_with(ostype), "{}", first);
+    assert_eq!(lines[2..], ["same uid", "0"]);
+    assert_eq!(run(), first);
+}
+
+#[test]
+fn login_shells_source_the_profiles_in_order_and_the_logout_file_last() {
+    let home = tempfile::tempdir().unwrap();
+    let system = script(home.path(), "system", "echo system profile\n");
+    fs::write(home.path().join(".bellos_profile"), "echo user profile\n").unwrap();
+    fs::write(home.path().join(".bellos_logout"), "echo logout $?\n").unwrap();
+    let path = script(
+        home.path(),
+        "script",
+        "trap 'echo exit trap' EXIT\necho body\n/bin/sh -c 'exit 4'\n",
+    );
+    let login = |configure: &dyn Fn(&mut Command)| {
+        let mut command = Command::new(BELLOS);
+        command
+            .env("HOME", home.path())
+            .env("BELLOS_SYSTEM_PROFILE", &system);
+        configure(&mut command);
+        command.arg(&path).output().unwrap()
+    };
+
+    let output = login(&|command| {
+        command.arg("--login");
+    });
+    assert_eq!(
+        stdout(&output),
+        "system profile\nuser profile\nbody\nlogout 4\nexit trap\n"
+    );
+    assert_eq!(output.status.code(), Some(4));
+
+    // A name starting with `-` makes a login shell too.
+    let output = login(&|command| {
+        command.arg0("-bellos");
+    });
+    assert_eq!(
+        stdout(&output),
+        "system profile\nuser profile\nbody\nlogout 4\nexit trap\n"
+    );
+
+    // Files that do not exist are skipped without a word.
+    let output = login(&|command| {
+        command
+            .arg("-l")
+            .env("BELLOS_SYSTEM_PROFILE", home.path().join("missing"))
+            .env("BELLOS_PROFILE", home.path().join("missing"))
+            .env("BELLOS_LOGOUT", &system);
+    });
+    assert_eq!(stdout(&output), "body\nsystem profile\nexit trap\n");
+    assert!(output.stderr.is_empty(), "{:?}", output.stderr);
+
+    let output = login(&|_| {});
+    assert_eq!(stdout(&output), "body\nexit trap\n");
+}
+
+#[test]
+fn date_formats_given_times_in_the_exported_zone() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "date -d @1700000000 '+%Y-%m-%d %H:%M:%S %z %Z %a %b'\n\
+         date -u -d @1700000000\n\
+         export TZ=America/New_York\n\
+         date -d @1700000000.5 '+%H:%M %Z %N %q'\n\
+         TZ=:Europe/Paris\n\
+         date -d @1700000000 '+%H:%M %Z %s'\n",
+    );
+    let run = |tz: &str| {
+        let output = Command::new(BELLOS)
+            .env("TZ", tz)
+            .arg(&path)
+            .output()
+            .unwrap();
+        stdout(&output)
+    };
+    assert_eq!(
+        run("Asia/Tokyo"),
+        "2023-11-15 07:13:20 +0900 JST Wed Nov\n\
+         Tue Nov 14 22:13:20 UTC 2023\n\
+         17:13 EST 500000000 %q\n\
+         23:13 CET 1700000000\n"
+    );
+    // A POSIX rule is understood as the process's own zone, without names.
+    assert!(run("JST-9").starts_with("2023-11-15 07:13:20 +0900 +0900 Wed Nov\n"));
+}
+
+#[test]
+fn exit_trap_runs_once_however_the_script_ends() {
+    let normal = run_script("trap 'echo \"exit $?\"' EXIT\necho body\n");
+    assert_eq!(stdout(&normal), "body\nexit 0\n");
+    assert!(normal.status.success());
+
+    let exited = run_script(
+        "trap 'echo \"exit $?\"' EXIT\n\
+         f() { exit 1; }\n\
+         f\n\
+         echo never\n",
+    );
+    assert_eq!(stdout(&exited), "exit 1\n");
+    assert_eq!(exited.status.code(), Some(1));
+
+    let debug = run_script("trap 'echo \"debug $LINENO\"' DEBUG\necho a\nx=1\necho b\n");
+    assert_eq!(stdout(&debug), "debug 2\na\ndebug 4\nb\n");
+}
+
+#[test]
+fn prompt_command_runs_before_each_prompt_and_survives_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log");
+    let output = run_interactive(format!(
+        "PROMPT_COMMAND='echo \"p $?\" >> {}'\n\
+         echo a\n\
+         false\n\
+         PROMPT_COMMAND='if then'\n\
+         echo still running\n",
+        log.display()
+    ));
+    assert_eq!(fs::read_to_string(&log).unwrap(), "p 0\np 0\np 1\n");
+    assert!(stdout(&output).contains("a\n"));
+    assert!(stdout(&output).contains("still running\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bellos: PROMPT_COMMAND: "), "{}", stderr);
+}
+
+#[test]
+fn complete_prints_and_removes_registrations() {
+    let output = run_script(
+        "complete -W 'a b' x\n\
+         complete -F f y\n\
+         complete -p\n\
+         complete -r x\n\
+         complete -p\n\
+         complete -r x\n\
+         echo \"status $?\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "complete -W 'a b' x\ncomplete -F f y\ncomplete -F f y\nstatus 1\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "complete: x: no completion specification\n"
+    );
+}
+
+/// Whether the process `pid` is still running, or died and is a zombie
+/// waiting for init to reap it.
+fn is_running(pid: &str) -> bool {
+    let state = fs::read_to_string(format!("/proc/{}/stat", pid)).ok();
+    match state {
+        Some(stat) => !stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .trim_start()
+            .starts_with('Z'),
+        None => Command::new("kill")
+            .args(["-0", pid])
+            .status()
+            .unwrap()
+            .success(),
+    }
+}
+
+#[test]
+fn jobs_outlive_the_shell_unless_huponexit_is_set() {
+    // Background jobs write elsewhere, so the output ends with the shell.
+    let pids = |body: &str| -> Vec<String> {
+        stdout(&run_script(body))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    let kept = pids("sleep 30 > /dev/null 2>&1 &\necho $!\n");
+    let hung_up = pids(
+        "shopt -s huponexit\n\
+         sleep 30 > /dev/null 2>&1 &\n\
+         echo $!\n\
+         sleep 30 > /dev/null 2>&1 &\n\
+         echo $!\n\
+         disown -h %2\n",
+    );
+    let pids: Vec<&String> = kept.iter().chain(&hung_up).collect();
+    assert_eq!(pids.len(), 3, "{:?}", pids);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while is_running(pids[1]) && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let running: Vec<bool> = pids.iter().map(|pid| is_running(pid)).collect();
+    for pid in [pids[0], pids[2]] {
+        let _ = Command::new("kill").arg(pid).status();
+    }
+    assert_eq!(running, [true, false, true]);
+}
+
+#[test]
+fn stopped_jobs_are_listed_and_resumed_by_bg_and_fg() {
+    let output = run_script(
+        "sleep 5 > /dev/null &\n\
+         kill -STOP %1\n\
+         sleep 0.2\n\
+         jobs\n\
+         bg\n\
+         sleep 0.2\n\
+         jobs\n\
+         kill %1\n\
+         sh -c 'kill -STOP $$; echo resumed'\n\
+         echo stopped $?\n\
+         fg\n\
+         echo fg $?\n",
+    );
+    let output = stdout(&output);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "[1]  Stopped                 sleep 5 >/dev/null",
+            "[1] sleep 5 >/dev/null &",
+            "[1]  Running                 sleep 5 >/dev/null",
+            "stopped 148",
+            "sh -c kill -STOP $$; echo resumed",
+            "resumed",
+            "fg 0",
+        ]
+    );
+}
+
+#[test]
+fn signal_deaths_give_128_plus_the_signal_and_are_described() {
+    let output = run_script(
+        "sh -c 'kill -TERM $$'\n\
+         echo term $?\n\
+         sh -c 'kill -KILL $$'\n\
+         echo kill $?\n\
+         sleep 5 &\n\
+         kill %1\n\
+         wait $!\n\
+         echo wait $?\n\
+         sleep 5 &\n\
+         kill -KILL %1\n\
+         sleep 0.2\n\
+         wait $!\n\
+         echo wait $?\n",
+    );
+    let out = stdout(&output);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines, ["term 143", "kill 137", "wait 143", "wait 137",]);
+    // How the jobs ended is reported on stderr, by `wait` or by the notice
+    // of a job that finished before it.
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[1]  Terminated              sleep 5\n[1]  Killed                  sleep 5\n"
+    );
+}
+
+#[test]
+fn dev_tcp_redirections_talk_to_a_server() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut request = String::new();
+        std::io::BufRead::read_line(&mut std::io::BufReader::new(&stream), &mut request).unwrap();
+        (&stream)
+            .write_all(format!("pong {}", request).as_bytes())
+            .unwrap();
+    });
+    let output = run_script(&format!(
+        "exec 3<>/dev/tcp/127.0.0.1/{port}\n\
+         echo ping >&3\n\
+         read -u 3 reply\n\
+         exec 3<&-\n\
+         echo \"got $reply\"\n\
+         cat < /dev/tcp/127.0.0.1/x\n"
+    ));
+    server.join().unwrap();
+    assert_eq!(stdout(&output), "got pong ping\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.ends_with("/dev/tcp/127.0.0.1/x: invalid port\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn functions_run_wherever_commands_do() {
+    let output = run_script(
+        "greet() { echo hi $1; }\n\
+         greet world\n\
+         if greet x > /dev/null; then echo condition; fi\n\
+         greet pipe | tr a-z A-Z\n\
+         out=$(greet substitution); echo \"[$out]\"\n\
+         fails() { return 4; }\n\
+         fails; echo $?\n\
+         fails || echo \"or $?\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "hi world\ncondition\nHI PIPE\n[hi substitution]\n4\nor 4\n"
+    );
+}
+
+#[test]
+fn exit_and_return_leave_only_their_own_context() {
+    let output = run_script(
+        "x=$(echo in; exit 3; echo no); echo \"[$x] $?\"\n\
+         g() { while :; do for i in 1 2; do return 6; done; done; echo no; }\n\
+         g; echo \"g $?\"\n\
+         for i in 1 2 3; do for j in a b; do continue 2; echo no; done; done; echo \"i $i\"\n\
+         f() { for i in 1 2; do exit 5; done; echo no; }\n\
+         f\n\
+         echo no\n",
+    );
+    assert_eq!(stdout(&output), "[in] 3\ng 6\ni 3\n");
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[test]
+fn time_reports_durations_and_keeps_the_status() {
+    let output = run_script("time sleep 0.2 | sh -c 'cat; exit 3'\necho \"status $?\"\n");
+    assert_eq!(stdout(&output), "status 3\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", stderr);
+    assert_eq!(lines[0], "");
+    for (line, label) in lines[1..].iter().zip(["real", "user", "sys"]) {
+        let (name, duration) = line.split_once('\t').unwrap();
+        assert_eq!(name, label);
+        let (minutes, seconds) = duration.split_once('m').unwrap();
+        assert_eq!(minutes, "0");
+        let seconds = seconds.strip_suffix('s').unwrap();
+        assert_eq!(seconds.split_once('.').unwrap().1.len(), 3);
+        if label == "real" {
+            assert!(seconds.parse::<f64>().unwrap() >= 0.2, "{}", line);
+        }
+    }
+}
+
+#[test]
+fn background_functions_and_groups_run_in_a_copy_of_the_shell() {
+    let output = run_script(
+        "x=before\n\
+         f() { x=changed; echo \"f $1 $x\"; return 6; }\n\
+         f arg > /dev/null &\n\
+         pid=$!\n\
+         wait $pid\n\
+         echo \"f $?\"\n\
+         { sleep 0.1; exit 5; } &\n\
+         if [ $! != $pid ]; then echo new pid; fi\n\
+         wait $!\n\
+         echo \"group $?\"\n\
+         f out &\n\
+         wait\n\
+         echo \"x $x\"\n\
+         sh -c 'exit 4' &\n\
+         sleep 0.2\n\
+         wait $!\n\
+         echo \"finished $?\"\n",
+    );
+    let lines: Vec<_> = stdout(&output).lines().map(str::to_string).collect();
+    assert_eq!(
+        lines,
+        [
+            "f 6",
+            "new pid",
+            "group 5",
+            "f out changed",
+            "x before",
+            "finished 4"
+        ]
+    );
+}
+
+/// Runs `input` through an interactive shell reading its standard input.
+fn run_interactive(input: impl AsRef<[u8]>) -> Output {
+    let mut child = Command::new(BELLOS)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_ref())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Input that reduces to nothing once blanks, separators and comments are
+/// gone.
+const DEGENERATE_INPUTS: [&str; 3] = ["   \t  \n", ";\n", "    # only a comment\n"];
+
+#[test]
+fn degenerate_input_succeeds_silently_in_scripts_and_interactive_mode() {
+    for input in DEGENERATE_INPUTS {
+        let script = run_script(input);
+        assert_eq!(stdout(&script), "", "{:?}", input);
+        assert_eq!(String::from_utf8_lossy(&script.stderr), "", "{:?}", input);
+        assert_eq!(script.status.code(), Some(0), "{:?}", input);
+
+        let interactive = run_interactive(format!("{}echo $?\n", input));
+        assert_eq!(
+            stdout(&interactive),
+            "bellos> bellos> 0\nbellos> \n",
+            "{:?}",
+            input
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&interactive.stderr),
+            "",
+            "{:?}",
+            input
+        );
+        assert_eq!(interactive.status.code(), Some(0), "{:?}", input);
+    }
+}
+
+#[test]
+fn interactive_input_that_is_not_utf8_is_replaced() {
+    let output = run_interactive(b"echo hi\n\xff\necho after\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "bellos> hi\nbellos> bellos> after\nbellos> \n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "bellos: \u{fffd}: command not found\n"
+    );
+}
+
+#[test]
+fn scripts_report_finished_jobs_once_and_wait_still_finds_them() {
+    let output = run_script(
+        "sleep 0.1 &\n\
+         sleep 0.4; echo next\n\
+         sh -c 'exit 4' &\n\
+         sleep 0.3\n\
+         wait $!\n\
+         echo finished $?\n\
+         wait $!\n\
+         echo again $?\n",
+    );
+    let out = stdout(&output);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines, ["next", "finished 4", "again 127"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let notices: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with('['))
+        .collect();
+    assert_eq!(
+        notices,
+        [
+            "[1]  Done                    sleep 0.1",
+            "[1]  Exit 4                  sh -c 'exit 4'",
+        ]
+    );
+}
+
+#[test]
+fn starting_a_job_is_reported_on_stderr_only_when_interactive() {
+    let script = run_script("x=$(sleep 0.1 &); echo \"[$x]\"\nsleep 0.1 &\nwait\n");
+    assert_eq!(stdout(&script), "[]\n");
+    assert!(!String::from_utf8_lossy(&script.stderr).contains("] "));
+
+    let interactive = run_interactive("sleep 0.1 &\necho \"$!\"\nwait\n");
+    let out = stdout(&interactive);
+    let pid = out.trim_start_matches("bellos> ").lines().next().unwrap();
+    assert_eq!(out, format!("bellos> bellos> {}\nbellos> bellos> \n", pid));
+    let stderr = String::from_utf8_lossy(&interactive.stderr);
+    assert!(stderr.starts_with(&format!("[1] {}\n", pid)), "{}", stderr);
+}
+
+#[test]
+fn interactive_mode_reports_finished_jobs_once() {
+    let output = run_interactive("true &\nsh -c 'exit 3' &\nsleep 0.3\n:\n:\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.matches("[1]  Done                    true").count(),
+        1,
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("Exit 3").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn argv_shows_each_argument_as_expanded() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["b.txt", "a.txt"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let body = format!(
+        "cd {}\n\
+         argv\n\
+         x='one  two'\n\
+         argv \"$x\" '' \"a b\"c *.txt '*.txt' $(echo p q)\n",
+        dir.path().display()
+    );
+    assert_eq!(
+        stdout(&run_script(&body)),
+        "argc: 0\nargc: 8\n<one  two>\n<>\n<a bc>\n<a.txt>\n<b.txt>\n<*.txt>\n<p>\n<q>\n"
+    );
+}
+
+#[test]
+fn brackets_run_test_alone_and_glob_inside_words() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("g")).unwrap();
+    for name in ["a1", "b2", "c3", "file[1]"] {
+        fs::write(dir.path().join("g").join(name), "").unwrap();
+    }
+    let body = format!(
+        "cd {}\n\
+         x=1\n\
+         if [ $x -eq 1 ]; then echo spaced; fi\n\
+         [$x -eq 1]\n\
+         echo \"unspaced $?\"\n\
+         [ $x -eq 1]\n\
+         echo \"unclosed $?\"\n\
+         [ -f 'g/file[1]' ] && echo quoted\n\
+         echo file[1] g/[ab]*\n",
+        dir.path().display()
+    );
+    let output = run_script(&body);
+    assert_eq!(
+        stdout(&output),
+        "spaced\nunspaced 127\nunclosed 2\nquoted\nfile[1] g/a1 g/b2\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[1: command not found"), "{}", stderr);
+    assert!(stderr.contains("missing `]'"), "{}", stderr);
+}
+
+#[test]
+fn keywords_print_as_arguments_and_still_start_conditionals() {
+    let output = run_script("echo if then fi\nif true; then echo y; fi\necho for in do done\n");
+    assert_eq!(stdout(&output), "if then fi\ny\nfor in do done\n");
+}
+
+#[test]
+fn keywords_in_argument_position_keep_their_text() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "echo done fi case
+         for in in a b; do echo \"<$in>\"; done
+         touch do; [ -f do ] && echo created
+         rm do; [ -e do ] || echo removed
+",
+    );
+    let output = Command::new(BELLOS)
+        .arg(&path)
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(
+        stdout(&output),
+        "done fi case\n<a>\n<b>\ncreated\nremoved\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn quoted_arithmetic_stays_in_one_argument() {
+    let output = run_script(
+        "a=2 b=3 unit=items\n\
+         argv \"total: $((a + b)) $unit\" \"$(( (a + 1) * (b - 1) ))\"x \"$a+$b=$((a+b))\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "argc: 3\n<total: 5 items>\n<6x>\n<2+3=5>\n"
+    );
+}
+
+#[test]
+fn single_quotes_print_their_text_as_written() {
+    let output = run_script("X=world\necho '$X'\necho \"$X\"\necho 'it'\\''s' '$(pwd) \\n'\n");
+    assert_eq!(stdout(&output), "$X\nworld\nit's $(pwd) \\n\n");
+}
+
+#[test]
+fn double_quotes_replace_their_escapes() {
+    let output = run_script(
+        "X=v\n\
+         argv \"a\\nb\" \"a\\tb\" \"back\\\\slash\" \"say \\\"hi\\\"\" \"cost \\$X\" \"trailing\\\\\" \"a\\qb\" \"$X\\$X\"\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "argc: 8\n<a\nb>\n<a\tb>\n<back\\slash>\n<say \"hi\">\n<cost $X>\n<trailing\\>\n<a\\qb>\n<v$X>\n"
+    );
+}
+
+#[test]
+fn bind_lists_and_removes_bindings() {
+    let output = run_script(
+        "bind '\"\\C-t\": \"hello\\tworld\"' '\"\\ex\": done'\n\
+         bind -x '\"\\C-x\\C-r\": echo \"reloaded\"'\n\
+         bind -p\n\
+         bind -r '\\C-t'\n\
+         echo removed $?\n\
+         bind -r '\\C-t'\n\
+         echo again $?\n\
+         bind -p\n",
+    );
+    assert_eq!(
+        stdout(&output),
+        "\"\\C-t\": \"hello\\tworld\"\n\
+         -x \"\\C-x\\C-r\": \"echo \\\"reloaded\\\"\"\n\
+         \"\\ex\": \"done\"\n\
+         removed 0\n\
+         again 1\n\
+         -x \"\\C-x\\C-r\": \"echo \\\"reloaded\\\"\"\n\
+         \"\\ex\": \"done\"\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "bind: \\C-t: not bound\n"
+    );
+}
+
+#[test]
+fn clear_writes_nothing_when_output_is_not_a_terminal() {
+    let output = run_script("clear\necho $?\necho \"[$(clear)]\"\n");
+    assert_eq!(stdout(&output), "0\n[]\n");
+}
+
+#[test]
+fn loops_feed_and_read_pipelines() {
+    let output = run_script(
+        "for i in 3 1 2; do echo $i; done | sort\n\
+         printf 'one\\ntwo words\\n' | while read l; do echo \"<$l>\"; done\n\
+         count=0\n\
+         printf 'a\\nb\\nc\\n' | while read l; do count=$((count + 1)); done\n\
+         echo $count\n",
+    );
+    assert_eq!(stdout(&output), "1\n2\n3\n<one>\n<two words>\n3\n");
+}
+
+#[test]
+fn pipelines_pass_every_byte_value_through() {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..=255u8).chain((0..=255u8).rev()).collect();
+    fs::write(dir.path().join("data"), &data).unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "cat data | write builtin-copy\n\
+         readfile -r data | cat | cat > external-copy\n\
+         readfile -r data | { cat; } > group-copy\n\
+         cat data | wc -c\n\
+         for copy in data builtin-copy external-copy group-copy; do cksum < $copy; done\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(dir.path())
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stdout = stdout(&output);
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next().map(str::trim), Some("512"));
+    let sums: Vec<&str> = lines.collect();
+    assert_eq!(sums.len(), 4, "{}", stdout);
+    assert!(sums.iter().all(|sum| *sum == sums[0]), "{}", stdout);
+    for copy in ["builtin-copy", "external-copy", "group-copy"] {
+        assert_eq!(fs::read(dir.path().join(copy)).unwrap(), data, "{}", copy);
+    }
+}
+
+#[test]
+fn listings_are_sorted_and_the_same_in_every_shell() {
+    let listing = "set; echo --; export; echo --; declare -F\n";
+    let dir = tempfile::tempdir().unwrap();
+    let run = |definitions: &str| {
+        let path = script(
+            dir.path(),
+            "script",
+            &format!("{}\n{}", definitions, listing),
+        );
+        let output = Command::new(BELLOS)
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .current_dir(dir.path())
+            .arg(&path)
+            .output()
+            .unwrap();
+        stdout(&output)
+    };
+    let first = run("zeta=1; alpha=2; export mid=3 beta=4; f2() { :; }; f1() { :; }; b() { :; }");
+    let second = run("b() { :; }; f1() { :; }; export beta=4 mid=3; f2() { :; }; alpha=2; zeta=1");
+    assert_eq!(first, second);
+
+    let sections: Vec<Vec<&str>> = first
+        .split("--\n")
+        .map(|section| section.lines().collect())
+        .collect();
+    assert_eq!(sections.len(), 3, "{}", first);
+    for section in &sections {
+        let mut sorted = section.clone();
+        sorted.sort();
+        assert_eq!(*section, sorted);
+    }
+    assert!(sections[0].contains(&"alpha=2") && sections[0].contains(&"zeta=1"));
+    assert!(sections[1].contains(&"export beta=4") && !sections[1].contains(&"export zeta=1"));
+    assert_eq!(
+        sections[2],
+        ["declare -f b", "declare -f f1", "declare -f f2"]
+    );
+}
+
+#[test]
+fn which_searches_path_for_executables_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+    fs::create_dir(&first).unwrap();
+    fs::create_dir(&second).unwrap();
+    script(&first, "tool", "");
+    script(&second, "tool", "");
+    fs::write(first.join("decoy"), "").unwrap();
+    script(&second, "decoy", "");
+    fs::write(first.join("plain"), "").unwrap();
+    let path = script(
+        dir.path(),
+        "script",
+        "which tool; echo $?\n\
+         which -a tool decoy; echo $?\n\
+         which plain; echo $?\n\
+         which -s tool; echo $?\n\
+         which -as tool missing; echo $?\n\
+         which echo; echo $?\n\
+         f() { :; }; which f; echo $?\n",
+    );
+    let output = Command::new(BELLOS)
+        .env("PATH", format!("{}:{}", first.display(), second.display()))
+        .arg(&path)
+        .output()
+        .unwrap();
+    let (first, second) = (first.display(), second.display());
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{first}/tool\n0\n\
+             {first}/tool\n{second}/tool\n{second}/decoy\n0\n\
+             1\n\
+             0\n\
+             1\n\
+             1\n\
+             1\n"
+        )
+    );
+}
+
+#[test]
+fn assertions_report_their_line_and_stop_the_script_unless_kept_going() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = script(
+        dir.path(),
+        "checks",
+        "x=5; name=\"a b\"\n\
+         assert [ \"$x\" -eq 5 ]\n\
+         assert_eq \"$name\" \"a b\"\n\
+         echo passed\n\
+         assert -k [ \"$x\" -eq 6 ]\n\
+         echo \"kept $?\"\n\
+         assert_eq -k \"$name\" \"a c\"\n\
+         echo \"kept $?\"\n\
+         assert -k false\n\
+         assert [ \"$name\" = \"\" ]\n\
+         echo never\n",
+    );
+    let output = Command::new(BELLOS).arg(&path).output().unwrap();
+    assert_eq!(stdout(&output), "passed\nkept 1\nkept 1\n");
+    assert_eq!(output.status.code(), Some(1));
+    let path = path.display();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!(
+            "{path}: line 5: assertion failed: [ \"$x\" -eq 6 ]\n  expanded: [ 5 -eq 6 ]\n\
+             {path}: line 7: assertion failed: assert_eq \"$name\" \"a c\"\n\
+             \x20 left:  \"a b\"\n  right: \"a c\"\n            ^\n\
+             {path}: line 9: assertion failed: false\n  expanded: false\n  status: 1\n\
+             {path}: line 10: assertion failed: [ \"$name\" = \"\" ]\n  expanded: [ \"a b\" = \"\" ]\n"
+        )
+    );
+}
+
+#[test]
+fn include_lists_the_files_it_loaded_from_bellos_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir_path = dir.path().canonicalize().unwrap();
+    let (first, second) = (dir_path.join("first"), dir_path.join("second"));
+    fs::create_dir(&first).unwrap();
+    fs::create_dir(&second).unwrap();
+    fs::write(first.join("util.bellos"), "echo first util\n").unwrap();
+    fs::write(second.join("util.bellos"), "echo second util\n").unwrap();
+    fs::write(
+        second.join("only.bellos"),
+        "echo only\ninclude util.bellos\n",
+    )
+    .unwrap();
+    let path = script(
+        &dir_path,
+        "script",
+        "include util.bellos\ninclude only.bellos\ninclude util.bellos\ninclude -l\ninclude nothing.bellos\necho $?\n",
+    );
+    let output = Command::new(BELLOS)
+        .env(
+            "BELLOS_PATH",
+            format!("{}:{}", first.display(), second.display()),
+        )
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "first util\nonly\n{}\n{}\n1\n",
+            first.join("util.bellos").display(),
+            second.join("only.bellos").display()
+        )
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "include: nothing.bellos: not found (searched {}, {}, {})",
+        first.display(),
+        second.display(),
+        dir_path.display()
+    )));
+}
+
+#[test]
+fn cd_looks_up_cdpath_after_the_current_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    for sub in ["work/local", "a/proj", "a/local", "b/proj", "b/only"] {
+        fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    let work = root.join("work");
+    let path = script(
+        &root,
+        "script",
+        "W=$PWD\n\
+         cd proj; echo \"pwd $PWD\"\n\
+         cd \"$W\"; cd only; echo \"pwd $PWD\"\n\
+         cd \"$W\"; cd local; echo \"pwd $PWD\"\n\
+         cd \"$W\"; cd ./proj 2>/dev/null; echo \"status $?\"\n\
+         cd \"$W\"; cd ../b/proj; echo \"pwd $PWD\"\n\
+         cd \"$W\"; cd \"$W/local\"; echo \"pwd $PWD\"\n",
+    );
+    let output = Command::new(BELLOS)
+        .current_dir(&work)
+        .env("PWD", &work)
+        .env(
+            "CDPATH",
+            format!("{}:{}", root.join("a").display(), root.join("b").display()),
+        )
+        .arg(&path)
+        .output()
+        .unwrap();
+    let root = root.display();
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{root}/a/proj\npwd {root}/a/proj\n\
+             {root}/b/only\npwd {root}/b/only\n\
+             pwd {root}/work/local\n\
+             status 1\n\
+             pwd {root}/b/proj\n\
+             pwd {root}/work/local\n"
+        )
+    );
+}
+
+#[test]
+fn fc_lists_reruns_and_edits_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let editor = script(
+        dir.path(),
+        "editor",
+        "#!/bin/sh\nsed 's/one/edited/' \"$1\" > \"$1.new\" && mv \"$1.new\" \"$1\"\n",
+    );
+    let input = format!(
+        "echo one\n\
+         echo two\n\
+         fc -l\n\
+         fc -lr 1 2\n\
+         fc -ln 2 3\n\
+         fc -s two=three 2\n\
+         EDITOR={}\n\
+         fc 1\n\
+         fc -l -3\n",
+        editor.display()
+    );
+    let mut child = Command::new(BELLOS)
+        .env("HOME", dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+        stdout(&output).replace("bellos> ", ""),
+        format!(
+            "one\n\
+             two\n\
+             1\techo one\n2\techo two\n\
+             2\techo two\n1\techo one\n\
+             \techo two\n\tfc -l\n\
+             three\n\
+             edited\n\
+             6\techo three\n7\tEDITOR={}\n8\techo edited\n\n",
+            editor.display()
+        )
+    );
+    // Commands run again are shown on standard error first.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, "echo three\necho edited\n");
+}
+
+#[test]
+fn scripts_given_with_f_share_one_shell_and_stop_at_a_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let setup = script(
+        dir.path(),
+        "setup",
+        "name=world\ngreet() { echo \"hello $name\"; }\n",
+    );
+    let fail = script(dir.path(), "fail", "echo failing\n/bin/sh -c 'exit 3'\n");
+    let deploy = script(dir.path(), "deploy", "greet\nname=again\n");
+    let last = script(dir.path(), "last", "greet\n");
+    let run = |args: &[&Path]| {
+        let mut command = Command::new(BELLOS);
+        for arg in args {
+            if arg.as_os_str() == "--keep-going" {
+                command.arg(arg);
+            } else {
+                command.arg("-f").arg(arg);
+            }
+        }
+        command.output().unwrap()
+    };
+
+    let shared = run(&[&setup, &deploy, &last]);
+    assert_eq!(stdout(&shared), "hello world\nhello again\n");
+    assert_eq!(shared.status.code(), Some(0));
+
+    let stopped = run(&[&setup, &fail, &deploy]);
+    assert_eq!(stdout(&stopped), "failing\n");
+    assert_eq!(stopped.status.code(), Some(3));
+
+    let kept_going = run(&[Path::new("--keep-going"), &setup, &fail, &deploy]);
+    assert_eq!(stdout(&kept_going), "failing\nhello world\n");
+    assert_eq!(kept_going.status.code(), Some(0));
+}
+
+/// Runs bellos with `args` in `dir` on a new pseudo-terminal that becomes
+/// its controlling terminal, so it turns job control on, typing each of
+/// `inputs` into it in turn, `pause` apart. Returns what appeared on the
+/// terminal.
+fn run_in_terminal(dir: &Path, args: &[&str], inputs: &[&str], pause: Duration) -> String {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let (mut master, mut slave) = (0, 0);
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(opened, 0, "openpty: {}", std::io::Error::last_os_error());
+    let (mut master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+    let mut command = Command::new(BELLOS);
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .stdin(slave.try_clone().unwrap())
+        .stdout(slave.try_clone().unwrap())
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    // The terminal hangs up once the shell and its children close it.
+    drop(command);
+
+    let mut reader = master.try_clone().unwrap();
+    let output = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buffer = [0; 4096];
+        // Reading fails with EIO after the hangup.
+        while let Ok(count @ 1..) = reader.read(&mut buffer) {
+            output.extend_from_slice(&buffer[..count]);
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    });
+    for (i, input) in inputs.iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(pause);
+        }
+        master.write_all(input.as_bytes()).unwrap();
+    }
+    assert!(child.wait().unwrap().success());
+    output.join().unwrap()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn foreground_jobs_get_a_process_group_shared_by_a_pipeline() {
+    let dir = tempfile::tempdir().unwrap();
+    // The fifth field of /proc/PID/stat is the process group.
+    let group = "cut -d' ' -f5 /proc/\\$$/stat";
+    let input = format!(
+        "echo $$ > shell\n\
+         sh -c \"echo \\$$ > single-pid; {group} > single\"\n\
+         sh -c \"echo \\$$ > first-pid; {group} > first\" | sh -c \"cat; {group} > second\"\n\
+         exit\n"
+    );
+    let terminal = run_in_terminal(dir.path(), &[], &[&input], Duration::ZERO);
+    let read = |name: &str| {
+        fs::read_to_string(dir.path().join(name))
+            .unwrap_or_else(|e| panic!("{}: {}\n{}", name, e, terminal))
+            .trim()
+            .to_string()
+    };
+    let shell = read("shell");
+    assert_eq!(read("single"), read("single-pid"));
+    assert_ne!(read("single"), shell);
+    assert_eq!(read("first"), read("first-pid"));
+    assert_eq!(read("second"), read("first"));
+    assert_ne!(read("first"), shell);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn job_notices_leave_the_prompt_on_a_line_of_its_own() {
+    let dir = tempfile::tempdir().unwrap();
+    // The job finishes while `echo mid` is being typed, between the
+    // second and third inputs.
+    let terminal = run_in_terminal(
+        dir.path(),
+        &[],
+        &["sleep 1 &\n", "echo mid", "dle\nexit\n"],
+        Duration::from_millis(700),
+    );
+    let lines: Vec<&str> = terminal.split("\r\n").collect();
+    let notice = lines
+        .iter()
+        .position(|line| line.contains("Done") && line.contains("sleep 1"))
+        .unwrap_or_else(|| panic!("no notice:\n{:?}", terminal));
+    // The notice replaces the line being edited, on a cleared row, and the
+    // line is drawn again from the start of the next.
+    let (edited, notice_text) = lines[notice].rsplit_once("\r\x1b[K").unwrap();
+    assert!(edited.ends_with("bellos> echo mid\x1b[K"), "{:?}", terminal);
+    assert!(notice_text.starts_with("[1]"), "{:?}", terminal);
+    assert!(
+        lines[notice + 1].starts_with("\rbellos> echo mid\x1b[K"),
+        "{:?}",
+        terminal
+    );
+    assert!(lines[notice + 2..].contains(&"middle"), "{:?}", terminal);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_script_run_with_i_leaves_its_definitions_at_the_prompt() {
+    let dir = tempfile::tempdir().unwrap();
+    script(
+        dir.path(),
+        "setup.bellos",
+        "greet() { echo \"hello $1\"; }\nplace=world\nno_such_command_here\n",
+    );
+    fs::write(dir.path().join(".bellosrc"), "from_rc=yes\n").unwrap();
+    let terminal = run_in_terminal(
+        dir.path(),
+        &["-i", "setup.bellos"],
+        &["greet $place\necho rc=$from_rc\nexit\n"],
+        Duration::ZERO,
+    );
+    let lines: Vec<&str> = terminal.split("\r\n").collect();
+    assert!(terminal.contains("no_such_command_here"), "{:?}", terminal);
+    assert!(lines.contains(&"hello world"), "{:?}", terminal);
+    assert!(lines.contains(&"rc=yes"), "{:?}", terminal);
+}
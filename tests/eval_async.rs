@@ -0,0 +1,127 @@
+// Copyright (C) 2024 Bellande Architecture Mechanism Research Innovation Center, Ronaldson Bellande
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs scripts through `Shell::eval_async` inside a tokio runtime, as an
+//! embedder does.
+#![cfg(all(unix, feature = "async"))]
+
+use bellos::executor_processes::testing::RecordingSpawner;
+use bellos::shell::shell::Shell;
+use std::fs;
+
+fn variable(shell: &Shell, name: &str) -> Option<String> {
+    shell.interpreter.variables.get(name).cloned()
+}
+
+#[tokio::test]
+async fn pipelines_run_through_tokio_processes() {
+    let mut shell = Shell::new();
+    let script = "first=$(printf 'b\\na\\nc\\n' | sort | head -n 1)\n\
+                  both=$(printf 'x\\ny\\n' | sort -r | tr -d '\\n')\n\
+                  echo one two | read word rest";
+    assert_eq!(shell.eval_async(script).await, Ok(0));
+    assert_eq!(variable(&shell, "first").as_deref(), Some("a"));
+    assert_eq!(variable(&shell, "both").as_deref(), Some("yx"));
+    assert_eq!(variable(&shell, "word").as_deref(), Some("one"));
+    assert_eq!(shell.eval_async("false | true").await, Ok(0));
+    assert_eq!(shell.eval_async("true | false").await, Ok(1));
+}
+
+#[tokio::test]
+async fn background_jobs_are_waited_for_on_tokio_timers() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let mut shell = Shell::new();
+    let script = format!(
+        "sleep 0.2 & job=$!\n\
+         wait $job; waited=$?\n\
+         f() {{ echo bg > {}; return 4; }}\n\
+         f & wait $!; function=$?",
+        out.display()
+    );
+    assert_eq!(shell.eval_async(&script).await, Ok(0));
+    assert!(variable(&shell, "job").is_some());
+    assert_eq!(variable(&shell, "waited").as_deref(), Some("0"));
+    assert_eq!(variable(&shell, "function").as_deref(), Some("4"));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "bg\n");
+}
+
+#[tokio::test]
+async fn exit_in_a_trap_resolves_the_future() {
+    let mut shell = Shell::new();
+    let script = "trap 'exit 5' ERR\nsh -c 'exit 1'\nnever=ran";
+    assert_eq!(shell.eval_async(script).await, Ok(5));
+    assert_eq!(variable(&shell, "never"), None);
+    assert_eq!(shell.eval_async("trap - ERR; true").await, Ok(0));
+}
+
+#[test]
+fn deep_nesting_runs_on_a_worker_thread_stack() {
+    let depth = bellos::parser::parser::MAX_NESTING - 1;
+    let script = "if true; then ".repeat(depth)
+        + "x="
+        + &"$(echo ".repeat(depth)
+        + "s"
+        + &")".repeat(depth)
+        + "; "
+        + &"fi; ".repeat(depth);
+    // The stack of a tokio worker, or of any thread spawned with the
+    // defaults.
+    let worker = std::thread::Builder::new().stack_size(2 << 20);
+    let x = worker
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let mut shell = Shell::new();
+            assert_eq!(runtime.block_on(shell.eval_async(&script)), Ok(0));
+            variable(&shell, "x")
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(x.as_deref(), Some("s"));
+}
+
+#[tokio::test]
+async fn a_spawner_runs_every_command_on_tokio() {
+    let spawner = RecordingSpawner::new();
+    let mut shell = Shell::new();
+    shell.processes.spawner = Some(Box::new(spawner.clone()));
+    let script = "worker foreground\n\
+                  worker first | worker second\n\
+                  worker background &\n\
+                  { worker brace; } &\n\
+                  f() { worker function; }; f &\n\
+                  wait";
+    assert_eq!(shell.eval_async(script).await, Ok(0));
+    let ran: Vec<_> = spawner
+        .invocations()
+        .into_iter()
+        .map(|invocation| invocation.args.join(" "))
+        .collect();
+    assert_eq!(
+        ran,
+        [
+            "foreground",
+            "first",
+            "second",
+            "background",
+            "brace",
+            "function"
+        ]
+    );
+}